@@ -0,0 +1,29 @@
+ALTER TABLE archives ADD COLUMN first_seen timestamp with time zone;
+ALTER TABLE archives ADD COLUMN last_seen timestamp with time zone;
+
+CREATE TABLE inventories (
+  job_id varchar(256) PRIMARY KEY REFERENCES jobs(job_id),
+  vault_arn varchar(256) NOT NULL REFERENCES vaults(vault_arn),
+  inventory_date timestamp with time zone NOT NULL
+);
+
+CREATE TABLE inventories_archives (
+  job_id varchar(256) REFERENCES inventories(job_id),
+  archive_id varchar(256) REFERENCES archives(archive_id),
+  archive_description varchar(256),
+  size bigint NOT NULL,
+  tree_hash varchar(256) NOT NULL,
+  PRIMARY KEY (job_id, archive_id)
+);
+
+GRANT SELECT, INSERT, UPDATE ON inventories TO updater;
+GRANT SELECT, INSERT, UPDATE ON inventories_archives TO updater;
+
+GRANT SELECT, INSERT, UPDATE ON vaults TO worker;
+GRANT SELECT, INSERT, UPDATE ON jobs TO worker;
+GRANT SELECT, INSERT, DELETE ON vaults_archives TO worker;
+GRANT SELECT, INSERT, UPDATE ON inventories TO worker;
+GRANT SELECT, INSERT, UPDATE ON inventories_archives TO worker;
+
+GRANT SELECT ON inventories TO api;
+GRANT SELECT ON inventories_archives TO api;
@@ -0,0 +1,2 @@
+GRANT DELETE ON inventories_archives TO updater;
+GRANT DELETE ON inventories_archives TO worker;
@@ -0,0 +1,11 @@
+ALTER TABLE jobs ADD COLUMN inventory_format varchar(16);
+ALTER TABLE jobs ADD COLUMN inventory_start_date timestamp with time zone;
+ALTER TABLE jobs ADD COLUMN inventory_end_date timestamp with time zone;
+ALTER TABLE jobs ADD COLUMN inventory_limit bigint;
+ALTER TABLE jobs ADD COLUMN inventory_marker varchar(1024);
+
+ALTER TABLE inventories ADD COLUMN partial boolean NOT NULL DEFAULT FALSE;
+ALTER TABLE inventories ADD COLUMN marker varchar(1024);
+ALTER TABLE inventories ADD COLUMN continuation_job_id varchar(256) REFERENCES jobs(job_id);
+
+ALTER TABLE vaults_archives ADD PRIMARY KEY (vault_arn, archive_id);
@@ -0,0 +1,20 @@
+CREATE TABLE backups (
+  archive_id varchar(256) PRIMARY KEY REFERENCES archives(archive_id),
+  vault_arn varchar(256) NOT NULL REFERENCES vaults(vault_arn),
+  creation_date timestamp with time zone NOT NULL,
+  compression varchar(16) NOT NULL,
+  source_size bigint NOT NULL
+);
+
+CREATE TABLE backups_sources (
+  archive_id varchar(256) REFERENCES backups(archive_id),
+  path varchar(4096) NOT NULL,
+  size bigint NOT NULL,
+  PRIMARY KEY (archive_id, path)
+);
+
+GRANT SELECT, INSERT, UPDATE ON backups TO worker;
+GRANT SELECT, INSERT, UPDATE ON backups_sources TO worker;
+
+GRANT SELECT ON backups TO api;
+GRANT SELECT ON backups_sources TO api;
@@ -0,0 +1 @@
+ALTER TABLE backups ADD COLUMN encryption varchar(32);
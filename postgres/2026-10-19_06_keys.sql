@@ -0,0 +1,19 @@
+CREATE TABLE keys (
+  key_id varchar(256) PRIMARY KEY,
+  key_type varchar(32) NOT NULL,
+  salt bytea NOT NULL,
+  iterations integer NOT NULL,
+  check_value varchar(64) NOT NULL,
+  creation_date timestamp with time zone NOT NULL,
+  active boolean NOT NULL
+);
+
+CREATE TABLE archives_keys (
+  archive_id varchar(256) REFERENCES archives(archive_id),
+  key_id varchar(256) REFERENCES keys(key_id),
+  wrapped_key bytea NOT NULL,
+  PRIMARY KEY (archive_id, key_id)
+);
+
+GRANT SELECT, INSERT, UPDATE ON keys TO worker;
+GRANT SELECT, INSERT, UPDATE, DELETE ON archives_keys TO worker;
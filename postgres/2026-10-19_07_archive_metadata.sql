@@ -0,0 +1,20 @@
+ALTER TABLE archives ALTER COLUMN archive_description TYPE varchar(1024);
+ALTER TABLE inventories_archives ALTER COLUMN archive_description TYPE varchar(1024);
+
+CREATE TABLE archives_metadata (
+  archive_id varchar(256) PRIMARY KEY REFERENCES archives(archive_id),
+  version smallint NOT NULL,
+  host varchar(256) NOT NULL,
+  backup_set varchar(256),
+  paths jsonb NOT NULL,
+  paths_truncated boolean NOT NULL,
+  creation_date timestamp with time zone NOT NULL,
+  encryption varchar(32),
+  key_ids jsonb NOT NULL,
+  content_hash varchar(256)
+);
+
+GRANT SELECT, INSERT, UPDATE ON archives_metadata TO updater;
+GRANT SELECT, INSERT, UPDATE ON archives_metadata TO worker;
+
+GRANT SELECT ON archives_metadata TO api;
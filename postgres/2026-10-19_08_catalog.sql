@@ -0,0 +1,17 @@
+CREATE TABLE catalog_entries (
+  archive_id varchar(256) REFERENCES backups(archive_id),
+  path varchar(4096) NOT NULL,
+  entry_type varchar(16) NOT NULL,
+  size bigint NOT NULL,
+  mtime timestamp with time zone NOT NULL,
+  mode integer NOT NULL,
+  content_hash varchar(64),
+  "offset" bigint NOT NULL,
+  PRIMARY KEY (archive_id, path)
+);
+
+CREATE INDEX catalog_entries_path ON catalog_entries (path);
+
+GRANT SELECT, INSERT ON catalog_entries TO worker;
+
+GRANT SELECT ON catalog_entries TO api;
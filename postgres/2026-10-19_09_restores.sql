@@ -0,0 +1,19 @@
+CREATE TABLE restores (
+  restore_id uuid PRIMARY KEY,
+  archive_id varchar(256) NOT NULL REFERENCES archives(archive_id),
+  vault_arn varchar(256) NOT NULL REFERENCES vaults(vault_arn),
+  tier varchar(16) NOT NULL,
+  destination varchar(4096) NOT NULL,
+  download_path varchar(4096) NOT NULL,
+  state varchar(32) NOT NULL,
+  job_id varchar(256) REFERENCES jobs(job_id),
+  downloaded_size bigint NOT NULL,
+  error varchar(4096),
+  creation_date timestamp with time zone NOT NULL,
+  update_date timestamp with time zone NOT NULL
+);
+
+GRANT SELECT, INSERT, UPDATE ON restores TO worker;
+GRANT SELECT, INSERT, UPDATE ON jobs_status TO worker;
+
+GRANT SELECT ON restores TO api;
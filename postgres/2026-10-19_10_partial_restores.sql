@@ -0,0 +1,18 @@
+ALTER TABLE jobs ADD COLUMN retrieval_byte_range varchar(64);
+
+ALTER TABLE backups ADD COLUMN encryption_header bytea;
+
+CREATE TABLE backups_frames (
+  archive_id varchar(256) REFERENCES backups(archive_id),
+  tar_offset bigint NOT NULL,
+  compressed_offset bigint NOT NULL,
+  PRIMARY KEY (archive_id, tar_offset)
+);
+
+ALTER TABLE restores ADD COLUMN paths varchar(4096)[] NOT NULL DEFAULT '{}';
+ALTER TABLE restores ADD COLUMN range_start bigint;
+ALTER TABLE restores ADD COLUMN range_end bigint;
+
+GRANT SELECT, INSERT ON backups_frames TO worker;
+
+GRANT SELECT ON backups_frames TO api;
@@ -0,0 +1,50 @@
+CREATE TABLE chunks (
+  chunk_hash varchar(64) PRIMARY KEY,
+  archive_id varchar(256) NOT NULL REFERENCES archives(archive_id),
+  "offset" bigint NOT NULL,
+  length bigint NOT NULL,
+  size bigint NOT NULL
+);
+
+CREATE INDEX chunks_archive_id ON chunks (archive_id);
+
+CREATE TABLE snapshots (
+  snapshot_id uuid PRIMARY KEY,
+  vault_arn varchar(256) NOT NULL REFERENCES vaults(vault_arn),
+  backup_set varchar(256),
+  host varchar(256) NOT NULL,
+  creation_date timestamp with time zone NOT NULL,
+  size bigint NOT NULL,
+  chunk_count bigint NOT NULL,
+  new_chunk_count bigint NOT NULL,
+  new_size bigint NOT NULL
+);
+
+CREATE TABLE snapshots_sources (
+  snapshot_id uuid REFERENCES snapshots(snapshot_id),
+  path varchar(4096) NOT NULL,
+  size bigint NOT NULL,
+  PRIMARY KEY (snapshot_id, path)
+);
+
+CREATE TABLE snapshots_chunks (
+  snapshot_id uuid REFERENCES snapshots(snapshot_id),
+  seq bigint NOT NULL,
+  chunk_hash varchar(64) NOT NULL REFERENCES chunks(chunk_hash),
+  PRIMARY KEY (snapshot_id, seq)
+);
+
+ALTER TABLE restores ADD COLUMN snapshot_id uuid REFERENCES snapshots(snapshot_id);
+ALTER TABLE restores ADD COLUMN snapshot_restore_id uuid;
+
+CREATE INDEX restores_snapshot_restore_id ON restores (snapshot_restore_id);
+
+GRANT SELECT, INSERT ON chunks TO worker;
+GRANT SELECT, INSERT ON snapshots TO worker;
+GRANT SELECT, INSERT ON snapshots_sources TO worker;
+GRANT SELECT, INSERT ON snapshots_chunks TO worker;
+
+GRANT SELECT ON chunks TO api;
+GRANT SELECT ON snapshots TO api;
+GRANT SELECT ON snapshots_sources TO api;
+GRANT SELECT ON snapshots_chunks TO api;
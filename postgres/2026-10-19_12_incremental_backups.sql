@@ -0,0 +1,18 @@
+ALTER TABLE backups ADD COLUMN backup_set varchar(256);
+ALTER TABLE backups ADD COLUMN parent_archive_id varchar(256) REFERENCES backups(archive_id);
+ALTER TABLE backups ADD COLUMN chain_length integer NOT NULL DEFAULT 0;
+
+CREATE INDEX backups_backup_set ON backups (backup_set, creation_date);
+
+CREATE TABLE backups_deletions (
+  archive_id varchar(256) REFERENCES backups(archive_id),
+  path varchar(4096) NOT NULL,
+  PRIMARY KEY (archive_id, path)
+);
+
+ALTER TABLE catalog_entries ADD COLUMN inode bigint;
+ALTER TABLE catalog_entries ADD COLUMN data_archive_id varchar(256) REFERENCES backups(archive_id);
+
+GRANT SELECT, INSERT ON backups_deletions TO worker;
+
+GRANT SELECT ON backups_deletions TO api;
@@ -0,0 +1,22 @@
+CREATE TABLE retention_policies (
+  vault_arn varchar(256) NOT NULL REFERENCES vaults(vault_arn),
+  backup_set varchar(256),
+  keep_last integer NOT NULL,
+  keep_daily integer NOT NULL,
+  keep_weekly integer NOT NULL,
+  keep_monthly integer NOT NULL
+);
+
+CREATE UNIQUE INDEX retention_policies_scope ON retention_policies (vault_arn, (COALESCE(backup_set, '')));
+
+CREATE TABLE archives_deletions (
+  archive_id varchar(256) PRIMARY KEY REFERENCES archives(archive_id),
+  vault_arn varchar(256) NOT NULL REFERENCES vaults(vault_arn),
+  deletion_date timestamp with time zone NOT NULL
+);
+
+GRANT SELECT, INSERT, UPDATE, DELETE ON retention_policies TO worker;
+GRANT SELECT, INSERT ON archives_deletions TO worker;
+
+GRANT SELECT ON retention_policies TO api;
+GRANT SELECT ON archives_deletions TO api;
@@ -0,0 +1,5 @@
+ALTER TABLE jobs_workers ADD COLUMN lease_expiry timestamp with time zone;
+ALTER TABLE jobs_workers ADD COLUMN worker_id uuid;
+
+GRANT DELETE ON jobs_workers TO updater;
+GRANT DELETE ON jobs_workers TO worker;
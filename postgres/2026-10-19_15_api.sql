@@ -0,0 +1 @@
+GRANT SELECT ON vaults_archives TO api;
@@ -0,0 +1,19 @@
+CREATE TABLE job_requests (
+  request_id uuid PRIMARY KEY,
+  vault_arn varchar(256) NOT NULL REFERENCES vaults(vault_arn),
+  action varchar(32) NOT NULL,
+  archive_id varchar(256) REFERENCES archives(archive_id),
+  tier varchar(16),
+  inventory_format varchar(8),
+  requested_by varchar(256) NOT NULL,
+  state varchar(16) NOT NULL,
+  job_id varchar(256) REFERENCES jobs(job_id),
+  error varchar(4096),
+  creation_date timestamp with time zone NOT NULL,
+  update_date timestamp with time zone NOT NULL
+);
+
+CREATE INDEX job_requests_pending ON job_requests (creation_date) WHERE state = 'pending';
+
+GRANT SELECT, INSERT ON job_requests TO api;
+GRANT SELECT, UPDATE ON job_requests TO updater;
@@ -0,0 +1,18 @@
+CREATE TABLE multipart_uploads (
+  upload_id varchar(256) PRIMARY KEY,
+  vault_arn varchar(256) NOT NULL REFERENCES vaults(vault_arn),
+  backup_set varchar(256),
+  paths varchar[] NOT NULL,
+  archive_description text NOT NULL,
+  compression varchar(16) NOT NULL,
+  part_size bigint NOT NULL,
+  parent_archive_id varchar(256),
+  encryption_header bytea,
+  key_ids varchar[] NOT NULL,
+  wrapped_keys bytea[] NOT NULL,
+  size bigint NOT NULL,
+  tree_hashes varchar[] NOT NULL,
+  creation_date timestamp with time zone NOT NULL
+);
+
+GRANT SELECT, INSERT, DELETE ON multipart_uploads TO worker;
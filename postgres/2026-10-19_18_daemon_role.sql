@@ -0,0 +1,28 @@
+CREATE ROLE daemon LOGIN PASSWORD '{{daemon_password}}';
+
+GRANT SELECT, INSERT, UPDATE ON vaults TO daemon;
+GRANT SELECT, INSERT, UPDATE ON vaults_status TO daemon;
+GRANT SELECT, INSERT, UPDATE ON archives TO daemon;
+GRANT SELECT, INSERT, DELETE ON vaults_archives TO daemon;
+GRANT SELECT, INSERT, UPDATE ON jobs TO daemon;
+GRANT SELECT, INSERT, UPDATE ON jobs_status TO daemon;
+GRANT SELECT, INSERT, UPDATE, DELETE ON jobs_workers TO daemon;
+GRANT SELECT, INSERT, UPDATE ON inventories TO daemon;
+GRANT SELECT, INSERT, UPDATE, DELETE ON inventories_archives TO daemon;
+GRANT SELECT, INSERT, UPDATE ON archives_metadata TO daemon;
+GRANT SELECT, INSERT, UPDATE ON backups TO daemon;
+GRANT SELECT, INSERT, UPDATE ON backups_sources TO daemon;
+GRANT SELECT, INSERT ON backups_frames TO daemon;
+GRANT SELECT, INSERT ON backups_deletions TO daemon;
+GRANT SELECT, INSERT ON catalog_entries TO daemon;
+GRANT SELECT, INSERT, UPDATE ON keys TO daemon;
+GRANT SELECT, INSERT, UPDATE, DELETE ON archives_keys TO daemon;
+GRANT SELECT, INSERT, UPDATE ON restores TO daemon;
+GRANT SELECT, INSERT ON chunks TO daemon;
+GRANT SELECT, INSERT ON snapshots TO daemon;
+GRANT SELECT, INSERT ON snapshots_sources TO daemon;
+GRANT SELECT, INSERT ON snapshots_chunks TO daemon;
+GRANT SELECT, INSERT, UPDATE, DELETE ON retention_policies TO daemon;
+GRANT SELECT, INSERT ON archives_deletions TO daemon;
+GRANT SELECT, UPDATE ON job_requests TO daemon;
+GRANT SELECT, INSERT, DELETE ON multipart_uploads TO daemon;
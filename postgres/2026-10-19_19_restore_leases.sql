@@ -0,0 +1,2 @@
+ALTER TABLE restores ADD COLUMN worker_id uuid;
+ALTER TABLE restores ADD COLUMN lease_expiry timestamp with time zone;
@@ -0,0 +1,3 @@
+ALTER TABLE snapshots_chunks DROP CONSTRAINT snapshots_chunks_chunk_hash_fkey;
+ALTER TABLE chunks DROP CONSTRAINT chunks_pkey;
+ALTER TABLE chunks ADD PRIMARY KEY (chunk_hash, archive_id);
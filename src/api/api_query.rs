@@ -0,0 +1,130 @@
+use super::ApiError;
+use crate::repo::Page;
+use chrono::{DateTime, FixedOffset};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+pub const DEFAULT_LIMIT: i64 = 100;
+pub const MAX_LIMIT: i64 = 1000;
+
+/// The parameters of the query string of a request.
+#[derive(Debug, Default)]
+pub struct Query {
+    parameters: HashMap<String, String>,
+}
+
+impl Query {
+    pub fn parse(query: Option<&str>) -> Self {
+        Query {
+            parameters: form_urlencoded::parse(query.unwrap_or_default().as_bytes())
+                .into_owned()
+                .collect(),
+        }
+    }
+
+    /// Fails for parameters other than the given ones and the pagination parameters, so typos do not go unnoticed.
+    pub fn expect(&self, names: &[&str]) -> Result<(), ApiError> {
+        match self.parameters.keys().find(|k| {
+            !names.contains(&k.as_str()) && k.as_str() != "limit" && k.as_str() != "offset"
+        }) {
+            Some(name) => Err(ApiError::bad_request(format!(
+                "unknown parameter \"{}\"",
+                name
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.parameters.get(name).map(|v| v.as_str())
+    }
+
+    pub fn parse_value<T: FromStr>(&self, name: &str) -> Result<Option<T>, ApiError> {
+        match self.get(name) {
+            Some(value) => value.parse().map(Some).map_err(|_| {
+                ApiError::bad_request(format!("invalid value \"{}\" of \"{}\"", value, name))
+            }),
+            None => Ok(None),
+        }
+    }
+
+    /// Parses a date in RFC 3339 format.
+    pub fn date(&self, name: &str) -> Result<Option<DateTime<FixedOffset>>, ApiError> {
+        match self.get(name) {
+            Some(value) => DateTime::parse_from_rfc3339(value).map(Some).map_err(|_| {
+                ApiError::bad_request(format!(
+                    "invalid date \"{}\" of \"{}\" (expected RFC 3339)",
+                    value, name
+                ))
+            }),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the page selected by `limit` (default 100, at most 1000) and `offset`.
+    pub fn page(&self) -> Result<Page, ApiError> {
+        let limit = self.parse_value("limit")?.unwrap_or(DEFAULT_LIMIT);
+        let offset = self.parse_value("offset")?.unwrap_or(0);
+
+        if !(1..=MAX_LIMIT).contains(&limit) {
+            return Err(ApiError::bad_request(format!(
+                "limit must be between 1 and {}",
+                MAX_LIMIT
+            )));
+        }
+
+        if offset < 0 {
+            return Err(ApiError::bad_request("offset must not be negative"));
+        }
+
+        Ok(Page { limit, offset })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_1() {
+        let query = Query::parse(Some(
+            "active=true&name=photos%202026&created_after=2026-10-01T00%3A00%3A00%2B02%3A00&limit=10",
+        ));
+
+        assert!(query.expect(&["active", "name", "created_after"]).is_ok());
+        assert!(query.expect(&["active", "name"]).is_err());
+        assert_eq!(query.parse_value::<bool>("active").unwrap(), Some(true));
+        assert_eq!(query.get("name"), Some("photos 2026"));
+        assert_eq!(
+            query.date("created_after").unwrap(),
+            Some(DateTime::parse_from_rfc3339("2026-10-01T00:00:00+02:00").unwrap())
+        );
+        assert_eq!(
+            query.page().unwrap(),
+            Page {
+                limit: 10,
+                offset: 0
+            }
+        );
+    }
+
+    #[test]
+    fn query_2() {
+        assert_eq!(
+            Query::parse(None).page().unwrap(),
+            Page {
+                limit: DEFAULT_LIMIT,
+                offset: 0
+            }
+        );
+        assert!(Query::parse(Some("limit=0")).page().is_err());
+        assert!(Query::parse(Some("limit=1001")).page().is_err());
+        assert!(Query::parse(Some("offset=-1")).page().is_err());
+        assert!(Query::parse(Some("active=yes"))
+            .parse_value::<bool>("active")
+            .is_err());
+        assert!(Query::parse(Some("created_after=yesterday"))
+            .date("created_after")
+            .is_err());
+    }
+}
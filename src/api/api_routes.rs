@@ -0,0 +1,260 @@
+use super::api_query::Query;
+use super::{ApiError, ApiState, Caller};
+use crate::aws::aws_inventory::AwsInventoryFormat;
+use crate::job_request::JobRequest;
+use crate::repo::{Page, Paged, Repository};
+use hyper::StatusCode;
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+const TIERS: [&str; 3] = ["Expedited", "Standard", "Bulk"];
+
+/// The body of a job request, with the fields of the Glacier API.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase", deny_unknown_fields)]
+struct JobRequestBody {
+    /// "inventory-retrieval" or "archive-retrieval"
+    #[serde(rename = "Type")]
+    job_type: String,
+    /// Format of an inventory, "JSON" (default) or "CSV".
+    format: Option<String>,
+    archive_id: Option<String>,
+    /// Tier of an archive retrieval, "Standard" by default.
+    tier: Option<String>,
+}
+
+/// Lists the vaults, filtered by `active` and a part of their `name`.
+pub async fn list_vaults(state: &ApiState, query: &Query) -> Result<Value, ApiError> {
+    query.expect(&["active", "name"])?;
+    let page = query.page()?;
+    let mut repo = Repository::new(&state.db_connection).await?;
+    let trans = repo.get_transaction().await?;
+    let vaults = Repository::get_vaults_page(
+        &trans,
+        query.parse_value("active")?,
+        query.get("name"),
+        &page,
+    )
+    .await?;
+
+    page_value(vaults, |(vault, active)| with_active(vault, active), &page)
+}
+
+pub async fn get_vault(state: &ApiState, vault_name: &str) -> Result<Value, ApiError> {
+    let mut repo = Repository::new(&state.db_connection).await?;
+    let trans = repo.get_transaction().await?;
+
+    match Repository::get_vault_by_name(&trans, vault_name).await? {
+        Some((vault, active)) => with_active(vault, active),
+        None => Err(vault_not_found(vault_name)),
+    }
+}
+
+/// Lists the archives of a vault, which are not deleted, filtered by `created_after` and `created_before`.
+pub async fn list_archives(
+    state: &ApiState,
+    vault_name: &str,
+    query: &Query,
+) -> Result<Value, ApiError> {
+    query.expect(&["created_after", "created_before"])?;
+    let page = query.page()?;
+    let mut repo = Repository::new(&state.db_connection).await?;
+    let trans = repo.get_transaction().await?;
+    let (vault, _) = Repository::get_vault_by_name(&trans, vault_name)
+        .await?
+        .ok_or_else(|| vault_not_found(vault_name))?;
+    let archives = Repository::get_vault_archives_page(
+        &trans,
+        &vault.vault_arn,
+        query.date("created_after")?,
+        query.date("created_before")?,
+        &page,
+    )
+    .await?;
+
+    page_value(
+        archives,
+        |archive| Ok(serde_json::to_value(archive)?),
+        &page,
+    )
+}
+
+/// Lists the jobs, filtered by `vault_name`, `action`, `status_code` and `active`.
+pub async fn list_jobs(state: &ApiState, query: &Query) -> Result<Value, ApiError> {
+    query.expect(&["vault_name", "action", "status_code", "active"])?;
+    let page = query.page()?;
+    let mut repo = Repository::new(&state.db_connection).await?;
+    let trans = repo.get_transaction().await?;
+    let vault_arn = match query.get("vault_name") {
+        Some(vault_name) => Some(
+            Repository::get_vault_by_name(&trans, vault_name)
+                .await?
+                .ok_or_else(|| vault_not_found(vault_name))?
+                .0
+                .vault_arn,
+        ),
+        None => None,
+    };
+    let jobs = Repository::get_jobs_page(
+        &trans,
+        vault_arn.as_deref(),
+        query.get("action"),
+        query.get("status_code"),
+        query.parse_value("active")?,
+        &page,
+    )
+    .await?;
+
+    page_value(jobs, |(job, active)| with_active(job, active), &page)
+}
+
+pub async fn get_job(state: &ApiState, job_id: &str) -> Result<Value, ApiError> {
+    let mut repo = Repository::new(&state.db_connection).await?;
+    let trans = repo.get_transaction().await?;
+
+    match Repository::get_job_with_status(&trans, job_id).await? {
+        Some((job, active)) => with_active(job, active),
+        None => Err(ApiError::not_found(format!("job \"{}\" not found", job_id))),
+    }
+}
+
+/// Queues an inventory or archive retrieval of a vault, which the token may request jobs for.
+pub async fn request_job(
+    state: &ApiState,
+    caller: &Caller<'_>,
+    vault_name: &str,
+    body: &Value,
+) -> Result<Value, ApiError> {
+    if !caller.token.may_request_jobs(vault_name) {
+        return Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            format!(
+                "token \"{}\" may not request jobs for vault \"{}\"",
+                caller.name, vault_name
+            ),
+        ));
+    }
+
+    let body: JobRequestBody = serde_json::from_value(body.clone())
+        .map_err(|e| ApiError::bad_request(format!("invalid job request: {}", e)))?;
+    let mut repo = Repository::new(&state.db_connection).await?;
+    let trans = repo.get_transaction().await?;
+    let (vault, _) = Repository::get_vault_by_name(&trans, vault_name)
+        .await?
+        .ok_or_else(|| vault_not_found(vault_name))?;
+    let request = match (&*body.job_type, body.archive_id.as_deref()) {
+        ("inventory-retrieval", None) if body.tier.is_none() => {
+            let format: AwsInventoryFormat = body
+                .format
+                .as_deref()
+                .unwrap_or("JSON")
+                .parse()
+                .map_err(|e: anyhow::Error| ApiError::bad_request(e.to_string()))?;
+
+            JobRequest::inventory_retrieval(&vault.vault_arn, format.as_str(), caller.name)
+        }
+        ("archive-retrieval", Some(archive_id)) if body.format.is_none() => {
+            let tier = body.tier.as_deref().unwrap_or("Standard");
+
+            if !TIERS.contains(&tier) {
+                return Err(ApiError::bad_request(format!(
+                    "unknown tier \"{}\" (expected {})",
+                    tier,
+                    TIERS.join(", ")
+                )));
+            }
+
+            if !Repository::is_vault_archive(&trans, &vault.vault_arn, archive_id).await? {
+                return Err(ApiError::not_found(format!(
+                    "archive \"{}\" not found in vault \"{}\"",
+                    archive_id, vault_name
+                )));
+            }
+
+            JobRequest::archive_retrieval(&vault.vault_arn, archive_id, tier, caller.name)
+        }
+        _ => {
+            return Err(ApiError::bad_request(
+                "expected an \"inventory-retrieval\" with an optional \"Format\" or an \"archive-retrieval\" with an \"ArchiveId\" and an optional \"Tier\"",
+            ))
+        }
+    };
+
+    Repository::create_job_request(&trans, &request).await?;
+    trans.commit().await?;
+    info!(
+        "token \"{}\" requested {} job \"{}\" for vault \"{}\"",
+        caller.name,
+        request.action.as_str(),
+        request.request_id,
+        vault_name
+    );
+
+    Ok(serde_json::to_value(&request)?)
+}
+
+/// Lists the job requests, filtered by `vault_name` and `state`.
+pub async fn list_job_requests(state: &ApiState, query: &Query) -> Result<Value, ApiError> {
+    query.expect(&["vault_name", "state"])?;
+    let page = query.page()?;
+    let mut repo = Repository::new(&state.db_connection).await?;
+    let trans = repo.get_transaction().await?;
+    let vault_arn = match query.get("vault_name") {
+        Some(vault_name) => Some(
+            Repository::get_vault_by_name(&trans, vault_name)
+                .await?
+                .ok_or_else(|| vault_not_found(vault_name))?
+                .0
+                .vault_arn,
+        ),
+        None => None,
+    };
+    let requests =
+        Repository::get_job_requests_page(&trans, vault_arn.as_deref(), query.get("state"), &page)
+            .await?;
+
+    page_value(
+        requests,
+        |request| Ok(serde_json::to_value(request)?),
+        &page,
+    )
+}
+
+pub async fn get_job_request(state: &ApiState, request_id: &str) -> Result<Value, ApiError> {
+    let not_found = || ApiError::not_found(format!("job request \"{}\" not found", request_id));
+    let request_id: Uuid = request_id.parse().map_err(|_| not_found())?;
+    let mut repo = Repository::new(&state.db_connection).await?;
+    let trans = repo.get_transaction().await?;
+
+    match Repository::get_job_request(&trans, &request_id).await? {
+        Some(request) => Ok(serde_json::to_value(request)?),
+        None => Err(not_found()),
+    }
+}
+
+fn vault_not_found(vault_name: &str) -> ApiError {
+    ApiError::not_found(format!("vault \"{}\" not found", vault_name))
+}
+
+/// Adds the status flag to the item; it is null, if the item has no status.
+fn with_active<T: Serialize>(item: T, active: Option<bool>) -> Result<Value, ApiError> {
+    let mut value = serde_json::to_value(item)?;
+
+    value["Active"] = json!(active);
+
+    Ok(value)
+}
+
+fn page_value<T, F>(paged: Paged<T>, item: F, page: &Page) -> Result<Value, ApiError>
+where
+    F: Fn(T) -> Result<Value, ApiError>,
+{
+    Ok(json!({
+        "Items": paged.items.into_iter().map(item).collect::<Result<Vec<_>, _>>()?,
+        "Total": paged.total,
+        "Limit": page.limit,
+        "Offset": page.offset,
+    }))
+}
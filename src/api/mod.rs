@@ -0,0 +1,289 @@
+pub mod api_query;
+pub mod api_routes;
+
+use crate::shutdown;
+use anyhow::Result;
+use api_query::Query;
+use hyper::body::HttpBody as _;
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use log::{debug, error, info};
+use ring::digest::{digest, SHA256};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+pub const DEFAULT_LISTEN: &str = "127.0.0.1:8080";
+const MAX_BODY_SIZE: u64 = 64 * 1024;
+
+/// A bearer token and the vaults, for which jobs may be requested with it.
+#[derive(Debug, Clone)]
+pub struct ApiToken {
+    pub token: String,
+    /// Vault names; "*" stands for all vaults.
+    pub vaults: Vec<String>,
+}
+
+impl ApiToken {
+    pub fn may_request_jobs(&self, vault_name: &str) -> bool {
+        self.vaults.iter().any(|v| v == "*" || v == vault_name)
+    }
+}
+
+/// What the handlers of the API server share.
+pub struct ApiState {
+    pub db_connection: String,
+    /// Accepted tokens by name.
+    pub tokens: BTreeMap<String, ApiToken>,
+}
+
+impl ApiState {
+    /// Returns the name and the token given in the authorization header.
+    ///
+    /// The digests of the tokens are compared, so the time of the comparison does not reveal the token.
+    pub fn authenticate(&self, authorization: Option<&str>) -> Option<(&str, &ApiToken)> {
+        let token = authorization?.strip_prefix("Bearer ")?;
+        let token_digest = digest(&SHA256, token.as_bytes());
+
+        self.tokens
+            .iter()
+            .find(|(_, t)| digest(&SHA256, t.token.as_bytes()).as_ref() == token_digest.as_ref())
+            .map(|(name, token)| (name.as_str(), token))
+    }
+}
+
+/// The name and permissions of the token a request was authenticated with.
+pub struct Caller<'a> {
+    pub name: &'a str,
+    pub token: &'a ApiToken,
+}
+
+/// An error reported to the client with its status code.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        ApiError {
+            status,
+            message: message.into(),
+        }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        ApiError::new(StatusCode::BAD_REQUEST, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        ApiError::new(StatusCode::NOT_FOUND, message)
+    }
+}
+
+/// Internal errors are logged, but not revealed to the client.
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        error!("{:?}", e);
+        ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "internal error")
+    }
+}
+
+impl From<tokio_postgres::Error> for ApiError {
+    fn from(e: tokio_postgres::Error) -> Self {
+        anyhow::Error::from(e).into()
+    }
+}
+
+impl From<serde_json::Error> for ApiError {
+    fn from(e: serde_json::Error) -> Self {
+        anyhow::Error::from(e).into()
+    }
+}
+
+/// Serves the API until a shutdown is requested, letting running requests finish.
+pub async fn serve(address: SocketAddr, state: ApiState) -> Result<()> {
+    let state = Arc::new(state);
+    let make_service = make_service_fn(move |_| {
+        let state = state.clone();
+
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let state = state.clone();
+
+                async move { Ok::<_, Infallible>(handle(&state, request).await) }
+            }))
+        }
+    });
+
+    info!("listening on {}", address);
+    Server::try_bind(&address)?
+        .serve(make_service)
+        .with_graceful_shutdown(shutdown::requested())
+        .await?;
+
+    Ok(())
+}
+
+async fn handle(state: &ApiState, request: Request<Body>) -> Response<Body> {
+    debug!("{} {}", request.method(), request.uri());
+
+    let result = match state.authenticate(
+        request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|v| v.to_str().ok()),
+    ) {
+        Some((name, token)) => {
+            debug!("authenticated token \"{}\"", name);
+            route(state, &Caller { name, token }, request).await
+        }
+        None => Err(ApiError::new(
+            StatusCode::UNAUTHORIZED,
+            "missing or invalid bearer token",
+        )),
+    };
+
+    match result {
+        Ok((status, value)) => json_response(status, &value),
+        Err(e) => json_response(e.status, &json!({ "Error": e.message })),
+    }
+}
+
+async fn route(
+    state: &ApiState,
+    caller: &Caller<'_>,
+    request: Request<Body>,
+) -> Result<(StatusCode, Value), ApiError> {
+    let (parts, body) = request.into_parts();
+    let query = Query::parse(parts.uri.query());
+    let segments: Vec<&str> = parts.uri.path().trim_matches('/').split('/').collect();
+    let ok = |value| Ok((StatusCode::OK, value));
+
+    match (&parts.method, segments.as_slice()) {
+        (&Method::GET, ["vaults"]) => ok(api_routes::list_vaults(state, &query).await?),
+        (&Method::GET, ["vaults", vault_name]) => {
+            ok(api_routes::get_vault(state, vault_name).await?)
+        }
+        (&Method::GET, ["vaults", vault_name, "archives"]) => {
+            ok(api_routes::list_archives(state, vault_name, &query).await?)
+        }
+        (&Method::POST, ["vaults", vault_name, "jobs"]) => Ok((
+            StatusCode::ACCEPTED,
+            api_routes::request_job(state, caller, vault_name, &read_json(body).await?).await?,
+        )),
+        (&Method::GET, ["jobs"]) => ok(api_routes::list_jobs(state, &query).await?),
+        (&Method::GET, ["jobs", job_id]) => ok(api_routes::get_job(state, job_id).await?),
+        (&Method::GET, ["job-requests"]) => ok(api_routes::list_job_requests(state, &query).await?),
+        (&Method::GET, ["job-requests", request_id]) => {
+            ok(api_routes::get_job_request(state, request_id).await?)
+        }
+        (_, ["vaults"])
+        | (_, ["vaults", _])
+        | (_, ["vaults", _, "archives"])
+        | (_, ["vaults", _, "jobs"])
+        | (_, ["jobs"])
+        | (_, ["jobs", _])
+        | (_, ["job-requests"])
+        | (_, ["job-requests", _]) => Err(ApiError::new(
+            StatusCode::METHOD_NOT_ALLOWED,
+            "method not allowed",
+        )),
+        _ => Err(ApiError::not_found("not found")),
+    }
+}
+
+/// Reads a JSON request body of at most 64 KiB.
+async fn read_json(body: Body) -> Result<Value, ApiError> {
+    if body.size_hint().lower() > MAX_BODY_SIZE {
+        return Err(ApiError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "request body too large",
+        ));
+    }
+
+    let mut content = Vec::new();
+    let mut body = body;
+
+    while let Some(chunk) = body.data().await {
+        content.extend_from_slice(&chunk.map_err(anyhow::Error::from)?);
+
+        if content.len() as u64 > MAX_BODY_SIZE {
+            return Err(ApiError::new(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "request body too large",
+            ));
+        }
+    }
+
+    serde_json::from_slice(&content)
+        .map_err(|e| ApiError::bad_request(format!("invalid JSON body: {}", e)))
+}
+
+fn json_response(status: StatusCode, value: &Value) -> Response<Body> {
+    let mut response = Response::new(Body::from(value.to_string()));
+
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, "application/json".parse().unwrap());
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn authenticate_1() {
+        let state = ApiState {
+            db_connection: String::new(),
+            tokens: vec![
+                (
+                    "dashboard".into(),
+                    ApiToken {
+                        token: "secret-1".into(),
+                        vaults: vec![],
+                    },
+                ),
+                (
+                    "team-a".into(),
+                    ApiToken {
+                        token: "secret-2".into(),
+                        vaults: vec!["photos".into(), "documents".into()],
+                    },
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        };
+        let (name, token) = state.authenticate(Some("Bearer secret-2")).unwrap();
+
+        assert_eq!(name, "team-a");
+        assert!(token.may_request_jobs("photos"));
+        assert!(!token.may_request_jobs("videos"));
+        assert!(state.authenticate(Some("Bearer secret-3")).is_none());
+        assert!(state.authenticate(Some("secret-1")).is_none());
+        assert!(state.authenticate(None).is_none());
+    }
+
+    #[test]
+    fn api_token_1() {
+        let token = ApiToken {
+            token: "secret".into(),
+            vaults: vec!["*".into()],
+        };
+
+        assert!(token.may_request_jobs("photos"));
+        assert!(!ApiToken {
+            vaults: vec![],
+            ..token
+        }
+        .may_request_jobs("photos"));
+    }
+}
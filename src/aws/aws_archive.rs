@@ -1,20 +1,134 @@
 use anyhow::Result;
-use chrono::{DateTime, FixedOffset};
-use serde::Deserialize;
+use chrono::{DateTime, FixedOffset, Utc};
+use data_encoding::BASE64URL_NOPAD;
+use serde::{Deserialize, Serialize};
 use std::convert::TryFrom;
 use tokio_postgres::Row;
 
-#[derive(Debug, Deserialize)]
+/// Prefix of archive descriptions carrying encoded metadata.
+pub const METADATA_PREFIX: &str = "br:";
+pub const METADATA_VERSION: u8 = 1;
+/// Maximum length of an archive description accepted by Glacier.
+pub const MAX_DESCRIPTION_LENGTH: usize = 1024;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct AwsArchive {
     pub archive_id: String,
     pub archive_description: String,
     pub creation_date: DateTime<FixedOffset>,
     pub size: i64,
-    #[serde(rename(deserialize = "SHA256TreeHash"))]
+    #[serde(rename = "SHA256TreeHash")]
     pub tree_hash: String,
 }
 
+impl AwsArchive {
+    /// Decodes the metadata embedded in the archive description.
+    ///
+    /// Returns `None` for free-form descriptions.
+    pub fn metadata(&self) -> Result<Option<ArchiveMetadata>> {
+        ArchiveMetadata::parse(&self.archive_description)
+    }
+}
+
+/// Metadata embedded in the description of archives created by this tool, so archives can be identified from an inventory alone.
+///
+/// It is encoded as compact JSON in URL-safe base64 behind a prefix, which keeps the description printable ASCII.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ArchiveMetadata {
+    #[serde(rename = "v")]
+    pub version: u8,
+    #[serde(rename = "h")]
+    pub host: String,
+    #[serde(rename = "s", default, skip_serializing_if = "Option::is_none")]
+    pub backup_set: Option<String>,
+    #[serde(rename = "p", default)]
+    pub paths: Vec<String>,
+    /// Set if not all paths fit into the description.
+    #[serde(rename = "pt", default, skip_serializing_if = "is_false")]
+    pub paths_truncated: bool,
+    #[serde(rename = "t", with = "chrono::serde::ts_seconds")]
+    pub timestamp: DateTime<Utc>,
+    #[serde(rename = "e", default, skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<String>,
+    #[serde(rename = "k", default, skip_serializing_if = "Vec::is_empty")]
+    pub key_ids: Vec<String>,
+    /// SHA256 of the archive as stored, set for the packs of deduplicated backups.
+    ///
+    /// Streamed backups leave it unset, because the description is fixed when the upload is initiated, before the content is known.
+    #[serde(rename = "c", default, skip_serializing_if = "Option::is_none")]
+    pub content_hash: Option<String>,
+}
+
+impl ArchiveMetadata {
+    pub fn parse(description: &str) -> Result<Option<Self>> {
+        match description.strip_prefix(METADATA_PREFIX) {
+            Some(encoded) => {
+                let metadata: ArchiveMetadata =
+                    serde_json::from_slice(&BASE64URL_NOPAD.decode(encoded.as_bytes())?)?;
+
+                if metadata.version > METADATA_VERSION {
+                    return Err(anyhow::Error::msg(format!(
+                        "unsupported metadata version {}",
+                        metadata.version
+                    )));
+                }
+
+                Ok(Some(metadata))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Encodes the metadata as archive description.
+    ///
+    /// Paths are dropped from the end until the description fits into the limit of Glacier.
+    pub fn render(&self) -> Result<String> {
+        let mut metadata = self.clone();
+
+        loop {
+            let description = format!(
+                "{}{}",
+                METADATA_PREFIX,
+                BASE64URL_NOPAD.encode(&serde_json::to_vec(&metadata)?)
+            );
+
+            if description.len() <= MAX_DESCRIPTION_LENGTH {
+                return Ok(description);
+            }
+
+            if metadata.paths.pop().is_none() {
+                return Err(anyhow::Error::msg("archive metadata is too long"));
+            }
+
+            metadata.paths_truncated = true;
+        }
+    }
+}
+
+/// Reads the metadata from a row of `archives_metadata`, with the JSON columns selected as text.
+impl TryFrom<&Row> for ArchiveMetadata {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &Row) -> Result<Self, Self::Error> {
+        Ok(ArchiveMetadata {
+            version: value.try_get::<_, i16>("version")? as u8,
+            host: value.try_get("host")?,
+            backup_set: value.try_get("backup_set")?,
+            paths: serde_json::from_str(value.try_get("paths")?)?,
+            paths_truncated: value.try_get("paths_truncated")?,
+            timestamp: value.try_get("creation_date")?,
+            encryption: value.try_get("encryption")?,
+            key_ids: serde_json::from_str(value.try_get("key_ids")?)?,
+            content_hash: value.try_get("content_hash")?,
+        })
+    }
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
 impl TryFrom<&str> for AwsArchive {
     type Error = anyhow::Error;
 
@@ -32,6 +146,14 @@ pub struct AwsIventoryResponse {
     pub archive_list: Vec<AwsArchive>,
 }
 
+impl TryFrom<&[u8]> for AwsIventoryResponse {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        serde_json::from_slice(value).map_err(|e| e.into())
+    }
+}
+
 impl TryFrom<&Row> for AwsArchive {
     type Error = anyhow::Error;
 
@@ -45,3 +167,54 @@ impl TryFrom<&Row> for AwsArchive {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn metadata() -> ArchiveMetadata {
+        ArchiveMetadata {
+            version: METADATA_VERSION,
+            host: "backup-host".into(),
+            backup_set: Some("etc".into()),
+            paths: vec!["/etc".into(), "/var/lib/\"quoted\"".into()],
+            paths_truncated: false,
+            timestamp: Utc.with_ymd_and_hms(2021, 7, 25, 12, 0, 0).unwrap(),
+            encryption: Some("aes-256-gcm".into()),
+            key_ids: vec!["ops".into()],
+            content_hash: Some(
+                "9e61852589d5cf69023c54e5c4fd7258401387520c5860c0a4331c555ea3dca9".into(),
+            ),
+        }
+    }
+
+    #[test]
+    fn metadata_1() {
+        let description = metadata().render().unwrap();
+
+        assert!(description.starts_with(METADATA_PREFIX));
+        assert!(description.bytes().all(|b| b.is_ascii_graphic()));
+        assert_eq!(
+            ArchiveMetadata::parse(&description).unwrap(),
+            Some(metadata())
+        );
+        assert_eq!(ArchiveMetadata::parse("nightly backup").unwrap(), None);
+        assert!(ArchiveMetadata::parse("br:not base64!").is_err());
+    }
+
+    #[test]
+    fn metadata_2() {
+        let mut metadata = metadata();
+
+        metadata.paths = (0..100).map(|i| format!("/srv/data/{}", i)).collect();
+
+        let parsed = ArchiveMetadata::parse(&metadata.render().unwrap())
+            .unwrap()
+            .unwrap();
+
+        assert!(parsed.paths_truncated);
+        assert!(parsed.paths.len() < 100);
+        assert_eq!(parsed.paths[..], metadata.paths[..parsed.paths.len()]);
+    }
+}
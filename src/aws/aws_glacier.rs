@@ -1,19 +1,28 @@
-use super::aws_archive::{AwsArchive, AwsIventoryResponse};
+use super::aws_archive::AwsIventoryResponse;
+use super::aws_inventory::AwsInventoryRetrievalParameters;
 use super::aws_job::{AwsJob, AwsJobListResponse};
+use super::aws_tree_hash::TreeHasher;
 use super::aws_vault::{AwsVault, AwsVaultListResponse};
+use crate::health;
+use crate::logging;
+use crate::metrics;
 use anyhow::Result;
 use chrono::{DateTime, Utc};
 use data_encoding::HEXLOWER;
+use hyper::body::Bytes;
 use hyper::Uri;
-use hyper::{Body, Client, Request};
+use hyper::{Body, Client, Request, Response, StatusCode};
 use hyper_tls::HttpsConnector;
 use log::debug;
 use ring::{digest, hmac};
+use std::convert::TryFrom;
+use std::time::Instant;
 
 pub struct AwsGlacier {
     secret_key: String,
     key_id: String,
     region: String,
+    endpoint: String,
 }
 
 impl AwsGlacier {
@@ -22,29 +31,21 @@ impl AwsGlacier {
             secret_key: secret_key.into(),
             key_id: key_id.into(),
             region: region.into(),
+            endpoint: format!("https://glacier.{}.amazonaws.com", region),
         }
     }
 
+    /// Sends requests to the given base URL instead of the endpoint of the region.
+    pub fn with_endpoint(mut self, endpoint: &str) -> Self {
+        self.endpoint = endpoint.trim_end_matches('/').into();
+        self
+    }
+
     pub async fn list_vaults(&self) -> Result<Vec<AwsVault>> {
-        let https = HttpsConnector::new();
-        let client = Client::builder().build::<_, hyper::Body>(https);
-        let date_time = Utc::now();
-        let uri =
-            format!("https://glacier.{}.amazonaws.com/-/vaults", self.region).parse::<Uri>()?;
-        let hash_body = sha_256_hash(&[])?;
-        let hash_request = hash_request("GET", &uri, &date_time, &*hash_body)?;
-        let signature = self.signature(&date_time, &*hash_request)?;
-        let req = Request::builder()
-            .method("GET")
-            .uri(uri)
-            .header("Authorization", format!("AWS4-HMAC-SHA256 Credential={}/{}/{}/glacier/aws4_request,SignedHeaders=host;x-amz-date;x-amz-glacier-version,Signature={}", self.key_id, date_time.format("%Y%m%d"), self.region, signature))
-            .header("x-amz-date", date_time.format("%Y%m%dT%H%M%SZ").to_string())
-            .header("x-amz-glacier-version", "2012-06-01")
-            .body(Body::from(""))?;
-        let resp = client.request(req).await?;
+        let resp = self.request("GET", "/-/vaults", &[], Bytes::new()).await?;
 
         match resp.status() {
-            hyper::StatusCode::OK => {
+            StatusCode::OK => {
                 let resp_body = hyper::body::to_bytes(resp).await?;
                 let resp_json: AwsVaultListResponse = serde_json::from_slice(&resp_body)?;
 
@@ -60,31 +61,45 @@ impl AwsGlacier {
         }
     }
 
+    pub async fn describe_vault(&self, vault_name: &str) -> Result<AwsVault> {
+        let resp = self
+            .request(
+                "GET",
+                &format!("/-/vaults/{}", vault_name),
+                &[],
+                Bytes::new(),
+            )
+            .await?;
+
+        match resp.status() {
+            StatusCode::OK => {
+                let resp_body = hyper::body::to_bytes(resp).await?;
+                let resp_json: AwsVault = serde_json::from_slice(&resp_body)?;
+
+                Ok(resp_json)
+            }
+            _ => {
+                debug!("{:?}", resp);
+                Err(anyhow::Error::msg(format!(
+                    "failed to describe vault (status: {})",
+                    resp.status()
+                )))
+            }
+        }
+    }
+
     pub async fn list_jobs_for_vault(&self, vault: &AwsVault) -> Result<Vec<AwsJob>> {
-        let http_method = "GET";
-        let body = "";
-        let https = HttpsConnector::new();
-        let client = Client::builder().build::<_, hyper::Body>(https);
-        let date_time = Utc::now();
-        let uri = format!(
-            "https://glacier.{}.amazonaws.com/-/vaults/{}/jobs",
-            self.region, vault.vault_name
-        )
-        .parse::<Uri>()?;
-        let hash_body = sha_256_hash(body.as_bytes())?;
-        let hash_request = hash_request(http_method, &uri, &date_time, &*hash_body)?;
-        let signature = self.signature(&date_time, &*hash_request)?;
-        let req = Request::builder()
-            .method(http_method)
-            .uri(uri)
-            .header("Authorization", format!("AWS4-HMAC-SHA256 Credential={}/{}/{}/glacier/aws4_request,SignedHeaders=host;x-amz-date;x-amz-glacier-version,Signature={}", self.key_id, date_time.format("%Y%m%d"), self.region, signature))
-            .header("x-amz-date", date_time.format("%Y%m%dT%H%M%SZ").to_string())
-            .header("x-amz-glacier-version", "2012-06-01")
-            .body(Body::from(body))?;
-        let resp = client.request(req).await?;
+        let resp = self
+            .request(
+                "GET",
+                &format!("/-/vaults/{}/jobs", vault.vault_name),
+                &[],
+                Bytes::new(),
+            )
+            .await?;
 
         match resp.status() {
-            hyper::StatusCode::OK => {
+            StatusCode::OK => {
                 let resp_body = hyper::body::to_bytes(resp).await?;
                 let resp_json: AwsJobListResponse = serde_json::from_slice(&resp_body)?;
 
@@ -100,31 +115,23 @@ impl AwsGlacier {
         }
     }
 
-    pub async fn init_inventory_job_for_vault(&self, vault: &AwsVault) -> Result<String> {
-        let http_method = "POST";
-        let body = format!("{{\"Type\": \"inventory-retrieval\", \"Description\": \"backup-remote\", \"Format\": \"JSON\"}}");
-        let https = HttpsConnector::new();
-        let client = Client::builder().build::<_, hyper::Body>(https);
-        let date_time = Utc::now();
-        let uri = format!(
-            "https://glacier.{}.amazonaws.com/-/vaults/{}/jobs",
-            self.region, vault.vault_name
-        )
-        .parse::<Uri>()?;
-        let hash_body = sha_256_hash(body.as_bytes())?;
-        let hash_request = hash_request(http_method, &uri, &date_time, &*hash_body)?;
-        let signature = self.signature(&date_time, &*hash_request)?;
-        let req = Request::builder()
-            .method(http_method)
-            .uri(uri)
-            .header("Authorization", format!("AWS4-HMAC-SHA256 Credential={}/{}/{}/glacier/aws4_request,SignedHeaders=host;x-amz-date;x-amz-glacier-version,Signature={}", self.key_id, date_time.format("%Y%m%d"), self.region, signature))
-            .header("x-amz-date", date_time.format("%Y%m%dT%H%M%SZ").to_string())
-            .header("x-amz-glacier-version", "2012-06-01")
-            .body(Body::from(body))?;
-        let resp = client.request(req).await?;
+    pub async fn init_inventory_job_for_vault(
+        &self,
+        vault: &AwsVault,
+        parameters: &AwsInventoryRetrievalParameters,
+    ) -> Result<String> {
+        let body = parameters.request_body("backup-remote")?;
+        let resp = self
+            .request(
+                "POST",
+                &format!("/-/vaults/{}/jobs", vault.vault_name),
+                &[],
+                Bytes::from(body),
+            )
+            .await?;
 
         match resp.status() {
-            hyper::StatusCode::ACCEPTED => Ok(resp.headers()["x-amz-job-id"].to_str()?.into()),
+            StatusCode::ACCEPTED => Ok(resp.headers()["x-amz-job-id"].to_str()?.into()),
             _ => {
                 debug!("{:?}", resp);
                 Err(anyhow::Error::msg(format!(
@@ -135,31 +142,63 @@ impl AwsGlacier {
         }
     }
 
+    /// Initiates a job retrieving an archive and returns the id of the job.
+    ///
+    /// The tier ("Expedited", "Standard" or "Bulk") determines how long the retrieval takes and what it costs.
+    /// A byte range ("first-last") must be megabyte aligned and retrieves only part of the archive.
+    pub async fn init_archive_retrieval_job(
+        &self,
+        vault: &AwsVault,
+        archive_id: &str,
+        tier: &str,
+        byte_range: Option<&str>,
+        description: &str,
+    ) -> Result<String> {
+        let mut body = serde_json::json!({
+            "Type": "archive-retrieval",
+            "ArchiveId": archive_id,
+            "Tier": tier,
+            "Description": description,
+        });
+
+        if let Some(byte_range) = byte_range {
+            body["RetrievalByteRange"] = byte_range.into();
+        }
+
+        let body = body.to_string();
+        let resp = self
+            .request(
+                "POST",
+                &format!("/-/vaults/{}/jobs", vault.vault_name),
+                &[],
+                Bytes::from(body),
+            )
+            .await?;
+
+        match resp.status() {
+            StatusCode::ACCEPTED => Ok(resp.headers()["x-amz-job-id"].to_str()?.into()),
+            _ => {
+                debug!("{:?}", resp);
+                Err(anyhow::Error::msg(format!(
+                    "failed to initiate archive retrieval job: {}",
+                    resp.status()
+                )))
+            }
+        }
+    }
+
     pub async fn get_job_by_id_vault(&self, vault: &AwsVault, job_id: &str) -> Result<AwsJob> {
-        let http_method = "GET";
-        let body = String::new();
-        let https = HttpsConnector::new();
-        let client = Client::builder().build::<_, hyper::Body>(https);
-        let date_time = Utc::now();
-        let uri = format!(
-            "https://glacier.{}.amazonaws.com/-/vaults/{}/jobs/{}",
-            self.region, vault.vault_name, job_id
-        )
-        .parse::<Uri>()?;
-        let hash_body = sha_256_hash(body.as_bytes())?;
-        let hash_request = hash_request(http_method, &uri, &date_time, &*hash_body)?;
-        let signature = self.signature(&date_time, &*hash_request)?;
-        let req = Request::builder()
-            .method(http_method)
-            .uri(uri)
-            .header("Authorization", format!("AWS4-HMAC-SHA256 Credential={}/{}/{}/glacier/aws4_request,SignedHeaders=host;x-amz-date;x-amz-glacier-version,Signature={}", self.key_id, date_time.format("%Y%m%d"), self.region, signature))
-            .header("x-amz-date", date_time.format("%Y%m%dT%H%M%SZ").to_string())
-            .header("x-amz-glacier-version", "2012-06-01")
-            .body(Body::from(body))?;
-        let resp = client.request(req).await?;
+        let resp = self
+            .request(
+                "GET",
+                &format!("/-/vaults/{}/jobs/{}", vault.vault_name, job_id),
+                &[],
+                Bytes::new(),
+            )
+            .await?;
 
         match resp.status() {
-            hyper::StatusCode::OK => {
+            StatusCode::OK => {
                 let resp_body = hyper::body::to_bytes(resp).await?;
                 let resp_json: AwsJob = serde_json::from_slice(&resp_body)?;
 
@@ -179,43 +218,266 @@ impl AwsGlacier {
         &self,
         vault: &AwsVault,
         job: &AwsJob,
-    ) -> Result<Vec<AwsArchive>> {
-        let http_method = "GET";
-        let body = "";
+    ) -> Result<AwsIventoryResponse> {
+        let output = hyper::body::to_bytes(self.get_job_output(vault, job).await?).await?;
+
+        AwsIventoryResponse::try_from(&*output)
+    }
+
+    /// Returns the output of a job as a stream.
+    pub async fn get_job_output(&self, vault: &AwsVault, job: &AwsJob) -> Result<Body> {
+        let resp = self
+            .request(
+                "GET",
+                &format!("/-/vaults/{}/jobs/{}/output", vault.vault_name, job.job_id),
+                &[],
+                Bytes::new(),
+            )
+            .await?;
+
+        match resp.status() {
+            StatusCode::OK => Ok(resp.into_body()),
+            _ => {
+                debug!("{:?}", resp);
+                Err(anyhow::Error::msg(format!(
+                    "failed to retrieve job output (status: {})",
+                    resp.status()
+                )))
+            }
+        }
+    }
+
+    /// Initiates a multipart upload and returns the upload id.
+    ///
+    /// The part size must be a megabyte multiplied by a power of two.
+    pub async fn initiate_multipart_upload(
+        &self,
+        vault: &AwsVault,
+        archive_description: &str,
+        part_size: usize,
+    ) -> Result<String> {
+        let resp = self
+            .request(
+                "POST",
+                &format!("/-/vaults/{}/multipart-uploads", vault.vault_name),
+                &[
+                    ("x-amz-archive-description", archive_description.into()),
+                    ("x-amz-part-size", part_size.to_string()),
+                ],
+                Bytes::new(),
+            )
+            .await?;
+
+        match resp.status() {
+            StatusCode::CREATED => Ok(resp.headers()["x-amz-multipart-upload-id"].to_str()?.into()),
+            _ => {
+                debug!("{:?}", resp);
+                Err(anyhow::Error::msg(format!(
+                    "failed to initiate multipart upload (status: {})",
+                    resp.status()
+                )))
+            }
+        }
+    }
+
+    /// Uploads a part of a multipart upload starting at `offset` and returns the tree hash of the part.
+    pub async fn upload_multipart_part(
+        &self,
+        vault: &AwsVault,
+        upload_id: &str,
+        offset: u64,
+        data: Bytes,
+    ) -> Result<String> {
+        let mut tree_hasher = TreeHasher::new();
+
+        tree_hasher.update(&data);
+
+        let tree_hash = tree_hasher.finish();
+
+        let resp = self
+            .request(
+                "PUT",
+                &format!(
+                    "/-/vaults/{}/multipart-uploads/{}",
+                    vault.vault_name, upload_id
+                ),
+                &[
+                    (
+                        "content-range",
+                        format!("bytes {}-{}/*", offset, offset + data.len() as u64 - 1),
+                    ),
+                    ("x-amz-sha256-tree-hash", tree_hash.clone()),
+                ],
+                data,
+            )
+            .await?;
+
+        match resp.status() {
+            StatusCode::NO_CONTENT => Ok(tree_hash),
+            _ => {
+                debug!("{:?}", resp);
+                Err(anyhow::Error::msg(format!(
+                    "failed to upload part (status: {})",
+                    resp.status()
+                )))
+            }
+        }
+    }
+
+    /// Completes a multipart upload and returns the archive id.
+    pub async fn complete_multipart_upload(
+        &self,
+        vault: &AwsVault,
+        upload_id: &str,
+        archive_size: u64,
+        tree_hash: &str,
+    ) -> Result<String> {
+        let resp = self
+            .request(
+                "POST",
+                &format!(
+                    "/-/vaults/{}/multipart-uploads/{}",
+                    vault.vault_name, upload_id
+                ),
+                &[
+                    ("x-amz-archive-size", archive_size.to_string()),
+                    ("x-amz-sha256-tree-hash", tree_hash.into()),
+                ],
+                Bytes::new(),
+            )
+            .await?;
+
+        match resp.status() {
+            StatusCode::CREATED => Ok(resp.headers()["x-amz-archive-id"].to_str()?.into()),
+            _ => {
+                debug!("{:?}", resp);
+                Err(anyhow::Error::msg(format!(
+                    "failed to complete multipart upload (status: {})",
+                    resp.status()
+                )))
+            }
+        }
+    }
+
+    pub async fn abort_multipart_upload(&self, vault: &AwsVault, upload_id: &str) -> Result<()> {
+        let resp = self
+            .request(
+                "DELETE",
+                &format!(
+                    "/-/vaults/{}/multipart-uploads/{}",
+                    vault.vault_name, upload_id
+                ),
+                &[],
+                Bytes::new(),
+            )
+            .await?;
+
+        match resp.status() {
+            StatusCode::NO_CONTENT => Ok(()),
+            _ => {
+                debug!("{:?}", resp);
+                Err(anyhow::Error::msg(format!(
+                    "failed to abort multipart upload (status: {})",
+                    resp.status()
+                )))
+            }
+        }
+    }
+
+    pub async fn delete_archive(&self, vault: &AwsVault, archive_id: &str) -> Result<()> {
+        let resp = self
+            .request(
+                "DELETE",
+                &format!("/-/vaults/{}/archives/{}", vault.vault_name, archive_id),
+                &[],
+                Bytes::new(),
+            )
+            .await?;
+
+        match resp.status() {
+            StatusCode::NO_CONTENT => Ok(()),
+            _ => {
+                debug!("{:?}", resp);
+                Err(anyhow::Error::msg(format!(
+                    "failed to delete archive \"{}\" (status: {})",
+                    archive_id,
+                    resp.status()
+                )))
+            }
+        }
+    }
+
+    /// Sends a signed request to the Glacier API.
+    ///
+    /// The additional headers are included in the signature.
+    async fn request(
+        &self,
+        http_method: &str,
+        path: &str,
+        headers: &[(&str, String)],
+        body: Bytes,
+    ) -> Result<Response<Body>> {
         let https = HttpsConnector::new();
         let client = Client::builder().build::<_, hyper::Body>(https);
         let date_time = Utc::now();
-        let uri = format!(
-            "https://glacier.{}.amazonaws.com/-/vaults/{}/jobs/{}/output",
-            self.region, vault.vault_name, job.job_id
-        )
-        .parse::<Uri>()?;
-        let hash_body = sha_256_hash(body.as_bytes())?;
-        let hash_request = hash_request(http_method, &uri, &date_time, &*hash_body)?;
-        let signature = self.signature(&date_time, &*hash_request)?;
-        let req = Request::builder()
+        let uri = format!("{}{}", self.endpoint, path).parse::<Uri>()?;
+        let hash_body = sha_256_hash(&body)?;
+        let hash_request = hash_request(http_method, &uri, &date_time, headers, &hash_body)?;
+        let signature = self.signature(&date_time, &hash_request)?;
+        let mut req = Request::builder()
             .method(http_method)
             .uri(uri)
-            .header("Authorization", format!("AWS4-HMAC-SHA256 Credential={}/{}/{}/glacier/aws4_request,SignedHeaders=host;x-amz-date;x-amz-glacier-version,Signature={}", self.key_id, date_time.format("%Y%m%d"), self.region, signature))
+            .header("Authorization", format!("AWS4-HMAC-SHA256 Credential={}/{}/{}/glacier/aws4_request,SignedHeaders={},Signature={}", self.key_id, date_time.format("%Y%m%d"), self.region, signed_headers(headers), signature))
             .header("x-amz-date", date_time.format("%Y%m%dT%H%M%SZ").to_string())
             .header("x-amz-glacier-version", "2012-06-01")
-            .body(Body::from(body))?;
-        let resp = client.request(req).await?;
+            .header("x-amz-content-sha256", hash_body);
 
-        match resp.status() {
-            hyper::StatusCode::OK => {
-                let resp_body = hyper::body::to_bytes(resp).await?;
-                let resp_json: AwsIventoryResponse = serde_json::from_slice(&resp_body)?;
+        for (name, value) in headers {
+            req = req.header(*name, value);
+        }
 
-                Ok(resp_json.archive_list)
-            }
-            _ => {
-                debug!("{:?}", resp);
-                Err(anyhow::Error::msg(
-                    "failed to retrieve inventory job result",
-                ))
+        let operation = operation(http_method, path);
+        let mut fields = vec![("operation", operation)];
+
+        fields.extend(path_fields(path));
+
+        logging::span("glacier_request", &fields, async {
+            let start = Instant::now();
+            let result = client.request(req.body(Body::from(body))?).await;
+
+            metrics::GLACIER_REQUEST_DURATION.observe(&[operation], start.elapsed().as_secs_f64());
+            metrics::GLACIER_REQUESTS.inc(&[
+                operation,
+                &match &result {
+                    Ok(resp) => resp.status().as_u16().to_string(),
+                    Err(_) => "error".into(),
+                },
+            ]);
+
+            match &result {
+                Ok(resp) => {
+                    match resp.status() {
+                        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                            health::glacier_credentials(false)
+                        }
+                        status if status.is_success() => health::glacier_credentials(true),
+                        _ => {}
+                    }
+
+                    let aws_request_id = resp
+                        .headers()
+                        .get("x-amzn-RequestId")
+                        .and_then(|v| v.to_str().ok())
+                        .unwrap_or_default();
+
+                    debug!(aws_request_id, status = resp.status().as_u16(); "{} returned {}", operation, resp.status());
+                }
+                Err(e) => debug!("{} failed: {}", operation, e),
             }
-        }
+
+            Ok(result?)
+        })
+        .await
     }
 
     fn signature(&self, date_time: &DateTime<Utc>, request_hash: &str) -> Result<String> {
@@ -256,26 +518,151 @@ impl AwsGlacier {
     }
 }
 
+/// Returns the name of the Glacier operation of a request, as in the Glacier API reference.
+fn operation(http_method: &str, path: &str) -> &'static str {
+    let path = path.split('?').next().unwrap_or_default();
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+    match (http_method, segments.as_slice()) {
+        ("GET", ["-", "vaults"]) => "ListVaults",
+        ("GET", ["-", "vaults", _]) => "DescribeVault",
+        ("GET", ["-", "vaults", _, "jobs"]) => "ListJobs",
+        ("POST", ["-", "vaults", _, "jobs"]) => "InitiateJob",
+        ("GET", ["-", "vaults", _, "jobs", _]) => "DescribeJob",
+        ("GET", ["-", "vaults", _, "jobs", _, "output"]) => "GetJobOutput",
+        ("POST", ["-", "vaults", _, "multipart-uploads"]) => "InitiateMultipartUpload",
+        ("PUT", ["-", "vaults", _, "multipart-uploads", _]) => "UploadMultipartPart",
+        ("POST", ["-", "vaults", _, "multipart-uploads", _]) => "CompleteMultipartUpload",
+        ("DELETE", ["-", "vaults", _, "multipart-uploads", _]) => "AbortMultipartUpload",
+        ("DELETE", ["-", "vaults", _, "archives", _]) => "DeleteArchive",
+        _ => "Other",
+    }
+}
+
+/// Returns the vault name and the job or archive id in the path of a request.
+fn path_fields(path: &str) -> Vec<(&'static str, &str)> {
+    let path = path.split('?').next().unwrap_or_default();
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let mut fields = Vec::new();
+
+    if let ["-", "vaults", vault_name, rest @ ..] = segments.as_slice() {
+        fields.push(("vault_name", *vault_name));
+
+        match rest {
+            ["jobs", job_id, ..] => fields.push(("job_id", *job_id)),
+            ["archives", archive_id] => fields.push(("archive_id", *archive_id)),
+            _ => {}
+        }
+    }
+
+    fields
+}
+
 fn sha_256_hash(data: &[u8]) -> Result<String> {
     Ok(HEXLOWER.encode(digest::digest(&digest::SHA256, data).as_ref()))
 }
 
+/// Hashes the canonical request consisting of the default headers and the additional headers provided.
 fn hash_request(
     verb: &str,
     uri: &Uri,
     date_time: &DateTime<Utc>,
+    headers: &[(&str, String)],
     payload_hash: &str,
 ) -> Result<String> {
-    let req = format!("{}\n{}\n\nhost:{}\nx-amz-date:{}\nx-amz-glacier-version:2012-06-01\n\nhost;x-amz-date;x-amz-glacier-version\n{}", verb, uri.path(), uri.host().unwrap(), date_time.format("%Y%m%dT%H%M%SZ"), payload_hash);
+    let mut canonical_headers = vec![
+        ("host".to_string(), uri.host().unwrap().to_string()),
+        (
+            "x-amz-date".to_string(),
+            date_time.format("%Y%m%dT%H%M%SZ").to_string(),
+        ),
+        (
+            "x-amz-glacier-version".to_string(),
+            "2012-06-01".to_string(),
+        ),
+    ];
+
+    for (name, value) in headers {
+        canonical_headers.push((name.to_lowercase(), value.trim().to_string()));
+    }
+
+    canonical_headers.sort();
+
+    let req = format!(
+        "{}\n{}\n\n{}\n{}\n{}",
+        verb,
+        uri.path(),
+        canonical_headers
+            .iter()
+            .map(|(name, value)| format!("{}:{}\n", name, value))
+            .collect::<String>(),
+        signed_headers(headers),
+        payload_hash
+    );
     sha_256_hash(req.as_bytes())
 }
 
+fn signed_headers(headers: &[(&str, String)]) -> String {
+    let mut names = vec![
+        "host".to_string(),
+        "x-amz-date".to_string(),
+        "x-amz-glacier-version".to_string(),
+    ];
+
+    for (name, _) in headers {
+        names.push(name.to_lowercase());
+    }
+
+    names.sort();
+    names.join(";")
+}
+
 #[cfg(test)]
 mod tests {
     use chrono::TimeZone;
 
     use super::*;
 
+    #[test]
+    fn operation_1() {
+        assert_eq!(operation("GET", "/-/vaults"), "ListVaults");
+        assert_eq!(
+            operation("GET", "/-/vaults/photos/jobs?marker=x"),
+            "ListJobs"
+        );
+        assert_eq!(operation("POST", "/-/vaults/photos/jobs"), "InitiateJob");
+        assert_eq!(
+            operation("GET", "/-/vaults/photos/jobs/job/output"),
+            "GetJobOutput"
+        );
+        assert_eq!(
+            operation("PUT", "/-/vaults/photos/multipart-uploads/upload"),
+            "UploadMultipartPart"
+        );
+        assert_eq!(
+            operation("DELETE", "/-/vaults/photos/archives/archive"),
+            "DeleteArchive"
+        );
+        assert_eq!(operation("PATCH", "/-/vaults"), "Other");
+    }
+
+    #[test]
+    fn path_fields_1() {
+        assert_eq!(path_fields("/-/vaults"), vec![]);
+        assert_eq!(
+            path_fields("/-/vaults/photos/jobs?marker=x"),
+            vec![("vault_name", "photos")]
+        );
+        assert_eq!(
+            path_fields("/-/vaults/photos/jobs/job/output"),
+            vec![("vault_name", "photos"), ("job_id", "job")]
+        );
+        assert_eq!(
+            path_fields("/-/vaults/photos/archives/archive"),
+            vec![("vault_name", "photos"), ("archive_id", "archive")]
+        );
+    }
+
     #[test]
     fn sha_256_hash_1() {
         assert_eq!(
@@ -292,7 +679,8 @@ mod tests {
                 &"https://glacier.us-east-1.amazonaws.com/-/vaults/examplevault"
                     .parse::<Uri>()
                     .unwrap(),
-                &Utc.ymd(2012, 5, 25).and_hms(0, 24, 53),
+                &Utc.with_ymd_and_hms(2012, 5, 25, 0, 24, 53).unwrap(),
+                &[],
                 "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
             )
             .unwrap(),
@@ -309,7 +697,7 @@ mod tests {
         );
         let sig = ag
             .signature(
-                &Utc.ymd(2012, 5, 25).and_hms(0, 24, 53),
+                &Utc.with_ymd_and_hms(2012, 5, 25, 0, 24, 53).unwrap(),
                 "5f1da1a2d0feb614dd03d71e87928b8e449ac87614479332aced3a701f916743",
             )
             .unwrap();
@@ -0,0 +1,640 @@
+use super::aws_archive::AwsArchive;
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset, Utc};
+use serde::{Deserialize, Serialize, Serializer};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum AwsInventoryFormat {
+    #[serde(rename = "JSON")]
+    Json,
+    #[serde(rename = "CSV")]
+    Csv,
+}
+
+impl AwsInventoryFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AwsInventoryFormat::Json => "JSON",
+            AwsInventoryFormat::Csv => "CSV",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            AwsInventoryFormat::Json => "json",
+            AwsInventoryFormat::Csv => "csv",
+        }
+    }
+}
+
+impl FromStr for AwsInventoryFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_uppercase().as_str() {
+            "JSON" => Ok(AwsInventoryFormat::Json),
+            "CSV" => Ok(AwsInventoryFormat::Csv),
+            _ => Err(anyhow::Error::msg(format!(
+                "unknown inventory format \"{}\"",
+                value
+            ))),
+        }
+    }
+}
+
+/// Parameters of an inventory retrieval job.
+///
+/// Inventories restricted by date or limit, as well as continuations from a marker, only cover part of a vault.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct AwsInventoryRetrievalParameters {
+    #[serde(skip_serializing)]
+    pub format: Option<AwsInventoryFormat>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_date"
+    )]
+    pub start_date: Option<DateTime<FixedOffset>>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_date"
+    )]
+    pub end_date: Option<DateTime<FixedOffset>>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "string_number"
+    )]
+    pub limit: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub marker: Option<String>,
+}
+
+impl AwsInventoryRetrievalParameters {
+    pub fn is_partial(&self) -> bool {
+        self.start_date.is_some()
+            || self.end_date.is_some()
+            || self.limit.is_some()
+            || self.marker.is_some()
+    }
+
+    pub fn request_body(&self, description: &str) -> Result<String> {
+        let mut body = serde_json::json!({
+            "Type": "inventory-retrieval",
+            "Description": description,
+            "Format": self.format.unwrap_or(AwsInventoryFormat::Json).as_str(),
+        });
+
+        if self.is_partial() {
+            body["InventoryRetrievalParameters"] = serde_json::to_value(self)?;
+        }
+
+        Ok(body.to_string())
+    }
+}
+
+fn serialize_date<S: Serializer>(
+    value: &Option<DateTime<FixedOffset>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match value {
+        Some(date) => serializer.serialize_str(
+            &date
+                .with_timezone(&Utc)
+                .format("%Y-%m-%dT%H:%M:%SZ")
+                .to_string(),
+        ),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// The Glacier API transfers the inventory limit as a string.
+mod string_number {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &Option<i64>, serializer: S) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(number) => serializer.serialize_str(&number.to_string()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<i64>, D::Error> {
+        match Option::<String>::deserialize(deserializer)? {
+            Some(value) => value.parse().map(Some).map_err(serde::de::Error::custom),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Parses an inventory job output that is delivered in pieces.
+pub enum AwsInventoryParser {
+    Json(AwsInventoryJsonParser),
+    Csv(AwsInventoryCsvParser),
+}
+
+impl AwsInventoryParser {
+    pub fn new(format: AwsInventoryFormat) -> Self {
+        match format {
+            AwsInventoryFormat::Json => AwsInventoryParser::Json(AwsInventoryJsonParser::new()),
+            AwsInventoryFormat::Csv => AwsInventoryParser::Csv(AwsInventoryCsvParser::new()),
+        }
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        match self {
+            AwsInventoryParser::Json(parser) => parser.push(data),
+            AwsInventoryParser::Csv(parser) => parser.push(data),
+        }
+    }
+
+    /// Returns the next complete archive or `None` if more data is needed.
+    pub fn next_archive(&mut self) -> Result<Option<AwsArchive>> {
+        match self {
+            AwsInventoryParser::Json(parser) => parser.next_archive(),
+            AwsInventoryParser::Csv(parser) => parser.next_archive(),
+        }
+    }
+
+    /// Signals the end of the data and returns the archives that were still pending.
+    pub fn finish(&mut self) -> Result<Vec<AwsArchive>> {
+        match self {
+            AwsInventoryParser::Json(parser) => parser.finish(),
+            AwsInventoryParser::Csv(parser) => parser.finish(),
+        }
+    }
+
+    /// Returns the inventory date, if the format contains one and it has been parsed already.
+    pub fn inventory_date(&self) -> Option<DateTime<FixedOffset>> {
+        match self {
+            AwsInventoryParser::Json(parser) => parser.inventory_date,
+            AwsInventoryParser::Csv(_) => None,
+        }
+    }
+}
+
+/// Parses a JSON inventory incrementally.
+///
+/// The objects of the archive list are deserialized one at a time as soon as they are complete.
+/// All other data is kept as a skeleton document, from which the inventory date is read.
+/// Archives are only returned once the inventory date is known, so an inventory date following a non-empty archive list is an error.
+pub struct AwsInventoryJsonParser {
+    buffer: Vec<u8>,
+    scan: usize,
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+    in_list: bool,
+    object_start: Option<usize>,
+    skeleton: Vec<u8>,
+    inventory_date: Option<DateTime<FixedOffset>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct AwsInventoryHeader {
+    inventory_date: Option<DateTime<FixedOffset>>,
+}
+
+impl AwsInventoryJsonParser {
+    pub fn new() -> Self {
+        AwsInventoryJsonParser {
+            buffer: Vec::new(),
+            scan: 0,
+            depth: 0,
+            in_string: false,
+            escaped: false,
+            in_list: false,
+            object_start: None,
+            skeleton: Vec::new(),
+            inventory_date: None,
+        }
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    pub fn next_archive(&mut self) -> Result<Option<AwsArchive>> {
+        while self.scan < self.buffer.len() {
+            let idx = self.scan;
+            let c = self.buffer[idx];
+
+            self.scan += 1;
+
+            if !self.in_list {
+                self.skeleton.push(c);
+            }
+
+            if self.in_string {
+                match c {
+                    _ if self.escaped => self.escaped = false,
+                    b'\\' => self.escaped = true,
+                    b'"' => self.in_string = false,
+                    _ => {}
+                }
+                continue;
+            }
+
+            match c {
+                b'"' => self.in_string = true,
+                b'[' if self.depth == 1 => {
+                    self.depth += 1;
+                    self.in_list = true;
+                    self.read_header()?;
+                }
+                b'{' if self.in_list && self.depth == 2 => {
+                    self.depth += 1;
+                    self.object_start = Some(idx);
+                }
+                b'{' | b'[' => self.depth += 1,
+                b'}' if self.in_list && self.depth == 3 => {
+                    self.depth -= 1;
+
+                    if let Some(start) = self.object_start.take() {
+                        if self.inventory_date.is_none() {
+                            return Err(anyhow::Error::msg(
+                                "inventory date not found before the archive list",
+                            ));
+                        }
+
+                        return Ok(Some(serde_json::from_slice(&self.buffer[start..=idx])?));
+                    }
+                }
+                b']' if self.in_list && self.depth == 2 => {
+                    self.depth -= 1;
+                    self.in_list = false;
+                    self.skeleton.push(c);
+                }
+                b'}' | b']' => {
+                    self.depth = self
+                        .depth
+                        .checked_sub(1)
+                        .ok_or_else(|| anyhow::Error::msg("unbalanced inventory document"))?;
+                }
+                _ => {}
+            }
+        }
+
+        // only keep the part of the buffer that belongs to an incomplete archive
+        let keep_from = self.object_start.unwrap_or(self.buffer.len());
+
+        self.buffer.drain(..keep_from);
+        self.scan -= keep_from;
+        self.object_start = self.object_start.map(|_| 0);
+
+        Ok(None)
+    }
+
+    pub fn finish(&mut self) -> Result<Vec<AwsArchive>> {
+        let mut res = Vec::<AwsArchive>::new();
+
+        while let Some(archive) = self.next_archive()? {
+            res.push(archive);
+        }
+
+        if self.depth != 0 || self.in_string || self.object_start.is_some() {
+            return Err(anyhow::Error::msg("incomplete inventory document"));
+        }
+
+        let header: AwsInventoryHeader = serde_json::from_slice(&self.skeleton)?;
+
+        self.inventory_date = header.inventory_date;
+
+        Ok(res)
+    }
+
+    /// Reads the inventory date from the fields preceding the archive list.
+    fn read_header(&mut self) -> Result<()> {
+        let mut header = self.skeleton.clone();
+
+        header.extend_from_slice(b"]}");
+        self.inventory_date = serde_json::from_slice::<AwsInventoryHeader>(&header)?.inventory_date;
+
+        Ok(())
+    }
+}
+
+impl Default for AwsInventoryJsonParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Parses a CSV inventory record by record.
+///
+/// Fields may be quoted.
+/// Within quoted fields, quotes can be escaped by doubling them or with a backslash, as Glacier uses the latter for archive descriptions.
+pub struct AwsInventoryCsvParser {
+    buffer: Vec<u8>,
+    position: usize,
+    header: Option<Vec<String>>,
+}
+
+impl AwsInventoryCsvParser {
+    pub fn new() -> Self {
+        AwsInventoryCsvParser {
+            buffer: Vec::new(),
+            position: 0,
+            header: None,
+        }
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        if self.position > 0 && self.position * 2 >= self.buffer.len() {
+            self.buffer.drain(..self.position);
+            self.position = 0;
+        }
+
+        self.buffer.extend_from_slice(data);
+    }
+
+    pub fn next_archive(&mut self) -> Result<Option<AwsArchive>> {
+        self.next_record(false)
+    }
+
+    pub fn finish(&mut self) -> Result<Vec<AwsArchive>> {
+        let mut res = Vec::<AwsArchive>::new();
+
+        while let Some(archive) = self.next_record(true)? {
+            res.push(archive);
+        }
+
+        Ok(res)
+    }
+
+    fn next_record(&mut self, at_end: bool) -> Result<Option<AwsArchive>> {
+        loop {
+            let (fields, consumed) = match parse_csv_record(&self.buffer[self.position..], at_end)?
+            {
+                Some(record) => record,
+                None => return Ok(None),
+            };
+
+            self.position += consumed;
+
+            if fields.len() == 1 && fields[0].is_empty() {
+                continue;
+            }
+
+            match &self.header {
+                None => self.header = Some(fields),
+                Some(header) => return Ok(Some(csv_archive(header, fields)?)),
+            }
+        }
+    }
+}
+
+impl Default for AwsInventoryCsvParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn csv_archive(header: &[String], fields: Vec<String>) -> Result<AwsArchive> {
+    if header.len() != fields.len() {
+        return Err(anyhow::Error::msg(format!(
+            "expected {} fields in inventory record, found {}",
+            header.len(),
+            fields.len()
+        )));
+    }
+
+    let field = |name: &str| -> Result<&str> {
+        header
+            .iter()
+            .position(|h| h == name)
+            .map(|idx| &*fields[idx])
+            .ok_or_else(|| anyhow::Error::msg(format!("column \"{}\" not found", name)))
+    };
+
+    Ok(AwsArchive {
+        archive_id: field("ArchiveId")?.into(),
+        archive_description: field("ArchiveDescription")?.into(),
+        creation_date: DateTime::parse_from_rfc3339(field("CreationDate")?)?,
+        size: field("Size")?.parse()?,
+        tree_hash: field("SHA256TreeHash")?.into(),
+    })
+}
+
+/// Parses a single record from the beginning of `data`.
+///
+/// Returns the fields and the number of bytes consumed or `None` if the record is incomplete.
+/// If `at_end` is set, the end of the data terminates the record.
+fn parse_csv_record(data: &[u8], at_end: bool) -> Result<Option<(Vec<String>, usize)>> {
+    if data.is_empty() {
+        return Ok(None);
+    }
+
+    let mut fields = Vec::<String>::new();
+    let mut field = Vec::<u8>::new();
+    let mut idx = 0;
+
+    loop {
+        if idx < data.len() && data[idx] == b'"' {
+            idx += 1;
+
+            loop {
+                match (data.get(idx), data.get(idx + 1)) {
+                    (None, _) if at_end => {
+                        return Err(anyhow::Error::msg("unterminated quoted field"))
+                    }
+                    (None, _) => return Ok(None),
+                    (Some(b'"'), Some(b'"')) | (Some(b'\\'), Some(b'"')) => {
+                        field.push(b'"');
+                        idx += 2;
+                    }
+                    (Some(b'\\'), Some(b'\\')) => {
+                        field.push(b'\\');
+                        idx += 2;
+                    }
+                    (Some(b'"'), None) if !at_end => return Ok(None),
+                    (Some(b'\\'), None) if !at_end => return Ok(None),
+                    (Some(b'"'), _) => {
+                        idx += 1;
+                        break;
+                    }
+                    (Some(c), _) => {
+                        field.push(*c);
+                        idx += 1;
+                    }
+                }
+            }
+        }
+
+        while idx < data.len() && !matches!(data[idx], b',' | b'\n' | b'\r') {
+            field.push(data[idx]);
+            idx += 1;
+        }
+
+        fields.push(String::from_utf8(std::mem::take(&mut field))?);
+
+        match data.get(idx) {
+            Some(b',') => idx += 1,
+            Some(b'\n') => return Ok(Some((fields, idx + 1))),
+            Some(b'\r') => match data.get(idx + 1) {
+                Some(b'\n') => return Ok(Some((fields, idx + 2))),
+                None if !at_end => return Ok(None),
+                _ => return Ok(Some((fields, idx + 1))),
+            },
+            _ if at_end => return Ok(Some((fields, idx))),
+            _ => return Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CSV_INVENTORY: &str = "ArchiveId,ArchiveDescription,CreationDate,Size,SHA256TreeHash\r\n\
+        id-1,\"backup, daily\",2021-07-25T10:00:00Z,1024,abc\r\n\
+        id-2,\"say \\\"hello\\\"\",2021-07-26T10:00:00Z,2048,def\r\n\
+        id-3,\"line\nbreak \"\"quoted\"\"\",2021-07-27T10:00:00Z,4096,ghi\r\n";
+
+    const JSON_INVENTORY: &str = r#"{"VaultARN":"arn:aws:glacier:us-east-1:012345678901:vaults/examplevault",
+        "InventoryDate":"2021-07-28T10:00:00Z",
+        "ArchiveList":[
+            {"ArchiveId":"id-1","ArchiveDescription":"braces {[\"]}","CreationDate":"2021-07-25T10:00:00Z","Size":1024,"SHA256TreeHash":"abc"},
+            {"ArchiveId":"id-2","ArchiveDescription":"","CreationDate":"2021-07-26T10:00:00Z","Size":2048,"SHA256TreeHash":"def"}
+        ]}"#;
+
+    #[test]
+    fn retrieval_parameters_1() {
+        let parameters: AwsInventoryRetrievalParameters = serde_json::from_str(
+            r#"{"Format":"CSV","StartDate":"2021-07-25T10:00:00Z","EndDate":null,"Limit":"100","Marker":"marker-1"}"#,
+        )
+        .unwrap();
+
+        assert_eq!(parameters.format, Some(AwsInventoryFormat::Csv));
+        assert_eq!(parameters.limit, Some(100));
+        assert!(parameters.is_partial());
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&parameters.request_body("test").unwrap())
+                .unwrap(),
+            serde_json::json!({
+                "Type": "inventory-retrieval",
+                "Description": "test",
+                "Format": "CSV",
+                "InventoryRetrievalParameters": {
+                    "StartDate": "2021-07-25T10:00:00Z",
+                    "Limit": "100",
+                    "Marker": "marker-1"
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn retrieval_parameters_2() {
+        let parameters = AwsInventoryRetrievalParameters::default();
+
+        assert!(!parameters.is_partial());
+        assert_eq!(
+            parameters.request_body("test").unwrap(),
+            r#"{"Description":"test","Format":"JSON","Type":"inventory-retrieval"}"#
+        );
+    }
+
+    #[test]
+    fn json_parser_1() {
+        let mut parser = AwsInventoryJsonParser::new();
+        let mut archives = Vec::new();
+
+        for chunk in JSON_INVENTORY.as_bytes().chunks(5) {
+            parser.push(chunk);
+
+            while let Some(archive) = parser.next_archive().unwrap() {
+                assert!(parser.inventory_date.is_some());
+                archives.push(archive);
+            }
+        }
+
+        archives.append(&mut parser.finish().unwrap());
+        assert_eq!(archives.len(), 2);
+        assert_eq!(archives[0].archive_description, "braces {[\"]}");
+        assert_eq!(archives[1].size, 2048);
+        assert_eq!(
+            parser.inventory_date,
+            Some(DateTime::parse_from_rfc3339("2021-07-28T10:00:00Z").unwrap())
+        );
+        assert!(parser.buffer.is_empty());
+    }
+
+    #[test]
+    fn json_parser_2() {
+        let mut parser = AwsInventoryJsonParser::new();
+
+        parser.push(br#"{"ArchiveList":[],"InventoryDate":"2021-07-28T10:00:00Z"}"#);
+        assert!(parser.next_archive().unwrap().is_none());
+        assert!(parser.inventory_date.is_none());
+        assert!(parser.finish().unwrap().is_empty());
+        assert!(parser.inventory_date.is_some());
+
+        let mut parser = AwsInventoryJsonParser::new();
+
+        parser.push(
+            br#"{"ArchiveList":[{"ArchiveId":"id-1"}],"InventoryDate":"2021-07-28T10:00:00Z"}"#,
+        );
+        assert!(parser.next_archive().is_err());
+    }
+
+    #[test]
+    fn json_parser_3() {
+        let mut parser = AwsInventoryJsonParser::new();
+
+        parser.push(br#"{"InventoryDate":"2021-07-28T10:00:00Z","ArchiveList":[{"ArchiveId":"#);
+        assert!(parser.next_archive().unwrap().is_none());
+        assert!(parser.finish().is_err());
+    }
+
+    #[test]
+    fn parse_csv_record_1() {
+        assert_eq!(
+            parse_csv_record(b"a,\"b,c\",d\n", false).unwrap(),
+            Some((vec!["a".into(), "b,c".into(), "d".into()], 10))
+        );
+        assert_eq!(parse_csv_record(b"a,\"b,c", false).unwrap(), None);
+        assert_eq!(parse_csv_record(b"a,b", false).unwrap(), None);
+        assert_eq!(
+            parse_csv_record(b"a,b", true).unwrap(),
+            Some((vec!["a".into(), "b".into()], 3))
+        );
+    }
+
+    #[test]
+    fn csv_parser_1() {
+        let mut parser = AwsInventoryCsvParser::new();
+        let mut archives = Vec::new();
+
+        for chunk in CSV_INVENTORY.as_bytes().chunks(7) {
+            parser.push(chunk);
+
+            while let Some(archive) = parser.next_archive().unwrap() {
+                archives.push(archive);
+            }
+        }
+
+        archives.append(&mut parser.finish().unwrap());
+        assert_eq!(archives.len(), 3);
+        assert_eq!(archives[0].archive_description, "backup, daily");
+        assert_eq!(archives[1].archive_description, "say \"hello\"");
+        assert_eq!(archives[2].archive_description, "line\nbreak \"quoted\"");
+        assert_eq!(archives[2].size, 4096);
+        assert_eq!(archives[2].tree_hash, "ghi");
+    }
+
+    #[test]
+    fn csv_parser_2() {
+        let mut parser = AwsInventoryCsvParser::new();
+
+        parser.push(b"ArchiveId,ArchiveDescription,CreationDate,Size,SHA256TreeHash\nid-1,,2021-07-25T10:00:00Z,1,abc");
+        assert!(parser.next_archive().unwrap().is_none());
+        assert_eq!(parser.finish().unwrap()[0].archive_id, "id-1");
+    }
+}
@@ -1,29 +1,53 @@
+use super::aws_inventory::{AwsInventoryFormat, AwsInventoryRetrievalParameters};
 use anyhow::Result;
 use chrono::{DateTime, FixedOffset};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::convert::TryFrom;
 use tokio_postgres::Row;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct AwsJob {
     pub job_id: String,
     pub action: String,
     pub archive_id: Option<String>,
-    #[serde(rename(deserialize = "ArchiveSHA256TreeHash"))]
+    #[serde(rename = "ArchiveSHA256TreeHash")]
     pub archive_tree_hash: Option<String>,
     pub archive_size_in_bytes: Option<i64>,
     pub completion_date: Option<DateTime<FixedOffset>>,
     pub creation_date: DateTime<FixedOffset>,
     pub inventory_size_in_bytes: Option<i64>,
+    pub inventory_retrieval_parameters: Option<AwsInventoryRetrievalParameters>,
     pub job_description: Option<String>,
+    /// Range of an archive retrieval job ("first-last" byte).
+    pub retrieval_byte_range: Option<String>,
     pub tree_hash: Option<String>,
     pub status_code: String,
     pub status_message: Option<String>,
-    #[serde(rename(deserialize = "VaultARN"))]
+    #[serde(rename = "VaultARN")]
     pub vault_arn: String,
 }
 
+impl AwsJob {
+    /// Returns the format of the output of an inventory job.
+    ///
+    /// Jobs without retrieval parameters are assumed to be JSON.
+    pub fn inventory_format(&self) -> AwsInventoryFormat {
+        self.inventory_retrieval_parameters
+            .as_ref()
+            .and_then(|p| p.format)
+            .unwrap_or(AwsInventoryFormat::Json)
+    }
+
+    /// Returns whether an inventory job only covers part of a vault.
+    pub fn is_partial_inventory(&self) -> bool {
+        self.inventory_retrieval_parameters
+            .as_ref()
+            .map(|p| p.is_partial())
+            .unwrap_or(false)
+    }
+}
+
 impl TryFrom<&str> for AwsJob {
     type Error = anyhow::Error;
 
@@ -52,7 +76,20 @@ impl TryFrom<&Row> for AwsJob {
             completion_date: value.try_get("completion_date")?,
             creation_date: value.try_get("creation_date")?,
             inventory_size_in_bytes: value.try_get("inventory_size_in_bytes")?,
+            inventory_retrieval_parameters: match value
+                .try_get::<_, Option<String>>("inventory_format")?
+            {
+                Some(format) => Some(AwsInventoryRetrievalParameters {
+                    format: Some(format.parse()?),
+                    start_date: value.try_get("inventory_start_date")?,
+                    end_date: value.try_get("inventory_end_date")?,
+                    limit: value.try_get("inventory_limit")?,
+                    marker: value.try_get("inventory_marker")?,
+                }),
+                None => None,
+            },
             job_description: value.try_get("job_description")?,
+            retrieval_byte_range: value.try_get("retrieval_byte_range")?,
             tree_hash: value.try_get("tree_hash")?,
             status_code: value.try_get("status_code")?,
             status_message: value.try_get("status_message")?,
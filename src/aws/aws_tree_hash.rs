@@ -0,0 +1,152 @@
+use data_encoding::HEXLOWER;
+use ring::digest;
+
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Computes the SHA256 tree hash Glacier uses to verify uploads.
+///
+/// The data is split into chunks of 1 MiB, which are hashed and then combined pairwise until a single hash remains.
+pub struct TreeHasher {
+    context: digest::Context,
+    chunk_length: usize,
+    hashes: Vec<Vec<u8>>,
+}
+
+impl TreeHasher {
+    pub fn new() -> Self {
+        TreeHasher {
+            context: digest::Context::new(&digest::SHA256),
+            chunk_length: 0,
+            hashes: Vec::new(),
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let length = usize::min(CHUNK_SIZE - self.chunk_length, data.len());
+
+            self.context.update(&data[..length]);
+            self.chunk_length += length;
+            data = &data[length..];
+
+            if self.chunk_length == CHUNK_SIZE {
+                self.finish_chunk();
+            }
+        }
+    }
+
+    /// Returns the tree hash as a hex encoded string.
+    pub fn finish(mut self) -> String {
+        if self.chunk_length > 0 || self.hashes.is_empty() {
+            self.finish_chunk();
+        }
+
+        HEXLOWER.encode(&combine_hashes(self.hashes))
+    }
+
+    fn finish_chunk(&mut self) {
+        let context = std::mem::replace(&mut self.context, digest::Context::new(&digest::SHA256));
+
+        self.hashes.push(context.finish().as_ref().to_vec());
+        self.chunk_length = 0;
+    }
+}
+
+impl Default for TreeHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Combines a list of hex encoded tree hashes (e.g. of the parts of a multipart upload) into the tree hash of the whole.
+///
+/// All but the last hash must cover a power of two number of chunks.
+pub fn combine_tree_hashes(hashes: &[String]) -> anyhow::Result<String> {
+    if hashes.is_empty() {
+        return Err(anyhow::Error::msg("no hashes to combine"));
+    }
+
+    let mut digests = Vec::with_capacity(hashes.len());
+
+    for hash in hashes {
+        digests.push(HEXLOWER.decode(hash.as_bytes())?);
+    }
+
+    Ok(HEXLOWER.encode(&combine_hashes(digests)))
+}
+
+fn combine_hashes(mut level: Vec<Vec<u8>>) -> Vec<u8> {
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => {
+                    let mut context = digest::Context::new(&digest::SHA256);
+
+                    context.update(left);
+                    context.update(right);
+                    context.finish().as_ref().to_vec()
+                }
+                _ => pair[0].clone(),
+            })
+            .collect();
+    }
+
+    level.swap_remove(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sha_256(data: &[u8]) -> Vec<u8> {
+        digest::digest(&digest::SHA256, data).as_ref().to_vec()
+    }
+
+    #[test]
+    fn tree_hash_1() {
+        let mut hasher = TreeHasher::new();
+
+        hasher.update(b"abc");
+        assert_eq!(hasher.finish(), HEXLOWER.encode(&sha_256(b"abc")));
+    }
+
+    #[test]
+    fn tree_hash_2() {
+        let data = vec![7u8; 3 * CHUNK_SIZE + 10];
+        let mut hasher = TreeHasher::new();
+        let h = |d: &[u8]| sha_256(d);
+        let pair = |a: &[u8], b: &[u8]| sha_256(&[a, b].concat());
+        let c1 = h(&data[..CHUNK_SIZE]);
+        let c2 = h(&data[CHUNK_SIZE..2 * CHUNK_SIZE]);
+        let c3 = h(&data[2 * CHUNK_SIZE..3 * CHUNK_SIZE]);
+        let c4 = h(&data[3 * CHUNK_SIZE..]);
+
+        for chunk in data.chunks(100_000) {
+            hasher.update(chunk);
+        }
+
+        assert_eq!(
+            hasher.finish(),
+            HEXLOWER.encode(&pair(&pair(&c1, &c2), &pair(&c3, &c4)))
+        );
+    }
+
+    #[test]
+    fn combine_tree_hashes_1() {
+        let data = vec![3u8; 5 * CHUNK_SIZE];
+        let mut whole = TreeHasher::new();
+        let mut parts = Vec::new();
+
+        whole.update(&data);
+
+        for part in data.chunks(2 * CHUNK_SIZE) {
+            let mut hasher = TreeHasher::new();
+
+            hasher.update(part);
+            parts.push(hasher.finish());
+        }
+
+        assert_eq!(combine_tree_hashes(&parts).unwrap(), whole.finish());
+    }
+}
@@ -1,17 +1,17 @@
 use anyhow::Result;
 use chrono::{DateTime, FixedOffset};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::convert::TryFrom;
 use tokio_postgres::Row;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct AwsVault {
     pub creation_date: DateTime<FixedOffset>,
     pub last_inventory_date: Option<DateTime<FixedOffset>>,
     pub number_of_archives: i64,
     pub size_in_bytes: i64,
-    #[serde(rename(deserialize = "VaultARN"))]
+    #[serde(rename = "VaultARN")]
     pub vault_arn: String,
     pub vault_name: String,
 }
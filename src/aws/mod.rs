@@ -1,4 +1,6 @@
 pub mod aws_archive;
 pub mod aws_glacier;
+pub mod aws_inventory;
 pub mod aws_job;
+pub mod aws_tree_hash;
 pub mod aws_vault;
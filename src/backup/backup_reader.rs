@@ -0,0 +1,225 @@
+use super::backup_segment::ArchiveSegment;
+use crate::crypto::crypto_stream::{DecryptReader, EncryptionHeader, HEADER_LENGTH, MAGIC};
+use crate::crypto::Secret;
+use anyhow::Result;
+use flate2::read::MultiGzDecoder;
+use std::io::{self, Cursor, Read};
+
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+
+/// Returns a reader for the tar stream of a downloaded backup archive.
+///
+/// Encryption and compression are detected from the data, so archives are decrypted transparently with the first of the secrets matching the encryption header.
+pub fn archive_reader<'a, R: Read + 'a>(
+    reader: R,
+    secrets: &[Secret],
+) -> Result<Box<dyn Read + 'a>> {
+    let (header, reader) = peek(reader, HEADER_LENGTH)?;
+    let reader: Box<dyn Read + 'a> = if header.starts_with(MAGIC) {
+        let header = EncryptionHeader::from_bytes(&header)?;
+        let secret = secrets
+            .iter()
+            .find(|s| header.matches(s))
+            .ok_or_else(|| anyhow::Error::msg(header.key_description()))?;
+
+        Box::new(DecryptReader::new(reader, secret)?)
+    } else {
+        Box::new(reader)
+    };
+    decompress(reader)
+}
+
+/// Returns a reader for part of the tar stream of an archive, read from a download of the byte range of the segment.
+///
+/// The encryption header is taken from the repository, because the range usually does not include the start of the archive.
+pub fn segment_reader<'a, R: Read + 'a>(
+    mut reader: R,
+    segment: &ArchiveSegment,
+    encryption_header: Option<&[u8]>,
+    secrets: &[Secret],
+) -> Result<Box<dyn Read + 'a>> {
+    skip(&mut reader, segment.read_offset)?;
+
+    let mut reader: Box<dyn Read + 'a> = match (segment.chunk_counter, encryption_header) {
+        (Some(counter), Some(header_bytes)) => {
+            let header = EncryptionHeader::from_bytes(header_bytes)?;
+            let secret = secrets
+                .iter()
+                .find(|s| header.matches(s))
+                .ok_or_else(|| anyhow::Error::msg(header.key_description()))?;
+
+            Box::new(DecryptReader::resume(
+                reader,
+                header_bytes,
+                secret,
+                counter,
+                segment.to_end,
+            )?)
+        }
+        (None, None) => Box::new(reader),
+        _ => {
+            return Err(anyhow::Error::msg(
+                "encryption header of the archive missing",
+            ))
+        }
+    };
+
+    skip(&mut reader, segment.frame_skip)?;
+
+    let mut reader = decompress(reader)?;
+
+    skip(&mut reader, segment.tar_skip)?;
+
+    match segment.tar_length {
+        Some(length) => Ok(Box::new(reader.take(length))),
+        None => Ok(reader),
+    }
+}
+
+/// Detects the compression of the stream and returns a reader for the decompressed data.
+///
+/// Archives consist of a sequence of independently compressed frames, all of which are read.
+fn decompress<'a, R: Read + 'a>(reader: R) -> Result<Box<dyn Read + 'a>> {
+    let (magic, reader) = peek(reader, 4)?;
+
+    if magic.starts_with(ZSTD_MAGIC) {
+        Ok(Box::new(zstd::Decoder::new(reader)?))
+    } else if magic.starts_with(GZIP_MAGIC) {
+        Ok(Box::new(MultiGzDecoder::new(reader)))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+fn skip<R: Read>(reader: &mut R, length: u64) -> Result<()> {
+    if io::copy(&mut reader.take(length), &mut io::sink())? < length {
+        return Err(anyhow::Error::msg("archive segment is truncated"));
+    }
+
+    Ok(())
+}
+
+/// Reads the first bytes of the stream and returns them together with a reader for the whole stream.
+fn peek<R: Read>(mut reader: R, length: usize) -> Result<(Vec<u8>, impl Read)> {
+    let mut magic = Vec::with_capacity(length);
+
+    reader
+        .by_ref()
+        .take(length as u64)
+        .read_to_end(&mut magic)?;
+
+    Ok((magic.clone(), Cursor::new(magic).chain(reader)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backup::backup_writer::{
+        CompressionWriter, CountingWriter, EncryptionWriter, FrameWriter,
+    };
+    use crate::backup::BackupCompression;
+    use crate::crypto::EncryptionAlgorithm;
+    use std::io::Write;
+
+    #[test]
+    fn archive_reader_1() {
+        let secret = Secret::Key([3u8; 32]);
+
+        for compression in &[BackupCompression::Zstd, BackupCompression::Gzip] {
+            let writer =
+                EncryptionWriter::encrypted(Vec::new(), &secret, EncryptionAlgorithm::Aes256Gcm)
+                    .unwrap();
+            let mut writer = CompressionWriter::new(*compression, writer).unwrap();
+            let mut data = String::new();
+
+            writer.write_all(b"tar data").unwrap();
+
+            let encrypted = writer.finish().unwrap().finish().unwrap();
+
+            assert!(archive_reader(&encrypted[..], &[]).is_err());
+            archive_reader(&encrypted[..], std::slice::from_ref(&secret))
+                .unwrap()
+                .read_to_string(&mut data)
+                .unwrap();
+            assert_eq!(data, "tar data");
+        }
+    }
+
+    #[test]
+    fn segment_reader_1() {
+        let secret = Secret::Key([5u8; 32]);
+        let sizes = [1_200_000, 1_200_000, 200_000, 1_000_000];
+        let mut seed = 1u32;
+        let mut files = Vec::new();
+        let mut offsets = Vec::new();
+
+        for size in &sizes {
+            files.push(
+                (0..*size)
+                    .map(|_| {
+                        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                        (seed >> 16) as u8
+                    })
+                    .collect::<Vec<u8>>(),
+            );
+        }
+
+        for compression in &[BackupCompression::Zstd, BackupCompression::Gzip] {
+            let writer =
+                EncryptionWriter::encrypted(Vec::new(), &secret, EncryptionAlgorithm::Aes256Gcm)
+                    .unwrap();
+            let header_bytes = writer.header_bytes().unwrap();
+            let mut builder = tar::Builder::new(CountingWriter::new(
+                FrameWriter::new(*compression, 256 * 1024, writer).unwrap(),
+            ));
+
+            offsets.clear();
+
+            for (i, file) in files.iter().enumerate() {
+                let mut header = tar::Header::new_gnu();
+
+                offsets.push(builder.get_ref().count());
+                header.set_size(file.len() as u64);
+                header.set_cksum();
+                builder
+                    .append_data(&mut header, format!("file{}", i), &file[..])
+                    .unwrap();
+            }
+
+            let (writer, frames) = builder.into_inner().unwrap().into_inner().finish().unwrap();
+            let archive = writer.finish().unwrap();
+            let segment = ArchiveSegment::new(
+                offsets[2],
+                Some(offsets[3]),
+                &frames,
+                Some(&EncryptionHeader::from_bytes(&header_bytes).unwrap()),
+                archive.len() as u64,
+            )
+            .unwrap();
+
+            assert!(frames.len() > 10);
+            assert_eq!(segment.range_start, 2 * 1024 * 1024);
+
+            let download = &archive[segment.range_start as usize..=segment.range_end as usize];
+            let mut tar = tar::Archive::new(
+                segment_reader(
+                    download,
+                    &segment,
+                    Some(&header_bytes),
+                    std::slice::from_ref(&secret),
+                )
+                .unwrap(),
+            );
+            let mut entries = tar.entries().unwrap();
+            let mut entry = entries.next().unwrap().unwrap();
+            let mut content = Vec::new();
+
+            assert_eq!(entry.path().unwrap().to_str(), Some("file2"));
+            entry.read_to_end(&mut content).unwrap();
+            assert_eq!(content, files[2]);
+            drop(entry);
+            assert!(entries.next().is_none());
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use crate::catalog::CatalogEntry;
+use crate::crypto::crypto_stream::{EncryptionHeader, HEADER_LENGTH, TAG_LENGTH};
+use anyhow::Result;
+use std::collections::HashSet;
+use std::convert::{TryFrom, TryInto};
+use tokio_postgres::Row;
+
+/// Glacier computes tree hashes over blocks of 1 MiB.
+const TREE_HASH_BLOCK_SIZE: u64 = 1024 * 1024;
+
+/// Start of an independently compressed frame of an archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BackupFrame {
+    /// Offset of the frame within the uncompressed tar stream.
+    pub tar_offset: i64,
+    /// Offset of the frame within the compressed stream before encryption.
+    pub compressed_offset: i64,
+}
+
+impl TryFrom<&Row> for BackupFrame {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &Row) -> Result<Self, Self::Error> {
+        Ok(BackupFrame {
+            tar_offset: value.try_get("tar_offset")?,
+            compressed_offset: value.try_get("compressed_offset")?,
+        })
+    }
+}
+
+/// The byte range of an archive to retrieve to read part of its tar stream, together with the positions to enter it at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveSegment {
+    /// First byte of the archive to retrieve.
+    pub range_start: u64,
+    /// Last byte of the archive to retrieve. The range is tree hash aligned, so the retrieval can be verified.
+    pub range_end: u64,
+    /// Offset within the retrieved range, at which reading starts. For encrypted archives this is the start of a chunk, otherwise the start of a frame.
+    pub read_offset: u64,
+    /// Counter of the chunk reading starts at, if the archive is encrypted.
+    pub chunk_counter: Option<u32>,
+    /// Number of decrypted bytes to skip to reach the start of the frame.
+    pub frame_skip: u64,
+    /// Number of decompressed bytes to skip to reach the requested part of the tar stream.
+    pub tar_skip: u64,
+    /// Length of the requested part of the tar stream, `None` if it extends to the end.
+    pub tar_length: Option<u64>,
+    /// Set if the range extends to the end of the archive, so its last chunk is complete.
+    pub to_end: bool,
+}
+
+impl ArchiveSegment {
+    /// Computes the segment containing the tar stream from `tar_start` to `tar_end` (exclusive) or to the end of the stream.
+    pub fn new(
+        tar_start: u64,
+        tar_end: Option<u64>,
+        frames: &[BackupFrame],
+        encryption_header: Option<&EncryptionHeader>,
+        archive_size: u64,
+    ) -> Result<Self> {
+        let first_frame = frames
+            .iter()
+            .rev()
+            .find(|f| f.tar_offset as u64 <= tar_start)
+            .ok_or_else(|| anyhow::Error::msg("archive has no frame index"))?;
+        let compressed_start = first_frame.compressed_offset as u64;
+        let compressed_end = tar_end.and_then(|tar_end| {
+            frames
+                .iter()
+                .find(|f| f.tar_offset as u64 >= tar_end)
+                .map(|f| f.compressed_offset as u64)
+        });
+        let (archive_start, archive_end, chunk_counter, frame_skip) = match encryption_header {
+            Some(header) => {
+                let chunk_size = header.chunk_size() as u64;
+                let sealed_size = chunk_size + TAG_LENGTH as u64;
+                let chunk = compressed_start / chunk_size;
+
+                (
+                    HEADER_LENGTH as u64 + chunk * sealed_size,
+                    compressed_end
+                        .map(|end| HEADER_LENGTH as u64 + end.div_ceil(chunk_size) * sealed_size),
+                    Some(chunk.try_into()?),
+                    compressed_start - chunk * chunk_size,
+                )
+            }
+            None => (compressed_start, compressed_end, None, 0),
+        };
+        let archive_end = archive_end.unwrap_or(archive_size).min(archive_size);
+        let (range_start, range_end) =
+            tree_hash_aligned_range(archive_start, archive_end, archive_size);
+
+        Ok(ArchiveSegment {
+            range_start,
+            range_end,
+            read_offset: archive_start - range_start,
+            chunk_counter,
+            frame_skip,
+            tar_skip: tar_start - first_frame.tar_offset as u64,
+            tar_length: tar_end.map(|tar_end| tar_end - tar_start),
+            to_end: range_end + 1 == archive_size,
+        })
+    }
+
+    /// Returns the range in the format of the retrieval byte range of a job.
+    pub fn byte_range(&self) -> String {
+        format!("{}-{}", self.range_start, self.range_end)
+    }
+}
+
+/// Returns the smallest tree hash aligned range (first and last byte) containing the bytes from `start` to `end` (exclusive).
+///
+/// Aligned ranges consist of a power of two number of blocks starting at a multiple of their size, or extend to the end of the archive.
+pub fn tree_hash_aligned_range(start: u64, end: u64, archive_size: u64) -> (u64, u64) {
+    let mut size = TREE_HASH_BLOCK_SIZE;
+
+    loop {
+        let range_start = start / size * size;
+
+        if end <= range_start + size || range_start + size >= archive_size {
+            return (range_start, u64::min(range_start + size, archive_size) - 1);
+        }
+
+        size *= 2;
+    }
+}
+
+/// The paths selected for a restore.
+///
+/// A path is looked up together with its parent directories, so selections listing every file of a large backup are matched without scanning them.
+pub struct PathSelector<'a> {
+    paths: HashSet<&'a str>,
+}
+
+impl<'a> PathSelector<'a> {
+    pub fn new(selected: &'a [String]) -> Self {
+        PathSelector {
+            paths: selected.iter().map(|s| s.trim_end_matches('/')).collect(),
+        }
+    }
+
+    /// Returns whether a path is one of the selected paths or within one of them.
+    pub fn selects(&self, path: &str) -> bool {
+        self.paths.contains(path)
+            || path
+                .match_indices('/')
+                .any(|(i, _)| self.paths.contains(&path[..i]))
+    }
+}
+
+/// Returns the part of the tar stream (start and exclusive end) containing the selected paths.
+///
+/// The catalog must be sorted by offset. The part ends at the entry following the last one selected.
+pub fn selected_tar_range(
+    catalog: &[CatalogEntry],
+    selected: &[String],
+) -> Option<(u64, Option<u64>)> {
+    let selector = PathSelector::new(selected);
+    let first = catalog.iter().position(|e| selector.selects(&e.path))?;
+    let last = catalog.iter().rposition(|e| selector.selects(&e.path))?;
+
+    Some((
+        catalog[first].offset as u64,
+        catalog.get(last + 1).map(|e| e.offset as u64),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIB: u64 = 1024 * 1024;
+
+    #[test]
+    fn tree_hash_aligned_range_1() {
+        assert_eq!(
+            tree_hash_aligned_range(MIB + 5, 2 * MIB - 3, 10 * MIB),
+            (MIB, 2 * MIB - 1)
+        );
+        assert_eq!(
+            tree_hash_aligned_range(MIB + 5, 2 * MIB + 3, 10 * MIB),
+            (0, 4 * MIB - 1)
+        );
+        assert_eq!(
+            tree_hash_aligned_range(9 * MIB + 5, 9 * MIB + 100, 9 * MIB + 100),
+            (9 * MIB, 9 * MIB + 99)
+        );
+        assert_eq!(
+            tree_hash_aligned_range(5 * MIB, 7 * MIB, 6 * MIB + 10),
+            (4 * MIB, 6 * MIB + 9)
+        );
+    }
+
+    #[test]
+    fn path_selector_1() {
+        let selected = vec!["/etc/nginx/".to_string(), "/var/log/syslog".to_string()];
+        let selector = PathSelector::new(&selected);
+
+        assert!(selector.selects("/etc/nginx"));
+        assert!(selector.selects("/etc/nginx/nginx.conf"));
+        assert!(!selector.selects("/etc/nginx2/nginx.conf"));
+        assert!(!selector.selects("/etc"));
+        assert!(selector.selects("/var/log/syslog"));
+        assert!(!selector.selects("/var/log/syslog.1"));
+
+        // the root selects everything
+        let selected = vec!["/".to_string()];
+
+        assert!(PathSelector::new(&selected).selects("/etc/hosts"));
+    }
+}
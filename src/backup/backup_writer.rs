@@ -0,0 +1,305 @@
+use super::backup_segment::BackupFrame;
+use super::BackupCompression;
+use crate::crypto::crypto_stream::{EncryptWriter, EncryptionHeader};
+use crate::crypto::{EncryptionAlgorithm, Secret};
+use flate2::{write::GzEncoder, Compression};
+use hyper::body::Bytes;
+use std::io::{self, Write};
+use tokio::sync::mpsc::Sender;
+
+/// Compresses the data written to it with the configured compression.
+pub enum CompressionWriter<W: Write> {
+    Zstd(zstd::Encoder<'static, W>),
+    Gzip(GzEncoder<W>),
+}
+
+impl<W: Write> CompressionWriter<W> {
+    pub fn new(compression: BackupCompression, writer: W) -> io::Result<Self> {
+        match compression {
+            BackupCompression::Zstd => Ok(CompressionWriter::Zstd(zstd::Encoder::new(writer, 0)?)),
+            BackupCompression::Gzip => Ok(CompressionWriter::Gzip(GzEncoder::new(
+                writer,
+                Compression::default(),
+            ))),
+        }
+    }
+
+    /// Writes the remaining compressed data and returns the inner writer.
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            CompressionWriter::Zstd(encoder) => encoder.finish(),
+            CompressionWriter::Gzip(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for CompressionWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            CompressionWriter::Zstd(encoder) => encoder.write(buf),
+            CompressionWriter::Gzip(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            CompressionWriter::Zstd(encoder) => encoder.flush(),
+            CompressionWriter::Gzip(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Compresses the data written to it in independent frames, so the stream can be decompressed starting at any frame.
+///
+/// The start of every frame is recorded with its offset in the data written and in the compressed stream.
+pub struct FrameWriter<W: Write> {
+    compression: BackupCompression,
+    frame_size: u64,
+    encoder: Option<CompressionWriter<CountingWriter<W>>>,
+    frame_input: u64,
+    input: u64,
+    frames: Vec<BackupFrame>,
+}
+
+impl<W: Write> FrameWriter<W> {
+    pub fn new(compression: BackupCompression, frame_size: u64, writer: W) -> io::Result<Self> {
+        Ok(FrameWriter {
+            compression,
+            frame_size,
+            encoder: Some(CompressionWriter::new(
+                compression,
+                CountingWriter::new(writer),
+            )?),
+            frame_input: 0,
+            input: 0,
+            frames: vec![BackupFrame {
+                tar_offset: 0,
+                compressed_offset: 0,
+            }],
+        })
+    }
+
+    /// Finishes the last frame and returns the inner writer together with the frames.
+    pub fn finish(mut self) -> io::Result<(W, Vec<BackupFrame>)> {
+        let writer = self.finish_frame()?.into_inner();
+
+        Ok((writer, self.frames))
+    }
+
+    fn finish_frame(&mut self) -> io::Result<CountingWriter<W>> {
+        self.encoder
+            .take()
+            .ok_or_else(|| io::Error::other("frame writer is finished"))?
+            .finish()
+    }
+
+    fn next_frame(&mut self) -> io::Result<()> {
+        let writer = self.finish_frame()?;
+
+        self.frames.push(BackupFrame {
+            tar_offset: self.input as i64,
+            compressed_offset: writer.count() as i64,
+        });
+        self.encoder = Some(CompressionWriter::new(self.compression, writer)?);
+        self.frame_input = 0;
+
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for FrameWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.frame_input == self.frame_size {
+            self.next_frame()?;
+        }
+
+        let length = u64::min(self.frame_size - self.frame_input, buf.len() as u64) as usize;
+        let length = self
+            .encoder
+            .as_mut()
+            .ok_or_else(|| io::Error::other("frame writer is finished"))?
+            .write(&buf[..length])?;
+
+        self.frame_input += length as u64;
+        self.input += length as u64;
+
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.encoder {
+            Some(encoder) => encoder.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Counts the bytes written to the inner writer.
+pub struct CountingWriter<W: Write> {
+    writer: W,
+    count: u64,
+}
+
+impl<W: Write> CountingWriter<W> {
+    pub fn new(writer: W) -> Self {
+        CountingWriter { writer, count: 0 }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let length = self.writer.write(buf)?;
+
+        self.count += length as u64;
+
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Encrypts the data written to it if encryption is configured.
+pub enum EncryptionWriter<W: Write> {
+    Plain(W),
+    Encrypted(Box<EncryptWriter<W>>),
+}
+
+impl<W: Write> EncryptionWriter<W> {
+    pub fn encrypted(
+        writer: W,
+        secret: &Secret,
+        algorithm: EncryptionAlgorithm,
+    ) -> anyhow::Result<Self> {
+        Ok(EncryptionWriter::Encrypted(Box::new(EncryptWriter::new(
+            writer, secret, algorithm,
+        )?)))
+    }
+
+    pub fn with_header(
+        writer: W,
+        header: EncryptionHeader,
+        secret: &Secret,
+    ) -> anyhow::Result<Self> {
+        Ok(EncryptionWriter::Encrypted(Box::new(
+            EncryptWriter::with_header(writer, header, secret)?,
+        )))
+    }
+
+    /// Returns the encryption header written at the start of the stream.
+    pub fn header_bytes(&self) -> Option<Vec<u8>> {
+        match self {
+            EncryptionWriter::Plain(_) => None,
+            EncryptionWriter::Encrypted(writer) => Some(writer.header().to_bytes()),
+        }
+    }
+
+    /// Writes the last chunk if encryption is configured and returns the inner writer.
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            EncryptionWriter::Plain(writer) => Ok(writer),
+            EncryptionWriter::Encrypted(writer) => writer.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for EncryptionWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            EncryptionWriter::Plain(writer) => writer.write(buf),
+            EncryptionWriter::Encrypted(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            EncryptionWriter::Plain(writer) => writer.flush(),
+            EncryptionWriter::Encrypted(writer) => writer.flush(),
+        }
+    }
+}
+
+/// Splits the data written to it into parts of a fixed size and sends them to the uploader.
+///
+/// Sending blocks while the uploader is busy, so at most a few parts are held in memory.
+pub struct PartWriter {
+    sender: Sender<Bytes>,
+    part_size: usize,
+    buffer: Vec<u8>,
+}
+
+impl PartWriter {
+    pub fn new(sender: Sender<Bytes>, part_size: usize) -> Self {
+        PartWriter {
+            sender,
+            part_size,
+            buffer: Vec::with_capacity(part_size),
+        }
+    }
+
+    /// Sends the last, possibly smaller part.
+    pub fn finish(mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            let part = std::mem::take(&mut self.buffer);
+
+            self.send(part)?;
+        }
+
+        Ok(())
+    }
+
+    fn send(&self, part: Vec<u8>) -> io::Result<()> {
+        self.sender
+            .blocking_send(Bytes::from(part))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "upload stopped"))
+    }
+}
+
+impl Write for PartWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let length = usize::min(self.part_size - self.buffer.len(), buf.len());
+
+        self.buffer.extend_from_slice(&buf[..length]);
+
+        if self.buffer.len() == self.part_size {
+            let part = std::mem::replace(&mut self.buffer, Vec::with_capacity(self.part_size));
+
+            self.send(part)?;
+        }
+
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn part_writer_1() {
+        let (sender, mut receiver) = mpsc::channel(10);
+        let mut writer = PartWriter::new(sender, 4);
+
+        writer.write_all(b"0123456789").unwrap();
+        writer.finish().unwrap();
+
+        assert_eq!(&receiver.try_recv().unwrap()[..], b"0123");
+        assert_eq!(&receiver.try_recv().unwrap()[..], b"4567");
+        assert_eq!(&receiver.try_recv().unwrap()[..], b"89");
+        assert!(receiver.try_recv().is_err());
+    }
+}
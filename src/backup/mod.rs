@@ -0,0 +1,1167 @@
+pub mod backup_reader;
+pub mod backup_segment;
+pub mod backup_writer;
+
+use crate::aws::{
+    aws_archive::{ArchiveMetadata, AwsArchive, METADATA_VERSION},
+    aws_glacier::AwsGlacier,
+    aws_tree_hash::{combine_tree_hashes, TreeHasher},
+    aws_vault::AwsVault,
+};
+use crate::catalog::{CatalogEntry, CatalogEntryType};
+use crate::config::BackupSetConfig;
+use crate::crypto::crypto_key::{DataKey, KeyRing};
+use crate::crypto::{crypto_stream::EncryptionHeader, EncryptionAlgorithm, Secret};
+use crate::dedup::{self, Snapshot};
+use crate::repo::Repository;
+use crate::shutdown;
+use anyhow::Result;
+use backup_segment::BackupFrame;
+use backup_writer::{CountingWriter, EncryptionWriter, FrameWriter, PartWriter};
+use chrono::{DateTime, FixedOffset, SubsecRound, Utc};
+use data_encoding::HEXLOWER;
+use hyper::body::Bytes;
+use log::{debug, error, info, warn};
+use ring::digest;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use tokio::sync::mpsc::{self, Receiver};
+use tokio_postgres::{Row, Transaction};
+
+const MIN_PART_SIZE: usize = 1024 * 1024;
+const MAX_PART_SIZE: usize = 4 * 1024 * 1024 * 1024;
+/// Size of the uncompressed data in a compression frame.
+const FRAME_SIZE: u64 = 4 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackupCompression {
+    Zstd,
+    Gzip,
+}
+
+impl BackupCompression {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BackupCompression::Zstd => "zstd",
+            BackupCompression::Gzip => "gzip",
+        }
+    }
+}
+
+impl FromStr for BackupCompression {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "zstd" => Ok(BackupCompression::Zstd),
+            "gzip" => Ok(BackupCompression::Gzip),
+            _ => Err(anyhow::Error::msg(format!(
+                "unknown compression \"{}\"",
+                value
+            ))),
+        }
+    }
+}
+
+pub struct BackupEncryption {
+    pub secret: Secret,
+    pub algorithm: EncryptionAlgorithm,
+    /// Ids of the keys the data key is wrapped for, which are recorded in the archive metadata.
+    pub key_ids: Vec<String>,
+}
+
+/// The previous backup of a backup set, which an incremental backup is based on.
+pub struct BaseBackup {
+    pub archive_id: String,
+    /// Number of incremental backups since the last full backup.
+    pub chain_length: i32,
+    pub catalog: Vec<CatalogEntry>,
+}
+
+pub struct BackupOptions {
+    pub compression: BackupCompression,
+    /// Size of the upload parts, which must be a megabyte multiplied by a power of two.
+    pub part_size: usize,
+    pub backup_set: Option<String>,
+    pub encryption: Option<BackupEncryption>,
+    /// Files unchanged since this backup are not uploaded again.
+    pub base: Option<BaseBackup>,
+}
+
+#[derive(Debug)]
+pub struct BackupSource {
+    pub path: String,
+    pub size: i64,
+}
+
+#[derive(Debug)]
+pub struct Backup {
+    pub archive_id: String,
+    pub archive_description: String,
+    pub vault_arn: String,
+    pub creation_date: DateTime<FixedOffset>,
+    pub compression: BackupCompression,
+    pub encryption: Option<EncryptionAlgorithm>,
+    pub size: i64,
+    pub tree_hash: String,
+    pub sources: Vec<BackupSource>,
+    pub backup_set: Option<String>,
+    /// The backup an incremental backup is based on.
+    pub parent_archive_id: Option<String>,
+    pub chain_length: i32,
+    /// The catalog lists all entries as of the backup, including those an incremental backup took from earlier backups.
+    pub catalog: Vec<CatalogEntry>,
+    /// Paths of the base backup, which no longer exist.
+    pub deletions: Vec<String>,
+    pub frames: Vec<BackupFrame>,
+    /// Header of the encrypted stream, which is needed to decrypt a part of the archive.
+    pub encryption_header: Option<Vec<u8>>,
+}
+
+impl Backup {
+    /// Returns the total size of the source files before compression.
+    pub fn source_size(&self) -> i64 {
+        self.sources.iter().map(|s| s.size).sum()
+    }
+
+    pub fn archive(&self) -> AwsArchive {
+        AwsArchive {
+            archive_id: self.archive_id.clone(),
+            archive_description: self.archive_description.clone(),
+            creation_date: self.creation_date,
+            size: self.size,
+            tree_hash: self.tree_hash.clone(),
+        }
+    }
+}
+
+/// A multipart upload of a backup interrupted by a shutdown, which the next backup of the same paths continues.
+///
+/// The archive has to be created the same way again, so its description, encryption header and data key are kept.
+#[derive(Debug)]
+pub struct PendingUpload {
+    pub upload_id: String,
+    pub vault_arn: String,
+    pub backup_set: Option<String>,
+    pub paths: Vec<String>,
+    pub archive_description: String,
+    pub compression: BackupCompression,
+    pub part_size: usize,
+    pub parent_archive_id: Option<String>,
+    pub encryption_header: Option<Vec<u8>>,
+    /// The data key wrapped for every key by key id, if keys are registered.
+    pub wrapped_keys: Vec<(String, Vec<u8>)>,
+    /// Number of bytes uploaded in the parts with the tree hashes.
+    pub size: u64,
+    pub tree_hashes: Vec<String>,
+}
+
+impl TryFrom<&Row> for PendingUpload {
+    type Error = anyhow::Error;
+
+    fn try_from(row: &Row) -> Result<Self> {
+        let key_ids: Vec<String> = row.try_get("key_ids")?;
+        let wrapped_keys: Vec<Vec<u8>> = row.try_get("wrapped_keys")?;
+
+        Ok(PendingUpload {
+            upload_id: row.try_get("upload_id")?,
+            vault_arn: row.try_get("vault_arn")?,
+            backup_set: row.try_get("backup_set")?,
+            paths: row.try_get("paths")?,
+            archive_description: row.try_get("archive_description")?,
+            compression: row.try_get::<_, &str>("compression")?.parse()?,
+            part_size: row.try_get::<_, i64>("part_size")? as usize,
+            parent_archive_id: row.try_get("parent_archive_id")?,
+            encryption_header: row.try_get("encryption_header")?,
+            wrapped_keys: key_ids.into_iter().zip(wrapped_keys).collect(),
+            size: row.try_get::<_, i64>("size")? as u64,
+            tree_hashes: row.try_get("tree_hashes")?,
+        })
+    }
+}
+
+/// The outcome of streaming a backup into a multipart upload.
+pub enum BackupUpload {
+    Completed(Backup),
+    /// The upload is left open to be continued later.
+    Interrupted(PendingUpload),
+}
+
+/// Streams the paths through tar, the compression and the encryption into a multipart upload to the vault.
+///
+/// Nothing is written to disk. A shutdown interrupts the upload after the current part.
+/// Given an upload interrupted before, the archive is created again and only the parts not uploaded yet are uploaded; the other parts must match the ones uploaded.
+pub async fn backup_paths(
+    aws_glacier: &AwsGlacier,
+    vault: &AwsVault,
+    sources: &[PathBuf],
+    options: BackupOptions,
+    pending: Option<PendingUpload>,
+) -> Result<BackupUpload> {
+    let BackupOptions {
+        compression,
+        part_size,
+        backup_set,
+        encryption,
+        base,
+    } = options;
+    let encryption_algorithm = encryption.as_ref().map(|e| e.algorithm);
+    let key_ids = encryption
+        .as_ref()
+        .map(|e| e.key_ids.clone())
+        .unwrap_or_default();
+
+    if !part_size.is_power_of_two() || !(MIN_PART_SIZE..=MAX_PART_SIZE).contains(&part_size) {
+        return Err(anyhow::Error::msg(format!(
+            "invalid part size {}",
+            part_size
+        )));
+    }
+
+    let paths: Vec<String> = sources.iter().map(|s| s.to_string_lossy().into()).collect();
+    let encryption = match encryption {
+        Some(encryption) => {
+            let header = match pending
+                .as_ref()
+                .and_then(|p| p.encryption_header.as_deref())
+            {
+                Some(header) => EncryptionHeader::from_bytes(header)?,
+                None => EncryptionHeader::new(encryption.algorithm, &encryption.secret)?,
+            };
+
+            Some((encryption.secret, header))
+        }
+        None => None,
+    };
+    let encryption_header = encryption.as_ref().map(|(_, header)| header.to_bytes());
+    let (upload_id, archive_description, uploaded) = match pending {
+        Some(pending) => {
+            info!(
+                "continuing multipart upload \"{}\" after {} bytes",
+                pending.upload_id, pending.size
+            );
+            (
+                pending.upload_id,
+                pending.archive_description,
+                pending.tree_hashes,
+            )
+        }
+        None => {
+            let archive_description = ArchiveMetadata {
+                version: METADATA_VERSION,
+                host: host_name(),
+                backup_set: backup_set.clone(),
+                paths: paths.clone(),
+                paths_truncated: false,
+                timestamp: Utc::now(),
+                encryption: encryption_algorithm.map(|a| a.as_str().into()),
+                key_ids,
+                content_hash: None,
+            }
+            .render()?;
+            let upload_id = aws_glacier
+                .initiate_multipart_upload(vault, &archive_description, part_size)
+                .await?;
+            debug!("started multipart upload \"{}\"", upload_id);
+
+            (upload_id, archive_description, Vec::new())
+        }
+    };
+
+    let parent_archive_id = base.as_ref().map(|b| b.archive_id.clone());
+    let chain_length = base.as_ref().map(|b| b.chain_length + 1).unwrap_or(0);
+    let (sender, mut receiver) = mpsc::channel(2);
+    let writer_sources = sources.to_vec();
+    let writer = tokio::task::spawn_blocking(move || {
+        write_archive(
+            &writer_sources,
+            compression,
+            encryption,
+            base.as_ref(),
+            PartWriter::new(sender, part_size),
+        )
+    });
+    let upload = upload_parts(
+        aws_glacier,
+        vault,
+        &upload_id,
+        &mut receiver,
+        &uploaded,
+        true,
+    )
+    .await;
+
+    // Closing the receiver stops the writer if the upload failed or was interrupted.
+    drop(receiver);
+
+    let result = match (upload, writer.await) {
+        (Ok(parts), _) if parts.interrupted => {
+            return Ok(BackupUpload::Interrupted(PendingUpload {
+                upload_id,
+                vault_arn: vault.vault_arn.clone(),
+                backup_set,
+                paths,
+                archive_description,
+                compression,
+                part_size,
+                parent_archive_id,
+                encryption_header,
+                wrapped_keys: Vec::new(),
+                size: parts.size,
+                tree_hashes: parts.tree_hashes,
+            }));
+        }
+        (Ok(parts), Ok(Ok(index))) => complete_upload(
+            aws_glacier,
+            vault,
+            &upload_id,
+            parts.size,
+            &parts.tree_hashes,
+        )
+        .await
+        .map(|(archive_id, tree_hash)| Backup {
+            archive_id,
+            archive_description,
+            vault_arn: vault.vault_arn.clone(),
+            creation_date: Utc::now().into(),
+            compression,
+            encryption: encryption_algorithm,
+            size: parts.size as i64,
+            tree_hash,
+            sources: index.sources,
+            backup_set,
+            parent_archive_id,
+            chain_length,
+            catalog: index.catalog,
+            deletions: index.deletions,
+            frames: index.frames,
+            encryption_header: index.encryption_header,
+        }),
+        (Err(e), _) => Err(e),
+        (_, Ok(Err(e))) => Err(e),
+        (_, Err(e)) => Err(e.into()),
+    };
+
+    if result.is_err() {
+        abort_upload(aws_glacier, vault, &upload_id).await;
+    }
+
+    result.map(BackupUpload::Completed)
+}
+
+/// Aborts a multipart upload. A failure is only logged, so it does not hide the error the upload is aborted for.
+async fn abort_upload(aws_glacier: &AwsGlacier, vault: &AwsVault, upload_id: &str) {
+    warn!("aborting multipart upload \"{}\"", upload_id);
+
+    if let Err(e) = aws_glacier.abort_multipart_upload(vault, upload_id).await {
+        error!("error aborting multipart upload \"{}\": {:?}", upload_id, e);
+    }
+}
+
+/// The outcome of a backup, which is an archive or, for deduplicated backups, a snapshot.
+pub enum StoredBackup {
+    Archive(Backup),
+    Snapshot(Snapshot),
+}
+
+/// Runs a backup with the vault, paths and options of a backup set and records it.
+pub async fn run_backup(
+    aws_glacier: &AwsGlacier,
+    repo: &mut Repository,
+    backup_set: Option<&str>,
+    settings: &BackupSetConfig,
+    secrets: &[Secret],
+) -> Result<StoredBackup> {
+    let vault_name = settings
+        .vault_name
+        .as_deref()
+        .ok_or_else(|| anyhow::Error::msg("no vault given for the backup"))?;
+
+    if settings.paths.is_empty() {
+        return Err(anyhow::Error::msg("no paths to back up given"));
+    }
+
+    let mut options = BackupOptions {
+        compression: settings.compression.as_deref().unwrap_or("zstd").parse()?,
+        part_size: settings.part_size.unwrap_or(16) * 1024 * 1024,
+        backup_set: backup_set.map(String::from),
+        encryption: None,
+        base: None,
+    };
+    let algorithm = settings
+        .encryption
+        .as_deref()
+        .unwrap_or("aes-256-gcm")
+        .parse()?;
+
+    if settings.dedup {
+        if settings.incremental {
+            return Err(anyhow::Error::msg(
+                "incremental and deduplicated backups cannot be combined",
+            ));
+        }
+
+        return Ok(StoredBackup::Snapshot(
+            dedup::store_snapshot(
+                aws_glacier,
+                repo,
+                vault_name,
+                &settings.paths,
+                options,
+                secrets.to_vec(),
+                algorithm,
+            )
+            .await?,
+        ));
+    }
+
+    if settings.incremental {
+        let backup_set = backup_set
+            .ok_or_else(|| anyhow::Error::msg("incremental backups need a backup set"))?;
+        let trans = repo.get_transaction().await?;
+
+        options.base = base_backup(&trans, backup_set, settings.full_every.unwrap_or(7)).await?;
+        trans.commit().await?;
+    }
+
+    Ok(StoredBackup::Archive(
+        store_backup(
+            aws_glacier,
+            repo,
+            vault_name,
+            &settings.paths,
+            options,
+            secrets.to_vec(),
+            algorithm,
+        )
+        .await?,
+    ))
+}
+
+/// Uploads a backup of the paths and records it with its catalog.
+///
+/// With registered keys the archive gets its own data key. Otherwise it is encrypted with the key file or passphrase directly.
+/// An upload interrupted by a shutdown is recorded and continued by the next backup of the paths.
+pub async fn store_backup(
+    aws_glacier: &AwsGlacier,
+    repo: &mut Repository,
+    vault_name: &str,
+    paths: &[String],
+    mut options: BackupOptions,
+    mut secrets: Vec<Secret>,
+    algorithm: EncryptionAlgorithm,
+) -> Result<Backup> {
+    let trans = repo.get_transaction().await?;
+    let recipients = Repository::get_keys(&trans).await?;
+
+    trans.commit().await?;
+
+    let key_ring = if recipients.is_empty() {
+        None
+    } else {
+        let key_ring = KeyRing::new(&recipients, &secrets)?;
+
+        for key_id in key_ring.locked_active_key_ids() {
+            warn!(
+                "key \"{}\" is not available, the data key must be rewrapped for it later",
+                key_id
+            );
+        }
+
+        Some(key_ring)
+    };
+    let vault = aws_glacier.describe_vault(vault_name).await?;
+    let mut sources = Vec::with_capacity(paths.len());
+
+    for path in paths {
+        sources.push(fs::canonicalize(path)?);
+    }
+
+    let (pending, data_key) = match continuable_upload(
+        aws_glacier,
+        repo,
+        &vault,
+        &sources,
+        &options,
+        algorithm,
+        key_ring.as_ref(),
+        !secrets.is_empty(),
+    )
+    .await?
+    {
+        Some((pending, data_key)) => (Some(pending), data_key),
+        None => match &key_ring {
+            Some(key_ring) => (None, Some(key_ring.new_data_key()?)),
+            None => {
+                if secrets.is_empty() {
+                    warn!("no key or passphrase configured, the archive is not encrypted");
+                }
+
+                (None, None)
+            }
+        },
+    };
+
+    options.encryption = match &data_key {
+        Some(data_key) => Some(BackupEncryption {
+            secret: data_key.secret(),
+            algorithm,
+            key_ids: data_key.key_ids(),
+        }),
+        None => secrets.pop().map(|secret| BackupEncryption {
+            secret,
+            algorithm,
+            key_ids: Vec::new(),
+        }),
+    };
+
+    let backup = match backup_paths(aws_glacier, &vault, &sources, options, pending).await? {
+        BackupUpload::Completed(backup) => backup,
+        BackupUpload::Interrupted(mut pending) => {
+            if let Some(data_key) = &data_key {
+                pending.wrapped_keys = data_key.wrapped_keys().to_vec();
+            }
+
+            let trans = repo.get_transaction().await?;
+
+            Repository::upsert_vault(&trans, &vault).await?;
+            Repository::create_pending_upload(&trans, &pending).await?;
+            trans.commit().await?;
+            info!(
+                "interrupted multipart upload \"{}\" after {} bytes, the next backup of the paths continues it",
+                pending.upload_id, pending.size
+            );
+
+            return Err(anyhow::Error::msg("backup interrupted by the shutdown"));
+        }
+    };
+    let trans = repo.get_transaction().await?;
+
+    Repository::upsert_vault(&trans, &vault).await?;
+    Repository::upsert_archive(&trans, &backup.archive()).await?;
+    Repository::create_archive_associations(&trans, &vault, &[backup.archive()]).await?;
+    Repository::create_backup(&trans, &backup).await?;
+    Repository::create_catalog_entries(&trans, &backup.archive_id, &backup.catalog).await?;
+
+    if let Some(data_key) = data_key {
+        Repository::upsert_archive_keys(&trans, &data_key.archive_keys(&backup.archive_id)).await?;
+    }
+
+    trans.commit().await?;
+    info!(
+        "uploaded archive \"{}\" ({} bytes, tree hash {})",
+        backup.archive_id, backup.size, backup.tree_hash
+    );
+
+    Ok(backup)
+}
+
+/// Takes the upload of the paths interrupted before and returns it with its data key, if the backup can continue it.
+///
+/// An upload started with other options or encryption is aborted.
+#[allow(clippy::too_many_arguments)]
+async fn continuable_upload(
+    aws_glacier: &AwsGlacier,
+    repo: &mut Repository,
+    vault: &AwsVault,
+    sources: &[PathBuf],
+    options: &BackupOptions,
+    algorithm: EncryptionAlgorithm,
+    key_ring: Option<&KeyRing>,
+    secret: bool,
+) -> Result<Option<(PendingUpload, Option<DataKey>)>> {
+    let paths: Vec<String> = sources.iter().map(|s| s.to_string_lossy().into()).collect();
+    let trans = repo.get_transaction().await?;
+    let pending = Repository::take_pending_upload(
+        &trans,
+        &vault.vault_arn,
+        options.backup_set.as_deref(),
+        &paths,
+    )
+    .await?;
+
+    trans.commit().await?;
+
+    let pending = match pending {
+        Some(pending) => pending,
+        None => return Ok(None),
+    };
+    let header = match &pending.encryption_header {
+        Some(header) => Some(EncryptionHeader::from_bytes(header)?),
+        None => None,
+    };
+    let data_key = if pending.compression != options.compression
+        || pending.part_size != options.part_size
+        || pending.parent_archive_id.as_deref() != options.base.as_ref().map(|b| &*b.archive_id)
+    {
+        Err(anyhow::Error::msg("it was started with other options"))
+    } else {
+        match (header, key_ring) {
+            (None, None) if !secret => Ok(None),
+            (Some(header), None)
+                if header.algorithm == algorithm && secret && pending.wrapped_keys.is_empty() =>
+            {
+                Ok(None)
+            }
+            (Some(header), Some(key_ring))
+                if header.algorithm == algorithm && !pending.wrapped_keys.is_empty() =>
+            {
+                key_ring.data_key(&pending.wrapped_keys).map(Some)
+            }
+            _ => Err(anyhow::Error::msg("it was started with another encryption")),
+        }
+    };
+
+    match data_key {
+        Ok(data_key) => Ok(Some((pending, data_key))),
+        Err(e) => {
+            warn!(
+                "multipart upload \"{}\" cannot be continued: {}",
+                pending.upload_id, e
+            );
+            abort_upload(aws_glacier, vault, &pending.upload_id).await;
+
+            Ok(None)
+        }
+    }
+}
+
+/// Returns the backup of the set an incremental backup is based on, or `None` if a full backup is due.
+///
+/// A full backup is due if the set has no backup yet or after `full_every` incremental backups.
+pub async fn base_backup(
+    transaction: &Transaction<'_>,
+    backup_set: &str,
+    full_every: i32,
+) -> Result<Option<BaseBackup>> {
+    let (archive_id, chain_length) =
+        match Repository::get_latest_backup(transaction, backup_set).await? {
+            Some(latest) => latest,
+            None => {
+                info!(
+                    "backup set \"{}\" has no backup yet, creating a full backup",
+                    backup_set
+                );
+                return Ok(None);
+            }
+        };
+
+    if chain_length >= full_every {
+        info!(
+            "{} incremental backups since the last full backup, creating a full backup",
+            chain_length
+        );
+        return Ok(None);
+    }
+
+    Ok(Some(BaseBackup {
+        catalog: Repository::get_catalog_entries(transaction, &archive_id).await?,
+        archive_id,
+        chain_length,
+    }))
+}
+
+/// Uploads data held in memory as archive in parts and returns the archive id and the tree hash of the archive.
+pub async fn upload_archive(
+    aws_glacier: &AwsGlacier,
+    vault: &AwsVault,
+    archive_description: &str,
+    data: Bytes,
+    part_size: usize,
+) -> Result<(String, String)> {
+    let upload_id = aws_glacier
+        .initiate_multipart_upload(vault, archive_description, part_size)
+        .await?;
+    let (sender, mut receiver) = mpsc::channel(data.len() / part_size + 1);
+    let size = data.len();
+
+    for offset in (0..size).step_by(part_size) {
+        sender
+            .send(data.slice(offset..usize::min(offset + part_size, size)))
+            .await?;
+    }
+
+    drop(sender);
+
+    // the data is at hand, so the upload is finished even if a shutdown is requested
+    let result = match upload_parts(aws_glacier, vault, &upload_id, &mut receiver, &[], false).await
+    {
+        Ok(parts) => {
+            complete_upload(
+                aws_glacier,
+                vault,
+                &upload_id,
+                parts.size,
+                &parts.tree_hashes,
+            )
+            .await
+        }
+        Err(e) => Err(e),
+    };
+
+    if result.is_err() {
+        abort_upload(aws_glacier, vault, &upload_id).await;
+    }
+
+    result
+}
+
+/// The parts uploaded, which are all parts unless the upload was interrupted.
+struct UploadedParts {
+    size: u64,
+    tree_hashes: Vec<String>,
+    interrupted: bool,
+}
+
+/// Uploads the parts received, except for the first ones, which have been uploaded with the given tree hashes before.
+///
+/// If `interruptible`, the upload stops before the next part once a shutdown is requested.
+async fn upload_parts(
+    aws_glacier: &AwsGlacier,
+    vault: &AwsVault,
+    upload_id: &str,
+    receiver: &mut Receiver<Bytes>,
+    uploaded: &[String],
+    interruptible: bool,
+) -> Result<UploadedParts> {
+    let mut offset = 0u64;
+    let mut tree_hashes = Vec::new();
+
+    while let Some(part) = receiver.recv().await {
+        let length = part.len() as u64;
+
+        if let Some(uploaded) = uploaded.get(tree_hashes.len()) {
+            let mut tree_hasher = TreeHasher::new();
+
+            tree_hasher.update(&part);
+
+            let tree_hash = tree_hasher.finish();
+
+            if tree_hash != *uploaded {
+                return Err(anyhow::Error::msg(format!(
+                    "the part at {} bytes differs from the part uploaded before, the files have changed",
+                    offset
+                )));
+            }
+
+            tree_hashes.push(tree_hash);
+            offset += length;
+            continue;
+        }
+
+        if interruptible && shutdown::is_requested() {
+            return Ok(UploadedParts {
+                size: offset,
+                tree_hashes,
+                interrupted: true,
+            });
+        }
+
+        tree_hashes.push(
+            aws_glacier
+                .upload_multipart_part(vault, upload_id, offset, part)
+                .await?,
+        );
+        offset += length;
+        info!("uploaded {} bytes", offset);
+    }
+
+    if tree_hashes.len() < uploaded.len() {
+        return Err(anyhow::Error::msg(
+            "the archive is shorter than the parts uploaded before, the files have changed",
+        ));
+    }
+
+    Ok(UploadedParts {
+        size: offset,
+        tree_hashes,
+        interrupted: false,
+    })
+}
+
+/// Completes the upload and returns the archive id and the tree hash of the archive.
+async fn complete_upload(
+    aws_glacier: &AwsGlacier,
+    vault: &AwsVault,
+    upload_id: &str,
+    size: u64,
+    tree_hashes: &[String],
+) -> Result<(String, String)> {
+    let tree_hash = combine_tree_hashes(tree_hashes)?;
+    let archive_id = aws_glacier
+        .complete_multipart_upload(vault, upload_id, size, &tree_hash)
+        .await?;
+
+    Ok((archive_id, tree_hash))
+}
+
+/// What was written to an archive besides the data itself.
+struct ArchiveIndex {
+    sources: Vec<BackupSource>,
+    catalog: Vec<CatalogEntry>,
+    deletions: Vec<String>,
+    frames: Vec<BackupFrame>,
+    encryption_header: Option<Vec<u8>>,
+}
+
+fn write_archive(
+    sources: &[PathBuf],
+    compression: BackupCompression,
+    encryption: Option<(Secret, EncryptionHeader)>,
+    base: Option<&BaseBackup>,
+    writer: PartWriter,
+) -> Result<ArchiveIndex> {
+    let writer = match encryption {
+        Some((secret, header)) => EncryptionWriter::with_header(writer, header, &secret)?,
+        None => EncryptionWriter::Plain(writer),
+    };
+    let encryption_header = writer.header_bytes();
+    let (backup_sources, catalog, writer) = write_tar(
+        sources,
+        base,
+        FrameWriter::new(compression, FRAME_SIZE, writer)?,
+    )?;
+    let (writer, frames) = writer.finish()?;
+
+    writer.finish()?.finish()?;
+
+    let deletions = match base {
+        Some(base) => deleted_paths(&base.catalog, &catalog),
+        None => Vec::new(),
+    };
+
+    Ok(ArchiveIndex {
+        sources: backup_sources,
+        catalog,
+        deletions,
+        frames,
+        encryption_header,
+    })
+}
+
+/// Writes the sources as tar stream and returns them with their sizes, the catalog and the writer.
+///
+/// Regular files unchanged since the base backup are left out and cataloged with the archive holding their content.
+pub(crate) fn write_tar<W: Write>(
+    sources: &[PathBuf],
+    base: Option<&BaseBackup>,
+    writer: W,
+) -> Result<(Vec<BackupSource>, Vec<CatalogEntry>, W)> {
+    let mut archive_builder = ArchiveBuilder {
+        builder: tar::Builder::new(CountingWriter::new(writer)),
+        catalog: Vec::new(),
+        base: base
+            .map(|base| {
+                base.catalog
+                    .iter()
+                    .map(|e| {
+                        let mut entry = e.clone();
+
+                        entry
+                            .data_archive_id
+                            .get_or_insert_with(|| base.archive_id.clone());
+                        (e.path.clone(), entry)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default(),
+    };
+    let mut backup_sources = Vec::with_capacity(sources.len());
+
+    for source in sources {
+        debug!("adding \"{}\" to archive", source.display());
+        backup_sources.push(BackupSource {
+            path: source.to_string_lossy().into(),
+            size: archive_builder.append_path(source, &archive_path(source))? as i64,
+        });
+    }
+
+    let writer = archive_builder.builder.into_inner()?.into_inner();
+
+    Ok((backup_sources, archive_builder.catalog, writer))
+}
+
+/// Writes the tar stream and records a catalog entry for everything appended.
+struct ArchiveBuilder<W: Write> {
+    builder: tar::Builder<CountingWriter<W>>,
+    catalog: Vec<CatalogEntry>,
+    /// Catalog of the base backup by path.
+    base: HashMap<String, CatalogEntry>,
+}
+
+impl<W: Write> ArchiveBuilder<W> {
+    /// Appends a path recursively and returns the size of the regular files appended.
+    fn append_path(&mut self, path: &Path, name: &Path) -> Result<u64> {
+        let metadata = fs::symlink_metadata(path)?;
+        let mtime = DateTime::<Utc>::from(metadata.modified()?).round_subsecs(6);
+
+        if metadata.is_file() {
+            if let Some(unchanged) = self.base.get(&*path.to_string_lossy()).filter(|e| {
+                e.entry_type == CatalogEntryType::File
+                    && e.size == metadata.len() as i64
+                    && e.mtime == mtime
+                    && e.inode == Some(metadata.ino() as i64)
+            }) {
+                self.catalog.push(unchanged.clone());
+
+                return Ok(metadata.len());
+            }
+        }
+
+        let offset = self.builder.get_ref().count() as i64;
+        let mut header = tar::Header::new_gnu();
+        let mut content_hash = None;
+
+        header.set_metadata(&metadata);
+
+        let entry_type = if metadata.is_dir() {
+            self.builder.append_data(&mut header, name, io::empty())?;
+            CatalogEntryType::Directory
+        } else if metadata.is_file() {
+            let mut reader = HashingReader::new(File::open(path)?);
+
+            self.builder.append_data(&mut header, name, &mut reader)?;
+            content_hash = Some(reader.finish());
+            CatalogEntryType::File
+        } else if metadata.file_type().is_symlink() {
+            self.builder
+                .append_link(&mut header, name, fs::read_link(path)?)?;
+            CatalogEntryType::Symlink
+        } else {
+            self.builder.append_data(&mut header, name, io::empty())?;
+            CatalogEntryType::Other
+        };
+
+        self.catalog.push(CatalogEntry {
+            path: path.to_string_lossy().into(),
+            entry_type,
+            size: if metadata.is_file() {
+                metadata.len() as i64
+            } else {
+                0
+            },
+            mtime,
+            mode: metadata.mode() as i32,
+            content_hash,
+            offset,
+            inode: Some(metadata.ino() as i64),
+            data_archive_id: None,
+        });
+
+        if metadata.is_dir() {
+            let mut entries = fs::read_dir(path)?.collect::<Result<Vec<_>, _>>()?;
+            let mut size = 0;
+
+            entries.sort_by_key(|e| e.file_name());
+
+            for entry in entries {
+                size += self.append_path(&entry.path(), &name.join(entry.file_name()))?;
+            }
+
+            Ok(size)
+        } else if metadata.is_file() {
+            Ok(metadata.len())
+        } else {
+            Ok(0)
+        }
+    }
+}
+
+/// Returns the paths of the base catalog, which are missing from the new one.
+fn deleted_paths(base: &[CatalogEntry], catalog: &[CatalogEntry]) -> Vec<String> {
+    let paths: HashSet<&str> = catalog.iter().map(|e| &*e.path).collect();
+
+    base.iter()
+        .filter(|e| !paths.contains(&*e.path))
+        .map(|e| e.path.clone())
+        .collect()
+}
+
+/// Computes the SHA256 hash of the data read through it.
+struct HashingReader<R: Read> {
+    reader: R,
+    context: digest::Context,
+}
+
+impl<R: Read> HashingReader<R> {
+    fn new(reader: R) -> Self {
+        HashingReader {
+            reader,
+            context: digest::Context::new(&digest::SHA256),
+        }
+    }
+
+    fn finish(self) -> String {
+        HEXLOWER.encode(self.context.finish().as_ref())
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let length = self.reader.read(buf)?;
+
+        self.context.update(&buf[..length]);
+
+        Ok(length)
+    }
+}
+
+pub(crate) fn host_name() -> String {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|h| h.trim().to_string())
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .unwrap_or_else(|_| "unknown".into())
+}
+
+/// Returns the path to use inside the archive, which is the absolute path without the root.
+fn archive_path(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn archive_path_1() {
+        assert_eq!(
+            archive_path(Path::new("/etc/nginx/nginx.conf")),
+            PathBuf::from("etc/nginx/nginx.conf")
+        );
+    }
+
+    #[test]
+    fn write_archive_1() {
+        let dir = std::env::temp_dir().join(format!("backup-{}", std::process::id()));
+        let (sender, mut receiver) = mpsc::channel(100);
+        let mut data = Vec::new();
+        let mut names = Vec::new();
+
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("a.txt"), b"hello").unwrap();
+        fs::write(dir.join("sub/b.txt"), b"world!").unwrap();
+
+        let ArchiveIndex {
+            sources,
+            catalog,
+            frames,
+            ..
+        } = write_archive(
+            std::slice::from_ref(&dir),
+            BackupCompression::Zstd,
+            None,
+            None,
+            PartWriter::new(sender, MIN_PART_SIZE),
+        )
+        .unwrap();
+
+        while let Ok(part) = receiver.try_recv() {
+            data.extend_from_slice(&part);
+        }
+
+        let tar_data = zstd::decode_all(&data[..]).unwrap();
+        let mut archive = tar::Archive::new(&tar_data[..]);
+
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let mut content = String::new();
+
+            entry.read_to_string(&mut content).unwrap();
+            names.push((entry.path().unwrap().into_owned(), content));
+        }
+
+        assert_eq!(sources.len(), 1);
+        assert_eq!(sources[0].size, 11);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(names.len(), 4);
+        assert_eq!(names[1].0, archive_path(&dir.join("a.txt")));
+        assert_eq!(names[1].1, "hello");
+        assert_eq!(names[3].1, "world!");
+        assert_eq!(catalog.len(), 4);
+        assert_eq!(catalog[1].path, dir.join("a.txt").to_string_lossy());
+        assert_eq!(catalog[1].entry_type, CatalogEntryType::File);
+        assert_eq!(
+            catalog[1].content_hash.as_deref(),
+            Some(&*HEXLOWER.encode(digest::digest(&digest::SHA256, b"hello").as_ref()))
+        );
+
+        // the offset points to the tar header of the entry
+        let offset = catalog[3].offset as usize;
+        let header = tar::Header::from_byte_slice(&tar_data[offset..offset + 512]);
+
+        assert_eq!(header.path().unwrap(), archive_path(&dir.join("sub/b.txt")));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn write_archive_2() {
+        let dir = std::env::temp_dir().join(format!("incremental-{}", std::process::id()));
+        let (sender, _receiver) = mpsc::channel(100);
+
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.txt"), b"unchanged").unwrap();
+        fs::write(dir.join("b.txt"), b"changed").unwrap();
+        fs::write(dir.join("c.txt"), b"deleted").unwrap();
+
+        let base = write_archive(
+            std::slice::from_ref(&dir),
+            BackupCompression::Zstd,
+            None,
+            None,
+            PartWriter::new(sender, MIN_PART_SIZE),
+        )
+        .unwrap();
+
+        fs::write(dir.join("b.txt"), b"changed content").unwrap();
+        fs::remove_file(dir.join("c.txt")).unwrap();
+
+        let (sender, mut receiver) = mpsc::channel(100);
+        let mut data = Vec::new();
+        let index = write_archive(
+            std::slice::from_ref(&dir),
+            BackupCompression::Zstd,
+            None,
+            Some(&BaseBackup {
+                archive_id: "base".into(),
+                chain_length: 0,
+                catalog: base.catalog.clone(),
+            }),
+            PartWriter::new(sender, MIN_PART_SIZE),
+        )
+        .unwrap();
+
+        while let Ok(part) = receiver.try_recv() {
+            data.extend_from_slice(&part);
+        }
+
+        let tar_data = zstd::decode_all(&data[..]).unwrap();
+        let names: Vec<PathBuf> = tar::Archive::new(&tar_data[..])
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().into_owned())
+            .collect();
+
+        // the unchanged file is cataloged with the base archive, but not written again
+        assert_eq!(
+            names,
+            vec![archive_path(&dir), archive_path(&dir.join("b.txt"))]
+        );
+        assert_eq!(index.catalog.len(), 3);
+        assert_eq!(index.catalog[1].data_archive_id.as_deref(), Some("base"));
+        assert_eq!(index.catalog[1].offset, base.catalog[1].offset);
+        assert_eq!(index.catalog[2].data_archive_id, None);
+        assert_eq!(
+            index.deletions,
+            vec![dir.join("c.txt").to_string_lossy().to_string()]
+        );
+        assert_eq!(index.sources[0].size, 24);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,100 @@
+extern crate backup_remote_rs;
+use anyhow::Result;
+use backup_remote_rs::api::{self, ApiState, ApiToken};
+use backup_remote_rs::config::{required, Config, CONFIG_ENV};
+use backup_remote_rs::logging;
+use backup_remote_rs::shutdown;
+extern crate clap;
+use clap::{App, Arg};
+use std::collections::BTreeMap;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    logging::init()?;
+
+    // Process arguments
+    let matches = App::new("backup-remote-api")
+        .version(env!("CARGO_PKG_VERSION"))
+        .author(env!("CARGO_PKG_AUTHORS"))
+        .about(env!("CARGO_PKG_DESCRIPTION"))
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .env(CONFIG_ENV)
+                .help("configuration file; defaults to the first of ./backup-remote.toml, ~/.config/backup-remote/config.toml and /etc/backup-remote/config.toml")
+                .takes_value(true),
+        )
+        .arg(Arg::with_name("db_connection").env("DB_CONNECTION"))
+        .arg(
+            Arg::with_name("listen")
+                .long("listen")
+                .env("API_LISTEN")
+                .help("address to listen on (default 127.0.0.1:8080)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("token")
+                .long("token")
+                .env("API_TOKEN")
+                .hide_env_values(true)
+                .help("bearer token accepted in addition to the tokens of the configuration, named \"default\", which may request jobs for all vaults")
+                .takes_value(true),
+        )
+        .get_matches();
+
+    let config = Config::load(matches.value_of("config"))?;
+    let db_connection = required(
+        matches.value_of("db_connection"),
+        config.db_connection()?,
+        "db_connection",
+    )?;
+    let address = matches
+        .value_of("listen")
+        .or(config.api.listen.as_deref())
+        .unwrap_or(api::DEFAULT_LISTEN)
+        .parse()?;
+    let mut tokens = BTreeMap::new();
+
+    for (name, token) in &config.api.tokens {
+        tokens.insert(
+            name.clone(),
+            ApiToken {
+                token: token.token(name)?,
+                vaults: token.vaults.clone(),
+            },
+        );
+    }
+
+    // the token of the command line may request jobs for all vaults
+    if let Some(token) = matches.value_of("token") {
+        if token.is_empty() {
+            return Err(anyhow::Error::msg(
+                "the API token given as argument or environment variable is empty",
+            ));
+        }
+
+        tokens.insert(
+            "default".into(),
+            ApiToken {
+                token: token.into(),
+                vaults: vec!["*".into()],
+            },
+        );
+    }
+
+    if tokens.is_empty() {
+        return Err(anyhow::Error::msg(
+            "no API token given as argument, environment variable or in the configuration",
+        ));
+    }
+
+    shutdown::listen()?;
+    api::serve(
+        address,
+        ApiState {
+            db_connection,
+            tokens,
+        },
+    )
+    .await
+}
@@ -0,0 +1,374 @@
+extern crate backup_remote_rs;
+use anyhow::Result;
+use backup_remote_rs::aws::{
+    aws_glacier::AwsGlacier, aws_inventory::AwsInventoryRetrievalParameters,
+};
+use backup_remote_rs::config::{required, Config, CONFIG_ENV};
+use backup_remote_rs::crypto::load_secrets;
+use backup_remote_rs::daemon::{self, daemon_tasks, Schedule, Task};
+use backup_remote_rs::health;
+use backup_remote_rs::logging;
+use backup_remote_rs::metrics::metrics_server;
+use backup_remote_rs::shutdown;
+extern crate clap;
+use clap::{App, Arg};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    logging::init()?;
+
+    // Process arguments
+    let matches = App::new("backup-remote-daemon")
+        .version(env!("CARGO_PKG_VERSION"))
+        .author(env!("CARGO_PKG_AUTHORS"))
+        .about(env!("CARGO_PKG_DESCRIPTION"))
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .env(CONFIG_ENV)
+                .help("configuration file; defaults to the first of ./backup-remote.toml, ~/.config/backup-remote/config.toml and /etc/backup-remote/config.toml")
+                .takes_value(true),
+        )
+        .arg(Arg::with_name("secret_key").env("AWS_SECRET_KEY"))
+        .arg(Arg::with_name("key_id").env("AWS_KEY_ID"))
+        .arg(Arg::with_name("region").env("AWS_REGION"))
+        .arg(Arg::with_name("db_connection").env("DB_CONNECTION"))
+        .arg(Arg::with_name("inventory_dir").env("INVENTORY_DIR"))
+        .arg(
+            Arg::with_name("key_file")
+                .long("key_file")
+                .env("ENCRYPTION_KEY_FILE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("passphrase")
+                .long("passphrase")
+                .env("ENCRYPTION_PASSPHRASE")
+                .hide_env_values(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("inventory_format")
+                .long("inventory_format")
+                .env("INVENTORY_FORMAT")
+                .takes_value(true)
+                .possible_values(&["JSON", "CSV"]),
+        )
+        .arg(
+            Arg::with_name("incremental_inventory")
+                .long("incremental_inventory")
+                .env("INCREMENTAL_INVENTORY")
+                .takes_value(true)
+                .possible_values(&["true", "false"]),
+        )
+        .arg(
+            Arg::with_name("inventory_limit")
+                .long("inventory_limit")
+                .env("INVENTORY_LIMIT")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("shutdown_timeout")
+                .long("shutdown_timeout")
+                .env("SHUTDOWN_TIMEOUT")
+                .help("seconds in-flight work is given to finish after SIGTERM or SIGINT (default 60)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("metrics_listen")
+                .long("metrics_listen")
+                .env("METRICS_LISTEN")
+                .help("address to serve Prometheus metrics and health checks on, e.g. 127.0.0.1:9090 (default none)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("health_exit_after")
+                .long("health_exit_after")
+                .env("HEALTH_EXIT_AFTER")
+                .help("minutes, within which every task has to succeed, or the process exits (default never)")
+                .takes_value(true),
+        )
+        .get_matches();
+
+    let config = Config::load(matches.value_of("config"))?;
+    let secret_key = required(
+        matches.value_of("secret_key"),
+        config.secret_key()?,
+        "secret_key",
+    )?;
+    let key_id = required(
+        matches.value_of("key_id"),
+        config.aws.key_id.clone(),
+        "key_id",
+    )?;
+    let region = required(
+        matches.value_of("region"),
+        config.aws.region.clone(),
+        "region",
+    )?;
+    let db_connection = Arc::new(required(
+        matches.value_of("db_connection"),
+        config.db_connection()?,
+        "db_connection",
+    )?);
+    let inventory_dir = Arc::new(required(
+        matches.value_of("inventory_dir"),
+        config.worker.inventory_dir.clone(),
+        "inventory_dir",
+    )?);
+    let secrets = Arc::new(load_secrets(
+        matches
+            .value_of("key_file")
+            .or(config.encryption.key_file.as_deref()),
+        match matches.value_of("passphrase") {
+            Some(passphrase) => Some(passphrase.into()),
+            None => config.passphrase()?,
+        }
+        .as_deref(),
+    )?);
+    let inventory_parameters = Arc::new(AwsInventoryRetrievalParameters {
+        format: Some(
+            matches
+                .value_of("inventory_format")
+                .or(config.updater.inventory_format.as_deref())
+                .unwrap_or("JSON")
+                .parse()?,
+        ),
+        limit: match matches.value_of("inventory_limit") {
+            Some(limit) => Some(limit.parse()?),
+            None => config.updater.inventory_limit,
+        },
+        ..Default::default()
+    });
+    let incremental_inventory = match matches.value_of("incremental_inventory") {
+        Some(incremental_inventory) => incremental_inventory == "true",
+        None => config.updater.incremental_inventory.unwrap_or(false),
+    };
+    let shutdown_timeout = Duration::from_secs(match matches.value_of("shutdown_timeout") {
+        Some(shutdown_timeout) => shutdown_timeout.parse()?,
+        None => config
+            .shutdown_timeout_seconds
+            .unwrap_or(shutdown::DEFAULT_TIMEOUT_SECONDS),
+    });
+    let mut aws_glacier = AwsGlacier::new(&secret_key, &key_id, &region);
+
+    if let Some(endpoint) = &config.aws.endpoint {
+        aws_glacier = aws_glacier.with_endpoint(endpoint);
+    }
+
+    for (name, task) in &config.daemon {
+        match &**name {
+            "vault_sync" | "job_sync" | "job_requests" | "inventory_import" | "retrievals" => {}
+            "backups" if task.cron.is_none() && task.interval_minutes.is_none() => {}
+            "backups" => {
+                return Err(anyhow::Error::msg(
+                    "backups are scheduled per backup set, not in the \"backups\" task",
+                ))
+            }
+            name => return Err(anyhow::Error::msg(format!("unknown task \"{}\"", name))),
+        }
+    }
+
+    let aws_glacier = Arc::new(aws_glacier);
+    let mut tasks = Vec::new();
+
+    tasks.extend(Task::from_config(
+        "vault_sync",
+        config.daemon.get("vault_sync"),
+        config.updater.interval_minutes.unwrap_or(60),
+        {
+            let aws_glacier = aws_glacier.clone();
+            let db_connection = db_connection.clone();
+
+            Arc::new(move || {
+                let aws_glacier = aws_glacier.clone();
+                let db_connection = db_connection.clone();
+
+                Box::pin(
+                    async move { daemon_tasks::sync_vaults(&aws_glacier, &db_connection).await },
+                )
+            })
+        },
+    )?);
+    tasks.extend(Task::from_config(
+        "job_sync",
+        config.daemon.get("job_sync"),
+        config.updater.interval_minutes.unwrap_or(60),
+        {
+            let aws_glacier = aws_glacier.clone();
+            let db_connection = db_connection.clone();
+
+            Arc::new(move || {
+                let aws_glacier = aws_glacier.clone();
+                let db_connection = db_connection.clone();
+                let inventory_parameters = inventory_parameters.clone();
+
+                Box::pin(async move {
+                    daemon_tasks::sync_jobs(
+                        &aws_glacier,
+                        &db_connection,
+                        &inventory_parameters,
+                        incremental_inventory,
+                    )
+                    .await
+                })
+            })
+        },
+    )?);
+    tasks.extend(Task::from_config(
+        "job_requests",
+        config.daemon.get("job_requests"),
+        5,
+        {
+            let aws_glacier = aws_glacier.clone();
+            let db_connection = db_connection.clone();
+
+            Arc::new(move || {
+                let aws_glacier = aws_glacier.clone();
+                let db_connection = db_connection.clone();
+
+                Box::pin(async move {
+                    daemon_tasks::process_job_requests(&aws_glacier, &db_connection).await
+                })
+            })
+        },
+    )?);
+    tasks.extend(Task::from_config(
+        "inventory_import",
+        config.daemon.get("inventory_import"),
+        config.worker.interval_minutes.unwrap_or(30),
+        {
+            let aws_glacier = aws_glacier.clone();
+            let db_connection = db_connection.clone();
+
+            Arc::new(move || {
+                let aws_glacier = aws_glacier.clone();
+                let db_connection = db_connection.clone();
+                let inventory_dir = inventory_dir.clone();
+
+                Box::pin(async move {
+                    daemon_tasks::import_inventories(&aws_glacier, &db_connection, &inventory_dir)
+                        .await
+                })
+            })
+        },
+    )?);
+    tasks.extend(Task::from_config(
+        "retrievals",
+        config.daemon.get("retrievals"),
+        config.worker.interval_minutes.unwrap_or(30),
+        {
+            let aws_glacier = aws_glacier.clone();
+            let db_connection = db_connection.clone();
+            let secrets = secrets.clone();
+
+            Arc::new(move || {
+                let aws_glacier = aws_glacier.clone();
+                let db_connection = db_connection.clone();
+                let secrets = secrets.clone();
+
+                Box::pin(async move {
+                    daemon_tasks::process_restores(&aws_glacier, &db_connection, &secrets).await
+                })
+            })
+        },
+    )?);
+
+    // every backup set with a schedule is a task of its own, with the backoff of the "backups" task
+    for (name, settings) in &config.backup_sets {
+        let schedule = match (&settings.cron, settings.interval_minutes) {
+            (Some(cron), None) => Schedule::Cron(cron.parse()?),
+            (None, Some(0)) => {
+                return Err(anyhow::Error::msg(format!(
+                    "the interval of backup set \"{}\" must be at least 1 minute",
+                    name
+                )))
+            }
+            (None, Some(interval)) => {
+                Schedule::Interval(chrono::Duration::minutes(interval as i64))
+            }
+            (None, None) => continue,
+            (Some(_), Some(_)) => {
+                return Err(anyhow::Error::msg(format!(
+                    "backup set \"{}\" has both a cron expression and an interval",
+                    name
+                )))
+            }
+        };
+        let run = {
+            let aws_glacier = aws_glacier.clone();
+            let db_connection = db_connection.clone();
+            let secrets = secrets.clone();
+            let name = Arc::new(name.clone());
+            let settings = Arc::new(settings.clone());
+
+            Arc::new(move || {
+                let aws_glacier = aws_glacier.clone();
+                let db_connection = db_connection.clone();
+                let secrets = secrets.clone();
+                let name = name.clone();
+                let settings = settings.clone();
+
+                Box::pin(async move {
+                    daemon_tasks::run_backup_set(
+                        &aws_glacier,
+                        &db_connection,
+                        &name,
+                        &settings,
+                        &secrets,
+                    )
+                    .await
+                }) as daemon::TaskFuture
+            })
+        };
+
+        if let Some(mut task) = Task::from_config(
+            &format!("backup {}", name),
+            config.daemon.get("backups"),
+            0,
+            run,
+        )? {
+            if let Schedule::Interval(_) = schedule {
+                task.previous_run = daemon_tasks::latest_backup_date(&db_connection, name).await?;
+            }
+
+            task.schedule = schedule;
+            tasks.push(task);
+        }
+    }
+
+    shutdown::listen()?;
+
+    if let Some(listen) = matches
+        .value_of("metrics_listen")
+        .or(config.metrics.listen.as_deref())
+    {
+        metrics_server::start(
+            listen.parse()?,
+            &db_connection,
+            Duration::from_secs(
+                config
+                    .health
+                    .stall_minutes
+                    .unwrap_or(health::DEFAULT_STALL_MINUTES)
+                    * 60,
+            ),
+        )?;
+    }
+
+    if let Some(exit_after) = match matches.value_of("health_exit_after") {
+        Some(exit_after) => Some(exit_after.parse::<u64>()?),
+        None => config.health.exit_after_minutes,
+    } {
+        health::watch(
+            Duration::from_secs(exit_after * 60),
+            tasks.iter().map(|task| task.name.clone()).collect(),
+        );
+    }
+
+    daemon::run(tasks, shutdown_timeout).await?;
+    daemon_tasks::release_job_leases(&db_connection).await?;
+    health::check()
+}
@@ -1,15 +1,22 @@
 extern crate backup_remote_rs;
 use anyhow::Result;
-use backup_remote_rs::aws::{aws_glacier::AwsGlacier, aws_vault::AwsVault};
-use backup_remote_rs::repo::Repository;
+use backup_remote_rs::aws::{
+    aws_glacier::AwsGlacier, aws_inventory::AwsInventoryRetrievalParameters,
+};
+use backup_remote_rs::config::{required, Config, CONFIG_ENV};
+use backup_remote_rs::daemon::daemon_tasks;
+use backup_remote_rs::health;
+use backup_remote_rs::logging;
+use backup_remote_rs::metrics::metrics_server;
+use backup_remote_rs::shutdown;
 extern crate clap;
 use clap::{App, Arg};
-use log::{debug, error, info};
+use log::{error, info};
 use tokio::time::{sleep, Duration};
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    env_logger::init();
+    logging::init()?;
 
     // Process arguments
     let matches = App::new("backup-remote-updater")
@@ -17,132 +24,196 @@ async fn main() -> Result<()> {
         .author(env!("CARGO_PKG_AUTHORS"))
         .about(env!("CARGO_PKG_DESCRIPTION"))
         .arg(
-            Arg::with_name("secret_key")
-                .required(true)
-                .env("AWS_SECRET_KEY"),
+            Arg::with_name("config")
+                .long("config")
+                .env(CONFIG_ENV)
+                .help("configuration file; defaults to the first of ./backup-remote.toml, ~/.config/backup-remote/config.toml and /etc/backup-remote/config.toml")
+                .takes_value(true),
         )
-        .arg(Arg::with_name("key_id").required(true).env("AWS_KEY_ID"))
-        .arg(Arg::with_name("region").required(true).env("AWS_REGION"))
+        .arg(Arg::with_name("secret_key").env("AWS_SECRET_KEY"))
+        .arg(Arg::with_name("key_id").env("AWS_KEY_ID"))
+        .arg(Arg::with_name("region").env("AWS_REGION"))
+        .arg(Arg::with_name("db_connection").env("DB_CONNECTION"))
         .arg(
-            Arg::with_name("db_connection")
-                .required(true)
-                .env("DB_CONNECTION"),
+            Arg::with_name("inventory_format")
+                .long("inventory_format")
+                .env("INVENTORY_FORMAT")
+                .takes_value(true)
+                .possible_values(&["JSON", "CSV"]),
+        )
+        .arg(
+            Arg::with_name("incremental_inventory")
+                .long("incremental_inventory")
+                .env("INCREMENTAL_INVENTORY")
+                .takes_value(true)
+                .possible_values(&["true", "false"]),
+        )
+        .arg(
+            Arg::with_name("inventory_limit")
+                .long("inventory_limit")
+                .env("INVENTORY_LIMIT")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("interval")
+                .long("interval")
+                .env("UPDATER_INTERVAL")
+                .help("minutes between updates (default 60)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("shutdown_timeout")
+                .long("shutdown_timeout")
+                .env("SHUTDOWN_TIMEOUT")
+                .help("seconds in-flight work is given to finish after SIGTERM or SIGINT (default 60)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("metrics_listen")
+                .long("metrics_listen")
+                .env("METRICS_LISTEN")
+                .help("address to serve Prometheus metrics and health checks on, e.g. 127.0.0.1:9090 (default none)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("health_exit_after")
+                .long("health_exit_after")
+                .env("HEALTH_EXIT_AFTER")
+                .help("minutes, within which every task has to succeed, or the process exits (default never)")
+                .takes_value(true),
         )
         .get_matches();
 
-    let secret_key = matches.value_of("secret_key").unwrap();
-    let key_id = matches.value_of("key_id").unwrap();
-    let region = matches.value_of("region").unwrap();
-    let db_connection = matches.value_of("db_connection").unwrap();
-
-    loop {
-        match update(secret_key, key_id, region, db_connection).await {
-            Ok(_) => info!("update succeeded"),
-            Err(e) => error!("{:?}", e),
-        }
-
-        sleep(Duration::from_secs(60 * 60)).await;
+    let config = Config::load(matches.value_of("config"))?;
+    let secret_key = required(
+        matches.value_of("secret_key"),
+        config.secret_key()?,
+        "secret_key",
+    )?;
+    let key_id = required(
+        matches.value_of("key_id"),
+        config.aws.key_id.clone(),
+        "key_id",
+    )?;
+    let region = required(
+        matches.value_of("region"),
+        config.aws.region.clone(),
+        "region",
+    )?;
+    let db_connection = required(
+        matches.value_of("db_connection"),
+        config.db_connection()?,
+        "db_connection",
+    )?;
+    let shutdown_timeout = Duration::from_secs(match matches.value_of("shutdown_timeout") {
+        Some(shutdown_timeout) => shutdown_timeout.parse()?,
+        None => config
+            .shutdown_timeout_seconds
+            .unwrap_or(shutdown::DEFAULT_TIMEOUT_SECONDS),
+    });
+    let mut aws_glacier = AwsGlacier::new(&secret_key, &key_id, &region);
+
+    if let Some(endpoint) = &config.aws.endpoint {
+        aws_glacier = aws_glacier.with_endpoint(endpoint);
     }
-}
-
-async fn update(secret_key: &str, key_id: &str, region: &str, db_connection: &str) -> Result<()> {
-    // Update list of vaults
-    debug!("creating aws glacier object");
-    let aws_glacier = AwsGlacier::new(secret_key, key_id, region);
-    debug!("creating repository object");
-    let mut repo = Repository::new(db_connection).await?;
-    let trans = repo.get_transaction().await?;
-    // Reset vault status active
-    debug!("resetting vault status active");
-    Repository::reset_vaults_status_active(&trans).await?;
 
-    let aws_vaults = aws_glacier.list_vaults().await?;
-    debug!("found {} aws vaults", aws_vaults.len());
-    let repo_vaults = Repository::get_vaults(&trans).await?;
-    debug!("found {} repository vaults", repo_vaults.len());
-    let mut vaults = Vec::<AwsVault>::new();
+    let inventory_parameters = AwsInventoryRetrievalParameters {
+        format: Some(
+            matches
+                .value_of("inventory_format")
+                .or(config.updater.inventory_format.as_deref())
+                .unwrap_or("JSON")
+                .parse()?,
+        ),
+        limit: match matches.value_of("inventory_limit") {
+            Some(limit) => Some(limit.parse()?),
+            None => config.updater.inventory_limit,
+        },
+        ..Default::default()
+    };
+    let incremental_inventory = match matches.value_of("incremental_inventory") {
+        Some(incremental_inventory) => incremental_inventory == "true",
+        None => config.updater.incremental_inventory.unwrap_or(false),
+    };
+    let interval = match matches.value_of("interval") {
+        Some(interval) => interval.parse()?,
+        None => config.updater.interval_minutes.unwrap_or(60),
+    };
+
+    shutdown::listen()?;
+
+    if let Some(listen) = matches
+        .value_of("metrics_listen")
+        .or(config.metrics.listen.as_deref())
+    {
+        metrics_server::start(
+            listen.parse()?,
+            &db_connection,
+            Duration::from_secs(
+                config
+                    .health
+                    .stall_minutes
+                    .unwrap_or(health::DEFAULT_STALL_MINUTES)
+                    * 60,
+            ),
+        )?;
+    }
 
-    for vault in aws_vaults {
-        match repo_vaults.iter().find(|&v| v.vault_arn == vault.vault_arn) {
-            None => {
-                vaults.push(Repository::create_vault(&trans, &vault).await?);
-                info!("added vault \"{}\" to repository", vault.vault_name);
-            }
-            Some(v) => {
-                vaults.push(Repository::update_vault(&trans, v).await?);
-                info!("updated vault \"{}\" in repository", v.vault_name);
-            }
-        }
+    if let Some(exit_after) = match matches.value_of("health_exit_after") {
+        Some(exit_after) => Some(exit_after.parse::<u64>()?),
+        None => config.health.exit_after_minutes,
+    } {
+        health::watch(Duration::from_secs(exit_after * 60), vec!["updater".into()]);
+    }
 
-        // set vault status active
-        debug!("setting vault \"{}\" status active", vault.vault_name);
-        Repository::set_vault_status_active(&trans, &vault).await?;
+    while !shutdown::is_requested() {
+        let round = async {
+            let cycle = health::Cycle::start("updater");
+            let result = cycle
+                .span(async {
+                    let result = config
+                        .retry
+                        .run("updater", || async {
+                            daemon_tasks::sync_vaults(&aws_glacier, &db_connection).await?;
+                            daemon_tasks::process_job_requests(&aws_glacier, &db_connection)
+                                .await?;
+                            daemon_tasks::sync_jobs(
+                                &aws_glacier,
+                                &db_connection,
+                                &inventory_parameters,
+                                incremental_inventory,
+                            )
+                            .await
+                        })
+                        .await;
+
+                    match &result {
+                        Ok(_) => info!("update succeeded"),
+                        Err(e) => error!("{:?}", e),
+                    }
 
-        // update the list of jobs for this vault
-        debug!("updating list of jobs for vault \"{}\"", vault.vault_name);
-        Repository::reset_jobs_status_active(&trans).await?;
-        let aws_jobs = aws_glacier.list_jobs_for_vault(&vault).await?;
+                    result
+                })
+                .await;
 
-        for job in aws_jobs {
-            debug!("processing job \"{}\"", job.job_id);
-            match Repository::get_job_by_id(&trans, &*job.job_id).await {
-                Ok(_) => {
-                    debug!("updating job \"{}\"", job.job_id);
-                    Repository::update_job(&trans, &job).await?
-                }
-                Err(_) => {
-                    debug!("creating job \"{}\"", job.job_id);
-                    Repository::create_job(&trans, &job).await?
-                }
-            };
+            cycle.finish(&result);
+        };
+        tokio::pin!(round);
 
-            debug!("setting job \"{}\" active", job.job_id);
-            Repository::set_job_status_active(&trans, &job).await?;
+        // a round in progress is given the shutdown timeout to finish
+        tokio::select! {
+            _ = &mut round => {}
+            _ = shutdown::requested() => {
+                shutdown::drain(round, shutdown_timeout).await;
+                break;
+            }
         }
 
-        // get the latest inventory job for this vault
-        // if the job is older then the inventory date of the vault => launch new inventory job
-        match vault.last_inventory_date {
-            Some(inv_date) => {
-                debug!("inventory date found for vault \"{}\"", vault.vault_name);
-                if match Repository::get_latest_job_by_action_vault(
-                    &trans,
-                    "InventoryRetrieval",
-                    &*vault.vault_arn,
-                )
-                .await
-                {
-                    Ok(latest_job) => {
-                        if latest_job.creation_date < inv_date {
-                            debug!("latest inventory job date older than inventory date of vault");
-                            true
-                        } else {
-                            false
-                        }
-                    }
-                    Err(_) => true,
-                } {
-                    // launch inventory job
-                    debug!("creating inventory job for \"{}\"", vault.vault_name);
-                    let job_id = aws_glacier.init_inventory_job_for_vault(&vault).await?;
-                    info!(
-                        "created inventory job for \"{}\" with id \"{}\"",
-                        vault.vault_name, job_id
-                    );
-
-                    // add job to repository
-                    let job = aws_glacier.get_job_by_id_vault(&vault, &*job_id).await?;
-                    Repository::create_job(&trans, &job).await?;
-                    Repository::set_job_status_active(&trans, &job).await?;
-                }
-            }
-            None => {
-                debug!("no inventory date found for vault \"{}\"", vault.vault_name);
-            }
+        tokio::select! {
+            _ = sleep(Duration::from_secs(interval * 60)) => {}
+            _ = shutdown::requested() => {}
         }
     }
 
-    trans.commit().await?;
-
-    Ok(())
+    health::check()
 }
@@ -1,15 +1,21 @@
 extern crate backup_remote_rs;
 use anyhow::Result;
 use backup_remote_rs::aws::aws_glacier::AwsGlacier;
-use backup_remote_rs::repo::Repository;
+use backup_remote_rs::config::{required, Config, CONFIG_ENV};
+use backup_remote_rs::crypto::load_secrets;
+use backup_remote_rs::daemon::daemon_tasks;
+use backup_remote_rs::health;
+use backup_remote_rs::logging;
+use backup_remote_rs::metrics::metrics_server;
+use backup_remote_rs::shutdown;
 extern crate clap;
 use clap::{App, Arg};
-use log::{debug, error, info};
+use log::{error, info};
 use tokio::time::{sleep, Duration};
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    env_logger::init();
+    logging::init()?;
 
     // Process arguments
     let matches = App::new("backup-remote-worker")
@@ -17,80 +23,185 @@ async fn main() -> Result<()> {
         .author(env!("CARGO_PKG_AUTHORS"))
         .about(env!("CARGO_PKG_DESCRIPTION"))
         .arg(
-            Arg::with_name("secret_key")
-                .required(true)
-                .env("AWS_SECRET_KEY"),
+            Arg::with_name("config")
+                .long("config")
+                .env(CONFIG_ENV)
+                .help("configuration file; defaults to the first of ./backup-remote.toml, ~/.config/backup-remote/config.toml and /etc/backup-remote/config.toml")
+                .takes_value(true),
         )
-        .arg(Arg::with_name("key_id").required(true).env("AWS_KEY_ID"))
-        .arg(Arg::with_name("region").required(true).env("AWS_REGION"))
+        .arg(Arg::with_name("secret_key").env("AWS_SECRET_KEY"))
+        .arg(Arg::with_name("key_id").env("AWS_KEY_ID"))
+        .arg(Arg::with_name("region").env("AWS_REGION"))
+        .arg(Arg::with_name("db_connection").env("DB_CONNECTION"))
+        .arg(Arg::with_name("inventory_dir").env("INVENTORY_DIR"))
         .arg(
-            Arg::with_name("db_connection")
-                .required(true)
-                .env("DB_CONNECTION"),
+            Arg::with_name("key_file")
+                .long("key_file")
+                .env("ENCRYPTION_KEY_FILE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("passphrase")
+                .long("passphrase")
+                .env("ENCRYPTION_PASSPHRASE")
+                .hide_env_values(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("interval")
+                .long("interval")
+                .env("WORKER_INTERVAL")
+                .help("minutes between updates (default 30)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("shutdown_timeout")
+                .long("shutdown_timeout")
+                .env("SHUTDOWN_TIMEOUT")
+                .help("seconds in-flight work is given to finish after SIGTERM or SIGINT (default 60)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("metrics_listen")
+                .long("metrics_listen")
+                .env("METRICS_LISTEN")
+                .help("address to serve Prometheus metrics and health checks on, e.g. 127.0.0.1:9090 (default none)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("health_exit_after")
+                .long("health_exit_after")
+                .env("HEALTH_EXIT_AFTER")
+                .help("minutes, within which every task has to succeed, or the process exits (default never)")
+                .takes_value(true),
         )
         .get_matches();
 
-    let secret_key = matches.value_of("secret_key").unwrap();
-    let key_id = matches.value_of("key_id").unwrap();
-    let region = matches.value_of("region").unwrap();
-    let db_connection = matches.value_of("db_connection").unwrap();
-
-    loop {
-        match update(secret_key, key_id, region, db_connection).await {
-            Ok(_) => info!("update succeeded"),
-            Err(e) => error!("{:?}", e),
+    let config = Config::load(matches.value_of("config"))?;
+    let secret_key = required(
+        matches.value_of("secret_key"),
+        config.secret_key()?,
+        "secret_key",
+    )?;
+    let key_id = required(
+        matches.value_of("key_id"),
+        config.aws.key_id.clone(),
+        "key_id",
+    )?;
+    let region = required(
+        matches.value_of("region"),
+        config.aws.region.clone(),
+        "region",
+    )?;
+    let db_connection = required(
+        matches.value_of("db_connection"),
+        config.db_connection()?,
+        "db_connection",
+    )?;
+    let inventory_dir = required(
+        matches.value_of("inventory_dir"),
+        config.worker.inventory_dir.clone(),
+        "inventory_dir",
+    )?;
+    let secrets = load_secrets(
+        matches
+            .value_of("key_file")
+            .or(config.encryption.key_file.as_deref()),
+        match matches.value_of("passphrase") {
+            Some(passphrase) => Some(passphrase.into()),
+            None => config.passphrase()?,
         }
+        .as_deref(),
+    )?;
+    let interval = match matches.value_of("interval") {
+        Some(interval) => interval.parse()?,
+        None => config.worker.interval_minutes.unwrap_or(30),
+    };
+    let shutdown_timeout = Duration::from_secs(match matches.value_of("shutdown_timeout") {
+        Some(shutdown_timeout) => shutdown_timeout.parse()?,
+        None => config
+            .shutdown_timeout_seconds
+            .unwrap_or(shutdown::DEFAULT_TIMEOUT_SECONDS),
+    });
+    let mut aws_glacier = AwsGlacier::new(&secret_key, &key_id, &region);
 
-        sleep(Duration::from_secs(60 * 30)).await;
+    if let Some(endpoint) = &config.aws.endpoint {
+        aws_glacier = aws_glacier.with_endpoint(endpoint);
     }
-}
-
-async fn update(secret_key: &str, key_id: &str, region: &str, db_connection: &str) -> Result<()> {
-    debug!("creating aws glacier object");
-    let aws_glacier = AwsGlacier::new(secret_key, key_id, region);
-    debug!("creating repository object");
-    let mut repo = Repository::new(db_connection).await?;
-    let aws_vaults = aws_glacier.list_vaults().await?;
 
-    for vault in aws_vaults {
-        let aws_jobs = aws_glacier.list_jobs_for_vault(&vault).await?;
+    shutdown::listen()?;
 
-        for job in aws_jobs {
-            match &*job.action {
-                "InventoryRetrieval" => {
-                    debug!("InventoryRetrieval job found");
+    if let Some(listen) = matches
+        .value_of("metrics_listen")
+        .or(config.metrics.listen.as_deref())
+    {
+        metrics_server::start(
+            listen.parse()?,
+            &db_connection,
+            Duration::from_secs(
+                config
+                    .health
+                    .stall_minutes
+                    .unwrap_or(health::DEFAULT_STALL_MINUTES)
+                    * 60,
+            ),
+        )?;
+    }
 
-                    match &*job.status_code {
-                        "Succeeded" => {
-                            let trans = repo.get_transaction().await?;
-                            Repository::delete_archive_associations(&trans, &vault).await?;
+    if let Some(exit_after) = match matches.value_of("health_exit_after") {
+        Some(exit_after) => Some(exit_after.parse::<u64>()?),
+        None => config.health.exit_after_minutes,
+    } {
+        health::watch(Duration::from_secs(exit_after * 60), vec!["worker".into()]);
+    }
 
-                            for archive in
-                                aws_glacier.get_inventory_job_result(&vault, &job).await?
-                            {
-                                match Repository::create_archive(&trans, &archive).await {
-                                    Err(_) => {
-                                        Repository::update_archive(&trans, &archive).await?;
-                                    }
-                                    _ => {}
-                                };
-                                Repository::create_archive_association(&trans, &vault, &archive)
-                                    .await?;
-                            }
+    while !shutdown::is_requested() {
+        let round = async {
+            let cycle = health::Cycle::start("worker");
+            let result = cycle
+                .span(async {
+                    let result = config
+                        .retry
+                        .run("worker", || async {
+                            daemon_tasks::import_inventories(
+                                &aws_glacier,
+                                &db_connection,
+                                &inventory_dir,
+                            )
+                            .await?;
+                            daemon_tasks::process_restores(&aws_glacier, &db_connection, &secrets)
+                                .await
+                        })
+                        .await;
 
-                            trans.commit().await?;
-                        }
-                        status_code => {
-                            info!("Job status \"{}\" => skipping job", status_code);
-                        }
+                    match &result {
+                        Ok(_) => info!("update succeeded"),
+                        Err(e) => error!("{:?}", e),
                     }
-                }
-                action => {
-                    info!("Unkonwn job action found: \"{}\"", action);
-                }
+
+                    result
+                })
+                .await;
+
+            cycle.finish(&result);
+        };
+        tokio::pin!(round);
+
+        // a round in progress is given the shutdown timeout to finish
+        tokio::select! {
+            _ = &mut round => {}
+            _ = shutdown::requested() => {
+                shutdown::drain(round, shutdown_timeout).await;
+                break;
             }
         }
+
+        tokio::select! {
+            _ = sleep(Duration::from_secs(interval * 60)) => {}
+            _ = shutdown::requested() => {}
+        }
     }
 
-    Ok(())
+    daemon_tasks::release_job_leases(&db_connection).await?;
+    health::check()
 }
@@ -1,29 +1,93 @@
 use anyhow::Result;
-use chrono::{DateTime, Utc};
+use backup_remote_rs::aws::aws_glacier::AwsGlacier;
+use backup_remote_rs::aws::aws_inventory::AwsInventoryRetrievalParameters;
+use backup_remote_rs::backup::{self, backup_reader, StoredBackup};
+use backup_remote_rs::catalog::{glob_to_regex, CatalogEntryType};
+use backup_remote_rs::config::{required, Config, CONFIG_ENV};
+use backup_remote_rs::cost::PriceTable;
+use backup_remote_rs::crypto::crypto_key::{ArchiveKey, KeyRing, RecipientKey};
+use backup_remote_rs::crypto::{load_secrets, Secret};
+use backup_remote_rs::inventory;
+use backup_remote_rs::logging;
+use backup_remote_rs::repo::Repository;
+use backup_remote_rs::restore;
+use backup_remote_rs::retention::{self, PruneAction, RetentionPolicy};
+use backup_remote_rs::store::InventoryStore;
+use chrono::{DateTime, FixedOffset, Utc};
 use data_encoding::HEXLOWER;
 use hyper::body::HttpBody as _;
 use hyper::Uri;
 use hyper::{Body, Client, Request};
 use hyper_tls::HttpsConnector;
+use log::warn;
 use ring::{digest, hmac};
 use std::env;
+use std::fs::File;
+use std::io::{stdin, Read};
 use tokio::io::{stdout, AsyncWriteExt as _};
+use tokio::time::{sleep, Duration};
+use uuid::Uuid;
 extern crate clap;
-use clap::{App, Arg, SubCommand};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+
+const RESTORE_POLL_INTERVAL_SECONDS: u64 = 15 * 60;
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    logging::init()?;
+
     let matches = App::new("backup-remote-rs")
         .version(env!("CARGO_PKG_VERSION"))
         .author(env!("CARGO_PKG_AUTHORS"))
         .about(env!("CARGO_PKG_DESCRIPTION"))
         .arg(
-            Arg::with_name("secret_key")
-                .required(true)
-                .env("AWS_SECRET_KEY"),
+            Arg::with_name("config")
+                .long("config")
+                .env(CONFIG_ENV)
+                .help("configuration file; defaults to the first of ./backup-remote.toml, ~/.config/backup-remote/config.toml and /etc/backup-remote/config.toml")
+                .takes_value(true)
+                .multiple(false),
+        )
+        .arg(Arg::with_name("secret_key").env("AWS_SECRET_KEY"))
+        .arg(Arg::with_name("key_id").env("AWS_KEY_ID"))
+        .arg(Arg::with_name("region").env("AWS_REGION"))
+        .arg(
+            Arg::with_name("db_connection")
+                .long("db_connection")
+                .env("DB_CONNECTION")
+                .takes_value(true)
+                .multiple(false),
+        )
+        .arg(
+            Arg::with_name("inventory_dir")
+                .long("inventory_dir")
+                .env("INVENTORY_DIR")
+                .takes_value(true)
+                .multiple(false),
+        )
+        .arg(
+            Arg::with_name("key_file")
+                .long("key_file")
+                .env("ENCRYPTION_KEY_FILE")
+                .takes_value(true)
+                .multiple(false),
+        )
+        .arg(
+            Arg::with_name("passphrase")
+                .long("passphrase")
+                .env("ENCRYPTION_PASSPHRASE")
+                .hide_env_values(true)
+                .takes_value(true)
+                .multiple(false),
+        )
+        .arg(
+            Arg::with_name("price_file")
+                .long("price_file")
+                .env("PRICE_FILE")
+                .help("JSON file mapping regions to prices, used instead of the built-in prices")
+                .takes_value(true)
+                .multiple(false),
         )
-        .arg(Arg::with_name("key_id").required(true).env("AWS_KEY_ID"))
-        .arg(Arg::with_name("region").required(true).env("AWS_REGION"))
         .subcommand(SubCommand::with_name("list-vaults").about("list all vaults"))
         .subcommand(
             SubCommand::with_name("init-inventory")
@@ -34,6 +98,38 @@ async fn main() -> Result<()> {
                         .long("vault_name")
                         .takes_value(true)
                         .multiple(false),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .multiple(false)
+                        .possible_values(&["JSON", "CSV"])
+                        .default_value("JSON"),
+                )
+                .arg(
+                    Arg::with_name("start_date")
+                        .long("start_date")
+                        .takes_value(true)
+                        .multiple(false),
+                )
+                .arg(
+                    Arg::with_name("end_date")
+                        .long("end_date")
+                        .takes_value(true)
+                        .multiple(false),
+                )
+                .arg(
+                    Arg::with_name("limit")
+                        .long("limit")
+                        .takes_value(true)
+                        .multiple(false),
+                )
+                .arg(
+                    Arg::with_name("marker")
+                        .long("marker")
+                        .takes_value(true)
+                        .multiple(false),
                 ),
         )
         .subcommand(
@@ -65,49 +161,1440 @@ async fn main() -> Result<()> {
                         .multiple(false),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("reprocess-inventory")
+                .about("import a stored inventory job output again")
+                .arg(
+                    Arg::with_name("job_id")
+                        .required(true)
+                        .long("job_id")
+                        .takes_value(true)
+                        .multiple(false),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("backup")
+                .about("archive, compress and upload paths to a vault")
+                .arg(
+                    Arg::with_name("vault_name")
+                        .long("vault_name")
+                        .takes_value(true)
+                        .multiple(false),
+                )
+                .arg(
+                    Arg::with_name("compression")
+                        .long("compression")
+                        .help("compression of the archive (default zstd)")
+                        .takes_value(true)
+                        .multiple(false)
+                        .possible_values(&["zstd", "gzip"]),
+                )
+                .arg(
+                    Arg::with_name("part_size")
+                        .long("part_size")
+                        .help("size of the upload parts in MiB, a power of two (default 16)")
+                        .takes_value(true)
+                        .multiple(false),
+                )
+                .arg(
+                    Arg::with_name("backup_set")
+                        .long("backup_set")
+                        .help("name of the backup set recorded in the archive description; the vault, paths and options of a backup set in the configuration are used unless given")
+                        .takes_value(true)
+                        .multiple(false),
+                )
+                .arg(
+                    Arg::with_name("encryption")
+                        .long("encryption")
+                        .help("encryption algorithm (default aes-256-gcm)")
+                        .takes_value(true)
+                        .multiple(false)
+                        .possible_values(&["aes-256-gcm", "chacha20-poly1305"]),
+                )
+                .arg(
+                    Arg::with_name("dedup")
+                        .long("dedup")
+                        .help("split the data into chunks and upload only chunks not stored by earlier snapshots"),
+                )
+                .arg(
+                    Arg::with_name("incremental")
+                        .long("incremental")
+                        .help("upload only files changed since the previous backup of the backup set")
+                        .requires("backup_set")
+                        .conflicts_with("dedup"),
+                )
+                .arg(
+                    Arg::with_name("full_every")
+                        .long("full_every")
+                        .help("create a full backup after this many incremental backups (default 7)")
+                        .takes_value(true)
+                        .multiple(false),
+                )
+                .arg(Arg::with_name("paths").multiple(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("extract")
+                .about("decrypt, decompress and extract a downloaded backup archive")
+                .arg(
+                    Arg::with_name("input")
+                        .required(true)
+                        .long("input")
+                        .help("archive file (\"-\" for stdin)")
+                        .takes_value(true)
+                        .multiple(false),
+                )
+                .arg(
+                    Arg::with_name("destination")
+                        .required(true)
+                        .long("destination")
+                        .takes_value(true)
+                        .multiple(false),
+                )
+                .arg(
+                    Arg::with_name("archive_id")
+                        .long("archive_id")
+                        .help("archive id to look up the data key in the repository")
+                        .takes_value(true)
+                        .multiple(false),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("generate-key").about("generate a random key for a key file"),
+        )
+        .subcommand(SubCommand::with_name("list-keys").about("list the keys archives are encrypted for"))
+        .subcommand(
+            SubCommand::with_name("add-recipient")
+                .about("add a key the data keys of new archives are wrapped for")
+                .arg(
+                    Arg::with_name("key_id")
+                        .required(true)
+                        .long("key_id")
+                        .takes_value(true)
+                        .multiple(false),
+                )
+                .arg(
+                    Arg::with_name("recipient_key_file")
+                        .long("recipient_key_file")
+                        .takes_value(true)
+                        .multiple(false)
+                        .required_unless("recipient_passphrase"),
+                )
+                .arg(
+                    Arg::with_name("recipient_passphrase")
+                        .long("recipient_passphrase")
+                        .takes_value(true)
+                        .multiple(false)
+                        .conflicts_with("recipient_key_file"),
+                )
+                .arg(
+                    Arg::with_name("inactive")
+                        .long("inactive")
+                        .help("do not wrap the data keys of new archives for this key (e.g. for offline keys)"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("retire-key")
+                .about("stop wrapping the data keys of new archives for a key")
+                .arg(
+                    Arg::with_name("key_id")
+                        .required(true)
+                        .long("key_id")
+                        .takes_value(true)
+                        .multiple(false),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("rewrap-keys")
+                .about("wrap the data keys of existing archives for a key")
+                .arg(
+                    Arg::with_name("key_id")
+                        .required(true)
+                        .long("key_id")
+                        .takes_value(true)
+                        .multiple(false),
+                )
+                .arg(
+                    Arg::with_name("recipient_key_file")
+                        .long("recipient_key_file")
+                        .takes_value(true)
+                        .multiple(false)
+                        .required_unless("recipient_passphrase"),
+                )
+                .arg(
+                    Arg::with_name("recipient_passphrase")
+                        .long("recipient_passphrase")
+                        .takes_value(true)
+                        .multiple(false)
+                        .conflicts_with("recipient_key_file"),
+                )
+                .arg(
+                    Arg::with_name("remove_key_id")
+                        .long("remove_key_id")
+                        .help("remove the wrapped data keys of this key afterwards")
+                        .takes_value(true)
+                        .multiple(false),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("find")
+                .about("find files in the catalog of the backups")
+                .arg(
+                    Arg::with_name("glob")
+                        .required(true)
+                        .help("path glob (\"*\" and \"?\" do not match \"/\", \"**\" does)"),
+                )
+                .arg(
+                    Arg::with_name("after")
+                        .long("after")
+                        .help("only backups created at or after this date (RFC 3339)")
+                        .takes_value(true)
+                        .multiple(false),
+                )
+                .arg(
+                    Arg::with_name("before")
+                        .long("before")
+                        .help("only backups created at or before this date (RFC 3339)")
+                        .takes_value(true)
+                        .multiple(false),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("ls")
+                .about("list a directory from the catalog of the latest backup containing it")
+                .arg(Arg::with_name("directory").required(true))
+                .arg(
+                    Arg::with_name("date")
+                        .long("date")
+                        .help("use the latest backup created at or before this date (RFC 3339)")
+                        .takes_value(true)
+                        .multiple(false),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("retention")
+                .about("manage the retention policies of vaults and backup sets")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("set")
+                        .about("set the policy of a vault or of a backup set within it")
+                        .arg(
+                            Arg::with_name("vault_name")
+                                .required(true)
+                                .long("vault_name")
+                                .takes_value(true)
+                                .multiple(false),
+                        )
+                        .arg(
+                            Arg::with_name("backup_set")
+                                .long("backup_set")
+                                .takes_value(true)
+                                .multiple(false),
+                        )
+                        .arg(
+                            Arg::with_name("keep_last")
+                                .long("keep_last")
+                                .help("keep the newest archives")
+                                .takes_value(true)
+                                .multiple(false)
+                                .default_value("0"),
+                        )
+                        .arg(
+                            Arg::with_name("keep_daily")
+                                .long("keep_daily")
+                                .help("keep the newest archive of every day for this many days")
+                                .takes_value(true)
+                                .multiple(false)
+                                .default_value("0"),
+                        )
+                        .arg(
+                            Arg::with_name("keep_weekly")
+                                .long("keep_weekly")
+                                .help("keep the newest archive of every week for this many weeks")
+                                .takes_value(true)
+                                .multiple(false)
+                                .default_value("0"),
+                        )
+                        .arg(
+                            Arg::with_name("keep_monthly")
+                                .long("keep_monthly")
+                                .help("keep the newest archive of every month for this many months")
+                                .takes_value(true)
+                                .multiple(false)
+                                .default_value("0"),
+                        ),
+                )
+                .subcommand(SubCommand::with_name("list").about("list the retention policies")),
+        )
+        .subcommand(
+            SubCommand::with_name("prune")
+                .about("show which archives the retention policies expire and optionally delete them")
+                .arg(
+                    Arg::with_name("vault_name")
+                        .long("vault_name")
+                        .help("only prune this vault")
+                        .takes_value(true)
+                        .multiple(false),
+                )
+                .arg(
+                    Arg::with_name("delete")
+                        .long("delete")
+                        .help("delete the expired archives instead of only showing the plan"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("cost")
+                .about("estimate storage and retrieval costs")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("storage")
+                        .about("estimate the monthly storage cost of the vaults and the charge for deleting their archives now")
+                        .arg(
+                            Arg::with_name("vault_name")
+                                .long("vault_name")
+                                .takes_value(true)
+                                .multiple(false),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("retrieval")
+                        .about("estimate the cost of retrieving archives or a number of bytes")
+                        .arg(
+                            Arg::with_name("archive_id")
+                                .long("archive_id")
+                                .required_unless("bytes")
+                                .takes_value(true)
+                                .multiple(true)
+                                .number_of_values(1),
+                        )
+                        .arg(
+                            Arg::with_name("bytes")
+                                .long("bytes")
+                                .conflicts_with("archive_id")
+                                .takes_value(true)
+                                .multiple(false),
+                        )
+                        .arg(
+                            Arg::with_name("tier")
+                                .long("tier")
+                                .takes_value(true)
+                                .multiple(false)
+                                .possible_values(&["Expedited", "Standard", "Bulk"])
+                                .default_value("Standard"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("list-snapshots").about("list the snapshots of deduplicated backups"),
+        )
+        .subcommand(
+            SubCommand::with_name("restore")
+                .about("retrieve, verify and extract archives")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("start")
+                        .about("request the retrieval of an archive or snapshot")
+                        .arg(
+                            Arg::with_name("archive_id")
+                                .required_unless("snapshot_id")
+                                .long("archive_id")
+                                .takes_value(true)
+                                .multiple(false),
+                        )
+                        .arg(
+                            Arg::with_name("snapshot_id")
+                                .long("snapshot_id")
+                                .help("restore a snapshot of a deduplicated backup, retrieving the part of every pack containing its chunks")
+                                .takes_value(true)
+                                .multiple(false)
+                                .conflicts_with_all(&["archive_id", "path"]),
+                        )
+                        .arg(
+                            Arg::with_name("destination")
+                                .required(true)
+                                .long("destination")
+                                .help("directory the archive is extracted to")
+                                .takes_value(true)
+                                .multiple(false),
+                        )
+                        .arg(
+                            Arg::with_name("tier")
+                                .long("tier")
+                                .takes_value(true)
+                                .multiple(false)
+                                .possible_values(&["Expedited", "Standard", "Bulk"])
+                                .default_value("Standard"),
+                        )
+                        .arg(
+                            Arg::with_name("path")
+                                .long("path")
+                                .help("restore only this file or directory (absolute path as in the catalog); only the part of the archive containing the paths is retrieved")
+                                .takes_value(true)
+                                .multiple(true)
+                                .number_of_values(1),
+                        )
+                        .arg(
+                            Arg::with_name("download_dir")
+                                .long("download_dir")
+                                .env("RESTORE_DOWNLOAD_DIR")
+                                .help("directory the archive is downloaded to before it is extracted")
+                                .takes_value(true)
+                                .multiple(false),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("status")
+                        .about("show the progress of restores")
+                        .arg(Arg::with_name("restore_id")),
+                )
+                .subcommand(
+                    SubCommand::with_name("run")
+                        .about("advance unfinished restores")
+                        .arg(Arg::with_name("restore_id"))
+                        .arg(
+                            Arg::with_name("wait")
+                                .long("wait")
+                                .help("keep polling until the restores are finished"),
+                        ),
+                ),
+        )
         .get_matches();
 
-    let secret_key = String::from(matches.value_of("secret_key").unwrap());
-    let key_id = String::from(matches.value_of("key_id").unwrap());
-    let region = String::from(matches.value_of("region").unwrap());
+    let config = Config::load(matches.value_of("config"))?;
+    let region = matches
+        .value_of("region")
+        .map(String::from)
+        .or_else(|| config.aws.region.clone());
+    let db_connection = match matches.value_of("db_connection") {
+        Some(db_connection) => Some(db_connection.into()),
+        None => config.db_connection()?,
+    };
+    let inventory_dir = matches
+        .value_of("inventory_dir")
+        .map(String::from)
+        .or_else(|| config.worker.inventory_dir.clone());
+    let secrets = load_secrets(
+        matches
+            .value_of("key_file")
+            .or(config.encryption.key_file.as_deref()),
+        match matches.value_of("passphrase") {
+            Some(passphrase) => Some(passphrase.into()),
+            None => config.passphrase()?,
+        }
+        .as_deref(),
+    )?;
+    let prices = PriceTable::for_region(
+        region.as_deref().unwrap_or_default(),
+        matches
+            .value_of("price_file")
+            .or(config.price_file.as_deref()),
+    )?;
+    // only the subcommands calling Glacier need credentials, the others work offline
+    let credentials = || Credentials::new(&matches, &config, region.as_deref());
+    let aws_glacier = || -> Result<AwsGlacier> {
+        let credentials = credentials()?;
+        let aws_glacier = AwsGlacier::new(
+            &credentials.secret_key,
+            &credentials.key_id,
+            &credentials.region,
+        );
+
+        Ok(match &config.aws.endpoint {
+            Some(endpoint) => aws_glacier.with_endpoint(endpoint),
+            None => aws_glacier,
+        })
+    };
 
-    match matches.subcommand {
+    match &matches.subcommand {
         Some(subcommand) => match &*subcommand.name {
-            "list-vaults" => list_vaults(&secret_key, &key_id, &region).await,
+            "list-vaults" => {
+                let credentials = credentials()?;
+
+                list_vaults(
+                    &credentials.secret_key,
+                    &credentials.key_id,
+                    &credentials.region,
+                )
+                .await
+            }
             "init-inventory" => {
+                let credentials = credentials()?;
+
                 init_inventory_retrieval(
-                    &secret_key,
-                    &key_id,
-                    &region,
+                    &credentials.secret_key,
+                    &credentials.key_id,
+                    &credentials.region,
                     subcommand.matches.value_of("vault_name").unwrap(),
+                    &AwsInventoryRetrievalParameters {
+                        format: Some(subcommand.matches.value_of("format").unwrap().parse()?),
+                        start_date: match subcommand.matches.value_of("start_date") {
+                            Some(date) => Some(DateTime::parse_from_rfc3339(date)?),
+                            None => None,
+                        },
+                        end_date: match subcommand.matches.value_of("end_date") {
+                            Some(date) => Some(DateTime::parse_from_rfc3339(date)?),
+                            None => None,
+                        },
+                        limit: match subcommand.matches.value_of("limit") {
+                            Some(limit) => Some(limit.parse()?),
+                            None => None,
+                        },
+                        marker: subcommand.matches.value_of("marker").map(String::from),
+                    },
                 )
                 .await
             }
             "list-jobs" => {
+                let credentials = credentials()?;
+
                 list_jobs(
-                    &secret_key,
-                    &key_id,
-                    &region,
+                    &credentials.secret_key,
+                    &credentials.key_id,
+                    &credentials.region,
                     subcommand.matches.value_of("vault_name").unwrap(),
                 )
                 .await
             }
             "job-output" => {
+                let credentials = credentials()?;
+
                 job_output(
-                    &secret_key,
-                    &key_id,
-                    &region,
+                    &credentials.secret_key,
+                    &credentials.key_id,
+                    &credentials.region,
                     subcommand.matches.value_of("vault_name").unwrap(),
                     subcommand.matches.value_of("job_id").unwrap(),
                 )
                 .await
             }
+            "reprocess-inventory" => {
+                reprocess_inventory(
+                    required_value(&db_connection, "db_connection")?,
+                    required_value(&inventory_dir, "inventory_dir")?,
+                    subcommand.matches.value_of("job_id").unwrap(),
+                )
+                .await
+            }
+            "backup" => {
+                let matches = &subcommand.matches;
+                let backup_set = matches.value_of("backup_set");
+                let mut settings = backup_set
+                    .and_then(|name| config.backup_set(name))
+                    .cloned()
+                    .unwrap_or_default();
+
+                if let Some(vault_name) = matches.value_of("vault_name") {
+                    settings.vault_name = Some(vault_name.into());
+                }
+
+                if let Some(paths) = matches.values_of("paths") {
+                    settings.paths = paths.map(String::from).collect();
+                }
+
+                if let Some(compression) = matches.value_of("compression") {
+                    settings.compression = Some(compression.into());
+                }
+
+                if let Some(part_size) = matches.value_of("part_size") {
+                    settings.part_size = Some(part_size.parse()?);
+                }
+
+                if let Some(encryption) = matches.value_of("encryption") {
+                    settings.encryption = Some(encryption.into());
+                }
+
+                if let Some(full_every) = matches.value_of("full_every") {
+                    settings.full_every = Some(full_every.parse()?);
+                }
+
+                settings.incremental |= matches.is_present("incremental");
+                settings.dedup |= matches.is_present("dedup");
+
+                let mut repo =
+                    Repository::new(required_value(&db_connection, "db_connection")?).await?;
+
+                match backup::run_backup(&aws_glacier()?, &mut repo, backup_set, &settings, &secrets)
+                    .await?
+                {
+                    StoredBackup::Archive(backup) => {
+                        println!(
+                            "uploaded archive \"{}\" ({} bytes, tree hash {})",
+                            backup.archive_id, backup.size, backup.tree_hash
+                        );
+
+                        if let Some(parent_archive_id) = &backup.parent_archive_id {
+                            println!(
+                                "incremental backup {} based on \"{}\" ({} deleted paths)",
+                                backup.chain_length,
+                                parent_archive_id,
+                                backup.deletions.len()
+                            );
+                        }
+                    }
+                    StoredBackup::Snapshot(snapshot) => println!(
+                        "stored snapshot \"{}\" ({} bytes in {} chunks, {} new chunks with {} bytes)",
+                        snapshot.snapshot_id,
+                        snapshot.size,
+                        snapshot.chunk_count,
+                        snapshot.new_chunk_count,
+                        snapshot.new_size
+                    ),
+                }
+
+                Ok(())
+            }
+            "extract" => {
+                extract(
+                    db_connection.as_deref(),
+                    subcommand.matches.value_of("input").unwrap(),
+                    subcommand.matches.value_of("destination").unwrap(),
+                    subcommand.matches.value_of("archive_id"),
+                    secrets,
+                )
+                .await
+            }
+            "generate-key" => {
+                println!("{}", Secret::generate_key()?);
+                Ok(())
+            }
+            "list-keys" => list_keys(required_value(&db_connection, "db_connection")?).await,
+            "add-recipient" => {
+                add_recipient(
+                    required_value(&db_connection, "db_connection")?,
+                    subcommand.matches.value_of("key_id").unwrap(),
+                    &recipient_secret(&subcommand.matches)?,
+                    !subcommand.matches.is_present("inactive"),
+                )
+                .await
+            }
+            "retire-key" => {
+                retire_key(
+                    required_value(&db_connection, "db_connection")?,
+                    subcommand.matches.value_of("key_id").unwrap(),
+                )
+                .await
+            }
+            "rewrap-keys" => {
+                rewrap_keys(
+                    required_value(&db_connection, "db_connection")?,
+                    subcommand.matches.value_of("key_id").unwrap(),
+                    &recipient_secret(&subcommand.matches)?,
+                    &secrets,
+                    subcommand.matches.value_of("remove_key_id"),
+                )
+                .await
+            }
+            "find" => {
+                find(
+                    required_value(&db_connection, "db_connection")?,
+                    subcommand.matches.value_of("glob").unwrap(),
+                    match subcommand.matches.value_of("after") {
+                        Some(date) => Some(DateTime::parse_from_rfc3339(date)?),
+                        None => None,
+                    },
+                    match subcommand.matches.value_of("before") {
+                        Some(date) => Some(DateTime::parse_from_rfc3339(date)?),
+                        None => None,
+                    },
+                )
+                .await
+            }
+            "ls" => {
+                ls(
+                    required_value(&db_connection, "db_connection")?,
+                    subcommand.matches.value_of("directory").unwrap(),
+                    match subcommand.matches.value_of("date") {
+                        Some(date) => Some(DateTime::parse_from_rfc3339(date)?),
+                        None => None,
+                    },
+                )
+                .await
+            }
+            "retention" => match subcommand.matches.subcommand() {
+                ("set", Some(matches)) => {
+                    set_retention(
+                        &aws_glacier()?,
+                        required_value(&db_connection, "db_connection")?,
+                        matches,
+                    )
+                    .await
+                }
+                ("list", Some(_)) => {
+                    list_retention(required_value(&db_connection, "db_connection")?).await
+                }
+                _ => Err(anyhow::Error::msg("unexpected subcommand")),
+            },
+            "prune" => {
+                prune(
+                    &aws_glacier()?,
+                    required_value(&db_connection, "db_connection")?,
+                    subcommand.matches.value_of("vault_name"),
+                    subcommand.matches.is_present("delete"),
+                )
+                .await
+            }
+            "cost" => match subcommand.matches.subcommand() {
+                ("storage", Some(matches)) => {
+                    storage_cost(
+                        required_value(&db_connection, "db_connection")?,
+                        &prices,
+                        matches.value_of("vault_name"),
+                    )
+                    .await
+                }
+                ("retrieval", Some(matches)) => {
+                    retrieval_cost(
+                        db_connection.as_deref(),
+                        &prices,
+                        matches.value_of("tier").unwrap(),
+                        matches.values_of("archive_id").map(|ids| ids.collect()),
+                        match matches.value_of("bytes") {
+                            Some(bytes) => Some(bytes.parse()?),
+                            None => None,
+                        },
+                    )
+                    .await
+                }
+                _ => Err(anyhow::Error::msg("unexpected subcommand")),
+            },
+            "list-snapshots" => {
+                list_snapshots(required_value(&db_connection, "db_connection")?).await
+            }
+            "restore" => match subcommand.matches.subcommand() {
+                ("start", Some(matches)) => {
+                    restore_start(
+                        &aws_glacier()?,
+                        required_value(&db_connection, "db_connection")?,
+                        matches,
+                        config.restore.download_dir.as_deref(),
+                        &prices,
+                        &secrets,
+                    )
+                    .await
+                }
+                ("status", Some(matches)) => {
+                    restore_status(
+                        required_value(&db_connection, "db_connection")?,
+                        match matches.value_of("restore_id") {
+                            Some(restore_id) => Some(restore_id.parse()?),
+                            None => None,
+                        },
+                    )
+                    .await
+                }
+                ("run", Some(matches)) => {
+                    restore_run(
+                        &aws_glacier()?,
+                        required_value(&db_connection, "db_connection")?,
+                        match matches.value_of("restore_id") {
+                            Some(restore_id) => Some(restore_id.parse()?),
+                            None => None,
+                        },
+                        matches.is_present("wait"),
+                        Duration::from_secs(
+                            config
+                                .restore
+                                .poll_interval_minutes
+                                .map_or(RESTORE_POLL_INTERVAL_SECONDS, |m| m * 60),
+                        ),
+                        &secrets,
+                    )
+                    .await
+                }
+                _ => Err(anyhow::Error::msg("unexpected subcommand")),
+            },
             _ => Err(anyhow::Error::msg("unexpected subcommand")),
         },
         None => Err(anyhow::Error::msg("no subcommand found")),
     }
 }
 
+/// The AWS credentials and region of the subcommands calling Glacier.
+struct Credentials {
+    secret_key: String,
+    key_id: String,
+    region: String,
+}
+
+impl Credentials {
+    fn new(matches: &ArgMatches, config: &Config, region: Option<&str>) -> Result<Self> {
+        Ok(Credentials {
+            secret_key: required(
+                matches.value_of("secret_key"),
+                config.secret_key()?,
+                "secret_key",
+            )?,
+            key_id: required(
+                matches.value_of("key_id"),
+                config.aws.key_id.clone(),
+                "key_id",
+            )?,
+            region: required(region, None, "region")?,
+        })
+    }
+}
+
+fn required_value<'a>(value: &'a Option<String>, name: &str) -> Result<&'a str> {
+    value
+        .as_deref()
+        .ok_or_else(|| anyhow::Error::msg(format!("argument \"{}\" is required", name)))
+}
+
+fn recipient_secret(matches: &ArgMatches) -> Result<Secret> {
+    load_secrets(
+        matches.value_of("recipient_key_file"),
+        matches.value_of("recipient_passphrase"),
+    )?
+    .pop()
+    .ok_or_else(|| anyhow::Error::msg("no recipient key file or passphrase provided"))
+}
+
+async fn list_snapshots(db_connection: &str) -> Result<()> {
+    let mut repo = Repository::new(db_connection).await?;
+    let trans = repo.get_transaction().await?;
+
+    for snapshot in Repository::get_snapshots(&trans).await? {
+        println!(
+            "{}\t{}\t{}\t{}\t{} bytes\t{}/{} new chunks",
+            snapshot.snapshot_id,
+            snapshot.creation_date.to_rfc3339(),
+            snapshot.host,
+            snapshot.backup_set.as_deref().unwrap_or("-"),
+            snapshot.size,
+            snapshot.new_chunk_count,
+            snapshot.chunk_count
+        );
+    }
+
+    Ok(())
+}
+
+async fn extract(
+    db_connection: Option<&str>,
+    input: &str,
+    destination: &str,
+    archive_id: Option<&str>,
+    mut secrets: Vec<Secret>,
+) -> Result<()> {
+    if let Some(archive_id) = archive_id {
+        let mut repo = Repository::new(
+            db_connection
+                .ok_or_else(|| anyhow::Error::msg("argument \"db_connection\" is required"))?,
+        )
+        .await?;
+        let trans = repo.get_transaction().await?;
+
+        secrets = restore::archive_secrets(&trans, archive_id, &secrets).await?;
+    }
+
+    let reader: Box<dyn Read> = match input {
+        "-" => Box::new(stdin()),
+        _ => Box::new(File::open(input)?),
+    };
+    let mut archive = tar::Archive::new(backup_reader::archive_reader(reader, &secrets)?);
+
+    archive.unpack(destination)?;
+    println!("extracted archive to \"{}\"", destination);
+
+    Ok(())
+}
+
+async fn find(
+    db_connection: &str,
+    glob: &str,
+    after: Option<DateTime<FixedOffset>>,
+    before: Option<DateTime<FixedOffset>>,
+) -> Result<()> {
+    let mut repo = Repository::new(db_connection).await?;
+    let trans = repo.get_transaction().await?;
+
+    for found in
+        Repository::find_catalog_entries(&trans, &glob_to_regex(glob), after, before).await?
+    {
+        println!(
+            "{}\t{}\t{}\t{}\t{}",
+            found.backup_date.to_rfc3339(),
+            found.archive_id,
+            found.entry.size,
+            found.entry.mtime.to_rfc3339(),
+            found.entry.path
+        );
+    }
+
+    Ok(())
+}
+
+async fn ls(
+    db_connection: &str,
+    directory: &str,
+    date: Option<DateTime<FixedOffset>>,
+) -> Result<()> {
+    let mut repo = Repository::new(db_connection).await?;
+    let trans = repo.get_transaction().await?;
+    let entries = Repository::list_catalog_directory(&trans, directory, date).await?;
+
+    match entries.first() {
+        Some(first) => println!(
+            "archive \"{}\" from {}",
+            first.archive_id,
+            first.backup_date.to_rfc3339()
+        ),
+        None => println!("\"{}\" not found in any backup", directory),
+    }
+
+    for found in entries {
+        println!(
+            "{:o}\t{}\t{}\t{}",
+            found.entry.mode & 0o7777,
+            found.entry.size,
+            found.entry.mtime.to_rfc3339(),
+            match found.entry.entry_type {
+                CatalogEntryType::Directory => format!("{}/", found.entry.path),
+                _ => found.entry.path,
+            }
+        );
+    }
+
+    Ok(())
+}
+
+async fn restore_start(
+    aws_glacier: &AwsGlacier,
+    db_connection: &str,
+    matches: &ArgMatches<'_>,
+    default_download_dir: Option<&str>,
+    prices: &PriceTable,
+    secrets: &[Secret],
+) -> Result<()> {
+    let paths: Vec<String> = matches
+        .values_of("path")
+        .map(|paths| paths.map(String::from).collect())
+        .unwrap_or_default();
+    let download_dir = match matches.value_of("download_dir").or(default_download_dir) {
+        Some(download_dir) => download_dir.into(),
+        None => env::temp_dir(),
+    };
+    let tier = matches.value_of("tier").unwrap();
+    let destination = std::path::absolute(matches.value_of("destination").unwrap())?;
+    let download_dir = std::path::absolute(download_dir)?;
+    let mut repo = Repository::new(db_connection).await?;
+    let trans = repo.get_transaction().await?;
+    let restores = match (
+        matches.value_of("archive_id"),
+        matches.value_of("snapshot_id"),
+    ) {
+        (_, Some(snapshot_id)) => {
+            restore::snapshot_restores(
+                &trans,
+                &snapshot_id.parse()?,
+                tier,
+                &destination.to_string_lossy(),
+                &download_dir.to_string_lossy(),
+            )
+            .await?
+        }
+        (Some(archive_id), None) => {
+            restore::backup_restores(
+                &trans,
+                archive_id,
+                tier,
+                &destination.to_string_lossy(),
+                &paths,
+                &download_dir.to_string_lossy(),
+            )
+            .await?
+        }
+        (None, None) => return Err(anyhow::Error::msg("no archive or snapshot given")),
+    };
+
+    let retrieval_size: i64 = restores.iter().map(|r| r.retrieval_size()).sum();
+
+    println!(
+        "retrieving {} bytes with {} job(s) ({}) costs about ${:.2}",
+        retrieval_size,
+        restores.len(),
+        tier,
+        prices.retrieval_cost(tier, retrieval_size, restores.len() as i64)?
+    );
+
+    for restore in &restores {
+        Repository::create_restore(&trans, restore).await?;
+    }
+
+    trans.commit().await?;
+
+    for restore in restores {
+        let restore = restore::process_restore(
+            aws_glacier,
+            &mut repo,
+            db_connection,
+            &restore.restore_id,
+            secrets,
+        )
+        .await?;
+
+        println!(
+            "restore \"{}\" is {}",
+            restore.restore_id,
+            restore.state.as_str()
+        );
+    }
+
+    Ok(())
+}
+
+async fn restore_status(db_connection: &str, restore_id: Option<Uuid>) -> Result<()> {
+    let mut repo = Repository::new(db_connection).await?;
+    let trans = repo.get_transaction().await?;
+    let restores = match restore_id {
+        Some(restore_id) => vec![Repository::get_restore(&trans, &restore_id).await?],
+        None => Repository::get_restores(&trans, false).await?,
+    };
+
+    for restore in restores {
+        println!(
+            "{}\t{}\t{}\t{}/{} bytes\t{}\t{}{}",
+            restore.restore_id,
+            restore.archive_id,
+            restore.state.as_str(),
+            restore.downloaded_size,
+            restore.retrieval_size(),
+            restore.update_date.to_rfc3339(),
+            restore.destination,
+            match &restore.error {
+                Some(error) => format!("\t{}", error),
+                None => String::new(),
+            }
+        );
+    }
+
+    Ok(())
+}
+
+async fn restore_run(
+    aws_glacier: &AwsGlacier,
+    db_connection: &str,
+    restore_id: Option<Uuid>,
+    wait: bool,
+    poll_interval: Duration,
+    secrets: &[Secret],
+) -> Result<()> {
+    let mut repo = Repository::new(db_connection).await?;
+
+    loop {
+        let trans = repo.get_transaction().await?;
+        let restore_ids: Vec<Uuid> = match restore_id {
+            Some(restore_id) => vec![restore_id],
+            None => Repository::get_restores(&trans, true)
+                .await?
+                .iter()
+                .map(|r| r.restore_id)
+                .collect(),
+        };
+
+        trans.commit().await?;
+
+        let mut unfinished = 0;
+
+        for restore_id in restore_ids {
+            match restore::process_restore(
+                aws_glacier,
+                &mut repo,
+                db_connection,
+                &restore_id,
+                secrets,
+            )
+            .await
+            {
+                Ok(restore) => {
+                    println!("restore \"{}\" is {}", restore_id, restore.state.as_str());
+
+                    if !restore.state.is_finished() {
+                        unfinished += 1;
+                    }
+                }
+                Err(e) => {
+                    warn!("restore \"{}\" will be retried: {}", restore_id, e);
+                    unfinished += 1;
+                }
+            }
+        }
+
+        if !wait || unfinished == 0 {
+            return Ok(());
+        }
+
+        sleep(poll_interval).await;
+    }
+}
+
+async fn set_retention(
+    aws_glacier: &AwsGlacier,
+    db_connection: &str,
+    matches: &ArgMatches<'_>,
+) -> Result<()> {
+    let vault = aws_glacier
+        .describe_vault(matches.value_of("vault_name").unwrap())
+        .await?;
+    let policy = RetentionPolicy {
+        vault_arn: vault.vault_arn.clone(),
+        backup_set: matches.value_of("backup_set").map(String::from),
+        keep_last: matches.value_of("keep_last").unwrap().parse()?,
+        keep_daily: matches.value_of("keep_daily").unwrap().parse()?,
+        keep_weekly: matches.value_of("keep_weekly").unwrap().parse()?,
+        keep_monthly: matches.value_of("keep_monthly").unwrap().parse()?,
+    };
+
+    if policy.keep_last + policy.keep_daily + policy.keep_weekly + policy.keep_monthly <= 0 {
+        return Err(anyhow::Error::msg(
+            "a retention policy must keep at least one archive",
+        ));
+    }
+
+    let mut repo = Repository::new(db_connection).await?;
+    let trans = repo.get_transaction().await?;
+    let dedup_sets = Repository::get_snapshot_backup_sets(&trans, &vault.vault_arn).await?;
+
+    // packs are shared by the snapshots of a deduplicated backup set, so they cannot expire with a snapshot
+    if let Some(backup_set) = policy.backup_set.as_ref() {
+        if dedup_sets.contains(&policy.backup_set) {
+            return Err(anyhow::Error::msg(format!(
+                "backup set \"{}\" is deduplicated and cannot be pruned",
+                backup_set
+            )));
+        }
+    } else if !dedup_sets.is_empty() {
+        println!(
+            "the policy does not apply to the deduplicated backup sets of vault \"{}\": {}",
+            vault.vault_name,
+            backup_set_names(&dedup_sets)
+        );
+    }
+
+    Repository::upsert_vault(&trans, &vault).await?;
+    Repository::upsert_retention_policy(&trans, &policy).await?;
+    trans.commit().await?;
+
+    Ok(())
+}
+
+async fn list_retention(db_connection: &str) -> Result<()> {
+    let mut repo = Repository::new(db_connection).await?;
+    let trans = repo.get_transaction().await?;
+
+    for policy in Repository::get_retention_policies(&trans).await? {
+        println!(
+            "{}\t{}\tlast {}\tdaily {}\tweekly {}\tmonthly {}",
+            policy.vault_arn,
+            policy.backup_set.as_deref().unwrap_or("*"),
+            policy.keep_last,
+            policy.keep_daily,
+            policy.keep_weekly,
+            policy.keep_monthly
+        );
+    }
+
+    Ok(())
+}
+
+/// Shows the monthly storage cost of the vaults, and what deleting their archives now would be charged for the rest of the minimum storage duration.
+async fn storage_cost(
+    db_connection: &str,
+    prices: &PriceTable,
+    vault_name: Option<&str>,
+) -> Result<()> {
+    let mut repo = Repository::new(db_connection).await?;
+    let trans = repo.get_transaction().await?;
+    let now = Utc::now();
+    let since = now - chrono::Duration::days(retention::MINIMUM_STORAGE_DAYS);
+    let mut total = 0.0;
+
+    for vault in Repository::get_vaults(&trans).await? {
+        if vault_name.is_some_and(|name| name != vault.vault_name) {
+            continue;
+        }
+
+        let monthly = prices.monthly_storage_cost(vault.size_in_bytes, vault.number_of_archives);
+        let early_deletion: f64 =
+            Repository::get_vault_archives_since(&trans, &vault, since.into())
+                .await?
+                .iter()
+                .map(|a| prices.early_deletion_cost(a.size, a.creation_date, now))
+                .sum();
+
+        total += monthly;
+        println!(
+            "{}	{} archives	{} bytes	${:.2}/month	${:.2} early deletion",
+            vault.vault_name,
+            vault.number_of_archives,
+            vault.size_in_bytes,
+            monthly,
+            early_deletion
+        );
+    }
+
+    trans.commit().await?;
+    println!("total	${:.2}/month", total);
+
+    Ok(())
+}
+
+/// Shows the cost of retrieving archives, each with its own job, or a number of bytes with a single job.
+async fn retrieval_cost(
+    db_connection: Option<&str>,
+    prices: &PriceTable,
+    tier: &str,
+    archive_ids: Option<Vec<&str>>,
+    bytes: Option<i64>,
+) -> Result<()> {
+    let (size, requests) = match (archive_ids, bytes) {
+        (Some(archive_ids), _) => {
+            let mut repo = Repository::new(
+                db_connection
+                    .ok_or_else(|| anyhow::Error::msg("argument \"db_connection\" is required"))?,
+            )
+            .await?;
+            let trans = repo.get_transaction().await?;
+            let mut size = 0;
+
+            for archive_id in &archive_ids {
+                size += Repository::get_archive(&trans, archive_id).await?.size;
+            }
+
+            trans.commit().await?;
+            (size, archive_ids.len() as i64)
+        }
+        (None, Some(bytes)) => (bytes, 1),
+        (None, None) => return Err(anyhow::Error::msg("no archive or size given")),
+    };
+
+    println!(
+        "retrieving {} bytes with {} job(s) ({}) costs about ${:.2}",
+        size,
+        requests,
+        tier,
+        prices.retrieval_cost(tier, size, requests)?
+    );
+
+    Ok(())
+}
+
+/// Applies the retention policies to the archives of backups. Without `delete`, only the plan is shown.
+async fn prune(
+    aws_glacier: &AwsGlacier,
+    db_connection: &str,
+    vault_name: Option<&str>,
+    delete: bool,
+) -> Result<()> {
+    let mut repo = Repository::new(db_connection).await?;
+    let trans = repo.get_transaction().await?;
+    let policies = Repository::get_retention_policies(&trans).await?;
+    let mut vaults = Vec::new();
+
+    for vault in Repository::get_vaults(&trans).await? {
+        if vault_name.is_none_or(|name| name == vault.vault_name)
+            && policies.iter().any(|p| p.vault_arn == vault.vault_arn)
+        {
+            let candidates = Repository::get_prune_candidates(&trans, &vault).await?;
+            let dedup_sets: Vec<Option<String>> =
+                Repository::get_snapshot_backup_sets(&trans, &vault.vault_arn)
+                    .await?
+                    .into_iter()
+                    .filter(|s| {
+                        RetentionPolicy::find(&policies, &vault.vault_arn, s.as_deref()).is_some()
+                    })
+                    .collect();
+
+            if !dedup_sets.is_empty() {
+                println!(
+                    "not pruning the deduplicated backup sets of vault \"{}\": {}",
+                    vault.vault_name,
+                    backup_set_names(&dedup_sets)
+                );
+            }
+
+            vaults.push((vault, candidates));
+        }
+    }
+
+    trans.commit().await?;
+
+    for (vault, candidates) in vaults {
+        let plan = retention::prune_plan(&policies, &candidates, Utc::now());
+        let mut deleted_size = 0;
+
+        for item in &plan {
+            let action = match &item.action {
+                PruneAction::Keep(reason) => format!("keep ({})", reason),
+                PruneAction::Delete => "delete".into(),
+                PruneAction::Defer(date) => format!("delete after {}", date.to_rfc3339()),
+            };
+
+            println!(
+                "{}\t{}\t{}\t{} bytes\t{}",
+                vault.vault_name,
+                item.candidate.creation_date.to_rfc3339(),
+                item.candidate.archive_id,
+                item.candidate.size,
+                action
+            );
+
+            if delete && item.action == PruneAction::Delete {
+                aws_glacier
+                    .delete_archive(&vault, &item.candidate.archive_id)
+                    .await?;
+
+                let trans = repo.get_transaction().await?;
+
+                Repository::create_archive_deletion(&trans, &vault, &item.candidate.archive_id)
+                    .await?;
+                trans.commit().await?;
+                deleted_size += item.candidate.size;
+            }
+        }
+
+        if delete {
+            println!(
+                "deleted {} bytes from vault \"{}\"",
+                deleted_size, vault.vault_name
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn backup_set_names(backup_sets: &[Option<String>]) -> String {
+    backup_sets
+        .iter()
+        .map(|s| match s {
+            Some(s) => format!("\"{}\"", s),
+            None => "(no backup set)".into(),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+async fn list_keys(db_connection: &str) -> Result<()> {
+    let mut repo = Repository::new(db_connection).await?;
+    let trans = repo.get_transaction().await?;
+
+    for key in Repository::get_keys(&trans).await? {
+        println!(
+            "{}\t{}\t{}\t{}\t{} archives",
+            key.key_id,
+            key.key_type,
+            if key.active { "active" } else { "inactive" },
+            key.creation_date.to_rfc3339(),
+            Repository::count_archive_keys(&trans, &key.key_id).await?
+        );
+    }
+
+    Ok(())
+}
+
+async fn add_recipient(
+    db_connection: &str,
+    key_id: &str,
+    secret: &Secret,
+    active: bool,
+) -> Result<()> {
+    let mut repo = Repository::new(db_connection).await?;
+    let trans = repo.get_transaction().await?;
+    let (mut key, _) = RecipientKey::new(key_id, secret)?;
+
+    key.active = active;
+    Repository::create_key(&trans, &key).await?;
+    trans.commit().await?;
+    println!("added key \"{}\"", key_id);
+
+    Ok(())
+}
+
+async fn retire_key(db_connection: &str, key_id: &str) -> Result<()> {
+    let mut repo = Repository::new(db_connection).await?;
+    let trans = repo.get_transaction().await?;
+
+    Repository::set_key_active(&trans, key_id, false).await?;
+    trans.commit().await?;
+    println!("retired key \"{}\"", key_id);
+
+    Ok(())
+}
+
+async fn rewrap_keys(
+    db_connection: &str,
+    key_id: &str,
+    recipient_secret: &Secret,
+    secrets: &[Secret],
+    remove_key_id: Option<&str>,
+) -> Result<()> {
+    let mut repo = Repository::new(db_connection).await?;
+    let trans = repo.get_transaction().await?;
+    let recipients = Repository::get_keys(&trans).await?;
+    let recipient = recipients
+        .iter()
+        .find(|k| k.key_id == key_id)
+        .ok_or_else(|| anyhow::Error::msg(format!("key \"{}\" not found", key_id)))?
+        .unlock(recipient_secret)?
+        .ok_or_else(|| {
+            anyhow::Error::msg(format!("the secret does not belong to key \"{}\"", key_id))
+        })?;
+    let key_ring = KeyRing::new(&recipients, secrets)?;
+    let archive_keys = Repository::get_archive_keys_missing_key(&trans, key_id).await?;
+    let mut rewrapped = Vec::new();
+    let mut skipped = 0;
+
+    for archive in archive_keys.chunk_by(|a, b| a.archive_id == b.archive_id) {
+        match key_ring.unwrap_data_key(archive) {
+            Ok(data_key) => rewrapped.push(ArchiveKey {
+                archive_id: archive[0].archive_id.clone(),
+                key_id: key_id.into(),
+                wrapped_key: recipient.wrap(&data_key)?,
+            }),
+            Err(e) => {
+                warn!("skipping archive \"{}\": {}", archive[0].archive_id, e);
+                skipped += 1;
+            }
+        }
+    }
+
+    Repository::upsert_archive_keys(&trans, &rewrapped).await?;
+
+    let removed = match remove_key_id {
+        Some(remove_key_id) => Repository::delete_archive_keys(&trans, remove_key_id).await?,
+        None => 0,
+    };
+
+    trans.commit().await?;
+    println!(
+        "rewrapped {} data keys for \"{}\" ({} skipped, {} removed)",
+        rewrapped.len(),
+        key_id,
+        skipped,
+        removed
+    );
+
+    Ok(())
+}
+
+async fn reprocess_inventory(db_connection: &str, inventory_dir: &str, job_id: &str) -> Result<()> {
+    let mut repo = Repository::new(db_connection).await?;
+    let inventory_store = InventoryStore::new(inventory_dir)?;
+    let trans = repo.get_transaction().await?;
+    let job = Repository::get_job_by_id(&trans, job_id).await?;
+    let vault = Repository::get_vault_by_arn(&trans, &job.vault_arn).await?;
+    let archive_count =
+        inventory::import_stored_output(&trans, &vault, &job, &inventory_store).await?;
+
+    trans.commit().await?;
+    println!(
+        "reprocessed inventory \"{}\" with {} archives",
+        job_id, archive_count
+    );
+
+    Ok(())
+}
+
 async fn job_output(
     secret_key: &str,
     key_id: &str,
@@ -126,8 +1613,8 @@ async fn job_output(
     )
     .parse::<Uri>()?;
     let hash_body = sha_256_hash(body.as_bytes())?;
-    let hash_request = hash_request(http_method, &uri, &date_time, &*hash_body)?;
-    let signature = signature(&*secret_key, &date_time, &*region, &*hash_request)?;
+    let hash_request = hash_request(http_method, &uri, &date_time, &hash_body)?;
+    let signature = signature(secret_key, &date_time, region, &hash_request)?;
     let req = Request::builder()
         .method(http_method)
         .uri(uri)
@@ -158,8 +1645,8 @@ async fn list_jobs(secret_key: &str, key_id: &str, region: &str, vault_name: &st
     )
     .parse::<Uri>()?;
     let hash_body = sha_256_hash(body.as_bytes())?;
-    let hash_request = hash_request(http_method, &uri, &date_time, &*hash_body)?;
-    let signature = signature(&*secret_key, &date_time, &*region, &*hash_request)?;
+    let hash_request = hash_request(http_method, &uri, &date_time, &hash_body)?;
+    let signature = signature(secret_key, &date_time, region, &hash_request)?;
     let req = Request::builder()
         .method(http_method)
         .uri(uri)
@@ -183,9 +1670,10 @@ async fn init_inventory_retrieval(
     key_id: &str,
     region: &str,
     vault_name: &str,
+    parameters: &AwsInventoryRetrievalParameters,
 ) -> Result<()> {
     let http_method = "POST";
-    let body = format!("{{\"Type\": \"inventory-retrieval\", \"Description\": \"{} inventory job\", \"Format\": \"JSON\"}}", vault_name);
+    let body = parameters.request_body(&format!("{} inventory job", vault_name))?;
     let https = HttpsConnector::new();
     let client = Client::builder().build::<_, hyper::Body>(https);
     let date_time = Utc::now();
@@ -195,8 +1683,8 @@ async fn init_inventory_retrieval(
     )
     .parse::<Uri>()?;
     let hash_body = sha_256_hash(body.as_bytes())?;
-    let hash_request = hash_request(http_method, &uri, &date_time, &*hash_body)?;
-    let signature = signature(&*secret_key, &date_time, &*region, &*hash_request)?;
+    let hash_request = hash_request(http_method, &uri, &date_time, &hash_body)?;
+    let signature = signature(secret_key, &date_time, region, &hash_request)?;
     let req = Request::builder()
         .method(http_method)
         .uri(uri)
@@ -221,8 +1709,8 @@ async fn list_vaults(secret_key: &str, key_id: &str, region: &str) -> Result<()>
     let date_time = Utc::now();
     let uri = format!("https://glacier.{}.amazonaws.com/-/vaults", region).parse::<Uri>()?;
     let hash_body = sha_256_hash(&[])?;
-    let hash_request = hash_request("GET", &uri, &date_time, &*hash_body)?;
-    let signature = signature(&*secret_key, &date_time, &*region, &*hash_request)?;
+    let hash_request = hash_request("GET", &uri, &date_time, &hash_body)?;
+    let signature = signature(secret_key, &date_time, region, &hash_request)?;
     let req = Request::builder()
         .method("GET")
         .uri(uri)
@@ -0,0 +1,142 @@
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset, Utc};
+use std::convert::TryFrom;
+use std::str::FromStr;
+use tokio_postgres::Row;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CatalogEntryType {
+    File,
+    Directory,
+    Symlink,
+    Other,
+}
+
+impl CatalogEntryType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CatalogEntryType::File => "file",
+            CatalogEntryType::Directory => "directory",
+            CatalogEntryType::Symlink => "symlink",
+            CatalogEntryType::Other => "other",
+        }
+    }
+}
+
+impl FromStr for CatalogEntryType {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "file" => Ok(CatalogEntryType::File),
+            "directory" => Ok(CatalogEntryType::Directory),
+            "symlink" => Ok(CatalogEntryType::Symlink),
+            "other" => Ok(CatalogEntryType::Other),
+            _ => Err(anyhow::Error::msg(format!(
+                "unknown catalog entry type \"{}\"",
+                value
+            ))),
+        }
+    }
+}
+
+/// A file, directory or link contained in an archive.
+#[derive(Debug, Clone)]
+pub struct CatalogEntry {
+    /// Absolute path of the entry on the source host.
+    pub path: String,
+    pub entry_type: CatalogEntryType,
+    pub size: i64,
+    pub mtime: DateTime<Utc>,
+    pub mode: i32,
+    /// SHA256 hash of the content of regular files.
+    pub content_hash: Option<String>,
+    /// Offset of the first tar header of the entry (including GNU long name headers) within the uncompressed tar stream.
+    pub offset: i64,
+    pub inode: Option<i64>,
+    /// Archive holding the content, if an incremental backup took the unchanged entry from an earlier backup. The offset refers to that archive.
+    pub data_archive_id: Option<String>,
+}
+
+impl TryFrom<&Row> for CatalogEntry {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &Row) -> Result<Self, Self::Error> {
+        Ok(CatalogEntry {
+            path: value.try_get("path")?,
+            entry_type: value.try_get::<_, &str>("entry_type")?.parse()?,
+            size: value.try_get("size")?,
+            mtime: value.try_get("mtime")?,
+            mode: value.try_get("mode")?,
+            content_hash: value.try_get("content_hash")?,
+            offset: value.try_get("offset")?,
+            inode: value.try_get("inode")?,
+            data_archive_id: value.try_get("data_archive_id")?,
+        })
+    }
+}
+
+/// A catalog entry found in an archive together with the date of the backup.
+#[derive(Debug)]
+pub struct CatalogMatch {
+    pub archive_id: String,
+    pub backup_date: DateTime<FixedOffset>,
+    pub entry: CatalogEntry,
+}
+
+impl TryFrom<&Row> for CatalogMatch {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &Row) -> Result<Self, Self::Error> {
+        Ok(CatalogMatch {
+            archive_id: value.try_get("archive_id")?,
+            backup_date: value.try_get("backup_date")?,
+            entry: CatalogEntry::try_from(value)?,
+        })
+    }
+}
+
+/// Converts a path glob into an anchored POSIX regular expression as used by Postgres.
+///
+/// `*` and `?` do not match `/`, while `**` matches across directories.
+pub fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    let mut chars = glob.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => {
+                if chars.peek() == Some(&'*') {
+                    chars.next();
+                    regex.push_str(".*");
+                } else {
+                    regex.push_str("[^/]*");
+                }
+            }
+            '?' => regex.push_str("[^/]"),
+            '.' | '+' | '(' | ')' | '|' | '^' | '$' | '{' | '}' | '[' | ']' | '\\' => {
+                regex.push('\\');
+                regex.push(c);
+            }
+            _ => regex.push(c),
+        }
+    }
+
+    regex.push('$');
+    regex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_to_regex_1() {
+        assert_eq!(
+            glob_to_regex("/etc/nginx/*.conf"),
+            "^/etc/nginx/[^/]*\\.conf$"
+        );
+        assert_eq!(glob_to_regex("/var/**/log?"), "^/var/.*/log[^/]$");
+        assert_eq!(glob_to_regex("/srv/a+b(1)"), "^/srv/a\\+b\\(1\\)$");
+    }
+}
@@ -0,0 +1,482 @@
+use crate::metrics;
+use crate::shutdown;
+use anyhow::Result;
+use log::{debug, warn};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use tokio::time::{sleep, Duration};
+
+/// Environment variable naming the configuration file.
+pub const CONFIG_ENV: &str = "BACKUP_REMOTE_CONFIG";
+
+/// Settings shared by all binaries, read from a TOML file.
+///
+/// Every value can be overridden by the corresponding environment variable, which in turn is overridden by the command line flag.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub aws: AwsConfig,
+    pub db: DbConfig,
+    pub encryption: EncryptionConfig,
+    pub updater: UpdaterConfig,
+    pub worker: WorkerConfig,
+    pub retry: RetryPolicy,
+    pub restore: RestoreConfig,
+    pub api: ApiConfig,
+    pub metrics: MetricsConfig,
+    pub health: HealthConfig,
+    pub price_file: Option<String>,
+    /// Time the daemons give in-flight work to finish after SIGTERM or SIGINT.
+    pub shutdown_timeout_seconds: Option<u64>,
+    pub backup_sets: BTreeMap<String, BackupSetConfig>,
+    /// Schedules of the daemon tasks by task name.
+    pub daemon: BTreeMap<String, TaskConfig>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AwsConfig {
+    pub region: Option<String>,
+    pub key_id: Option<String>,
+    pub secret_key: Option<String>,
+    /// File containing the secret key, so it does not have to be stored in the configuration.
+    pub secret_key_file: Option<String>,
+    /// Base URL of the Glacier API, e.g. for a proxy; defaults to the endpoint of the region.
+    pub endpoint: Option<String>,
+}
+
+/// The database is given either as connection string or by its parts.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DbConfig {
+    pub connection: Option<String>,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    pub password_file: Option<String>,
+    pub dbname: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EncryptionConfig {
+    pub key_file: Option<String>,
+    pub passphrase_file: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UpdaterConfig {
+    pub interval_minutes: Option<u64>,
+    pub inventory_format: Option<String>,
+    pub incremental_inventory: Option<bool>,
+    pub inventory_limit: Option<i64>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorkerConfig {
+    pub interval_minutes: Option<u64>,
+    pub inventory_dir: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RestoreConfig {
+    pub download_dir: Option<String>,
+    pub poll_interval_minutes: Option<u64>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiConfig {
+    /// Address the API server listens on, e.g. "127.0.0.1:8080".
+    pub listen: Option<String>,
+    /// Tokens accepted by the API server by name.
+    pub tokens: BTreeMap<String, ApiTokenConfig>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// Address the daemons serve their metrics and health on, e.g. "127.0.0.1:9090"; nothing is served without one.
+    pub listen: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthConfig {
+    /// Time after which a running cycle makes the liveness check fail.
+    pub stall_minutes: Option<u64>,
+    /// Time without a successful cycle, after which the daemons exit; they never do without one.
+    pub exit_after_minutes: Option<u64>,
+}
+
+/// A token is given either in the configuration or in a file.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiTokenConfig {
+    pub token: Option<String>,
+    pub token_file: Option<String>,
+    /// Names of the vaults, for which jobs may be requested with the token; "*" stands for all vaults.
+    pub vaults: Vec<String>,
+}
+
+impl ApiTokenConfig {
+    /// Returns the token from the configuration or the token file, which must not be empty.
+    pub fn token(&self, name: &str) -> Result<String> {
+        let token = match (&self.token, &self.token_file) {
+            (Some(token), _) => token.clone(),
+            (None, Some(file)) => read_secret(file)?,
+            (None, None) => {
+                return Err(anyhow::Error::msg(format!(
+                    "API token \"{}\" has neither a token nor a token file",
+                    name
+                )))
+            }
+        };
+
+        if token.is_empty() {
+            return Err(anyhow::Error::msg(format!(
+                "API token \"{}\" is empty",
+                name
+            )));
+        }
+
+        Ok(token)
+    }
+}
+
+/// A named backup, so its vault, paths and options need not be repeated on every run.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackupSetConfig {
+    pub vault_name: Option<String>,
+    pub paths: Vec<String>,
+    pub compression: Option<String>,
+    pub encryption: Option<String>,
+    pub part_size: Option<usize>,
+    pub incremental: bool,
+    pub full_every: Option<i32>,
+    pub dedup: bool,
+    /// The daemon runs the backup at the times of the cron expression or in the interval.
+    pub cron: Option<String>,
+    pub interval_minutes: Option<u64>,
+}
+
+/// When and how the daemon runs a task.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TaskConfig {
+    pub enabled: Option<bool>,
+    pub interval_minutes: Option<u64>,
+    pub cron: Option<String>,
+    /// Number of runs of the task, which may overlap.
+    pub concurrency: Option<usize>,
+    /// Delay before a failed task is run again, doubled for every further failure.
+    pub backoff_seconds: Option<u64>,
+    pub max_backoff_seconds: Option<u64>,
+}
+
+/// How often a failing round of the updater or worker is retried before waiting for the next one.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryPolicy {
+    /// Number of attempts including the first one.
+    pub attempts: u32,
+    /// Delay before the first retry, doubled for every further one.
+    pub delay_seconds: u64,
+    pub max_delay_seconds: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: 3,
+            delay_seconds: 60,
+            max_delay_seconds: 15 * 60,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Returns the delay before the given retry, starting with 1.
+    pub fn delay(&self, retry: u32) -> Duration {
+        let factor = 1u64
+            .checked_shl(retry.saturating_sub(1))
+            .unwrap_or(u64::MAX);
+
+        Duration::from_secs(
+            self.delay_seconds
+                .saturating_mul(factor)
+                .min(self.max_delay_seconds),
+        )
+    }
+
+    /// Runs the operation until it succeeds or all attempts failed, returning the last error.
+    ///
+    /// The retries are counted in the metrics of the given task.
+    pub async fn run<F, Fut, T>(&self, task: &str, mut operation: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut retry = 0;
+
+        loop {
+            match operation().await {
+                Ok(value) => return Ok(value),
+                // no further attempts once a shutdown is requested
+                Err(e) if retry + 1 < self.attempts && !shutdown::is_requested() => {
+                    retry += 1;
+                    metrics::RETRIES.inc(&[task]);
+                    warn!(
+                        "attempt {} of {} failed, retrying in {:?}: {:?}",
+                        retry,
+                        self.attempts,
+                        self.delay(retry),
+                        e
+                    );
+                    tokio::select! {
+                        _ = sleep(self.delay(retry)) => {}
+                        _ = shutdown::requested() => return Err(e),
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Config {
+    /// Loads the configuration from the given file or, without one, from the first existing default location.
+    ///
+    /// Without any file, the empty configuration is returned.
+    pub fn load(path: Option<&str>) -> Result<Self> {
+        let path = match path {
+            Some(path) => PathBuf::from(path),
+            None => match default_paths().into_iter().find(|p| p.is_file()) {
+                Some(path) => path,
+                None => {
+                    debug!("no configuration file found");
+                    return Ok(Config::default());
+                }
+            },
+        };
+
+        debug!("loading configuration from \"{}\"", path.display());
+        Config::parse(&fs::read_to_string(&path).map_err(|e| {
+            anyhow::Error::msg(format!(
+                "error reading configuration \"{}\": {}",
+                path.display(),
+                e
+            ))
+        })?)
+    }
+
+    pub fn parse(content: &str) -> Result<Self> {
+        toml::from_str(content)
+            .map_err(|e| anyhow::Error::msg(format!("error parsing configuration: {}", e)))
+    }
+
+    /// Returns the secret key from the configuration or the secret key file.
+    pub fn secret_key(&self) -> Result<Option<String>> {
+        match (&self.aws.secret_key, &self.aws.secret_key_file) {
+            (Some(secret_key), _) => Ok(Some(secret_key.clone())),
+            (None, Some(file)) => Ok(Some(read_secret(file)?)),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// Returns the database connection string, building it from its parts if no connection string is configured.
+    pub fn db_connection(&self) -> Result<Option<String>> {
+        let db = &self.db;
+
+        if db.connection.is_some() {
+            return Ok(db.connection.clone());
+        }
+
+        let password = match (&db.password, &db.password_file) {
+            (Some(password), _) => Some(password.clone()),
+            (None, Some(file)) => Some(read_secret(file)?),
+            (None, None) => None,
+        };
+        let parts: Vec<String> = [
+            ("host", db.host.clone()),
+            ("port", db.port.map(|p| p.to_string())),
+            ("user", db.user.clone()),
+            ("password", password),
+            ("dbname", db.dbname.clone()),
+        ]
+        .iter()
+        .filter_map(|(name, value)| {
+            value.as_ref().map(|v| {
+                format!(
+                    "{}='{}'",
+                    name,
+                    v.replace('\\', "\\\\").replace('\'', "\\'")
+                )
+            })
+        })
+        .collect();
+
+        match parts.is_empty() {
+            true => Ok(None),
+            false => Ok(Some(parts.join(" "))),
+        }
+    }
+
+    /// Returns the passphrase from the passphrase file.
+    pub fn passphrase(&self) -> Result<Option<String>> {
+        match &self.encryption.passphrase_file {
+            Some(file) => Ok(Some(read_secret(file)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn backup_set(&self, name: &str) -> Option<&BackupSetConfig> {
+        self.backup_sets.get(name)
+    }
+}
+
+/// The locations searched for a configuration file, in order.
+pub fn default_paths() -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::from("backup-remote.toml")];
+
+    match (env::var_os("XDG_CONFIG_HOME"), env::var_os("HOME")) {
+        (Some(config_home), _) => {
+            paths.push(Path::new(&config_home).join("backup-remote/config.toml"))
+        }
+        (None, Some(home)) => {
+            paths.push(Path::new(&home).join(".config/backup-remote/config.toml"))
+        }
+        (None, None) => {}
+    }
+
+    paths.push(PathBuf::from("/etc/backup-remote/config.toml"));
+    paths
+}
+
+/// Returns the value given on the command line or in the environment, falling back to the configuration file.
+pub fn required(value: Option<&str>, config: Option<String>, name: &str) -> Result<String> {
+    value.map(String::from).or(config).ok_or_else(|| {
+        anyhow::Error::msg(format!(
+            "\"{}\" is neither given as argument, environment variable nor in the configuration",
+            name
+        ))
+    })
+}
+
+fn read_secret(file: &str) -> Result<String> {
+    Ok(fs::read_to_string(file)
+        .map_err(|e| anyhow::Error::msg(format!("error reading \"{}\": {}", file, e)))?
+        .trim_end()
+        .into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_1() {
+        let config = Config::parse(
+            r#"
+            price_file = "/etc/backup-remote/prices.json"
+
+            [aws]
+            region = "eu-central-1"
+            key_id = "key id"
+            endpoint = "https://glacier.example.com"
+
+            [db]
+            host = "localhost"
+            port = 5432
+            user = "worker"
+            password = "it's secret"
+            dbname = "backup_remote"
+
+            [worker]
+            interval_minutes = 10
+
+            [retry]
+            attempts = 5
+
+            [metrics]
+            listen = "127.0.0.1:9090"
+
+            [backup_sets.etc]
+            vault_name = "vault"
+            paths = ["/etc"]
+            incremental = true
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.aws.region.as_deref(), Some("eu-central-1"));
+        assert_eq!(config.secret_key().unwrap(), None);
+        assert_eq!(
+            config.db_connection().unwrap().as_deref(),
+            Some("host='localhost' port='5432' user='worker' password='it\\'s secret' dbname='backup_remote'")
+        );
+        assert_eq!(config.worker.interval_minutes, Some(10));
+        assert_eq!(config.updater.interval_minutes, None);
+        assert_eq!(config.metrics.listen.as_deref(), Some("127.0.0.1:9090"));
+        assert_eq!(
+            config.retry,
+            RetryPolicy {
+                attempts: 5,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            config.backup_set("etc"),
+            Some(&BackupSetConfig {
+                vault_name: Some("vault".into()),
+                paths: vec!["/etc".into()],
+                incremental: true,
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn parse_2() {
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+        assert_eq!(Config::default().db_connection().unwrap(), None);
+        assert!(Config::parse("[aws]\nregion = 1").is_err());
+        assert!(Config::parse("[aws]\nsecret = \"typo\"").is_err());
+    }
+
+    #[test]
+    fn api_token_1() {
+        let token = ApiTokenConfig {
+            token: Some("secret".into()),
+            ..Default::default()
+        };
+
+        assert_eq!(token.token("ops").unwrap(), "secret");
+        assert!(ApiTokenConfig::default().token("ops").is_err());
+        assert!(ApiTokenConfig {
+            token: Some("".into()),
+            ..Default::default()
+        }
+        .token("ops")
+        .is_err());
+    }
+
+    #[test]
+    fn retry_policy_1() {
+        let policy = RetryPolicy::default();
+
+        assert_eq!(policy.delay(1), Duration::from_secs(60));
+        assert_eq!(policy.delay(2), Duration::from_secs(120));
+        assert_eq!(policy.delay(5), Duration::from_secs(900));
+        assert_eq!(policy.delay(100), Duration::from_secs(900));
+    }
+}
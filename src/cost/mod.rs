@@ -0,0 +1,154 @@
+use crate::retention::MINIMUM_STORAGE_DAYS;
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset, Utc};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+
+const GIB: f64 = 1024.0 * 1024.0 * 1024.0;
+/// Glacier stores an index of 32 KiB with every archive, which is charged like the archive.
+pub const ARCHIVE_OVERHEAD_BYTES: i64 = 32 * 1024;
+/// Days of a month as used to prorate charges.
+const DAYS_PER_MONTH: f64 = 30.0;
+
+/// Prices of a region in USD.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct PriceTable {
+    pub storage_per_gib_month: f64,
+    pub retrieval_per_gib: TierPrices,
+    pub retrieval_per_1000_requests: TierPrices,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct TierPrices {
+    pub expedited: f64,
+    pub standard: f64,
+    pub bulk: f64,
+}
+
+impl TierPrices {
+    pub fn get(&self, tier: &str) -> Result<f64> {
+        match tier {
+            "Expedited" => Ok(self.expedited),
+            "Standard" => Ok(self.standard),
+            "Bulk" => Ok(self.bulk),
+            _ => Err(anyhow::Error::msg(format!("unknown tier \"{}\"", tier))),
+        }
+    }
+}
+
+impl Default for PriceTable {
+    /// The list prices of us-east-1.
+    fn default() -> Self {
+        PriceTable {
+            storage_per_gib_month: 0.0036,
+            retrieval_per_gib: TierPrices {
+                expedited: 0.03,
+                standard: 0.01,
+                bulk: 0.0025,
+            },
+            retrieval_per_1000_requests: TierPrices {
+                expedited: 10.0,
+                standard: 0.05,
+                bulk: 0.025,
+            },
+        }
+    }
+}
+
+impl PriceTable {
+    /// Returns the prices of the region.
+    ///
+    /// The price file maps region names to price tables, with the entry "default" applying to regions not listed. Without a file or entry, the built-in prices are used.
+    pub fn for_region(region: &str, price_file: Option<&str>) -> Result<Self> {
+        let mut tables: HashMap<String, PriceTable> = match price_file {
+            Some(price_file) => serde_json::from_slice(&fs::read(price_file)?)?,
+            None => HashMap::new(),
+        };
+
+        Ok(tables
+            .remove(region)
+            .or_else(|| tables.remove("default"))
+            .unwrap_or_default())
+    }
+
+    /// Estimates the monthly storage cost of a number of archives of the given total size.
+    pub fn monthly_storage_cost(&self, size: i64, archive_count: i64) -> f64 {
+        (size + archive_count * ARCHIVE_OVERHEAD_BYTES) as f64 / GIB * self.storage_per_gib_month
+    }
+
+    /// Estimates the charge for deleting an archive before the end of the minimum storage duration, which is the storage of the remaining days.
+    pub fn early_deletion_cost(
+        &self,
+        size: i64,
+        creation_date: DateTime<FixedOffset>,
+        deletion_date: DateTime<Utc>,
+    ) -> f64 {
+        let stored_days = (deletion_date - creation_date.with_timezone(&Utc)).num_days();
+        let remaining_days = (MINIMUM_STORAGE_DAYS - stored_days).max(0);
+
+        self.monthly_storage_cost(size, 1) * remaining_days as f64 / DAYS_PER_MONTH
+    }
+
+    /// Estimates the cost of retrieving a number of bytes with a number of retrieval jobs.
+    pub fn retrieval_cost(&self, tier: &str, size: i64, requests: i64) -> Result<f64> {
+        Ok(size as f64 / GIB * self.retrieval_per_gib.get(tier)?
+            + requests as f64 / 1000.0 * self.retrieval_per_1000_requests.get(tier)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn price_table_1() {
+        let prices = PriceTable::default();
+        let now = Utc::now();
+
+        assert!((prices.monthly_storage_cost(1024 * 1024 * 1024, 0) - 0.0036).abs() < 1e-9);
+        assert!(
+            (prices
+                .retrieval_cost("Expedited", 2 * 1024 * 1024 * 1024, 1)
+                .unwrap()
+                - 0.07)
+                .abs()
+                < 1e-9
+        );
+        assert!(prices.retrieval_cost("Fast", 1, 1).is_err());
+
+        // 60 of the 90 days remain
+        let created = (now - chrono::Duration::days(30)).into();
+
+        assert!(
+            (prices.early_deletion_cost(30 * 1024 * 1024 * 1024, created, now)
+                - prices.monthly_storage_cost(30 * 1024 * 1024 * 1024, 1) * 2.0)
+                .abs()
+                < 1e-9
+        );
+        assert_eq!(
+            prices.early_deletion_cost(1024, (now - chrono::Duration::days(100)).into(), now),
+            0.0
+        );
+    }
+
+    #[test]
+    fn price_table_2() {
+        let tables: HashMap<String, PriceTable> = serde_json::from_str(
+            r#"{"eu-central-1": {"storage_per_gib_month": 0.0045,
+                "retrieval_per_gib": {"Expedited": 0.036, "Standard": 0.012, "Bulk": 0.003},
+                "retrieval_per_1000_requests": {"Expedited": 12.0, "Standard": 0.06, "Bulk": 0.03}}}"#,
+        )
+        .unwrap();
+
+        assert_eq!(tables["eu-central-1"].storage_per_gib_month, 0.0045);
+        assert_eq!(
+            tables["eu-central-1"]
+                .retrieval_per_gib
+                .get("Bulk")
+                .unwrap(),
+            0.003
+        );
+    }
+}
@@ -0,0 +1,387 @@
+use super::{random_key, Secret, KEY_LENGTH, PBKDF2_ITERATIONS, SALT_LENGTH};
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset, Utc};
+use data_encoding::HEXLOWER;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::convert::{TryFrom, TryInto};
+use tokio_postgres::Row;
+
+const WRAP_VERSION: u8 = 1;
+
+/// A key data keys are wrapped for, e.g. an operations key or an offline recovery key.
+///
+/// The key itself is never stored. The check value allows to find out, which secret belongs to which key.
+#[derive(Debug)]
+pub struct RecipientKey {
+    pub key_id: String,
+    pub key_type: String,
+    pub salt: Vec<u8>,
+    pub iterations: i32,
+    pub check_value: String,
+    pub creation_date: DateTime<FixedOffset>,
+    /// Data keys of new archives are wrapped for all active keys.
+    pub active: bool,
+}
+
+impl TryFrom<&Row> for RecipientKey {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &Row) -> Result<Self, Self::Error> {
+        Ok(RecipientKey {
+            key_id: value.try_get("key_id")?,
+            key_type: value.try_get("key_type")?,
+            salt: value.try_get("salt")?,
+            iterations: value.try_get("iterations")?,
+            check_value: value.try_get("check_value")?,
+            creation_date: value.try_get("creation_date")?,
+            active: value.try_get("active")?,
+        })
+    }
+}
+
+impl RecipientKey {
+    /// Creates a new recipient for the secret and returns it together with the unlocked key.
+    pub fn new(key_id: &str, secret: &Secret) -> Result<(Self, UnlockedKey)> {
+        let mut salt = Vec::new();
+        let iterations = match secret {
+            Secret::Passphrase(_) => {
+                salt.resize(SALT_LENGTH, 0);
+                SystemRandom::new()
+                    .fill(&mut salt)
+                    .map_err(|_| anyhow::Error::msg("error generating salt"))?;
+                PBKDF2_ITERATIONS
+            }
+            Secret::Key(_) => 0,
+            Secret::DataKey(_) => {
+                return Err(anyhow::Error::msg("a data key cannot be a recipient"));
+            }
+        };
+        let key = UnlockedKey {
+            key_id: key_id.into(),
+            key: secret.derive_key(iterations, &salt)?,
+        };
+
+        Ok((
+            RecipientKey {
+                key_id: key_id.into(),
+                key_type: secret.kind().into(),
+                salt,
+                iterations: iterations as i32,
+                check_value: key.check_value(),
+                creation_date: Utc::now().into(),
+                active: true,
+            },
+            key,
+        ))
+    }
+
+    /// Derives the key from the secret, if the secret belongs to this recipient.
+    pub fn unlock(&self, secret: &Secret) -> Result<Option<UnlockedKey>> {
+        if self.key_type != secret.kind() {
+            return Ok(None);
+        }
+
+        let key = UnlockedKey {
+            key_id: self.key_id.clone(),
+            key: secret.derive_key(self.iterations as u32, &self.salt)?,
+        };
+
+        if key.check_value() == self.check_value {
+            Ok(Some(key))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// The data key of an archive wrapped for a recipient.
+#[derive(Debug)]
+pub struct ArchiveKey {
+    pub archive_id: String,
+    pub key_id: String,
+    pub wrapped_key: Vec<u8>,
+}
+
+impl TryFrom<&Row> for ArchiveKey {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &Row) -> Result<Self, Self::Error> {
+        Ok(ArchiveKey {
+            archive_id: value.try_get("archive_id")?,
+            key_id: value.try_get("key_id")?,
+            wrapped_key: value.try_get("wrapped_key")?,
+        })
+    }
+}
+
+pub struct UnlockedKey {
+    pub key_id: String,
+    key: [u8; KEY_LENGTH],
+}
+
+impl UnlockedKey {
+    /// Wraps a data key with AES-256-GCM. The result consists of a version byte, the nonce and the sealed key.
+    pub fn wrap(&self, data_key: &[u8; KEY_LENGTH]) -> Result<Vec<u8>> {
+        let mut nonce = [0u8; NONCE_LEN];
+        let mut sealed = data_key.to_vec();
+
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| anyhow::Error::msg("error generating nonce"))?;
+        self.aead_key()?
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(self.key_id.as_bytes()),
+                &mut sealed,
+            )
+            .map_err(|_| anyhow::Error::msg("error wrapping key"))?;
+
+        let mut wrapped = vec![WRAP_VERSION];
+
+        wrapped.extend_from_slice(&nonce);
+        wrapped.append(&mut sealed);
+
+        Ok(wrapped)
+    }
+
+    pub fn unwrap(&self, wrapped: &[u8]) -> Result<[u8; KEY_LENGTH]> {
+        if wrapped.len() <= 1 + NONCE_LEN || wrapped[0] != WRAP_VERSION {
+            return Err(anyhow::Error::msg("invalid wrapped key"));
+        }
+
+        let mut sealed = wrapped[1 + NONCE_LEN..].to_vec();
+        let data_key = self
+            .aead_key()?
+            .open_in_place(
+                Nonce::try_assume_unique_for_key(&wrapped[1..1 + NONCE_LEN])
+                    .map_err(|_| anyhow::Error::msg("invalid nonce"))?,
+                Aad::from(self.key_id.as_bytes()),
+                &mut sealed,
+            )
+            .map_err(|_| {
+                anyhow::Error::msg(format!("error unwrapping key for \"{}\"", self.key_id))
+            })?;
+
+        Ok(data_key.try_into()?)
+    }
+
+    fn check_value(&self) -> String {
+        HEXLOWER.encode(
+            &hmac::sign(
+                &hmac::Key::new(hmac::HMAC_SHA256, &self.key),
+                b"backup-remote key check",
+            )
+            .as_ref()[..16],
+        )
+    }
+
+    fn aead_key(&self) -> Result<LessSafeKey> {
+        Ok(LessSafeKey::new(
+            UnboundKey::new(&AES_256_GCM, &self.key)
+                .map_err(|_| anyhow::Error::msg("invalid key"))?,
+        ))
+    }
+}
+
+/// The key an archive is encrypted with together with its wrapped copies.
+pub struct DataKey {
+    key: [u8; KEY_LENGTH],
+    wrapped_keys: Vec<(String, Vec<u8>)>,
+}
+
+impl DataKey {
+    pub fn secret(&self) -> Secret {
+        Secret::DataKey(self.key)
+    }
+
+    pub fn key_ids(&self) -> Vec<String> {
+        self.wrapped_keys
+            .iter()
+            .map(|(key_id, _)| key_id.clone())
+            .collect()
+    }
+
+    /// Returns the wrapped copies of the key by key id.
+    pub fn wrapped_keys(&self) -> &[(String, Vec<u8>)] {
+        &self.wrapped_keys
+    }
+
+    pub fn archive_keys(&self, archive_id: &str) -> Vec<ArchiveKey> {
+        self.wrapped_keys
+            .iter()
+            .map(|(key_id, wrapped_key)| ArchiveKey {
+                archive_id: archive_id.into(),
+                key_id: key_id.clone(),
+                wrapped_key: wrapped_key.clone(),
+            })
+            .collect()
+    }
+}
+
+/// The keys that could be unlocked with the secrets at hand.
+pub struct KeyRing {
+    keys: Vec<UnlockedKey>,
+    active_key_ids: Vec<String>,
+    locked_key_ids: Vec<String>,
+}
+
+impl KeyRing {
+    pub fn new(recipients: &[RecipientKey], secrets: &[Secret]) -> Result<Self> {
+        let mut keys = Vec::new();
+        let mut locked_key_ids = Vec::new();
+
+        for recipient in recipients {
+            let mut unlocked = None;
+
+            for secret in secrets {
+                unlocked = recipient.unlock(secret)?;
+
+                if unlocked.is_some() {
+                    break;
+                }
+            }
+
+            match unlocked {
+                Some(key) => keys.push(key),
+                None => locked_key_ids.push(recipient.key_id.clone()),
+            }
+        }
+
+        Ok(KeyRing {
+            keys,
+            active_key_ids: recipients
+                .iter()
+                .filter(|r| r.active)
+                .map(|r| r.key_id.clone())
+                .collect(),
+            locked_key_ids,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn get(&self, key_id: &str) -> Option<&UnlockedKey> {
+        self.keys.iter().find(|k| k.key_id == key_id)
+    }
+
+    /// Returns the ids of the active keys, which could not be unlocked.
+    pub fn locked_active_key_ids(&self) -> Vec<&str> {
+        self.active_key_ids
+            .iter()
+            .filter(|id| self.locked_key_ids.contains(id))
+            .map(|id| id.as_str())
+            .collect()
+    }
+
+    /// Generates a new data key and wraps it for all active keys that are unlocked.
+    pub fn new_data_key(&self) -> Result<DataKey> {
+        let key = random_key()?;
+        let mut wrapped_keys = Vec::new();
+
+        for unlocked_key in self
+            .keys
+            .iter()
+            .filter(|k| self.active_key_ids.contains(&k.key_id))
+        {
+            wrapped_keys.push((unlocked_key.key_id.clone(), unlocked_key.wrap(&key)?));
+        }
+
+        if wrapped_keys.is_empty() {
+            return Err(anyhow::Error::msg(
+                "none of the active keys could be unlocked",
+            ));
+        }
+
+        Ok(DataKey { key, wrapped_keys })
+    }
+
+    /// Unwraps a data key generated before, e.g. for an upload to be continued, with any of the unlocked keys.
+    pub fn data_key(&self, wrapped_keys: &[(String, Vec<u8>)]) -> Result<DataKey> {
+        for (key_id, wrapped_key) in wrapped_keys {
+            if let Some(key) = self.get(key_id) {
+                return Ok(DataKey {
+                    key: key.unwrap(wrapped_key)?,
+                    wrapped_keys: wrapped_keys.to_vec(),
+                });
+            }
+        }
+
+        Err(anyhow::Error::msg(
+            "the data key is not wrapped for any of the available keys",
+        ))
+    }
+
+    /// Unwraps the data key of an archive with any of the unlocked keys.
+    pub fn unwrap_data_key(&self, archive_keys: &[ArchiveKey]) -> Result<[u8; KEY_LENGTH]> {
+        for archive_key in archive_keys {
+            if let Some(key) = self.get(&archive_key.key_id) {
+                return key.unwrap(&archive_key.wrapped_key);
+            }
+        }
+
+        Err(anyhow::Error::msg(
+            "the data key is not wrapped for any of the available keys",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_ring_1() {
+        let (ops, _) = RecipientKey::new("ops", &Secret::Key([1u8; 32])).unwrap();
+        let (mut recovery, _) = RecipientKey::new("recovery", &Secret::Key([2u8; 32])).unwrap();
+
+        recovery.active = false;
+
+        let recipients = vec![ops, recovery];
+        let key_ring = KeyRing::new(&recipients, &[Secret::Key([1u8; 32])]).unwrap();
+        let new_key = key_ring.new_data_key().unwrap();
+        let data_key = new_key.key;
+        let archive_keys = new_key.archive_keys("archive");
+
+        assert_eq!(archive_keys.len(), 1);
+        assert_eq!(archive_keys[0].key_id, "ops");
+        assert!(key_ring.locked_active_key_ids().is_empty());
+        assert_eq!(key_ring.unwrap_data_key(&archive_keys).unwrap(), data_key);
+        assert_eq!(
+            key_ring.data_key(new_key.wrapped_keys()).unwrap().key,
+            data_key
+        );
+
+        // the recovery key can unwrap a data key after it has been rewrapped for it
+        let recovery_ring = KeyRing::new(&recipients, &[Secret::Key([2u8; 32])]).unwrap();
+        let rewrapped = ArchiveKey {
+            archive_id: "archive".into(),
+            key_id: "recovery".into(),
+            wrapped_key: recovery_ring
+                .get("recovery")
+                .unwrap()
+                .wrap(&key_ring.unwrap_data_key(&archive_keys).unwrap())
+                .unwrap(),
+        };
+
+        assert!(recovery_ring.unwrap_data_key(&archive_keys).is_err());
+        assert_eq!(recovery_ring.locked_active_key_ids(), vec!["ops"]);
+        assert_eq!(
+            recovery_ring.unwrap_data_key(&[rewrapped]).unwrap(),
+            data_key
+        );
+    }
+
+    #[test]
+    fn unwrap_1() {
+        let (_, key) = RecipientKey::new("ops", &Secret::Key([1u8; 32])).unwrap();
+        let mut wrapped = key.wrap(&[9u8; 32]).unwrap();
+
+        assert_eq!(key.unwrap(&wrapped).unwrap(), [9u8; 32]);
+        wrapped[20] ^= 1;
+        assert!(key.unwrap(&wrapped).is_err());
+    }
+}
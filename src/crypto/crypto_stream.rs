@@ -0,0 +1,566 @@
+use super::{EncryptionAlgorithm, Secret, PBKDF2_ITERATIONS, SALT_LENGTH};
+use anyhow::Result;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::convert::TryInto;
+use std::io::{self, Read, Write};
+
+pub const MAGIC: &[u8; 4] = b"BRAE";
+pub const VERSION: u8 = 1;
+pub const CHUNK_SIZE: usize = 64 * 1024;
+pub const HEADER_LENGTH: usize = 4 + 1 + 1 + 1 + 4 + 4 + SALT_LENGTH + NONCE_PREFIX_LENGTH;
+pub const TAG_LENGTH: usize = 16;
+
+const NONCE_PREFIX_LENGTH: usize = 7;
+
+/// Header of an encrypted stream.
+///
+/// The stream consists of the header followed by chunks of `chunk_size` bytes of plain text, each sealed separately with the header as additional data.
+/// The nonce of a chunk is the random prefix, the chunk counter and a flag marking the last chunk, so reordered, truncated or extended streams are detected.
+/// The last chunk is always shorter than `chunk_size` and may be empty.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncryptionHeader {
+    pub algorithm: EncryptionAlgorithm,
+    key_type: u8,
+    chunk_size: u32,
+    iterations: u32,
+    salt: [u8; SALT_LENGTH],
+    nonce_prefix: [u8; NONCE_PREFIX_LENGTH],
+}
+
+impl EncryptionHeader {
+    pub fn new(algorithm: EncryptionAlgorithm, secret: &Secret) -> Result<Self> {
+        let random = SystemRandom::new();
+        let mut salt = [0u8; SALT_LENGTH];
+        let mut nonce_prefix = [0u8; NONCE_PREFIX_LENGTH];
+        let iterations = match secret {
+            Secret::Passphrase(_) => {
+                random
+                    .fill(&mut salt)
+                    .map_err(|_| anyhow::Error::msg("error generating salt"))?;
+                PBKDF2_ITERATIONS
+            }
+            Secret::Key(_) | Secret::DataKey(_) => 0,
+        };
+
+        random
+            .fill(&mut nonce_prefix)
+            .map_err(|_| anyhow::Error::msg("error generating nonce"))?;
+
+        Ok(EncryptionHeader {
+            algorithm,
+            key_type: secret.key_type(),
+            chunk_size: CHUNK_SIZE as u32,
+            iterations,
+            salt,
+            nonce_prefix,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LENGTH);
+
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        bytes.push(self.algorithm.id());
+        bytes.push(self.key_type);
+        bytes.extend_from_slice(&self.chunk_size.to_be_bytes());
+        bytes.extend_from_slice(&self.iterations.to_be_bytes());
+        bytes.extend_from_slice(&self.salt);
+        bytes.extend_from_slice(&self.nonce_prefix);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != HEADER_LENGTH || &bytes[..4] != MAGIC {
+            return Err(anyhow::Error::msg("invalid encryption header"));
+        }
+
+        if bytes[4] != VERSION {
+            return Err(anyhow::Error::msg(format!(
+                "unsupported encryption version {}",
+                bytes[4]
+            )));
+        }
+
+        let chunk_size = u32::from_be_bytes(bytes[7..11].try_into()?);
+
+        if chunk_size == 0 {
+            return Err(anyhow::Error::msg("invalid chunk size"));
+        }
+
+        Ok(EncryptionHeader {
+            algorithm: EncryptionAlgorithm::from_id(bytes[5])?,
+            key_type: bytes[6],
+            chunk_size,
+            iterations: u32::from_be_bytes(bytes[11..15].try_into()?),
+            salt: bytes[15..15 + SALT_LENGTH].try_into()?,
+            nonce_prefix: bytes[15 + SALT_LENGTH..].try_into()?,
+        })
+    }
+
+    /// Returns the number of bytes of plain text sealed in a chunk.
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size as usize
+    }
+
+    /// Returns whether the stream was encrypted with the kind of secret.
+    pub fn matches(&self, secret: &Secret) -> bool {
+        self.key_type == secret.key_type()
+    }
+
+    pub fn key_description(&self) -> &'static str {
+        match self.key_type {
+            1 => "archive is encrypted with a passphrase",
+            2 => "archive is encrypted with a key file",
+            _ => "archive is encrypted with a data key stored in the repository",
+        }
+    }
+
+    fn key(&self, secret: &Secret) -> Result<LessSafeKey> {
+        if !self.matches(secret) {
+            return Err(anyhow::Error::msg(self.key_description()));
+        }
+
+        let key = secret.derive_key(self.iterations, &self.salt)?;
+
+        Ok(LessSafeKey::new(
+            UnboundKey::new(self.algorithm.aead_algorithm(), &key)
+                .map_err(|_| anyhow::Error::msg("invalid key"))?,
+        ))
+    }
+
+    fn nonce(&self, counter: u32, last: bool) -> Nonce {
+        let mut nonce = [0u8; NONCE_LEN];
+
+        nonce[..NONCE_PREFIX_LENGTH].copy_from_slice(&self.nonce_prefix);
+        nonce[NONCE_PREFIX_LENGTH..NONCE_LEN - 1].copy_from_slice(&counter.to_be_bytes());
+        nonce[NONCE_LEN - 1] = last as u8;
+        Nonce::assume_unique_for_key(nonce)
+    }
+}
+
+/// Encrypts the data written to it in chunks.
+pub struct EncryptWriter<W: Write> {
+    writer: W,
+    header: EncryptionHeader,
+    header_bytes: Vec<u8>,
+    key: LessSafeKey,
+    buffer: Vec<u8>,
+    counter: u32,
+}
+
+impl<W: Write> EncryptWriter<W> {
+    pub fn new(writer: W, secret: &Secret, algorithm: EncryptionAlgorithm) -> Result<Self> {
+        Self::with_header(writer, EncryptionHeader::new(algorithm, secret)?, secret)
+    }
+
+    /// Encrypts with a given header, so the same data and secret yield the same stream again.
+    pub fn with_header(mut writer: W, header: EncryptionHeader, secret: &Secret) -> Result<Self> {
+        let header_bytes = header.to_bytes();
+        let key = header.key(secret)?;
+
+        writer.write_all(&header_bytes)?;
+
+        Ok(EncryptWriter {
+            writer,
+            header,
+            header_bytes,
+            key,
+            buffer: Vec::with_capacity(CHUNK_SIZE + TAG_LENGTH),
+            counter: 0,
+        })
+    }
+
+    pub fn header(&self) -> &EncryptionHeader {
+        &self.header
+    }
+
+    /// Writes the last chunk and returns the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_chunk(true)?;
+
+        Ok(self.writer)
+    }
+
+    fn write_chunk(&mut self, last: bool) -> io::Result<()> {
+        let nonce = self.header.nonce(self.counter, last);
+
+        self.key
+            .seal_in_place_append_tag(nonce, Aad::from(&self.header_bytes), &mut self.buffer)
+            .map_err(|_| io::Error::other("error encrypting chunk"))?;
+        self.writer.write_all(&self.buffer)?;
+        self.buffer.clear();
+        self.counter = self
+            .counter
+            .checked_add(1)
+            .ok_or_else(|| io::Error::other("too many chunks"))?;
+
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for EncryptWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // a full chunk is left in the buffer only if sealing or writing it failed
+        if self.buffer.len() >= CHUNK_SIZE {
+            return Err(io::Error::other("writing a chunk failed before"));
+        }
+
+        let length = usize::min(CHUNK_SIZE - self.buffer.len(), buf.len());
+
+        self.buffer.extend_from_slice(&buf[..length]);
+
+        if self.buffer.len() == CHUNK_SIZE {
+            self.write_chunk(false)?;
+        }
+
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Decrypts and authenticates an encrypted stream chunk by chunk.
+pub struct DecryptReader<R: Read> {
+    reader: R,
+    header: EncryptionHeader,
+    header_bytes: Vec<u8>,
+    key: LessSafeKey,
+    buffer: Vec<u8>,
+    position: usize,
+    length: usize,
+    counter: u32,
+    finished: bool,
+    partial: bool,
+}
+
+impl<R: Read> DecryptReader<R> {
+    /// Reads the header from the stream and derives the key from the secret.
+    pub fn new(mut reader: R, secret: &Secret) -> Result<Self> {
+        let mut header_bytes = vec![0u8; HEADER_LENGTH];
+
+        reader.read_exact(&mut header_bytes)?;
+
+        let header = EncryptionHeader::from_bytes(&header_bytes)?;
+        let key = header.key(secret)?;
+
+        Ok(DecryptReader {
+            reader,
+            buffer: vec![0u8; header.chunk_size as usize + TAG_LENGTH],
+            header,
+            header_bytes,
+            key,
+            position: 0,
+            length: 0,
+            counter: 0,
+            finished: false,
+            partial: false,
+        })
+    }
+
+    /// Decrypts part of a stream, which starts at the chunk with the given counter.
+    ///
+    /// The header is taken from the start of the stream. Unless the part extends to the end of the stream, it may end anywhere, so an incomplete chunk at its end is dropped.
+    pub fn resume(
+        reader: R,
+        header_bytes: &[u8],
+        secret: &Secret,
+        counter: u32,
+        to_end: bool,
+    ) -> Result<Self> {
+        let header = EncryptionHeader::from_bytes(header_bytes)?;
+        let key = header.key(secret)?;
+
+        Ok(DecryptReader {
+            reader,
+            buffer: vec![0u8; header.chunk_size as usize + TAG_LENGTH],
+            header,
+            header_bytes: header_bytes.to_vec(),
+            key,
+            position: 0,
+            length: 0,
+            counter,
+            finished: false,
+            partial: !to_end,
+        })
+    }
+
+    pub fn header(&self) -> &EncryptionHeader {
+        &self.header
+    }
+
+    fn read_chunk(&mut self) -> io::Result<()> {
+        let mut read = 0;
+
+        while read < self.buffer.len() {
+            match self.reader.read(&mut self.buffer[read..])? {
+                0 => break,
+                n => read += n,
+            }
+        }
+
+        let last = read < self.buffer.len();
+
+        if read < TAG_LENGTH {
+            if self.partial {
+                self.finish_partial();
+                return Ok(());
+            }
+
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "encrypted stream is truncated",
+            ));
+        }
+
+        let nonce = self.header.nonce(self.counter, last);
+        let counter = self.counter;
+        let plain_text = match self.key.open_in_place(
+            nonce,
+            Aad::from(&self.header_bytes),
+            &mut self.buffer[..read],
+        ) {
+            Ok(plain_text) => plain_text,
+            // the incomplete chunk at the end of a part cannot be authenticated
+            Err(_) if self.partial && last => {
+                self.finish_partial();
+                return Ok(());
+            }
+            Err(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("chunk {} failed authentication", counter),
+                ))
+            }
+        };
+
+        self.length = plain_text.len();
+        self.position = 0;
+        self.finished = last;
+        self.counter = self
+            .counter
+            .checked_add(1)
+            .ok_or_else(|| io::Error::other("too many chunks"))?;
+
+        Ok(())
+    }
+}
+
+impl<R: Read> DecryptReader<R> {
+    fn finish_partial(&mut self) {
+        self.length = 0;
+        self.position = 0;
+        self.finished = true;
+    }
+}
+
+impl<R: Read> Read for DecryptReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.length {
+            if self.finished {
+                return Ok(0);
+            }
+
+            self.read_chunk()?;
+        }
+
+        let length = usize::min(self.length - self.position, buf.len());
+
+        buf[..length].copy_from_slice(&self.buffer[self.position..self.position + length]);
+        self.position += length;
+
+        Ok(length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encrypt(data: &[u8], secret: &Secret, algorithm: EncryptionAlgorithm) -> Vec<u8> {
+        let mut writer = EncryptWriter::new(Vec::new(), secret, algorithm).unwrap();
+
+        writer.write_all(data).unwrap();
+        writer.finish().unwrap()
+    }
+
+    fn decrypt(data: &[u8], secret: &Secret) -> io::Result<Vec<u8>> {
+        let mut reader = DecryptReader::new(data, secret).unwrap();
+        let mut plain_text = Vec::new();
+
+        reader.read_to_end(&mut plain_text)?;
+        Ok(plain_text)
+    }
+
+    #[test]
+    fn round_trip_1() {
+        let secret = Secret::Key([7u8; 32]);
+
+        for length in &[0, 1, CHUNK_SIZE, 2 * CHUNK_SIZE + 5] {
+            let data: Vec<u8> = (0..*length).map(|i| i as u8).collect();
+            let encrypted = encrypt(&data, &secret, EncryptionAlgorithm::Aes256Gcm);
+
+            assert_eq!(
+                encrypted.len(),
+                HEADER_LENGTH + data.len() + (data.len() / CHUNK_SIZE + 1) * TAG_LENGTH
+            );
+            assert_eq!(decrypt(&encrypted, &secret).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn round_trip_2() {
+        let secret = Secret::Passphrase("correct horse battery staple".into());
+        let encrypted = encrypt(b"hello", &secret, EncryptionAlgorithm::ChaCha20Poly1305);
+
+        assert_eq!(decrypt(&encrypted, &secret).unwrap(), b"hello");
+        assert!(
+            DecryptReader::new(&encrypted[..], &Secret::Passphrase("wrong".into()))
+                .unwrap()
+                .read_to_end(&mut Vec::new())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn resume_1() {
+        let secret = Secret::Key([4u8; 32]);
+        let data: Vec<u8> = (0..3 * CHUNK_SIZE + 100).map(|i| (i / 7) as u8).collect();
+        let encrypted = encrypt(&data, &secret, EncryptionAlgorithm::Aes256Gcm);
+        let chunk_start = HEADER_LENGTH + CHUNK_SIZE + TAG_LENGTH;
+        let mut plain_text = Vec::new();
+
+        // a part starting at the second chunk and ending within the third one
+        DecryptReader::resume(
+            &encrypted[chunk_start..chunk_start + CHUNK_SIZE + TAG_LENGTH + 1000],
+            &encrypted[..HEADER_LENGTH],
+            &secret,
+            1,
+            false,
+        )
+        .unwrap()
+        .read_to_end(&mut plain_text)
+        .unwrap();
+        assert_eq!(plain_text, &data[CHUNK_SIZE..2 * CHUNK_SIZE]);
+
+        // a wrong counter fails authentication
+        assert!(DecryptReader::resume(
+            &encrypted[chunk_start..],
+            &encrypted[..HEADER_LENGTH],
+            &secret,
+            2,
+            true,
+        )
+        .unwrap()
+        .read_to_end(&mut Vec::new())
+        .is_err());
+    }
+
+    #[test]
+    fn tamper_1() {
+        let secret = Secret::Key([1u8; 32]);
+        let data = vec![5u8; 2 * CHUNK_SIZE];
+        let encrypted = encrypt(&data, &secret, EncryptionAlgorithm::Aes256Gcm);
+        let mut modified = encrypted.clone();
+
+        modified[HEADER_LENGTH + 10] ^= 1;
+        assert!(decrypt(&modified, &secret).is_err());
+
+        // dropping the last chunk must be detected
+        assert!(decrypt(
+            &encrypted[..HEADER_LENGTH + 2 * (CHUNK_SIZE + TAG_LENGTH)],
+            &secret
+        )
+        .is_err());
+
+        // swapping chunks must be detected
+        let mut swapped = encrypted[..HEADER_LENGTH].to_vec();
+
+        swapped.extend_from_slice(
+            &encrypted[HEADER_LENGTH + CHUNK_SIZE + TAG_LENGTH
+                ..HEADER_LENGTH + 2 * (CHUNK_SIZE + TAG_LENGTH)],
+        );
+        swapped
+            .extend_from_slice(&encrypted[HEADER_LENGTH..HEADER_LENGTH + CHUNK_SIZE + TAG_LENGTH]);
+        swapped.extend_from_slice(&encrypted[HEADER_LENGTH + 2 * (CHUNK_SIZE + TAG_LENGTH)..]);
+        assert!(decrypt(&swapped, &secret).is_err());
+    }
+
+    #[test]
+    fn tamper_2() {
+        let secret = Secret::Key([6u8; 32]);
+        let data = vec![8u8; 2 * CHUNK_SIZE + 100];
+        let encrypted = encrypt(&data, &secret, EncryptionAlgorithm::Aes256Gcm);
+        let chunk_start = HEADER_LENGTH + CHUNK_SIZE + TAG_LENGTH;
+        let resume = |encrypted: &[u8], end: usize, to_end: bool| {
+            let mut plain_text = Vec::new();
+
+            DecryptReader::resume(
+                &encrypted[chunk_start..end],
+                &encrypted[..HEADER_LENGTH],
+                &secret,
+                1,
+                to_end,
+            )
+            .unwrap()
+            .read_to_end(&mut plain_text)
+            .map(|_| plain_text)
+        };
+
+        assert_eq!(
+            resume(&encrypted, encrypted.len(), true).unwrap(),
+            &data[CHUNK_SIZE..]
+        );
+
+        // the last chunk of a part extending to the end of the stream is complete and must authenticate
+        let mut modified = encrypted.clone();
+
+        modified[encrypted.len() - 1] ^= 1;
+        assert_eq!(
+            resume(&modified, encrypted.len(), true).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        // only an incomplete chunk at the end of a part is dropped
+        let mut modified = encrypted.clone();
+        let end = chunk_start + CHUNK_SIZE + TAG_LENGTH + 50;
+
+        assert_eq!(
+            resume(&modified, end, false).unwrap(),
+            &data[CHUNK_SIZE..2 * CHUNK_SIZE]
+        );
+        modified[chunk_start + 10] ^= 1;
+        assert_eq!(
+            resume(&modified, end, false).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn with_header_1() {
+        let secret = Secret::Key([3u8; 32]);
+        let header = EncryptionHeader::new(EncryptionAlgorithm::Aes256Gcm, &secret).unwrap();
+        let data = vec![1u8; CHUNK_SIZE + 7];
+        let encrypt_with_header = || {
+            let mut writer =
+                EncryptWriter::with_header(Vec::new(), header.clone(), &secret).unwrap();
+
+            writer.write_all(&data).unwrap();
+            writer.finish().unwrap()
+        };
+
+        // the same header and data yield the same stream, so an interrupted upload can be continued
+        assert_eq!(encrypt_with_header(), encrypt_with_header());
+
+        // once writing a chunk failed, further writes fail instead of overflowing the buffer
+        let mut writer =
+            EncryptWriter::with_header(io::Cursor::new([0u8; HEADER_LENGTH + 10]), header, &secret)
+                .unwrap();
+
+        assert!(writer.write_all(&data).is_err());
+        assert!(writer.write(&data).is_err());
+    }
+}
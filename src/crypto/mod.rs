@@ -0,0 +1,164 @@
+pub mod crypto_key;
+pub mod crypto_stream;
+
+use anyhow::Result;
+use data_encoding::HEXLOWER_PERMISSIVE;
+use ring::aead;
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::convert::TryInto;
+use std::fs;
+use std::num::NonZeroU32;
+use std::path::Path;
+use std::str::FromStr;
+
+pub const KEY_LENGTH: usize = 32;
+pub const SALT_LENGTH: usize = 16;
+pub const PBKDF2_ITERATIONS: u32 = 600_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncryptionAlgorithm {
+    Aes256Gcm,
+    ChaCha20Poly1305,
+}
+
+impl EncryptionAlgorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EncryptionAlgorithm::Aes256Gcm => "aes-256-gcm",
+            EncryptionAlgorithm::ChaCha20Poly1305 => "chacha20-poly1305",
+        }
+    }
+
+    fn id(&self) -> u8 {
+        match self {
+            EncryptionAlgorithm::Aes256Gcm => 1,
+            EncryptionAlgorithm::ChaCha20Poly1305 => 2,
+        }
+    }
+
+    fn from_id(id: u8) -> Result<Self> {
+        match id {
+            1 => Ok(EncryptionAlgorithm::Aes256Gcm),
+            2 => Ok(EncryptionAlgorithm::ChaCha20Poly1305),
+            _ => Err(anyhow::Error::msg(format!(
+                "unknown encryption algorithm {}",
+                id
+            ))),
+        }
+    }
+
+    fn aead_algorithm(&self) -> &'static aead::Algorithm {
+        match self {
+            EncryptionAlgorithm::Aes256Gcm => &aead::AES_256_GCM,
+            EncryptionAlgorithm::ChaCha20Poly1305 => &aead::CHACHA20_POLY1305,
+        }
+    }
+}
+
+impl FromStr for EncryptionAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "aes-256-gcm" => Ok(EncryptionAlgorithm::Aes256Gcm),
+            "chacha20-poly1305" => Ok(EncryptionAlgorithm::ChaCha20Poly1305),
+            _ => Err(anyhow::Error::msg(format!(
+                "unknown encryption algorithm \"{}\"",
+                value
+            ))),
+        }
+    }
+}
+
+/// The secret an archive key is obtained from.
+#[derive(Clone)]
+pub enum Secret {
+    /// The key is derived from the passphrase with PBKDF2 and a random salt stored in the archive header.
+    Passphrase(String),
+    Key([u8; KEY_LENGTH]),
+    /// A random key generated for a single archive, which is stored wrapped for the recipients in the repository.
+    DataKey([u8; KEY_LENGTH]),
+}
+
+impl Secret {
+    /// Loads a key from a file containing either the raw 32 bytes or their hex encoding.
+    pub fn from_key_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let data = fs::read(path)?;
+        let key = match data.len() {
+            KEY_LENGTH => data,
+            _ => HEXLOWER_PERMISSIVE.decode(String::from_utf8(data)?.trim().as_bytes())?,
+        };
+
+        Ok(Secret::Key(key.try_into().map_err(|_| {
+            anyhow::Error::msg("key file does not contain a 256 bit key")
+        })?))
+    }
+
+    /// Generates a random key and returns its hex encoding, which can be stored as a key file.
+    pub fn generate_key() -> Result<String> {
+        Ok(HEXLOWER_PERMISSIVE.encode(&random_key()?))
+    }
+
+    /// Returns the name of the kind of secret as stored in the repository.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Secret::Passphrase(_) => "passphrase",
+            Secret::Key(_) => "key",
+            Secret::DataKey(_) => "data key",
+        }
+    }
+
+    fn key_type(&self) -> u8 {
+        match self {
+            Secret::Passphrase(_) => 1,
+            Secret::Key(_) => 2,
+            Secret::DataKey(_) => 3,
+        }
+    }
+
+    pub(crate) fn derive_key(&self, iterations: u32, salt: &[u8]) -> Result<[u8; KEY_LENGTH]> {
+        match self {
+            Secret::Passphrase(passphrase) => {
+                let mut key = [0u8; KEY_LENGTH];
+
+                pbkdf2::derive(
+                    pbkdf2::PBKDF2_HMAC_SHA256,
+                    NonZeroU32::new(iterations)
+                        .ok_or_else(|| anyhow::Error::msg("invalid number of iterations"))?,
+                    salt,
+                    passphrase.as_bytes(),
+                    &mut key,
+                );
+
+                Ok(key)
+            }
+            Secret::Key(key) | Secret::DataKey(key) => Ok(*key),
+        }
+    }
+}
+
+/// Loads the configured key file and passphrase.
+pub fn load_secrets(key_file: Option<&str>, passphrase: Option<&str>) -> Result<Vec<Secret>> {
+    let mut secrets = Vec::new();
+
+    if let Some(key_file) = key_file {
+        secrets.push(Secret::from_key_file(key_file)?);
+    }
+
+    if let Some(passphrase) = passphrase {
+        secrets.push(Secret::Passphrase(passphrase.into()));
+    }
+
+    Ok(secrets)
+}
+
+fn random_key() -> Result<[u8; KEY_LENGTH]> {
+    let mut key = [0u8; KEY_LENGTH];
+
+    SystemRandom::new()
+        .fill(&mut key)
+        .map_err(|_| anyhow::Error::msg("error generating key"))?;
+
+    Ok(key)
+}
@@ -0,0 +1,215 @@
+use anyhow::Result;
+use chrono::{DateTime, Datelike, Duration, Timelike, Utc};
+use std::fmt;
+use std::str::FromStr;
+
+/// A cron expression with the five fields minute, hour, day of month, month and day of week, evaluated in UTC.
+///
+/// Every field is `*` or a list of values and ranges (`1,15`, `1-5`), each optionally with a step (`*/15`, `0-30/10`). Day of week 0 and 7 are Sunday. Like cron, a time matches if the day of month or the day of week matches, if both are restricted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cron {
+    expression: String,
+    minutes: Vec<bool>,
+    hours: Vec<bool>,
+    days: Vec<bool>,
+    months: Vec<bool>,
+    weekdays: Vec<bool>,
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl FromStr for Cron {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+
+        if fields.len() != 5 {
+            return Err(anyhow::Error::msg(format!(
+                "cron expression \"{}\" does not have 5 fields",
+                s
+            )));
+        }
+
+        let mut weekdays = parse_field(fields[4], 0, 7)?;
+
+        // 7 is another name for Sunday
+        weekdays[0] |= weekdays[7];
+        weekdays.truncate(7);
+
+        Ok(Cron {
+            expression: fields.join(" "),
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            weekdays,
+            days_restricted: fields[2] != "*",
+            weekdays_restricted: fields[4] != "*",
+        })
+    }
+}
+
+impl fmt::Display for Cron {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.expression)
+    }
+}
+
+impl Cron {
+    /// Returns the first matching minute after the given time.
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut time = after.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        // any expression matches within a few years, e.g. the 29th of February
+        let end = after + Duration::days(5 * 366);
+
+        while time < end {
+            if !self.months[time.month() as usize] {
+                time = next_month(time)?;
+            } else if !self.day_matches(time) {
+                time = time.with_hour(0)?.with_minute(0)? + Duration::days(1);
+            } else if !self.hours[time.hour() as usize] {
+                time = time.with_minute(0)? + Duration::hours(1);
+            } else if !self.minutes[time.minute() as usize] {
+                time += Duration::minutes(1);
+            } else {
+                return Some(time);
+            }
+        }
+
+        None
+    }
+
+    fn day_matches(&self, time: DateTime<Utc>) -> bool {
+        let day = self.days[time.day() as usize];
+        let weekday = self.weekdays[time.weekday().num_days_from_sunday() as usize];
+
+        match (self.days_restricted, self.weekdays_restricted) {
+            (true, true) => day || weekday,
+            (true, false) => day,
+            (false, true) => weekday,
+            (false, false) => true,
+        }
+    }
+}
+
+/// Returns the flags of the values `0..=max` selected by the field; values below `min` are never selected.
+fn parse_field(field: &str, min: u32, max: u32) -> Result<Vec<bool>> {
+    let mut selected = vec![false; max as usize + 1];
+
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>()?),
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            range => match range.split_once('-') {
+                Some((start, end)) => (start.parse()?, end.parse()?),
+                None if step > 1 => (range.parse()?, max),
+                None => (range.parse()?, range.parse()?),
+            },
+        };
+
+        if step == 0 || start < min || end > max || start > end {
+            return Err(anyhow::Error::msg(format!(
+                "invalid cron field \"{}\" (values {} to {})",
+                field, min, max
+            )));
+        }
+
+        for value in (start..=end).step_by(step as usize) {
+            selected[value as usize] = true;
+        }
+    }
+
+    Ok(selected)
+}
+
+fn next_month(time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let first = time.with_day(1)?.with_hour(0)?.with_minute(0)?;
+
+    match first.month() {
+        12 => first.with_month(1)?.with_year(first.year() + 1),
+        month => first.with_month(month + 1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn cron_1() {
+        let cron: Cron = "30 2 * * *".parse().unwrap();
+
+        assert_eq!(
+            cron.next_after(time("2026-10-19T01:00:00Z")),
+            Some(time("2026-10-19T02:30:00Z"))
+        );
+        assert_eq!(
+            cron.next_after(time("2026-10-19T02:30:00Z")),
+            Some(time("2026-10-20T02:30:00Z"))
+        );
+
+        let cron: Cron = "*/15 * * * *".parse().unwrap();
+
+        assert_eq!(
+            cron.next_after(time("2026-10-19T01:07:42Z")),
+            Some(time("2026-10-19T01:15:00Z"))
+        );
+        assert_eq!(
+            cron.next_after(time("2026-12-31T23:59:00Z")),
+            Some(time("2027-01-01T00:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn cron_2() {
+        // Sundays (as 7) at midnight; the 19th of October 2026 is a Monday
+        let cron: Cron = "0 0 * * 7".parse().unwrap();
+
+        assert_eq!(
+            cron.next_after(time("2026-10-19T12:00:00Z")),
+            Some(time("2026-10-25T00:00:00Z"))
+        );
+
+        // the first of the month or Mondays to Fridays
+        let cron: Cron = "0 12 1 * 1-5".parse().unwrap();
+
+        assert_eq!(
+            cron.next_after(time("2026-10-31T13:00:00Z")),
+            Some(time("2026-11-01T12:00:00Z"))
+        );
+        assert_eq!(
+            cron.next_after(time("2026-11-01T13:00:00Z")),
+            Some(time("2026-11-02T12:00:00Z"))
+        );
+
+        let cron: Cron = "0 0 29 2 *".parse().unwrap();
+
+        assert_eq!(
+            cron.next_after(time("2026-10-19T00:00:00Z")),
+            Some(time("2028-02-29T00:00:00Z"))
+        );
+        assert_eq!(
+            "0 0 31 2 *".parse::<Cron>().unwrap().next_after(Utc::now()),
+            None
+        );
+    }
+
+    #[test]
+    fn cron_3() {
+        assert!("* * * *".parse::<Cron>().is_err());
+        assert!("60 * * * *".parse::<Cron>().is_err());
+        assert!("* * 0 * *".parse::<Cron>().is_err());
+        assert!("*/0 * * * *".parse::<Cron>().is_err());
+        assert!("5-1 * * * *".parse::<Cron>().is_err());
+        assert!("a * * * *".parse::<Cron>().is_err());
+        assert!("0,30 8-18/2 * 1,7 0".parse::<Cron>().is_ok());
+    }
+}
@@ -0,0 +1,386 @@
+use crate::aws::{
+    aws_glacier::AwsGlacier, aws_inventory::AwsInventoryRetrievalParameters, aws_job::AwsJob,
+    aws_vault::AwsVault,
+};
+use crate::backup::{self, StoredBackup};
+use crate::config::BackupSetConfig;
+use crate::crypto::Secret;
+use crate::inventory;
+use crate::job_request;
+use crate::lease::{LeaseRenewal, WORKER_ID};
+use crate::logging;
+use crate::repo::{Repository, UpsertResult};
+use crate::restore;
+use crate::shutdown;
+use crate::store::InventoryStore;
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use log::{debug, info, warn};
+use std::process;
+use tokio_postgres::Transaction;
+
+/// Time after which the lease of a job expires, if the process holding it did not complete it.
+const JOB_LEASE_HOURS: i64 = 1;
+
+/// Interval in which the lease of a job being processed is renewed, well within its expiry.
+const JOB_LEASE_RENEWAL_MINUTES: u64 = 10;
+
+async fn renew_job_lease(db_connection: &str, job_id: &str) -> Result<bool> {
+    let mut repo = Repository::new(db_connection).await?;
+    let trans = repo.get_transaction().await?;
+    let lease_expiry = Utc::now() + Duration::hours(JOB_LEASE_HOURS);
+    let renewed = Repository::renew_job_lease(&trans, job_id, &WORKER_ID, &lease_expiry).await?;
+
+    trans.commit().await?;
+
+    Ok(renewed)
+}
+
+/// Updates the list of vaults and marks the vaults no longer listed inactive.
+pub async fn sync_vaults(aws_glacier: &AwsGlacier, db_connection: &str) -> Result<()> {
+    let mut repo = Repository::new(db_connection).await?;
+    let trans = repo.get_transaction().await?;
+
+    debug!("resetting vault status active");
+    Repository::reset_vaults_status_active(&trans).await?;
+
+    let aws_vaults = aws_glacier.list_vaults().await?;
+    debug!("found {} aws vaults", aws_vaults.len());
+
+    for vault in aws_vaults {
+        match Repository::upsert_vault(&trans, &vault).await? {
+            UpsertResult::Inserted => info!(
+                vault_name = vault.vault_name;
+                "added vault \"{}\" to repository", vault.vault_name
+            ),
+            UpsertResult::Updated => info!(
+                vault_name = vault.vault_name;
+                "updated vault \"{}\" in repository", vault.vault_name
+            ),
+            UpsertResult::Unchanged => debug!("vault \"{}\" unchanged", vault.vault_name),
+        }
+
+        debug!("setting vault \"{}\" status active", vault.vault_name);
+        Repository::upsert_vault_status(&trans, &vault, true).await?;
+    }
+
+    trans.commit().await?;
+
+    Ok(())
+}
+
+/// Updates the list of jobs of every vault and starts inventory jobs for vaults whose inventory changed since the last one.
+///
+/// Paginated inventories are continued with follow-up jobs.
+pub async fn sync_jobs(
+    aws_glacier: &AwsGlacier,
+    db_connection: &str,
+    inventory_parameters: &AwsInventoryRetrievalParameters,
+    incremental_inventory: bool,
+) -> Result<()> {
+    let mut repo = Repository::new(db_connection).await?;
+    let trans = repo.get_transaction().await?;
+
+    Repository::reset_jobs_status_active(&trans).await?;
+
+    for vault in aws_glacier.list_vaults().await? {
+        debug!("updating list of jobs for vault \"{}\"", vault.vault_name);
+        Repository::upsert_vault(&trans, &vault).await?;
+
+        for job in aws_glacier.list_jobs_for_vault(&vault).await? {
+            debug!("processing job \"{}\"", job.job_id);
+            match Repository::upsert_job(&trans, &job).await? {
+                UpsertResult::Inserted => debug!("created job \"{}\"", job.job_id),
+                UpsertResult::Updated => debug!("updated job \"{}\"", job.job_id),
+                UpsertResult::Unchanged => debug!("job \"{}\" unchanged", job.job_id),
+            }
+
+            debug!("setting job \"{}\" active", job.job_id);
+            Repository::upsert_job_status(&trans, &job, true).await?;
+        }
+
+        // if the latest inventory job is older than the inventory date of the vault => launch new inventory job
+        match vault.last_inventory_date {
+            Some(inv_date) => {
+                debug!("inventory date found for vault \"{}\"", vault.vault_name);
+                if match Repository::get_latest_job_by_action_vault(
+                    &trans,
+                    "InventoryRetrieval",
+                    &vault.vault_arn,
+                )
+                .await
+                {
+                    Ok(latest_job) => {
+                        if latest_job.creation_date < inv_date {
+                            debug!("latest inventory job date older than inventory date of vault");
+                            true
+                        } else {
+                            false
+                        }
+                    }
+                    Err(_) => true,
+                } {
+                    let mut parameters = inventory_parameters.clone();
+
+                    if incremental_inventory {
+                        parameters.start_date =
+                            Repository::get_inventories_for_vault(&trans, &vault.vault_arn)
+                                .await?
+                                .last()
+                                .map(|i| i.inventory_date);
+                    }
+
+                    init_inventory_job(aws_glacier, &trans, &vault, &parameters).await?;
+                }
+            }
+            None => {
+                debug!("no inventory date found for vault \"{}\"", vault.vault_name);
+            }
+        }
+
+        for inventory in Repository::get_inventories_to_continue(&trans, &vault.vault_arn).await? {
+            let previous_job = Repository::get_job_by_id(&trans, &inventory.job_id).await?;
+            let mut parameters = previous_job
+                .inventory_retrieval_parameters
+                .unwrap_or_default();
+
+            parameters.marker = inventory.marker.clone();
+            debug!("continuing inventory \"{}\"", inventory.job_id);
+            let job = init_inventory_job(aws_glacier, &trans, &vault, &parameters).await?;
+            Repository::set_inventory_continuation(&trans, &inventory.job_id, &job.job_id).await?;
+        }
+    }
+
+    trans.commit().await?;
+
+    Ok(())
+}
+
+async fn init_inventory_job(
+    aws_glacier: &AwsGlacier,
+    trans: &Transaction<'_>,
+    vault: &AwsVault,
+    parameters: &AwsInventoryRetrievalParameters,
+) -> Result<AwsJob> {
+    debug!("creating inventory job for \"{}\"", vault.vault_name);
+    let job_id = aws_glacier
+        .init_inventory_job_for_vault(vault, parameters)
+        .await?;
+    info!(
+        vault_name = vault.vault_name, job_id;
+        "created inventory job for \"{}\" with id \"{}\"",
+        vault.vault_name, job_id
+    );
+
+    let job = aws_glacier.get_job_by_id_vault(vault, &job_id).await?;
+    Repository::upsert_job(trans, &job).await?;
+    Repository::upsert_job_status(trans, &job, true).await?;
+
+    Ok(job)
+}
+
+/// Imports the output of every succeeded inventory job not imported yet.
+pub async fn import_inventories(
+    aws_glacier: &AwsGlacier,
+    db_connection: &str,
+    inventory_dir: &str,
+) -> Result<()> {
+    let mut repo = Repository::new(db_connection).await?;
+    let inventory_store = InventoryStore::new(inventory_dir)?;
+
+    for vault in aws_glacier.list_vaults().await? {
+        for job in aws_glacier.list_jobs_for_vault(&vault).await? {
+            match (&*job.action, &*job.status_code) {
+                ("InventoryRetrieval", "Succeeded") => {
+                    shutdown::check()?;
+
+                    let trans = repo.get_transaction().await?;
+
+                    if Repository::get_inventory(&trans, &job.job_id)
+                        .await?
+                        .is_some()
+                    {
+                        debug!("inventory \"{}\" already imported", job.job_id);
+                        continue;
+                    }
+
+                    Repository::upsert_vault(&trans, &vault).await?;
+                    Repository::upsert_job(&trans, &job).await?;
+
+                    let lease_expiry = Utc::now() + Duration::hours(JOB_LEASE_HOURS);
+
+                    if !Repository::lease_job(
+                        &trans,
+                        &job,
+                        &WORKER_ID,
+                        process::id(),
+                        &lease_expiry,
+                    )
+                    .await?
+                    {
+                        debug!("inventory \"{}\" is leased to another worker", job.job_id);
+                        continue;
+                    }
+
+                    trans.commit().await?;
+
+                    logging::span(
+                        "inventory_import",
+                        &[("vault_name", &vault.vault_name), ("job_id", &job.job_id)],
+                        async {
+                            let _renewal = {
+                                let db_connection = db_connection.to_string();
+                                let job_id = job.job_id.clone();
+
+                                LeaseRenewal::start(
+                                    format!("job \"{}\"", job.job_id),
+                                    std::time::Duration::from_secs(JOB_LEASE_RENEWAL_MINUTES * 60),
+                                    move || {
+                                        let db_connection = db_connection.clone();
+                                        let job_id = job_id.clone();
+
+                                        async move { renew_job_lease(&db_connection, &job_id).await }
+                                    },
+                                )
+                            };
+                            let output = aws_glacier.get_job_output(&vault, &job).await?;
+                            let trans = repo.get_transaction().await?;
+
+                            inventory::import_job_output(
+                                &trans,
+                                &vault,
+                                &job,
+                                output,
+                                &inventory_store,
+                            )
+                            .await?;
+                            if !Repository::complete_job_lease(&trans, &job.job_id, &WORKER_ID)
+                                .await?
+                            {
+                                return Err(anyhow::Error::msg(format!(
+                                    "lost the lease of inventory \"{}\" while importing it",
+                                    job.job_id
+                                )));
+                            }
+
+                            trans.commit().await?;
+
+                            Ok::<_, anyhow::Error>(())
+                        },
+                    )
+                    .await?;
+                }
+                ("InventoryRetrieval", status_code) => {
+                    debug!(
+                        "inventory job \"{}\" is {}, skipping",
+                        job.job_id, status_code
+                    );
+                }
+                (action, _) => {
+                    debug!(
+                        "{} job \"{}\" is not an inventory job, skipping",
+                        action, job.job_id
+                    );
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Advances all unfinished restores; failing restores are retried in the next run.
+pub async fn process_restores(
+    aws_glacier: &AwsGlacier,
+    db_connection: &str,
+    secrets: &[Secret],
+) -> Result<()> {
+    let mut repo = Repository::new(db_connection).await?;
+    let trans = repo.get_transaction().await?;
+    let restores = Repository::get_restores(&trans, true).await?;
+
+    trans.commit().await?;
+
+    for restore in restores {
+        shutdown::check()?;
+
+        let restore_id = restore.restore_id.to_string();
+        let mut fields = vec![
+            ("restore_id", restore_id.as_str()),
+            ("archive_id", restore.archive_id.as_str()),
+        ];
+
+        if let Some(job_id) = &restore.job_id {
+            fields.push(("job_id", job_id));
+        }
+
+        logging::span("restore", &fields, async {
+            if let Err(e) = restore::process_restore(
+                aws_glacier,
+                &mut repo,
+                db_connection,
+                &restore.restore_id,
+                secrets,
+            )
+            .await
+            {
+                warn!("restore \"{}\" will be retried: {}", restore.restore_id, e);
+            }
+        })
+        .await;
+    }
+
+    Ok(())
+}
+
+/// Starts the jobs requested through the API.
+pub async fn process_job_requests(aws_glacier: &AwsGlacier, db_connection: &str) -> Result<()> {
+    let mut repo = Repository::new(db_connection).await?;
+
+    job_request::process_job_requests(aws_glacier, &mut repo).await
+}
+
+/// Releases the leases this process holds on jobs it did not complete, so other processes can pick them up.
+pub async fn release_job_leases(db_connection: &str) -> Result<()> {
+    let mut repo = Repository::new(db_connection).await?;
+    let trans = repo.get_transaction().await?;
+    let released = Repository::release_job_leases(&trans, &WORKER_ID).await?;
+
+    trans.commit().await?;
+    info!("released {} job leases", released);
+
+    Ok(())
+}
+
+/// Returns the date of the latest backup of a backup set, from which its interval schedule continues.
+pub async fn latest_backup_date(db_connection: &str, name: &str) -> Result<Option<DateTime<Utc>>> {
+    let mut repo = Repository::new(db_connection).await?;
+    let trans = repo.get_transaction().await?;
+
+    Repository::get_latest_backup_date(&trans, name).await
+}
+
+/// Runs the backup of a backup set.
+pub async fn run_backup_set(
+    aws_glacier: &AwsGlacier,
+    db_connection: &str,
+    name: &str,
+    settings: &BackupSetConfig,
+    secrets: &[Secret],
+) -> Result<()> {
+    let mut repo = Repository::new(db_connection).await?;
+
+    match backup::run_backup(aws_glacier, &mut repo, Some(name), settings, secrets).await? {
+        StoredBackup::Archive(backup) => info!(
+            archive_id = backup.archive_id;
+            "backup set \"{}\" stored in archive \"{}\"",
+            name, backup.archive_id
+        ),
+        StoredBackup::Snapshot(snapshot) => info!(
+            "backup set \"{}\" stored in snapshot \"{}\"",
+            name, snapshot.snapshot_id
+        ),
+    }
+
+    Ok(())
+}
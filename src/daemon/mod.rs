@@ -0,0 +1,429 @@
+pub mod daemon_cron;
+pub mod daemon_tasks;
+
+use crate::config::TaskConfig;
+use crate::health;
+use crate::metrics;
+use crate::shutdown;
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use daemon_cron::Cron;
+use log::{debug, error, info, warn};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::{mpsc, Semaphore};
+use tokio::time::sleep;
+
+pub type TaskFuture = Pin<Box<dyn Future<Output = Result<()>> + Send>>;
+pub type TaskFn = Arc<dyn Fn() -> TaskFuture + Send + Sync>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Schedule {
+    /// The task runs an interval after its previous run, at startup if there was none, and then in the interval.
+    Interval(Duration),
+    Cron(Cron),
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Schedule::Interval(interval) => write!(f, "every {} minutes", interval.num_minutes()),
+            Schedule::Cron(cron) => write!(f, "cron \"{}\"", cron),
+        }
+    }
+}
+
+impl Schedule {
+    /// Returns the first run of the task after the given time.
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Schedule::Interval(interval) => Some(after + *interval),
+            Schedule::Cron(cron) => cron.next_after(after),
+        }
+    }
+
+    /// Returns the first run of the task after it was started, given its previous run before the start.
+    pub fn first_after(
+        &self,
+        start: DateTime<Utc>,
+        previous: Option<DateTime<Utc>>,
+    ) -> Option<DateTime<Utc>> {
+        match self {
+            Schedule::Interval(interval) => Some(match previous {
+                Some(previous) => (previous + *interval).max(start),
+                None => start,
+            }),
+            Schedule::Cron(cron) => cron.next_after(start),
+        }
+    }
+}
+
+/// The delay before a failed task is run again, doubled for every consecutive failure.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            initial: Duration::minutes(1),
+            max: Duration::minutes(30),
+        }
+    }
+}
+
+impl Backoff {
+    pub fn delay(&self, failures: u32) -> Duration {
+        let mut delay = self.initial;
+
+        for _ in 1..failures {
+            if delay >= self.max {
+                break;
+            }
+
+            delay = delay * 2;
+        }
+
+        delay.min(self.max)
+    }
+}
+
+pub struct Task {
+    pub name: String,
+    pub schedule: Schedule,
+    /// Number of runs, which may overlap; a run due while this many are still running is skipped.
+    pub concurrency: usize,
+    pub backoff: Backoff,
+    /// The previous run before the daemon started, from which an interval schedule computes the first run.
+    pub previous_run: Option<DateTime<Utc>>,
+    run: TaskFn,
+}
+
+impl Task {
+    pub fn new(name: &str, schedule: Schedule, run: TaskFn) -> Self {
+        Task {
+            name: name.into(),
+            schedule,
+            concurrency: 1,
+            backoff: Backoff::default(),
+            previous_run: None,
+            run,
+        }
+    }
+
+    /// Creates the task with the settings of the configuration, falling back to the default interval.
+    ///
+    /// Returns `None` if the task is disabled.
+    pub fn from_config(
+        name: &str,
+        config: Option<&TaskConfig>,
+        default_interval_minutes: u64,
+        run: TaskFn,
+    ) -> Result<Option<Self>> {
+        let default = TaskConfig::default();
+        let config = config.unwrap_or(&default);
+
+        if config.enabled == Some(false) {
+            info!("task \"{}\" is disabled", name);
+            return Ok(None);
+        }
+
+        let schedule = match (&config.cron, config.interval_minutes) {
+            (Some(_), Some(_)) => {
+                return Err(anyhow::Error::msg(format!(
+                    "task \"{}\" has both a cron expression and an interval",
+                    name
+                )))
+            }
+            (Some(cron), None) => Schedule::Cron(cron.parse()?),
+            (None, Some(0)) => {
+                return Err(anyhow::Error::msg(format!(
+                    "the interval of task \"{}\" must be at least 1 minute",
+                    name
+                )))
+            }
+            (None, interval) => Schedule::Interval(Duration::minutes(
+                interval.unwrap_or(default_interval_minutes) as i64,
+            )),
+        };
+        let mut task = Task::new(name, schedule, run);
+
+        if let Some(concurrency) = config.concurrency {
+            if concurrency == 0 {
+                return Err(anyhow::Error::msg(format!(
+                    "the concurrency of task \"{}\" must be at least 1",
+                    name
+                )));
+            }
+
+            task.concurrency = concurrency;
+        }
+
+        if let Some(backoff_seconds) = config.backoff_seconds {
+            task.backoff.initial = Duration::seconds(backoff_seconds as i64);
+        }
+
+        if let Some(max_backoff_seconds) = config.max_backoff_seconds {
+            task.backoff.max = Duration::seconds(max_backoff_seconds as i64);
+        }
+
+        Ok(Some(task))
+    }
+}
+
+/// Runs every task on its own schedule until one of them stops or a shutdown is requested.
+///
+/// After a shutdown is requested, no further runs are started and the running ones are given the timeout to finish.
+pub async fn run(tasks: Vec<Task>, shutdown_timeout: std::time::Duration) -> Result<()> {
+    let (stopped_sender, mut stopped_receiver) = mpsc::unbounded_channel();
+
+    for task in tasks {
+        let stopped_sender = stopped_sender.clone();
+
+        tokio::spawn(async move {
+            let name = task.name.clone();
+            let _ = stopped_sender.send((name, run_task(task).await));
+        });
+    }
+
+    drop(stopped_sender);
+
+    tokio::select! {
+        stopped = stopped_receiver.recv() => match stopped {
+            _ if shutdown::is_requested() => {}
+            Some((name, Ok(()))) => {
+                return Err(anyhow::Error::msg(format!(
+                    "task \"{}\" has no further runs",
+                    name
+                )))
+            }
+            Some((name, Err(e))) => return Err(e.context(format!("task \"{}\" stopped", name))),
+            None => return Err(anyhow::Error::msg("no tasks to run")),
+        },
+        _ = shutdown::requested() => {}
+    }
+
+    shutdown::drain(
+        async { while stopped_receiver.recv().await.is_some() {} },
+        shutdown_timeout,
+    )
+    .await;
+
+    Ok(())
+}
+
+/// Runs the task whenever it is due, until its schedule has no further runs.
+///
+/// After a failure, the next run is due after the backoff delay instead of at the next scheduled time, until a run succeeds.
+/// Once a shutdown is requested, it waits for the running runs and returns.
+async fn run_task(task: Task) -> Result<()> {
+    let semaphore = Arc::new(Semaphore::new(task.concurrency));
+    let (result_sender, mut result_receiver) = mpsc::unbounded_channel::<bool>();
+    let mut failures = 0;
+    let mut next = task.schedule.first_after(Utc::now(), task.previous_run);
+
+    info!("scheduled task \"{}\" ({})", task.name, task.schedule);
+
+    while !shutdown::is_requested() {
+        let due = match next {
+            Some(due) => due,
+            None => return Ok(()),
+        };
+
+        debug!("task \"{}\" is due at {}", task.name, due.to_rfc3339());
+
+        tokio::select! {
+            _ = sleep((due - Utc::now()).to_std().unwrap_or_default()) => {
+                match semaphore.clone().try_acquire_owned() {
+                    Ok(permit) => {
+                        let run = task.run.clone();
+                        let result_sender = result_sender.clone();
+                        let name = task.name.clone();
+
+                        if failures > 0 {
+                            metrics::RETRIES.inc(&[&name]);
+                        }
+
+                        tokio::spawn(async move {
+                            let cycle = health::Cycle::start(&name);
+                            let result = cycle
+                                .span(async {
+                                    info!("running task \"{}\"", name);
+                                    let result = run().await;
+
+                                    if let Err(e) = &result {
+                                        error!("task \"{}\" failed: {:?}", name, e);
+                                    } else {
+                                        info!("task \"{}\" succeeded", name);
+                                    }
+
+                                    result
+                                })
+                                .await;
+
+                            cycle.finish(&result);
+
+                            drop(permit);
+                            let _ = result_sender.send(result.is_ok());
+                        });
+                    }
+                    Err(_) => warn!(
+                        "task \"{}\" is still running {} times, skipping this run",
+                        task.name, task.concurrency
+                    ),
+                }
+
+                next = task.schedule.next_after(Utc::now());
+            }
+            Some(succeeded) = result_receiver.recv() => {
+                if succeeded {
+                    if failures > 0 {
+                        failures = 0;
+                        next = task.schedule.next_after(Utc::now());
+                    }
+                } else {
+                    failures += 1;
+                    let delay = task.backoff.delay(failures);
+
+                    warn!(
+                        "task \"{}\" failed {} times in a row, running it again in {} seconds",
+                        task.name,
+                        failures,
+                        delay.num_seconds()
+                    );
+                    next = Some(Utc::now() + delay);
+                }
+            }
+            _ = shutdown::requested() => {}
+        }
+    }
+
+    debug!("waiting for the running runs of task \"{}\"", task.name);
+    let _ = semaphore.acquire_many(task.concurrency as u32).await;
+    info!("stopped task \"{}\"", task.name);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn backoff_1() {
+        let backoff = Backoff::default();
+
+        assert_eq!(backoff.delay(1), Duration::minutes(1));
+        assert_eq!(backoff.delay(2), Duration::minutes(2));
+        assert_eq!(backoff.delay(5), Duration::minutes(16));
+        assert_eq!(backoff.delay(6), Duration::minutes(30));
+        assert_eq!(backoff.delay(1000), Duration::minutes(30));
+    }
+
+    #[test]
+    fn task_1() {
+        let run: TaskFn = Arc::new(|| Box::pin(async { Ok(()) }));
+        let task = Task::from_config(
+            "sync",
+            Some(&TaskConfig {
+                cron: Some("0 * * * *".into()),
+                concurrency: Some(2),
+                backoff_seconds: Some(10),
+                ..Default::default()
+            }),
+            60,
+            run.clone(),
+        )
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(task.schedule, Schedule::Cron("0 * * * *".parse().unwrap()));
+        assert_eq!(task.concurrency, 2);
+        assert_eq!(task.backoff.initial, Duration::seconds(10));
+
+        let task = Task::from_config("sync", None, 30, run.clone())
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(task.schedule, Schedule::Interval(Duration::minutes(30)));
+        assert!(Task::from_config(
+            "sync",
+            Some(&TaskConfig {
+                enabled: Some(false),
+                ..Default::default()
+            }),
+            30,
+            run.clone()
+        )
+        .unwrap()
+        .is_none());
+        assert!(Task::from_config(
+            "sync",
+            Some(&TaskConfig {
+                cron: Some("0 * * * *".into()),
+                interval_minutes: Some(5),
+                ..Default::default()
+            }),
+            30,
+            run.clone()
+        )
+        .is_err());
+        assert!(Task::from_config(
+            "sync",
+            Some(&TaskConfig {
+                interval_minutes: Some(0),
+                ..Default::default()
+            }),
+            30,
+            run
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn schedule_1() {
+        let schedule = Schedule::Interval(Duration::minutes(60));
+        let start = Utc::now();
+
+        assert_eq!(schedule.first_after(start, None), Some(start));
+        assert_eq!(
+            schedule.first_after(start, Some(start - Duration::minutes(20))),
+            Some(start + Duration::minutes(40))
+        );
+        assert_eq!(
+            schedule.first_after(start, Some(start - Duration::minutes(90))),
+            Some(start)
+        );
+    }
+
+    #[tokio::test]
+    async fn run_task_1() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let counter = runs.clone();
+        let mut task = Task::new(
+            "failing",
+            Schedule::Interval(Duration::hours(1)),
+            Arc::new(move || {
+                let counter = counter.clone();
+
+                Box::pin(async move {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    Err(anyhow::Error::msg("failed"))
+                })
+            }),
+        );
+
+        task.backoff.initial = Duration::milliseconds(10);
+        task.backoff.max = Duration::milliseconds(20);
+
+        // failed runs are repeated after the backoff delay instead of the interval
+        let _ = tokio::time::timeout(std::time::Duration::from_millis(500), run_task(task)).await;
+
+        assert!(runs.load(Ordering::SeqCst) > 3);
+    }
+}
@@ -0,0 +1,231 @@
+use data_encoding::HEXLOWER;
+use ring::digest;
+use std::io::{self, Write};
+use tokio::sync::mpsc::Sender;
+
+/// Random values for the bytes, generated with SplitMix64 so the cut points never change.
+const GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut state = 0u64;
+    let mut i = 0;
+
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+        let mut z = state;
+
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+
+    table
+}
+
+/// Finds content-defined cut points with the gear hash of FastCDC.
+///
+/// Cut points depend only on the preceding bytes, so inserting or removing data shifts the chunks without changing them.
+#[derive(Debug, Clone, Copy)]
+pub struct Chunker {
+    min_size: usize,
+    avg_size: usize,
+    max_size: usize,
+    /// Mask below the average size, which makes cuts less likely.
+    mask_small: u64,
+    /// Mask above the average size, which makes cuts more likely.
+    mask_large: u64,
+}
+
+impl Chunker {
+    /// Creates a chunker for chunks of the given sizes. The average size must be a power of two.
+    pub fn new(min_size: usize, avg_size: usize, max_size: usize) -> Self {
+        let bits = avg_size.trailing_zeros();
+
+        Chunker {
+            min_size,
+            avg_size,
+            max_size,
+            mask_small: high_bits(bits + 2),
+            mask_large: high_bits(bits - 2),
+        }
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Returns the length of the first chunk of the data.
+    ///
+    /// The data is cut at the maximum chunk size at the latest. Shorter data is returned as one chunk if no cut point is found.
+    pub fn cut(&self, data: &[u8]) -> usize {
+        if data.len() <= self.min_size {
+            return data.len();
+        }
+
+        let end = usize::min(data.len(), self.max_size);
+        let normal = usize::min(self.avg_size, end);
+        let mut hash = 0u64;
+
+        for (i, byte) in data.iter().enumerate().take(end).skip(self.min_size) {
+            hash = (hash << 1).wrapping_add(GEAR[*byte as usize]);
+
+            let mask = if i < normal {
+                self.mask_small
+            } else {
+                self.mask_large
+            };
+
+            if hash & mask == 0 {
+                return i + 1;
+            }
+        }
+
+        end
+    }
+}
+
+/// Returns a mask of the highest bits, which depend on the most bytes of the gear hash.
+fn high_bits(bits: u32) -> u64 {
+    !0u64 << (64 - bits)
+}
+
+/// A chunk of data identified by its SHA256 hash.
+#[derive(Debug)]
+pub struct Chunk {
+    pub hash: String,
+    pub data: Vec<u8>,
+}
+
+impl Chunk {
+    pub fn new(data: Vec<u8>) -> Self {
+        Chunk {
+            hash: HEXLOWER.encode(digest::digest(&digest::SHA256, &data).as_ref()),
+            data,
+        }
+    }
+}
+
+/// Splits the data written to it into content-defined chunks and sends them to the deduplication.
+///
+/// Data is buffered until a maximum sized chunk is available, so every cut point is found on the complete data.
+pub struct ChunkWriter {
+    sender: Sender<Chunk>,
+    chunker: Chunker,
+    buffer: Vec<u8>,
+}
+
+impl ChunkWriter {
+    pub fn new(sender: Sender<Chunk>, chunker: Chunker) -> Self {
+        ChunkWriter {
+            sender,
+            chunker,
+            buffer: Vec::with_capacity(2 * chunker.max_size()),
+        }
+    }
+
+    /// Sends the remaining chunks.
+    pub fn finish(mut self) -> io::Result<()> {
+        while !self.buffer.is_empty() {
+            self.send_chunk()?;
+        }
+
+        Ok(())
+    }
+
+    fn send_chunk(&mut self) -> io::Result<()> {
+        let length = self.chunker.cut(&self.buffer);
+        let rest = self.buffer[length..].to_vec();
+
+        self.buffer.truncate(length);
+
+        let data = std::mem::replace(&mut self.buffer, rest);
+
+        self.sender
+            .blocking_send(Chunk::new(data))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "deduplication stopped"))
+    }
+}
+
+impl Write for ChunkWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+
+        while self.buffer.len() >= self.chunker.max_size() {
+            self.send_chunk()?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc;
+
+    fn test_data(length: usize) -> Vec<u8> {
+        let mut state = 1u64;
+
+        (0..length)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    fn chunks(data: &[u8]) -> Vec<Chunk> {
+        let (sender, mut receiver) = mpsc::channel(1000);
+        let mut writer = ChunkWriter::new(sender, Chunker::new(64, 256, 1024));
+        let mut chunks = Vec::new();
+
+        for part in data.chunks(100) {
+            writer.write_all(part).unwrap();
+        }
+
+        writer.finish().unwrap();
+
+        while let Ok(chunk) = receiver.try_recv() {
+            chunks.push(chunk);
+        }
+
+        chunks
+    }
+
+    #[test]
+    fn chunk_writer_1() {
+        let data = test_data(100_000);
+        let chunks = chunks(&data);
+        let joined: Vec<u8> = chunks.iter().flat_map(|c| c.data.clone()).collect();
+
+        assert_eq!(joined, data);
+        assert!(chunks.len() > 100_000 / 1024);
+        assert!(chunks[..chunks.len() - 1]
+            .iter()
+            .all(|c| (64..=1024).contains(&c.data.len())));
+    }
+
+    #[test]
+    fn chunk_writer_2() {
+        let data = test_data(100_000);
+        let mut shifted = b"inserted".to_vec();
+
+        shifted.extend_from_slice(&data);
+
+        let hashes: Vec<String> = chunks(&data).into_iter().map(|c| c.hash).collect();
+        let shifted_hashes: Vec<String> = chunks(&shifted).into_iter().map(|c| c.hash).collect();
+        let shared = shifted_hashes.iter().filter(|h| hashes.contains(h)).count();
+
+        // only the chunks around the insertion change
+        assert!(shared >= hashes.len() - 2);
+    }
+}
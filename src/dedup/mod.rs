@@ -0,0 +1,612 @@
+pub mod dedup_chunker;
+
+use crate::aws::{
+    aws_archive::{ArchiveMetadata, AwsArchive, METADATA_VERSION},
+    aws_glacier::AwsGlacier,
+    aws_vault::AwsVault,
+};
+use crate::backup::{
+    self, backup_reader,
+    backup_writer::{CompressionWriter, EncryptionWriter},
+    BackupCompression, BackupOptions, BackupSource,
+};
+use crate::crypto::{
+    crypto_key::{DataKey, KeyRing},
+    EncryptionAlgorithm, Secret,
+};
+use crate::repo::Repository;
+use crate::shutdown;
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset, Utc};
+use data_encoding::HEXLOWER;
+use dedup_chunker::{Chunk, ChunkWriter, Chunker};
+use hyper::body::Bytes;
+use log::{debug, info, warn};
+use ring::digest;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read, Write};
+use std::os::unix::fs::FileExt;
+use tokio::sync::mpsc::{self, Receiver};
+use tokio_postgres::Row;
+use uuid::Uuid;
+
+const MIN_CHUNK_SIZE: usize = 256 * 1024;
+const AVG_CHUNK_SIZE: usize = 1024 * 1024;
+const MAX_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+/// Size at which a pack of chunks is uploaded as archive.
+const PACK_SIZE: usize = 64 * 1024 * 1024;
+
+/// How the packs of a deduplicated backup are encrypted.
+pub enum PackEncryption {
+    /// Every pack is encrypted with its own data key, which is wrapped for the keys of the key ring.
+    KeyRing(KeyRing, EncryptionAlgorithm),
+    /// All packs are encrypted with the key directly.
+    Key(Secret, EncryptionAlgorithm),
+}
+
+pub struct DedupOptions {
+    pub compression: BackupCompression,
+    /// Size of the upload parts, which must be a megabyte multiplied by a power of two.
+    pub part_size: usize,
+    pub backup_set: Option<String>,
+    pub encryption: Option<PackEncryption>,
+}
+
+/// Where a chunk is stored: a blob of the compressed and possibly encrypted chunk within a pack archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkLocation {
+    pub chunk_hash: String,
+    pub archive_id: String,
+    /// Offset of the blob within the archive.
+    pub offset: i64,
+    /// Length of the blob.
+    pub length: i64,
+    /// Size of the chunk before compression.
+    pub size: i64,
+}
+
+impl TryFrom<&Row> for ChunkLocation {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &Row) -> Result<Self, Self::Error> {
+        Ok(ChunkLocation {
+            chunk_hash: value.try_get("chunk_hash")?,
+            archive_id: value.try_get("archive_id")?,
+            offset: value.try_get("offset")?,
+            length: value.try_get("length")?,
+            size: value.try_get("size")?,
+        })
+    }
+}
+
+/// A deduplicated backup. Its manifest lists the chunks of the tar stream in order.
+#[derive(Debug)]
+pub struct Snapshot {
+    pub snapshot_id: Uuid,
+    pub vault_arn: String,
+    pub backup_set: Option<String>,
+    pub host: String,
+    pub creation_date: DateTime<FixedOffset>,
+    /// Size of the tar stream.
+    pub size: i64,
+    pub chunk_count: i64,
+    /// Number and size of the chunks, which were not stored by earlier snapshots.
+    pub new_chunk_count: i64,
+    pub new_size: i64,
+    pub sources: Vec<BackupSource>,
+    /// Hashes of the chunks of the tar stream, which is empty unless loaded explicitly.
+    pub manifest: Vec<String>,
+}
+
+impl TryFrom<&Row> for Snapshot {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &Row) -> Result<Self, Self::Error> {
+        Ok(Snapshot {
+            snapshot_id: value.try_get("snapshot_id")?,
+            vault_arn: value.try_get("vault_arn")?,
+            backup_set: value.try_get("backup_set")?,
+            host: value.try_get("host")?,
+            creation_date: value.try_get("creation_date")?,
+            size: value.try_get("size")?,
+            chunk_count: value.try_get("chunk_count")?,
+            new_chunk_count: value.try_get("new_chunk_count")?,
+            new_size: value.try_get("new_size")?,
+            sources: Vec::new(),
+            manifest: Vec::new(),
+        })
+    }
+}
+
+/// Chunks waiting to be uploaded together as one archive.
+struct Pack {
+    data: Vec<u8>,
+    chunks: Vec<ChunkLocation>,
+    /// Rendered into the description on upload, once the content hash is known.
+    metadata: ArchiveMetadata,
+    secret: Option<(Secret, EncryptionAlgorithm)>,
+    data_key: Option<DataKey>,
+}
+
+/// Stores the chunks of a snapshot, which are not stored yet, in packs.
+struct Deduplicator<'a> {
+    aws_glacier: &'a AwsGlacier,
+    repo: &'a mut Repository,
+    vault: &'a AwsVault,
+    options: &'a DedupOptions,
+    stored: HashSet<String>,
+    pack: Option<Pack>,
+    manifest: Vec<String>,
+    size: i64,
+    new_chunk_count: i64,
+    new_size: i64,
+}
+
+impl Deduplicator<'_> {
+    async fn add(&mut self, chunk: Chunk) -> Result<()> {
+        self.manifest.push(chunk.hash.clone());
+        self.size += chunk.data.len() as i64;
+
+        if self.stored.contains(&chunk.hash) {
+            return Ok(());
+        }
+
+        let trans = self.repo.get_transaction().await?;
+        let exists = Repository::chunk_exists(&trans, &self.vault.vault_arn, &chunk.hash).await?;
+
+        trans.commit().await?;
+        self.stored.insert(chunk.hash.clone());
+
+        if exists {
+            return Ok(());
+        }
+
+        if self.pack.is_none() {
+            self.pack = Some(self.new_pack()?);
+        }
+
+        let pack = self.pack.as_mut().unwrap();
+        let compression = self.options.compression;
+        let secret = pack.secret.clone();
+        let Chunk { hash, data } = chunk;
+        let size = data.len() as i64;
+        let blob = tokio::task::spawn_blocking(move || {
+            encode_blob(&data, compression, secret.as_ref().map(|(s, a)| (s, *a)))
+        })
+        .await??;
+
+        pack.chunks.push(ChunkLocation {
+            chunk_hash: hash,
+            archive_id: String::new(),
+            offset: pack.data.len() as i64,
+            length: blob.len() as i64,
+            size,
+        });
+        pack.data.extend_from_slice(&blob);
+        self.new_chunk_count += 1;
+        self.new_size += size;
+
+        if pack.data.len() >= PACK_SIZE {
+            self.upload_pack().await?;
+        }
+
+        Ok(())
+    }
+
+    fn new_pack(&self) -> Result<Pack> {
+        let (secret, data_key) = match &self.options.encryption {
+            Some(PackEncryption::KeyRing(key_ring, algorithm)) => {
+                let data_key = key_ring.new_data_key()?;
+
+                (Some((data_key.secret(), *algorithm)), Some(data_key))
+            }
+            Some(PackEncryption::Key(secret, algorithm)) => {
+                (Some((secret.clone(), *algorithm)), None)
+            }
+            None => (None, None),
+        };
+        let metadata = ArchiveMetadata {
+            version: METADATA_VERSION,
+            host: backup::host_name(),
+            backup_set: self.options.backup_set.clone(),
+            paths: Vec::new(),
+            paths_truncated: false,
+            timestamp: Utc::now(),
+            encryption: secret.as_ref().map(|(_, a)| a.as_str().into()),
+            key_ids: data_key.as_ref().map(|k| k.key_ids()).unwrap_or_default(),
+            content_hash: None,
+        };
+
+        Ok(Pack {
+            data: Vec::with_capacity(PACK_SIZE + MAX_CHUNK_SIZE),
+            chunks: Vec::new(),
+            metadata,
+            secret,
+            data_key,
+        })
+    }
+
+    /// Uploads the current pack and adds its chunks to the index.
+    async fn upload_pack(&mut self) -> Result<()> {
+        let mut pack = match self.pack.take() {
+            Some(pack) => pack,
+            None => return Ok(()),
+        };
+        let size = pack.data.len() as i64;
+
+        // the packs uploaded before are recorded, so the next backup does not upload their chunks again
+        shutdown::check()?;
+        debug!("uploading pack of {} chunks", pack.chunks.len());
+
+        pack.metadata.content_hash =
+            Some(HEXLOWER.encode(digest::digest(&digest::SHA256, &pack.data).as_ref()));
+
+        let archive_description = pack.metadata.render()?;
+        let (archive_id, tree_hash) = backup::upload_archive(
+            self.aws_glacier,
+            self.vault,
+            &archive_description,
+            Bytes::from(pack.data),
+            self.options.part_size,
+        )
+        .await?;
+        let archive = AwsArchive {
+            archive_id: archive_id.clone(),
+            archive_description,
+            creation_date: Utc::now().into(),
+            size,
+            tree_hash,
+        };
+
+        for chunk in &mut pack.chunks {
+            chunk.archive_id = archive_id.clone();
+        }
+
+        let trans = self.repo.get_transaction().await?;
+
+        Repository::upsert_archive(&trans, &archive).await?;
+        Repository::create_archive_associations(&trans, self.vault, &[archive]).await?;
+        Repository::create_chunks(&trans, &pack.chunks).await?;
+
+        if let Some(data_key) = pack.data_key {
+            Repository::upsert_archive_keys(&trans, &data_key.archive_keys(&archive_id)).await?;
+        }
+
+        trans.commit().await?;
+        info!(
+            "uploaded pack \"{}\" with {} chunks ({} bytes)",
+            archive_id,
+            pack.chunks.len(),
+            size
+        );
+
+        Ok(())
+    }
+}
+
+/// Stores the paths as snapshot of a deduplicated backup and records it.
+///
+/// With registered keys every pack gets its own data key. Otherwise the packs are encrypted with the key file directly, because deriving a key from the passphrase for every chunk would be too slow.
+pub async fn store_snapshot(
+    aws_glacier: &AwsGlacier,
+    repo: &mut Repository,
+    vault_name: &str,
+    paths: &[String],
+    options: BackupOptions,
+    secrets: Vec<Secret>,
+    algorithm: EncryptionAlgorithm,
+) -> Result<Snapshot> {
+    let trans = repo.get_transaction().await?;
+    let recipients = Repository::get_keys(&trans).await?;
+
+    trans.commit().await?;
+
+    let encryption = if recipients.is_empty() {
+        let passphrase_only = !secrets.is_empty();
+
+        match secrets.into_iter().find(|s| matches!(s, Secret::Key(_))) {
+            Some(secret) => Some(PackEncryption::Key(secret, algorithm)),
+            None if passphrase_only => {
+                return Err(anyhow::Error::msg(
+                    "deduplicated backups need a key file or registered keys to be encrypted",
+                ))
+            }
+            None => {
+                warn!("no key configured, the packs are not encrypted");
+                None
+            }
+        }
+    } else {
+        let key_ring = KeyRing::new(&recipients, &secrets)?;
+
+        for key_id in key_ring.locked_active_key_ids() {
+            warn!(
+                "key \"{}\" is not available, the data keys must be rewrapped for it later",
+                key_id
+            );
+        }
+
+        Some(PackEncryption::KeyRing(key_ring, algorithm))
+    };
+    let vault = aws_glacier.describe_vault(vault_name).await?;
+    let trans = repo.get_transaction().await?;
+
+    Repository::upsert_vault(&trans, &vault).await?;
+    trans.commit().await?;
+
+    let snapshot = backup_snapshot(
+        aws_glacier,
+        repo,
+        &vault,
+        paths,
+        DedupOptions {
+            compression: options.compression,
+            part_size: options.part_size,
+            backup_set: options.backup_set,
+            encryption,
+        },
+    )
+    .await?;
+    let trans = repo.get_transaction().await?;
+
+    Repository::create_snapshot(&trans, &snapshot).await?;
+    trans.commit().await?;
+    info!(
+        "stored snapshot \"{}\" ({} bytes in {} chunks, {} new chunks with {} bytes)",
+        snapshot.snapshot_id,
+        snapshot.size,
+        snapshot.chunk_count,
+        snapshot.new_chunk_count,
+        snapshot.new_size
+    );
+
+    Ok(snapshot)
+}
+
+/// Splits the tar stream of the paths into content-defined chunks and uploads the chunks, which are not stored yet, in packs.
+///
+/// Every pack is recorded as soon as it is uploaded, so an interrupted backup does not upload its chunks again. The snapshot is recorded at the end.
+pub async fn backup_snapshot(
+    aws_glacier: &AwsGlacier,
+    repo: &mut Repository,
+    vault: &AwsVault,
+    paths: &[String],
+    options: DedupOptions,
+) -> Result<Snapshot> {
+    let mut sources = Vec::with_capacity(paths.len());
+
+    for path in paths {
+        sources.push(fs::canonicalize(path)?);
+    }
+
+    let (sender, mut receiver) = mpsc::channel(2);
+    let writer = tokio::task::spawn_blocking(move || -> Result<Vec<BackupSource>> {
+        let chunker = Chunker::new(MIN_CHUNK_SIZE, AVG_CHUNK_SIZE, MAX_CHUNK_SIZE);
+        let (sources, _, writer) =
+            backup::write_tar(&sources, None, ChunkWriter::new(sender, chunker))?;
+
+        writer.finish()?;
+
+        Ok(sources)
+    });
+    let mut deduplicator = Deduplicator {
+        aws_glacier,
+        repo,
+        vault,
+        options: &options,
+        stored: HashSet::new(),
+        pack: None,
+        manifest: Vec::new(),
+        size: 0,
+        new_chunk_count: 0,
+        new_size: 0,
+    };
+    let stored = store_chunks(&mut deduplicator, &mut receiver).await;
+
+    // Closing the receiver stops the writer if storing failed.
+    drop(receiver);
+
+    let sources = match (stored, writer.await) {
+        (Ok(()), Ok(Ok(sources))) => sources,
+        (Err(e), _) => return Err(e),
+        (_, Ok(Err(e))) => return Err(e),
+        (_, Err(e)) => return Err(e.into()),
+    };
+
+    deduplicator.upload_pack().await?;
+
+    Ok(Snapshot {
+        snapshot_id: Uuid::new_v4(),
+        vault_arn: vault.vault_arn.clone(),
+        backup_set: options.backup_set.clone(),
+        host: backup::host_name(),
+        creation_date: Utc::now().into(),
+        size: deduplicator.size,
+        chunk_count: deduplicator.manifest.len() as i64,
+        new_chunk_count: deduplicator.new_chunk_count,
+        new_size: deduplicator.new_size,
+        sources,
+        manifest: deduplicator.manifest,
+    })
+}
+
+async fn store_chunks(
+    deduplicator: &mut Deduplicator<'_>,
+    receiver: &mut Receiver<Chunk>,
+) -> Result<()> {
+    while let Some(chunk) = receiver.recv().await {
+        deduplicator.add(chunk).await?;
+    }
+
+    Ok(())
+}
+
+/// Compresses and optionally encrypts a chunk as a blob, which reads like a complete archive.
+pub fn encode_blob(
+    data: &[u8],
+    compression: BackupCompression,
+    encryption: Option<(&Secret, EncryptionAlgorithm)>,
+) -> Result<Vec<u8>> {
+    let writer = match encryption {
+        Some((secret, algorithm)) => EncryptionWriter::encrypted(Vec::new(), secret, algorithm)?,
+        None => EncryptionWriter::Plain(Vec::new()),
+    };
+    let mut writer = CompressionWriter::new(compression, writer)?;
+
+    writer.write_all(data)?;
+
+    Ok(writer.finish()?.finish()?)
+}
+
+/// Decrypts and decompresses a blob.
+pub fn decode_blob(blob: &[u8], secrets: &[Secret]) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+
+    backup_reader::archive_reader(blob, secrets)?.read_to_end(&mut data)?;
+
+    Ok(data)
+}
+
+/// A downloaded byte range of a pack archive.
+pub struct PackFile {
+    pub file: File,
+    /// Offset of the download within the archive.
+    pub range_start: u64,
+    /// The secrets to decrypt the blobs of the pack with.
+    pub secrets: Vec<Secret>,
+}
+
+/// Reads the tar stream of a snapshot by assembling its chunks from the downloaded packs.
+///
+/// Every chunk is verified against its hash.
+pub struct SnapshotReader {
+    chunks: std::vec::IntoIter<ChunkLocation>,
+    packs: HashMap<String, PackFile>,
+    current: Cursor<Vec<u8>>,
+}
+
+impl SnapshotReader {
+    /// Creates a reader for the chunks in the order of the manifest. The packs are identified by their archive id.
+    pub fn new(chunks: Vec<ChunkLocation>, packs: HashMap<String, PackFile>) -> Self {
+        SnapshotReader {
+            chunks: chunks.into_iter(),
+            packs,
+            current: Cursor::new(Vec::new()),
+        }
+    }
+
+    fn read_chunk(&self, chunk: &ChunkLocation) -> Result<Vec<u8>> {
+        let pack = self.packs.get(&chunk.archive_id).ok_or_else(|| {
+            anyhow::Error::msg(format!("pack \"{}\" not downloaded", chunk.archive_id))
+        })?;
+        let mut blob = vec![0u8; chunk.length as usize];
+
+        pack.file
+            .read_exact_at(&mut blob, chunk.offset as u64 - pack.range_start)?;
+
+        let data = decode_blob(&blob, &pack.secrets)?;
+
+        if Chunk::new(data.clone()).hash != chunk.chunk_hash {
+            return Err(anyhow::Error::msg(format!(
+                "chunk \"{}\" is corrupt",
+                chunk.chunk_hash
+            )));
+        }
+
+        Ok(data)
+    }
+}
+
+impl Read for SnapshotReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let length = self.current.read(buf)?;
+
+            if length > 0 || buf.is_empty() {
+                return Ok(length);
+            }
+
+            match self.chunks.next() {
+                Some(chunk) => {
+                    self.current = Cursor::new(self.read_chunk(&chunk).map_err(io::Error::other)?)
+                }
+                None => return Ok(0),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::KEY_LENGTH;
+
+    #[test]
+    fn snapshot_reader_1() {
+        let path = std::env::temp_dir().join(format!("pack-{}", std::process::id()));
+        let secret = Secret::Key([7u8; KEY_LENGTH]);
+        let mut pack = b"skipped".to_vec();
+        let mut chunks = Vec::new();
+
+        for data in &[&b"first chunk "[..], b"second chunk"] {
+            let blob = encode_blob(
+                data,
+                BackupCompression::Zstd,
+                Some((&secret, EncryptionAlgorithm::Aes256Gcm)),
+            )
+            .unwrap();
+
+            chunks.push(ChunkLocation {
+                chunk_hash: Chunk::new(data.to_vec()).hash,
+                archive_id: "pack".into(),
+                offset: pack.len() as i64 + 100,
+                length: blob.len() as i64,
+                size: data.len() as i64,
+            });
+            pack.extend_from_slice(&blob);
+        }
+
+        fs::write(&path, &pack[7..]).unwrap();
+
+        // the second chunk appears twice in the manifest
+        chunks.push(chunks[1].clone());
+
+        let packs = vec![(
+            "pack".to_string(),
+            PackFile {
+                file: File::open(&path).unwrap(),
+                range_start: 107,
+                secrets: vec![secret.clone()],
+            },
+        )]
+        .into_iter()
+        .collect();
+        let mut data = String::new();
+
+        SnapshotReader::new(chunks.clone(), packs)
+            .read_to_string(&mut data)
+            .unwrap();
+        assert_eq!(data, "first chunk second chunksecond chunk");
+
+        // a chunk not matching its hash is rejected
+        chunks[0].chunk_hash = chunks[1].chunk_hash.clone();
+
+        let packs = vec![(
+            "pack".to_string(),
+            PackFile {
+                file: File::open(&path).unwrap(),
+                range_start: 107,
+                secrets: vec![secret],
+            },
+        )]
+        .into_iter()
+        .collect();
+
+        assert!(SnapshotReader::new(chunks, packs)
+            .read_to_string(&mut data)
+            .is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,269 @@
+use crate::logging;
+use crate::metrics;
+use crate::shutdown;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use log::error;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+use tokio::time::{sleep, Duration};
+use uuid::Uuid;
+
+/// Time after which a running cycle is considered stuck.
+pub const DEFAULT_STALL_MINUTES: u64 = 240;
+
+static HEALTH: Mutex<Health> = Mutex::new(Health::new());
+static EXPIRED: AtomicBool = AtomicBool::new(false);
+
+/// The cycles of the daemons, their outcome and what is known about the Glacier credentials.
+#[derive(Debug)]
+pub struct Health {
+    /// Task and start of the running cycles by cycle id.
+    running: BTreeMap<Uuid, (String, DateTime<Utc>)>,
+    /// End of the last successful cycle by task.
+    last_success: BTreeMap<String, DateTime<Utc>>,
+    last_error: Option<(DateTime<Utc>, String)>,
+    /// Whether Glacier accepted the credentials of the last request; unknown before the first one.
+    glacier_credentials: Option<bool>,
+}
+
+impl Health {
+    const fn new() -> Self {
+        Health {
+            running: BTreeMap::new(),
+            last_success: BTreeMap::new(),
+            last_error: None,
+            glacier_credentials: None,
+        }
+    }
+
+    /// Returns the task and start of the oldest cycle running for longer than the stall time.
+    pub fn stalled(&self, now: DateTime<Utc>, stall: Duration) -> Option<(&str, DateTime<Utc>)> {
+        self.running
+            .values()
+            .filter(|(_, started)| (now - *started).to_std().unwrap_or_default() > stall)
+            .min_by_key(|(_, started)| *started)
+            .map(|(task, started)| (task.as_str(), *started))
+    }
+
+    /// Returns the first of the tasks, which did not succeed within the window, counted from its last success or, before it, from the given start.
+    pub fn expired<'a>(
+        &self,
+        now: DateTime<Utc>,
+        start: DateTime<Utc>,
+        window: Duration,
+        tasks: &'a [String],
+    ) -> Option<&'a str> {
+        tasks
+            .iter()
+            .find(|task| {
+                (now - self.last_success.get(*task).copied().unwrap_or(start))
+                    .to_std()
+                    .unwrap_or_default()
+                    > window
+            })
+            .map(|task| task.as_str())
+    }
+
+    fn value(&self, status: &str) -> Value {
+        let mut running: Vec<_> = self.running.iter().collect();
+
+        running.sort_by_key(|(_, (_, started))| *started);
+
+        json!({
+            "Status": status,
+            "RunningCycles": running.into_iter().map(|(id, (task, started))| json!({
+                "CycleId": id.to_string(),
+                "Task": task,
+                "Since": started.to_rfc3339(),
+            })).collect::<Vec<_>>(),
+            "LastSuccess": self.last_success.values().max().map(|d| d.to_rfc3339()),
+            "LastSuccessByTask": self.last_success.iter().map(|(task, d)| (task.clone(), json!(d.to_rfc3339()))).collect::<serde_json::Map<_, _>>(),
+            "LastError": self.last_error.as_ref().map(|(_, e)| e),
+            "LastErrorDate": self.last_error.as_ref().map(|(d, _)| d.to_rfc3339()),
+        })
+    }
+}
+
+fn health() -> std::sync::MutexGuard<'static, Health> {
+    HEALTH.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// A run of a task (an update cycle), which is running until it is finished or dropped.
+pub struct Cycle {
+    id: Uuid,
+    task: String,
+    started: Instant,
+}
+
+impl Cycle {
+    pub fn start(task: &str) -> Self {
+        let id = Uuid::new_v4();
+
+        health().running.insert(id, (task.into(), Utc::now()));
+
+        Cycle {
+            id,
+            task: task.into(),
+            started: Instant::now(),
+        }
+    }
+
+    /// Runs the future in the span of the cycle, so the lines it logs carry the cycle id and the task.
+    pub async fn span<F: Future>(&self, future: F) -> F::Output {
+        logging::span(
+            "cycle",
+            &[("cycle_id", &self.id.to_string()), ("task", &self.task)],
+            future,
+        )
+        .await
+    }
+
+    /// Records the outcome of the cycle, also in the metrics.
+    pub fn finish<T>(self, result: &Result<T>) {
+        metrics::observe_task(&self.task, self.started.elapsed(), result.is_ok());
+
+        let mut health = health();
+
+        match result {
+            Ok(_) => {
+                health.last_success.insert(self.task.clone(), Utc::now());
+            }
+            Err(e) => {
+                health.last_error = Some((Utc::now(), format!("task \"{}\": {:#}", self.task, e)))
+            }
+        }
+    }
+}
+
+impl Drop for Cycle {
+    fn drop(&mut self) {
+        health().running.remove(&self.id);
+    }
+}
+
+/// Records whether Glacier accepted the credentials of a request.
+pub fn glacier_credentials(valid: bool) {
+    health().glacier_credentials = Some(valid);
+}
+
+/// Returns whether no cycle is stuck, with the cycles and the last success and error.
+pub fn liveness(stall: Duration) -> (bool, Value) {
+    let health = health();
+
+    match health.stalled(Utc::now(), stall) {
+        Some(_) => (false, health.value("stalled")),
+        None => (true, health.value("ok")),
+    }
+}
+
+/// Returns whether the daemon is ready, given the result of connecting to the database.
+///
+/// Credentials rejected by Glacier in the last request make it unready until a request succeeds.
+pub fn readiness(database: &Result<()>) -> (bool, Value) {
+    let health = health();
+    let ready = database.is_ok() && health.glacier_credentials != Some(false);
+    let mut value = health.value(if ready { "ok" } else { "unavailable" });
+
+    value["Database"] = match database {
+        Ok(()) => json!("ok"),
+        Err(e) => json!(format!("{:#}", e)),
+    };
+    value["GlacierCredentials"] = match health.glacier_credentials {
+        Some(true) => json!("ok"),
+        Some(false) => json!("rejected"),
+        None => json!("unknown"),
+    };
+
+    (ready, value)
+}
+
+/// Requests a shutdown, once one of the tasks did not succeed within the window.
+///
+/// The daemon then fails with the error of `check`.
+pub fn watch(window: Duration, tasks: Vec<String>) {
+    let start = Utc::now();
+
+    tokio::spawn(async move {
+        while !shutdown::is_requested() {
+            tokio::select! {
+                _ = sleep(window.min(Duration::from_secs(60))) => {}
+                _ = shutdown::requested() => return,
+            }
+
+            if let Some(task) = health().expired(Utc::now(), start, window, &tasks) {
+                error!(
+                    "task \"{}\" did not succeed within {} minutes, shutting down",
+                    task,
+                    window.as_secs() / 60
+                );
+                EXPIRED.store(true, Ordering::SeqCst);
+                shutdown::request();
+            }
+        }
+    });
+}
+
+/// Fails, if the daemon was shut down because a task did not succeed in time.
+pub fn check() -> Result<()> {
+    match EXPIRED.load(Ordering::SeqCst) {
+        true => Err(anyhow::Error::msg(
+            "shut down because a task did not succeed in time",
+        )),
+        false => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn health_1() {
+        let start = Utc.with_ymd_and_hms(2026, 10, 19, 0, 0, 0).unwrap();
+        let mut health = Health::new();
+
+        health
+            .running
+            .insert(Uuid::new_v4(), ("worker".into(), start));
+        health.running.insert(
+            Uuid::new_v4(),
+            ("updater".into(), start + chrono::Duration::minutes(30)),
+        );
+
+        let now = start + chrono::Duration::minutes(60);
+
+        assert_eq!(health.stalled(now, Duration::from_secs(90 * 60)), None);
+        assert_eq!(
+            health.stalled(now, Duration::from_secs(20 * 60)),
+            Some(("worker", start))
+        );
+
+        let tasks = vec!["worker".to_string(), "updater".to_string()];
+        let window = Duration::from_secs(30 * 60);
+
+        assert_eq!(health.expired(now, start, window, &tasks), Some("worker"));
+
+        health
+            .last_success
+            .insert("worker".into(), start + chrono::Duration::minutes(45));
+
+        assert_eq!(health.expired(now, start, window, &tasks), Some("updater"));
+
+        health
+            .last_success
+            .insert("updater".into(), start + chrono::Duration::minutes(40));
+
+        assert_eq!(health.expired(now, start, window, &tasks), None);
+        assert_eq!(health.value("ok")["RunningCycles"][1]["Task"], "updater");
+        assert_eq!(
+            health.value("ok")["LastSuccess"],
+            (start + chrono::Duration::minutes(45)).to_rfc3339()
+        );
+    }
+}
@@ -0,0 +1,220 @@
+use crate::aws::{
+    aws_archive::AwsArchive,
+    aws_inventory::{AwsInventoryFormat, AwsInventoryParser},
+    aws_job::AwsJob,
+    aws_vault::AwsVault,
+};
+use crate::metrics;
+use crate::repo::{repo_inventory::InventoryImport, Repository};
+use crate::store::InventoryStore;
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset};
+use hyper::body::HttpBody as _;
+use hyper::Body;
+use log::{debug, info};
+use std::io::{Read, Write};
+use std::time::Instant;
+use tokio_postgres::Transaction;
+
+const IMPORT_BATCH_SIZE: usize = 1000;
+const IMPORT_PROGRESS_INTERVAL: usize = 10_000;
+
+/// Imports an inventory job output that is delivered in pieces, writing the archives to the repository in batches.
+pub struct InventoryImporter<'a, 't> {
+    transaction: &'a Transaction<'t>,
+    vault: &'a AwsVault,
+    job: &'a AwsJob,
+    parser: AwsInventoryParser,
+    inventory_date: Option<DateTime<FixedOffset>>,
+    import: Option<InventoryImport>,
+    batch: Vec<AwsArchive>,
+    archive_count: usize,
+    started: Instant,
+}
+
+impl<'a, 't> InventoryImporter<'a, 't> {
+    pub fn new(
+        transaction: &'a Transaction<'t>,
+        vault: &'a AwsVault,
+        job: &'a AwsJob,
+        format: AwsInventoryFormat,
+    ) -> Self {
+        // CSV inventories do not contain an inventory date, so the completion date of the job is used instead.
+        let inventory_date = match format {
+            AwsInventoryFormat::Json => None,
+            AwsInventoryFormat::Csv => Some(job.completion_date.unwrap_or(job.creation_date)),
+        };
+
+        InventoryImporter {
+            transaction,
+            vault,
+            job,
+            parser: AwsInventoryParser::new(format),
+            inventory_date,
+            import: None,
+            batch: Vec::with_capacity(IMPORT_BATCH_SIZE),
+            archive_count: 0,
+            started: Instant::now(),
+        }
+    }
+
+    pub async fn push(&mut self, data: &[u8]) -> Result<()> {
+        self.parser.push(data);
+
+        while let Some(archive) = self.parser.next_archive()? {
+            self.batch.push(archive);
+
+            if self.batch.len() >= IMPORT_BATCH_SIZE {
+                self.flush().await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Imports the remaining archives and returns the number of archives imported.
+    pub async fn finish(mut self) -> Result<usize> {
+        let mut archives = self.parser.finish()?;
+
+        self.batch.append(&mut archives);
+
+        if self.inventory_date.is_none() {
+            self.inventory_date = self.parser.inventory_date();
+        }
+
+        if self.inventory_date.is_none() {
+            return Err(anyhow::Error::msg(format!(
+                "inventory date of inventory \"{}\" not found",
+                self.job.job_id
+            )));
+        }
+
+        self.flush().await?;
+        info!(
+            vault_name = self.vault.vault_name, job_id = self.job.job_id;
+            "imported inventory \"{}\" with {} archives in {:.1}s",
+            self.job.job_id,
+            self.archive_count,
+            self.started.elapsed().as_secs_f64()
+        );
+        metrics::ARCHIVES_IMPORTED.add(&[&self.vault.vault_name], self.archive_count as f64);
+
+        Ok(self.archive_count)
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        if self.inventory_date.is_none() {
+            self.inventory_date = self.parser.inventory_date();
+        }
+
+        // The parsers only return archives once the inventory date is known, so no batch is held back.
+        let inventory_date = self.inventory_date.ok_or_else(|| {
+            anyhow::Error::msg(format!(
+                "inventory date of inventory \"{}\" not found",
+                self.job.job_id
+            ))
+        })?;
+
+        if self.import.is_none() {
+            self.import = Some(
+                Repository::start_inventory_import(
+                    self.transaction,
+                    self.vault,
+                    self.job,
+                    &inventory_date,
+                )
+                .await?,
+            );
+        }
+
+        if let Some(import) = &self.import {
+            if !self.batch.is_empty() {
+                Repository::import_inventory_archives(
+                    self.transaction,
+                    self.vault,
+                    import,
+                    &self.batch,
+                )
+                .await?;
+                let previous_count = self.archive_count;
+
+                self.archive_count += self.batch.len();
+                self.batch.clear();
+                debug!(
+                    "imported {} archives of inventory \"{}\"",
+                    self.archive_count, self.job.job_id
+                );
+
+                if previous_count / IMPORT_PROGRESS_INTERVAL
+                    != self.archive_count / IMPORT_PROGRESS_INTERVAL
+                {
+                    let elapsed = self.started.elapsed().as_secs_f64();
+
+                    info!(
+                        "importing inventory \"{}\": {} archives after {:.1}s ({:.0} archives/s)",
+                        self.job.job_id,
+                        self.archive_count,
+                        elapsed,
+                        self.archive_count as f64 / elapsed.max(0.001)
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Stores the output of an inventory job and imports it at the same time.
+///
+/// The output is stored completely, even if the import fails.
+pub async fn import_job_output(
+    transaction: &Transaction<'_>,
+    vault: &AwsVault,
+    job: &AwsJob,
+    mut output: Body,
+    store: &InventoryStore,
+) -> Result<usize> {
+    let format = job.inventory_format();
+    let mut writer = store.create(&job.job_id, format)?;
+    let mut importer = InventoryImporter::new(transaction, vault, job, format);
+    let mut import_result = Ok(());
+
+    while let Some(chunk) = output.data().await {
+        let chunk = chunk?;
+
+        writer.write_all(&chunk)?;
+
+        if import_result.is_ok() {
+            import_result = importer.push(&chunk).await;
+        }
+    }
+
+    writer.finish()?;
+    import_result?;
+    importer.finish().await
+}
+
+/// Imports an inventory job output from the store.
+pub async fn import_stored_output(
+    transaction: &Transaction<'_>,
+    vault: &AwsVault,
+    job: &AwsJob,
+    store: &InventoryStore,
+) -> Result<usize> {
+    let (format, mut reader) = store.open(&job.job_id)?;
+    let mut importer = InventoryImporter::new(transaction, vault, job, format);
+    let mut buffer = vec![0u8; 64 * 1024];
+
+    loop {
+        let len = reader.read(&mut buffer)?;
+
+        if len == 0 {
+            break;
+        }
+
+        importer.push(&buffer[..len]).await?;
+    }
+
+    importer.finish().await
+}
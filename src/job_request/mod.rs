@@ -0,0 +1,296 @@
+use crate::aws::{aws_glacier::AwsGlacier, aws_inventory::AwsInventoryRetrievalParameters};
+use crate::logging;
+use crate::repo::Repository;
+use crate::shutdown;
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset, Utc};
+use log::{debug, info, warn};
+use serde::Serialize;
+use std::convert::TryFrom;
+use std::str::FromStr;
+use tokio_postgres::Row;
+use uuid::Uuid;
+
+/// The jobs that can be requested, named like the actions of Glacier jobs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum JobRequestAction {
+    InventoryRetrieval,
+    ArchiveRetrieval,
+}
+
+impl JobRequestAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobRequestAction::InventoryRetrieval => "InventoryRetrieval",
+            JobRequestAction::ArchiveRetrieval => "ArchiveRetrieval",
+        }
+    }
+}
+
+impl FromStr for JobRequestAction {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "InventoryRetrieval" => Ok(JobRequestAction::InventoryRetrieval),
+            "ArchiveRetrieval" => Ok(JobRequestAction::ArchiveRetrieval),
+            _ => Err(anyhow::Error::msg(format!(
+                "unknown job request action \"{}\"",
+                value
+            ))),
+        }
+    }
+}
+
+/// A request stays pending until its job is started; errors are recorded and the job is tried again the next time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobRequestState {
+    Pending,
+    Started,
+}
+
+impl JobRequestState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobRequestState::Pending => "pending",
+            JobRequestState::Started => "started",
+        }
+    }
+}
+
+impl FromStr for JobRequestState {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "pending" => Ok(JobRequestState::Pending),
+            "started" => Ok(JobRequestState::Started),
+            _ => Err(anyhow::Error::msg(format!(
+                "unknown job request state \"{}\"",
+                value
+            ))),
+        }
+    }
+}
+
+/// A job requested through the API, which the updater starts.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct JobRequest {
+    pub request_id: Uuid,
+    #[serde(rename = "VaultARN")]
+    pub vault_arn: String,
+    pub action: JobRequestAction,
+    pub archive_id: Option<String>,
+    /// Retrieval tier of an archive retrieval ("Expedited", "Standard" or "Bulk").
+    pub tier: Option<String>,
+    /// Format of an inventory ("JSON" or "CSV").
+    pub inventory_format: Option<String>,
+    /// Name of the API token the job was requested with.
+    pub requested_by: String,
+    pub state: JobRequestState,
+    pub job_id: Option<String>,
+    pub error: Option<String>,
+    pub creation_date: DateTime<FixedOffset>,
+    pub update_date: DateTime<FixedOffset>,
+}
+
+impl JobRequest {
+    fn new(vault_arn: &str, action: JobRequestAction, requested_by: &str) -> Self {
+        let now = Utc::now().into();
+
+        JobRequest {
+            request_id: Uuid::new_v4(),
+            vault_arn: vault_arn.into(),
+            action,
+            archive_id: None,
+            tier: None,
+            inventory_format: None,
+            requested_by: requested_by.into(),
+            state: JobRequestState::Pending,
+            job_id: None,
+            error: None,
+            creation_date: now,
+            update_date: now,
+        }
+    }
+
+    pub fn inventory_retrieval(vault_arn: &str, format: &str, requested_by: &str) -> Self {
+        JobRequest {
+            inventory_format: Some(format.into()),
+            ..JobRequest::new(
+                vault_arn,
+                JobRequestAction::InventoryRetrieval,
+                requested_by,
+            )
+        }
+    }
+
+    pub fn archive_retrieval(
+        vault_arn: &str,
+        archive_id: &str,
+        tier: &str,
+        requested_by: &str,
+    ) -> Self {
+        JobRequest {
+            archive_id: Some(archive_id.into()),
+            tier: Some(tier.into()),
+            ..JobRequest::new(vault_arn, JobRequestAction::ArchiveRetrieval, requested_by)
+        }
+    }
+}
+
+impl TryFrom<&Row> for JobRequest {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &Row) -> Result<Self, Self::Error> {
+        Ok(JobRequest {
+            request_id: value.try_get("request_id")?,
+            vault_arn: value.try_get("vault_arn")?,
+            action: value.try_get::<_, &str>("action")?.parse()?,
+            archive_id: value.try_get("archive_id")?,
+            tier: value.try_get("tier")?,
+            inventory_format: value.try_get("inventory_format")?,
+            requested_by: value.try_get("requested_by")?,
+            state: value.try_get::<_, &str>("state")?.parse()?,
+            job_id: value.try_get("job_id")?,
+            error: value.try_get("error")?,
+            creation_date: value.try_get("creation_date")?,
+            update_date: value.try_get("update_date")?,
+        })
+    }
+}
+
+/// Starts the jobs of all pending requests, oldest first.
+///
+/// Every request is locked while its job is started, so several updaters do not start the same job. A request, whose job could not be started, keeps the error and is tried again in the next run.
+pub async fn process_job_requests(aws_glacier: &AwsGlacier, repo: &mut Repository) -> Result<()> {
+    let mut failed = Vec::new();
+
+    loop {
+        shutdown::check()?;
+
+        let trans = repo.get_transaction().await?;
+        let mut request = match Repository::lock_pending_job_request(&trans, &failed).await? {
+            Some(request) => request,
+            None => return Ok(()),
+        };
+
+        debug!(
+            "starting {} job of request \"{}\"",
+            request.action.as_str(),
+            request.request_id
+        );
+
+        let request_id = request.request_id.to_string();
+        let mut fields = vec![("request_id", request_id.as_str())];
+
+        if let Some(archive_id) = &request.archive_id {
+            fields.push(("archive_id", archive_id));
+        }
+
+        match logging::span(
+            "job_request",
+            &fields,
+            start_job(aws_glacier, &trans, &request),
+        )
+        .await
+        {
+            Ok(job_id) => {
+                info!(
+                    request_id, job_id;
+                    "started job \"{}\" of request \"{}\" by \"{}\"",
+                    job_id, request.request_id, request.requested_by
+                );
+                request.state = JobRequestState::Started;
+                request.job_id = Some(job_id);
+                request.error = None;
+            }
+            Err(e) => {
+                warn!(
+                    request_id;
+                    "job of request \"{}\" will be retried: {:?}",
+                    request.request_id, e
+                );
+                request.error = Some(e.to_string());
+                failed.push(request.request_id);
+            }
+        }
+
+        Repository::update_job_request(&trans, &request).await?;
+        trans.commit().await?;
+    }
+}
+
+/// Starts the job and records it in the repository.
+async fn start_job(
+    aws_glacier: &AwsGlacier,
+    trans: &tokio_postgres::Transaction<'_>,
+    request: &JobRequest,
+) -> Result<String> {
+    let vault = Repository::get_vault_by_arn(trans, &request.vault_arn).await?;
+    let description = format!("request {}", request.request_id);
+    let job_id = match request.action {
+        JobRequestAction::InventoryRetrieval => {
+            aws_glacier
+                .init_inventory_job_for_vault(
+                    &vault,
+                    &AwsInventoryRetrievalParameters {
+                        format: request
+                            .inventory_format
+                            .as_deref()
+                            .map(str::parse)
+                            .transpose()?,
+                        ..Default::default()
+                    },
+                )
+                .await?
+        }
+        JobRequestAction::ArchiveRetrieval => {
+            aws_glacier
+                .init_archive_retrieval_job(
+                    &vault,
+                    request.archive_id.as_deref().unwrap_or_default(),
+                    request.tier.as_deref().unwrap_or("Standard"),
+                    None,
+                    &description,
+                )
+                .await?
+        }
+    };
+    let job = aws_glacier.get_job_by_id_vault(&vault, &job_id).await?;
+
+    Repository::upsert_job(trans, &job).await?;
+    Repository::upsert_job_status(trans, &job, true).await?;
+
+    Ok(job_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn job_request_1() {
+        let request = JobRequest::archive_retrieval("arn", "archive", "Bulk", "team-a");
+
+        assert_eq!(request.action, JobRequestAction::ArchiveRetrieval);
+        assert_eq!(request.state, JobRequestState::Pending);
+        assert_eq!(
+            "ArchiveRetrieval".parse::<JobRequestAction>().unwrap(),
+            request.action
+        );
+        assert_eq!(
+            request.state.as_str().parse::<JobRequestState>().unwrap(),
+            request.state
+        );
+
+        let value = serde_json::to_value(&request).unwrap();
+
+        assert_eq!(value["Action"], "ArchiveRetrieval");
+        assert_eq!(value["State"], "pending");
+        assert_eq!(value["VaultARN"], "arn");
+        assert_eq!(value["RequestedBy"], "team-a");
+    }
+}
@@ -0,0 +1,44 @@
+use anyhow::Result;
+use log::warn;
+use std::future::Future;
+use std::sync::LazyLock;
+use tokio::task::JoinHandle;
+use tokio::time::Duration;
+use uuid::Uuid;
+
+/// Identifies the leases of this process, as process ids repeat across hosts and containers.
+pub static WORKER_ID: LazyLock<Uuid> = LazyLock::new(Uuid::new_v4);
+
+/// Renews a lease of this worker in the background until it is dropped.
+///
+/// The renewal runs in its own task, so it does not wait for the work done under the lease. It stops once the lease is lost.
+pub struct LeaseRenewal(JoinHandle<()>);
+
+impl LeaseRenewal {
+    pub fn start<F, R>(name: String, interval: Duration, renew: F) -> Self
+    where
+        F: Fn() -> R + Send + 'static,
+        R: Future<Output = Result<bool>> + Send,
+    {
+        LeaseRenewal(tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+
+                match renew().await {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        warn!("lost the lease of {}", name);
+                        return;
+                    }
+                    Err(e) => warn!("error renewing the lease of {}: {:#}", name, e),
+                }
+            }
+        }))
+    }
+}
+
+impl Drop for LeaseRenewal {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
@@ -1,2 +1,20 @@
+pub mod api;
 pub mod aws;
+pub mod backup;
+pub mod catalog;
+pub mod config;
+pub mod cost;
+pub mod crypto;
+pub mod daemon;
+pub mod dedup;
+pub mod health;
+pub mod inventory;
+pub mod job_request;
+pub mod lease;
+pub mod logging;
+pub mod metrics;
 pub mod repo;
+pub mod restore;
+pub mod retention;
+pub mod shutdown;
+pub mod store;
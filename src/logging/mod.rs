@@ -0,0 +1,170 @@
+use anyhow::Result;
+use chrono::{SecondsFormat, Utc};
+use log::kv::{self, Key, VisitSource};
+use log::Record;
+use serde_json::{json, Map, Value};
+use std::env;
+use std::future::Future;
+use std::io::Write;
+use std::str::FromStr;
+
+pub const LOG_FORMAT_ENV: &str = "LOG_FORMAT";
+
+tokio::task_local! {
+    static CONTEXT: Context;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// The default format of `env_logger`.
+    Text,
+    /// One JSON object per line with the fields of the event and its spans.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(anyhow::Error::msg(format!(
+                "unknown log format \"{}\" (expected text or json)",
+                value
+            ))),
+        }
+    }
+}
+
+/// The names of the spans a future runs in and their fields, innermost last.
+#[derive(Debug, Clone, Default)]
+struct Context {
+    spans: Vec<&'static str>,
+    fields: Vec<(&'static str, String)>,
+}
+
+/// Initializes the logger like `env_logger::init`, in the format given in `LOG_FORMAT` (text by default).
+pub fn init() -> Result<()> {
+    let format = match env::var(LOG_FORMAT_ENV) {
+        Ok(format) => format.parse()?,
+        Err(_) => LogFormat::Text,
+    };
+    let mut builder = env_logger::Builder::from_default_env();
+
+    if format == LogFormat::Json {
+        builder.format(|buf, record| writeln!(buf, "{}", json_line(record)));
+    }
+
+    builder.try_init()?;
+
+    Ok(())
+}
+
+/// Runs the future in a span, whose name and fields are added to the JSON lines logged in it.
+///
+/// Fields of an inner span replace those of the same name of the outer spans. Tasks spawned in the span do not inherit it.
+pub async fn span<F: Future>(
+    name: &'static str,
+    fields: &[(&'static str, &str)],
+    future: F,
+) -> F::Output {
+    let mut context = CONTEXT.try_with(|c| c.clone()).unwrap_or_default();
+
+    context.spans.push(name);
+
+    for (key, value) in fields {
+        context.fields.retain(|(k, _)| k != key);
+        context.fields.push((key, value.to_string()));
+    }
+
+    CONTEXT.scope(context, future).await
+}
+
+/// Collects the key-values of an event.
+struct Fields<'a>(&'a mut Map<String, Value>);
+
+impl<'kvs> VisitSource<'kvs> for Fields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        let value = if let Some(value) = value.to_bool() {
+            json!(value)
+        } else if let Some(value) = value.to_i64() {
+            json!(value)
+        } else if let Some(value) = value.to_u64() {
+            json!(value)
+        } else {
+            json!(value.to_string())
+        };
+
+        self.0.insert(key.as_str().into(), value);
+
+        Ok(())
+    }
+}
+
+/// Formats the event with the fields of its spans and its own key-values, which take precedence.
+fn json_line(record: &Record) -> String {
+    let mut line = Map::new();
+
+    line.insert(
+        "timestamp".into(),
+        json!(Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)),
+    );
+    line.insert("level".into(), json!(record.level().as_str()));
+    line.insert("target".into(), json!(record.target()));
+    line.insert("message".into(), json!(record.args().to_string()));
+
+    let _ = CONTEXT.try_with(|context| {
+        if !context.spans.is_empty() {
+            line.insert("spans".into(), json!(context.spans));
+        }
+
+        for (key, value) in &context.fields {
+            line.insert(key.to_string(), json!(value));
+        }
+    });
+    let _ = record.key_values().visit(&mut Fields(&mut line));
+
+    Value::Object(line).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+
+    #[tokio::test]
+    async fn json_line_1() {
+        let line = span("cycle", &[("cycle_id", "c1"), ("task", "worker")], async {
+            span("glacier_request", &[("task", "inner")], async {
+                let key_values: [(&str, &str); 1] = [("job_id", "j1")];
+
+                json_line(
+                    &Record::builder()
+                        .args(format_args!("imported \"{}\"", "j1"))
+                        .level(Level::Info)
+                        .target("backup_remote_rs::inventory")
+                        .key_values(&key_values)
+                        .build(),
+                )
+            })
+            .await
+        })
+        .await;
+        let value: Value = serde_json::from_str(&line).unwrap();
+
+        assert_eq!(value["level"], "INFO");
+        assert_eq!(value["message"], "imported \"j1\"");
+        assert_eq!(value["spans"], json!(["cycle", "glacier_request"]));
+        assert_eq!(value["cycle_id"], "c1");
+        assert_eq!(value["task"], "inner");
+        assert_eq!(value["job_id"], "j1");
+    }
+
+    #[test]
+    fn log_format_1() {
+        assert_eq!("json".parse::<LogFormat>().unwrap(), LogFormat::Json);
+        assert_eq!("text".parse::<LogFormat>().unwrap(), LogFormat::Text);
+        assert!("JSON".parse::<LogFormat>().is_err());
+    }
+}
@@ -0,0 +1,139 @@
+use super::Gauge;
+use crate::health;
+use crate::repo::Repository;
+use crate::shutdown;
+use anyhow::Result;
+use hyper::header::CONTENT_TYPE;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use log::{debug, error, info};
+use serde_json::Value;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::time::{timeout, Duration};
+
+const READINESS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Starts serving the metrics, the liveness and the readiness in the background until a shutdown is requested.
+///
+/// The gauges are read from the repository on every scrape; if it cannot be read, only the recorded metrics are served.
+/// Cycles running for longer than the stall time make the liveness check fail.
+pub fn start(address: SocketAddr, db_connection: &str, stall: Duration) -> Result<()> {
+    let builder = Server::try_bind(&address)?;
+    let db_connection = Arc::new(db_connection.to_string());
+    let make_service = make_service_fn(move |_| {
+        let db_connection = db_connection.clone();
+
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let db_connection = db_connection.clone();
+
+                async move { Ok::<_, Infallible>(handle(&db_connection, stall, request).await) }
+            }))
+        }
+    });
+
+    info!("serving metrics and health on {}", address);
+    tokio::spawn(async move {
+        if let Err(e) = builder
+            .serve(make_service)
+            .with_graceful_shutdown(shutdown::requested())
+            .await
+        {
+            error!("metrics server failed: {:?}", e);
+        }
+    });
+
+    Ok(())
+}
+
+async fn handle(db_connection: &str, stall: Duration, request: Request<Body>) -> Response<Body> {
+    debug!("{} {}", request.method(), request.uri());
+
+    let (status, body) = match (request.method(), request.uri().path()) {
+        (&Method::GET, "/healthz") => return json_response(health::liveness(stall)),
+        (&Method::GET, "/readyz") => {
+            let database = match timeout(READINESS_TIMEOUT, ping(db_connection)).await {
+                Ok(result) => result,
+                Err(_) => Err(anyhow::Error::msg("database timed out")),
+            };
+
+            return json_response(health::readiness(&database));
+        }
+        (&Method::GET, "/metrics") => {
+            let gauges = match repository_gauges(db_connection).await {
+                Ok(gauges) => gauges,
+                Err(e) => {
+                    error!("error reading the repository gauges: {:?}", e);
+                    Vec::new()
+                }
+            };
+
+            (StatusCode::OK, super::render(&gauges))
+        }
+        (_, "/metrics") | (_, "/healthz") | (_, "/readyz") => (
+            StatusCode::METHOD_NOT_ALLOWED,
+            "method not allowed\n".into(),
+        ),
+        _ => (StatusCode::NOT_FOUND, "not found\n".into()),
+    };
+    let mut response = Response::new(Body::from(body));
+
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, "text/plain; version=0.0.4".parse().unwrap());
+
+    response
+}
+
+/// Responds with the status as JSON, with 503 if the check failed.
+fn json_response((ok, value): (bool, Value)) -> Response<Body> {
+    let mut response = Response::new(Body::from(value.to_string()));
+
+    if !ok {
+        *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+    }
+
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, "application/json".parse().unwrap());
+
+    response
+}
+
+async fn ping(db_connection: &str) -> Result<()> {
+    let mut repo = Repository::new(db_connection).await?;
+    let trans = repo.get_transaction().await?;
+
+    Repository::ping(&trans).await
+}
+
+async fn repository_gauges(db_connection: &str) -> Result<Vec<Gauge>> {
+    let mut repo = Repository::new(db_connection).await?;
+    let trans = repo.get_transaction().await?;
+    let (vaults, size_in_bytes) = Repository::get_vault_totals(&trans).await?;
+    let jobs = Repository::count_active_jobs_by_status(&trans).await?;
+
+    Ok(vec![
+        Gauge {
+            name: "backup_remote_vaults",
+            help: "Active vaults in the repository.",
+            values: vec![(vec![], vaults as f64)],
+        },
+        Gauge {
+            name: "backup_remote_vault_size_bytes",
+            help: "Bytes stored in the active vaults, as of their last inventory.",
+            values: vec![(vec![], size_in_bytes as f64)],
+        },
+        Gauge {
+            name: "backup_remote_active_jobs",
+            help: "Jobs Glacier still lists, by status code.",
+            values: jobs
+                .into_iter()
+                .map(|(status_code, count)| (vec![("status_code", status_code)], count as f64))
+                .collect(),
+        },
+    ])
+}
@@ -0,0 +1,342 @@
+pub mod metrics_server;
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Buckets of short operations like requests, in seconds.
+const REQUEST_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+/// Buckets of long operations like update cycles, in seconds.
+const TASK_BUCKETS: &[f64] = &[1.0, 5.0, 15.0, 60.0, 300.0, 900.0, 3600.0, 10800.0];
+
+pub static GLACIER_REQUESTS: Counter = Counter::new(
+    "backup_remote_glacier_requests_total",
+    "Glacier requests by operation and HTTP status (\"error\" if no response was received).",
+    &["operation", "status"],
+);
+pub static GLACIER_REQUEST_DURATION: Histogram = Histogram::new(
+    "backup_remote_glacier_request_duration_seconds",
+    "Time until the response of a Glacier request was received.",
+    &["operation"],
+    REQUEST_BUCKETS,
+);
+pub static RETRIES: Counter = Counter::new(
+    "backup_remote_retries_total",
+    "Runs of a task repeated after a failure.",
+    &["task"],
+);
+pub static TASK_RUNS: Counter = Counter::new(
+    "backup_remote_task_runs_total",
+    "Finished runs of a task (an update cycle) by outcome.",
+    &["task", "outcome"],
+);
+pub static TASK_DURATION: Histogram = Histogram::new(
+    "backup_remote_task_duration_seconds",
+    "Duration of the runs of a task (an update cycle).",
+    &["task"],
+    TASK_BUCKETS,
+);
+pub static ARCHIVES_IMPORTED: Counter = Counter::new(
+    "backup_remote_archives_imported_total",
+    "Archives imported from inventories.",
+    &["vault"],
+);
+
+/// Records the duration and outcome of a run of a task.
+pub fn observe_task(task: &str, duration: Duration, succeeded: bool) {
+    TASK_DURATION.observe(&[task], duration.as_secs_f64());
+    TASK_RUNS.inc(&[task, if succeeded { "success" } else { "failure" }]);
+}
+
+/// The values of all series by metric name and label values.
+static REGISTRY: Mutex<BTreeMap<&'static str, Family>> = Mutex::new(BTreeMap::new());
+
+struct Family {
+    help: &'static str,
+    kind: &'static str,
+    label_names: &'static [&'static str],
+    series: BTreeMap<Vec<String>, Series>,
+}
+
+enum Series {
+    Counter(f64),
+    Histogram {
+        buckets: &'static [f64],
+        counts: Vec<u64>,
+        sum: f64,
+        count: u64,
+    },
+}
+
+/// A metric, which only increases.
+pub struct Counter {
+    name: &'static str,
+    help: &'static str,
+    label_names: &'static [&'static str],
+}
+
+impl Counter {
+    pub const fn new(
+        name: &'static str,
+        help: &'static str,
+        label_names: &'static [&'static str],
+    ) -> Self {
+        Counter {
+            name,
+            help,
+            label_names,
+        }
+    }
+
+    /// Increments the series of the label values, given in the order of the label names.
+    pub fn inc(&self, labels: &[&str]) {
+        self.add(labels, 1.0);
+    }
+
+    pub fn add(&self, labels: &[&str], value: f64) {
+        update(
+            self.name,
+            self.help,
+            "counter",
+            self.label_names,
+            labels,
+            || Series::Counter(0.0),
+            |series| {
+                if let Series::Counter(total) = series {
+                    *total += value;
+                }
+            },
+        );
+    }
+}
+
+/// A metric counting observations in buckets of their value.
+pub struct Histogram {
+    name: &'static str,
+    help: &'static str,
+    label_names: &'static [&'static str],
+    buckets: &'static [f64],
+}
+
+impl Histogram {
+    pub const fn new(
+        name: &'static str,
+        help: &'static str,
+        label_names: &'static [&'static str],
+        buckets: &'static [f64],
+    ) -> Self {
+        Histogram {
+            name,
+            help,
+            label_names,
+            buckets,
+        }
+    }
+
+    pub fn observe(&self, labels: &[&str], value: f64) {
+        let buckets = self.buckets;
+
+        update(
+            self.name,
+            self.help,
+            "histogram",
+            self.label_names,
+            labels,
+            || Series::Histogram {
+                buckets,
+                counts: vec![0; buckets.len()],
+                sum: 0.0,
+                count: 0,
+            },
+            |series| {
+                if let Series::Histogram {
+                    buckets,
+                    counts,
+                    sum,
+                    count,
+                } = series
+                {
+                    for (bucket, bucket_count) in buckets.iter().zip(counts.iter_mut()) {
+                        if value <= *bucket {
+                            *bucket_count += 1;
+                        }
+                    }
+
+                    *sum += value;
+                    *count += 1;
+                }
+            },
+        );
+    }
+}
+
+fn update<C, U>(
+    name: &'static str,
+    help: &'static str,
+    kind: &'static str,
+    label_names: &'static [&'static str],
+    labels: &[&str],
+    create: C,
+    update: U,
+) where
+    C: FnOnce() -> Series,
+    U: FnOnce(&mut Series),
+{
+    debug_assert_eq!(label_names.len(), labels.len());
+
+    let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+    let family = registry.entry(name).or_insert_with(|| Family {
+        help,
+        kind,
+        label_names,
+        series: BTreeMap::new(),
+    });
+
+    update(
+        family
+            .series
+            .entry(labels.iter().map(|l| l.to_string()).collect())
+            .or_insert_with(create),
+    );
+}
+
+/// A value taken at the time of the scrape.
+pub struct Gauge {
+    pub name: &'static str,
+    pub help: &'static str,
+    /// Values by the label values.
+    pub values: Vec<(Vec<(&'static str, String)>, f64)>,
+}
+
+/// Renders all recorded metrics and the gauges in the Prometheus text format.
+pub fn render(gauges: &[Gauge]) -> String {
+    let registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+    let mut text = String::new();
+
+    for (name, family) in registry.iter() {
+        let _ = writeln!(text, "# HELP {} {}", name, family.help);
+        let _ = writeln!(text, "# TYPE {} {}", name, family.kind);
+
+        for (labels, series) in &family.series {
+            let labels: Vec<(&str, String)> = family
+                .label_names
+                .iter()
+                .cloned()
+                .zip(labels.iter().cloned())
+                .collect();
+
+            match series {
+                Series::Counter(total) => {
+                    let _ = writeln!(text, "{}{} {}", name, render_labels(&labels), total);
+                }
+                Series::Histogram {
+                    buckets,
+                    counts,
+                    sum,
+                    count,
+                } => {
+                    for (bucket, bucket_count) in buckets.iter().zip(counts) {
+                        let mut bucket_labels = labels.clone();
+
+                        bucket_labels.push(("le", bucket.to_string()));
+                        let _ = writeln!(
+                            text,
+                            "{}_bucket{} {}",
+                            name,
+                            render_labels(&bucket_labels),
+                            bucket_count
+                        );
+                    }
+
+                    let mut bucket_labels = labels.clone();
+
+                    bucket_labels.push(("le", "+Inf".into()));
+                    let _ = writeln!(
+                        text,
+                        "{}_bucket{} {}",
+                        name,
+                        render_labels(&bucket_labels),
+                        count
+                    );
+                    let _ = writeln!(text, "{}_sum{} {}", name, render_labels(&labels), sum);
+                    let _ = writeln!(text, "{}_count{} {}", name, render_labels(&labels), count);
+                }
+            }
+        }
+    }
+
+    for gauge in gauges {
+        let _ = writeln!(text, "# HELP {} {}", gauge.name, gauge.help);
+        let _ = writeln!(text, "# TYPE {} gauge", gauge.name);
+
+        for (labels, value) in &gauge.values {
+            let _ = writeln!(text, "{}{} {}", gauge.name, render_labels(labels), value);
+        }
+    }
+
+    text
+}
+
+fn render_labels(labels: &[(&str, String)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(name, value)| {
+            format!(
+                "{}=\"{}\"",
+                name,
+                value
+                    .replace('\\', "\\\\")
+                    .replace('"', "\\\"")
+                    .replace('\n', "\\n")
+            )
+        })
+        .collect();
+
+    format!("{{{}}}", labels.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_1() {
+        static TEST_COUNTER: Counter = Counter::new(
+            "test_requests_total",
+            "Test requests.",
+            &["operation", "status"],
+        );
+        static TEST_HISTOGRAM: Histogram =
+            Histogram::new("test_duration_seconds", "Test durations.", &[], &[1.0, 5.0]);
+
+        TEST_COUNTER.inc(&["ListVaults", "200"]);
+        TEST_COUNTER.inc(&["ListVaults", "200"]);
+        TEST_COUNTER.inc(&["Describe\"Vault", "error"]);
+        TEST_HISTOGRAM.observe(&[], 0.5);
+        TEST_HISTOGRAM.observe(&[], 3.0);
+
+        let text = render(&[Gauge {
+            name: "test_vaults",
+            help: "Test vaults.",
+            values: vec![(vec![], 2.0)],
+        }]);
+
+        assert!(text.contains(
+            "# HELP test_requests_total Test requests.\n# TYPE test_requests_total counter\n"
+        ));
+        assert!(text.contains("test_requests_total{operation=\"ListVaults\",status=\"200\"} 2\n"));
+        assert!(text
+            .contains("test_requests_total{operation=\"Describe\\\"Vault\",status=\"error\"} 1\n"));
+        assert!(text.contains("test_duration_seconds_bucket{le=\"1\"} 1\n"));
+        assert!(text.contains("test_duration_seconds_bucket{le=\"5\"} 2\n"));
+        assert!(text.contains("test_duration_seconds_bucket{le=\"+Inf\"} 2\n"));
+        assert!(text.contains("test_duration_seconds_sum 3.5\n"));
+        assert!(text.contains("test_duration_seconds_count 2\n"));
+        assert!(text.contains("# TYPE test_vaults gauge\ntest_vaults 2\n"));
+    }
+}
@@ -1,10 +1,59 @@
 pub mod repo_archive;
+pub mod repo_backup;
+pub mod repo_catalog;
+pub mod repo_inventory;
 pub mod repo_job;
+pub mod repo_job_request;
+pub mod repo_key;
+pub mod repo_restore;
+pub mod repo_retention;
+pub mod repo_snapshot;
+pub mod repo_upload;
 pub mod repo_vault;
 
 use anyhow::Result;
 use std::str;
-use tokio_postgres::{Client, NoTls, Transaction};
+use tokio_postgres::{Client, NoTls, Row, Transaction};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpsertResult {
+    Inserted,
+    Updated,
+    Unchanged,
+}
+
+impl UpsertResult {
+    /// Interprets the rows returned by an upsert statement ending in `RETURNING (xmax = 0) AS inserted`.
+    ///
+    /// The update branch of the statements is guarded by `IS DISTINCT FROM`, so no row is returned if nothing changed.
+    fn from_rows(rows: &[Row]) -> Result<Self> {
+        match rows.len() {
+            0 => Ok(UpsertResult::Unchanged),
+            1 => {
+                if rows[0].try_get("inserted")? {
+                    Ok(UpsertResult::Inserted)
+                } else {
+                    Ok(UpsertResult::Updated)
+                }
+            }
+            _ => Err(anyhow::Error::msg("unexpected number of rows from upsert")),
+        }
+    }
+}
+
+/// A page of a listing, selected by offset and limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Page {
+    pub limit: i64,
+    pub offset: i64,
+}
+
+/// The items of a page and the number of items in the whole listing.
+#[derive(Debug)]
+pub struct Paged<T> {
+    pub items: Vec<T>,
+    pub total: i64,
+}
 
 pub struct Repository {
     client: Client,
@@ -28,4 +77,11 @@ impl Repository {
     pub async fn get_transaction(&mut self) -> Result<Transaction<'_>> {
         self.client.transaction().await.map_err(|e| e.into())
     }
+
+    /// Checks that the database answers queries.
+    pub async fn ping(transaction: &Transaction<'_>) -> Result<()> {
+        transaction.simple_query("SELECT 1").await?;
+
+        Ok(())
+    }
 }
@@ -1,7 +1,10 @@
-use super::Repository;
-use crate::aws::{aws_archive::AwsArchive, aws_vault::AwsVault};
+use super::{Page, Paged, Repository, UpsertResult};
+use crate::aws::aws_archive::{ArchiveMetadata, AwsArchive};
+use crate::aws::aws_vault::AwsVault;
 use anyhow::Result;
-use log::debug;
+use chrono::{DateTime, FixedOffset};
+use log::{debug, warn};
+use std::collections::hash_map::{Entry, HashMap};
 use std::convert::TryFrom;
 use tokio_postgres::Transaction;
 
@@ -37,4 +40,293 @@ impl Repository {
             _ => Err(anyhow::Error::msg("error updating archive")),
         }
     }
+
+    pub async fn upsert_archive(
+        transaction: &Transaction<'_>,
+        archive: &AwsArchive,
+    ) -> Result<UpsertResult> {
+        debug!("upserting archive \"{}\"", archive.archive_id);
+        let rows = transaction.query(
+            "INSERT INTO archives (archive_id, archive_description, creation_date, size, tree_hash) VALUES ($1, $2, $3, $4, $5) \
+            ON CONFLICT (archive_id) DO UPDATE SET archive_description=EXCLUDED.archive_description, creation_date=EXCLUDED.creation_date, size=EXCLUDED.size, tree_hash=EXCLUDED.tree_hash \
+            WHERE (archives.archive_description, archives.creation_date, archives.size, archives.tree_hash) IS DISTINCT FROM (EXCLUDED.archive_description, EXCLUDED.creation_date, EXCLUDED.size, EXCLUDED.tree_hash) \
+            RETURNING (xmax = 0) AS inserted",
+            &[&archive.archive_id, &archive.archive_description, &archive.creation_date, &archive.size, &archive.tree_hash]
+        ).await?;
+
+        Repository::upsert_archives_metadata(transaction, std::slice::from_ref(archive)).await?;
+        UpsertResult::from_rows(&rows)
+    }
+
+    /// Upserts a batch of archives with a single statement.
+    ///
+    /// An archive listed more than once is upserted as last listed, as a statement cannot update a row twice.
+    pub async fn upsert_archives(
+        transaction: &Transaction<'_>,
+        archives: &[AwsArchive],
+    ) -> Result<()> {
+        let archives = &distinct_archives(archives);
+
+        debug!("upserting {} archives", archives.len());
+        let archive_ids: Vec<&str> = archives.iter().map(|a| &*a.archive_id).collect();
+        let archive_descriptions: Vec<&str> =
+            archives.iter().map(|a| &*a.archive_description).collect();
+        let creation_dates: Vec<_> = archives.iter().map(|a| a.creation_date).collect();
+        let sizes: Vec<i64> = archives.iter().map(|a| a.size).collect();
+        let tree_hashes: Vec<&str> = archives.iter().map(|a| &*a.tree_hash).collect();
+
+        transaction.query(
+            "INSERT INTO archives (archive_id, archive_description, creation_date, size, tree_hash) \
+            SELECT * FROM UNNEST($1::varchar[], $2::varchar[], $3::timestamptz[], $4::bigint[], $5::varchar[]) \
+            ON CONFLICT (archive_id) DO UPDATE SET archive_description=EXCLUDED.archive_description, creation_date=EXCLUDED.creation_date, size=EXCLUDED.size, tree_hash=EXCLUDED.tree_hash \
+            WHERE (archives.archive_description, archives.creation_date, archives.size, archives.tree_hash) IS DISTINCT FROM (EXCLUDED.archive_description, EXCLUDED.creation_date, EXCLUDED.size, EXCLUDED.tree_hash)",
+            &[&archive_ids, &archive_descriptions, &creation_dates, &sizes, &tree_hashes]
+        ).await?;
+
+        Repository::upsert_archives_metadata(transaction, archives).await
+    }
+
+    /// Stores the metadata decoded from the descriptions of the archives.
+    ///
+    /// Archives with free-form descriptions are skipped, as are descriptions that cannot be decoded.
+    pub async fn upsert_archives_metadata(
+        transaction: &Transaction<'_>,
+        archives: &[AwsArchive],
+    ) -> Result<()> {
+        let mut archive_ids = Vec::new();
+        let mut metadata = Vec::new();
+
+        for archive in archives {
+            match archive.metadata() {
+                Ok(Some(m)) => {
+                    archive_ids.push(&*archive.archive_id);
+                    metadata.push(m);
+                }
+                Ok(None) => {}
+                Err(e) => warn!(
+                    "failed to decode metadata of archive \"{}\": {}",
+                    archive.archive_id, e
+                ),
+            }
+        }
+
+        if metadata.is_empty() {
+            return Ok(());
+        }
+
+        debug!("upserting metadata of {} archives", metadata.len());
+        let versions: Vec<i16> = metadata.iter().map(|m| m.version as i16).collect();
+        let hosts: Vec<&str> = metadata.iter().map(|m| &*m.host).collect();
+        let backup_sets: Vec<Option<&str>> =
+            metadata.iter().map(|m| m.backup_set.as_deref()).collect();
+        let paths = metadata
+            .iter()
+            .map(|m| serde_json::to_string(&m.paths))
+            .collect::<Result<Vec<String>, _>>()?;
+        let paths_truncated: Vec<bool> = metadata.iter().map(|m| m.paths_truncated).collect();
+        let creation_dates: Vec<_> = metadata.iter().map(|m| m.timestamp).collect();
+        let encryptions: Vec<Option<&str>> =
+            metadata.iter().map(|m| m.encryption.as_deref()).collect();
+        let key_ids = metadata
+            .iter()
+            .map(|m| serde_json::to_string(&m.key_ids))
+            .collect::<Result<Vec<String>, _>>()?;
+        let content_hashes: Vec<Option<&str>> =
+            metadata.iter().map(|m| m.content_hash.as_deref()).collect();
+
+        transaction.query(
+            "INSERT INTO archives_metadata (archive_id, version, host, backup_set, paths, paths_truncated, creation_date, encryption, key_ids, content_hash) \
+            SELECT a, v, h, s, p::jsonb, pt, t, e, k::jsonb, c FROM UNNEST($1::varchar[], $2::smallint[], $3::varchar[], $4::varchar[], $5::varchar[], $6::boolean[], $7::timestamptz[], $8::varchar[], $9::varchar[], $10::varchar[]) AS m(a, v, h, s, p, pt, t, e, k, c) \
+            ON CONFLICT (archive_id) DO UPDATE SET version=EXCLUDED.version, host=EXCLUDED.host, backup_set=EXCLUDED.backup_set, paths=EXCLUDED.paths, paths_truncated=EXCLUDED.paths_truncated, \
+            creation_date=EXCLUDED.creation_date, encryption=EXCLUDED.encryption, key_ids=EXCLUDED.key_ids, content_hash=EXCLUDED.content_hash",
+            &[&archive_ids, &versions, &hosts, &backup_sets, &paths, &paths_truncated, &creation_dates, &encryptions, &key_ids, &content_hashes]
+        ).await?;
+
+        Ok(())
+    }
+
+    pub async fn get_archive_metadata(
+        transaction: &Transaction<'_>,
+        archive_id: &str,
+    ) -> Result<Option<ArchiveMetadata>> {
+        debug!("getting metadata of archive \"{}\"", archive_id);
+        let rows = transaction
+            .query(
+                "SELECT version, host, backup_set, paths::text AS paths, paths_truncated, creation_date, encryption, key_ids::text AS key_ids, content_hash FROM archives_metadata WHERE archive_id=$1",
+                &[&archive_id],
+            )
+            .await?;
+
+        match rows.len() {
+            0 => Ok(None),
+            1 => Ok(Some(ArchiveMetadata::try_from(&rows[0])?)),
+            _ => Err(anyhow::Error::msg("error getting archive metadata")),
+        }
+    }
+
+    pub async fn get_archive(
+        transaction: &Transaction<'_>,
+        archive_id: &str,
+    ) -> Result<AwsArchive> {
+        debug!("getting archive \"{}\"", archive_id);
+        let rows = transaction
+            .query("SELECT * FROM archives WHERE archive_id=$1", &[&archive_id])
+            .await?;
+
+        match rows.len() {
+            1 => Ok(AwsArchive::try_from(&rows[0])?),
+            _ => Err(anyhow::Error::msg(format!(
+                "archive \"{}\" not found",
+                archive_id
+            ))),
+        }
+    }
+
+    /// Returns the archives created for a backup set according to their metadata, newest first.
+    pub async fn get_archives_for_backup_set(
+        transaction: &Transaction<'_>,
+        backup_set: &str,
+    ) -> Result<Vec<AwsArchive>> {
+        debug!("getting archives for backup set \"{}\"", backup_set);
+        let rows = transaction
+            .query(
+                "SELECT a.* FROM archives a JOIN archives_metadata m ON a.archive_id=m.archive_id WHERE m.backup_set=$1 ORDER BY m.creation_date DESC",
+                &[&backup_set],
+            )
+            .await?;
+
+        rows.iter().map(AwsArchive::try_from).collect()
+    }
+
+    /// Returns the archives of a vault created after the given date, which are not deleted.
+    pub async fn get_vault_archives_since(
+        transaction: &Transaction<'_>,
+        vault: &AwsVault,
+        since: DateTime<FixedOffset>,
+    ) -> Result<Vec<AwsArchive>> {
+        debug!(
+            "getting archives of vault \"{}\" created since {}",
+            vault.vault_name, since
+        );
+        let rows = transaction
+            .query(
+                "SELECT a.* FROM archives a JOIN vaults_archives va ON a.archive_id=va.archive_id \
+                WHERE va.vault_arn=$1 AND a.creation_date > $2 \
+                AND NOT EXISTS (SELECT 1 FROM archives_deletions d WHERE d.archive_id=a.archive_id) \
+                ORDER BY a.creation_date",
+                &[&vault.vault_arn, &since],
+            )
+            .await?;
+
+        rows.iter().map(AwsArchive::try_from).collect()
+    }
+
+    /// Returns whether the archive is stored in the vault and not deleted.
+    pub async fn is_vault_archive(
+        transaction: &Transaction<'_>,
+        vault_arn: &str,
+        archive_id: &str,
+    ) -> Result<bool> {
+        debug!(
+            "checking whether archive \"{}\" is in vault \"{}\"",
+            archive_id, vault_arn
+        );
+        let rows = transaction
+            .query(
+                "SELECT 1 FROM vaults_archives va WHERE va.vault_arn=$1 AND va.archive_id=$2 \
+                AND NOT EXISTS (SELECT 1 FROM archives_deletions d WHERE d.archive_id=va.archive_id)",
+                &[&vault_arn, &archive_id],
+            )
+            .await?;
+
+        Ok(!rows.is_empty())
+    }
+
+    /// Returns a page of the archives of a vault, which are not deleted, optionally created within a period, newest first.
+    pub async fn get_vault_archives_page(
+        transaction: &Transaction<'_>,
+        vault_arn: &str,
+        created_after: Option<DateTime<FixedOffset>>,
+        created_before: Option<DateTime<FixedOffset>>,
+        page: &Page,
+    ) -> Result<Paged<AwsArchive>> {
+        debug!("getting archives of vault \"{}\"", vault_arn);
+        let filter = "FROM archives a JOIN vaults_archives va ON a.archive_id=va.archive_id \
+            WHERE va.vault_arn=$1 AND ($2::timestamptz IS NULL OR a.creation_date > $2) AND ($3::timestamptz IS NULL OR a.creation_date < $3) \
+            AND NOT EXISTS (SELECT 1 FROM archives_deletions d WHERE d.archive_id=a.archive_id)";
+        let rows = transaction
+            .query(
+                format!(
+                    "SELECT a.* {} ORDER BY a.creation_date DESC, a.archive_id LIMIT $4 OFFSET $5",
+                    filter
+                )
+                .as_str(),
+                &[
+                    &vault_arn,
+                    &created_after,
+                    &created_before,
+                    &page.limit,
+                    &page.offset,
+                ],
+            )
+            .await?;
+        let total = transaction
+            .query_one(
+                format!("SELECT count(*) AS total {}", filter).as_str(),
+                &[&vault_arn, &created_after, &created_before],
+            )
+            .await?
+            .try_get("total")?;
+
+        Ok(Paged {
+            items: rows
+                .iter()
+                .map(AwsArchive::try_from)
+                .collect::<Result<_>>()?,
+            total,
+        })
+    }
+}
+
+/// Returns the archives with distinct ids in the order first listed, each as last listed.
+pub fn distinct_archives(archives: &[AwsArchive]) -> Vec<AwsArchive> {
+    let mut positions = HashMap::new();
+    let mut distinct: Vec<AwsArchive> = Vec::with_capacity(archives.len());
+
+    for archive in archives {
+        match positions.entry(&*archive.archive_id) {
+            Entry::Occupied(entry) => distinct[*entry.get()] = archive.clone(),
+            Entry::Vacant(entry) => {
+                entry.insert(distinct.len());
+                distinct.push(archive.clone());
+            }
+        }
+    }
+
+    distinct
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distinct_archives_1() {
+        let archive = |archive_id: &str, size: i64| AwsArchive {
+            archive_id: archive_id.into(),
+            archive_description: "".into(),
+            creation_date: DateTime::parse_from_rfc3339("2026-10-19T00:00:00Z").unwrap(),
+            size,
+            tree_hash: "".into(),
+        };
+        let distinct = distinct_archives(&[archive("a", 1), archive("b", 2), archive("a", 3)]);
+
+        assert_eq!(
+            distinct
+                .iter()
+                .map(|a| (&*a.archive_id, a.size))
+                .collect::<Vec<_>>(),
+            vec![("a", 3), ("b", 2)]
+        );
+    }
 }
@@ -0,0 +1,141 @@
+use super::Repository;
+use crate::backup::{backup_segment::BackupFrame, Backup};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use log::debug;
+use std::convert::TryFrom;
+use tokio_postgres::Transaction;
+
+impl Repository {
+    /// Records a backup and its sources. The archive itself has to be stored before.
+    pub async fn create_backup(transaction: &Transaction<'_>, backup: &Backup) -> Result<()> {
+        debug!("creating backup \"{}\"", backup.archive_id);
+        transaction
+            .query(
+                "INSERT INTO backups (archive_id, vault_arn, creation_date, compression, encryption, source_size, encryption_header, backup_set, parent_archive_id, chain_length) \
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+                &[
+                    &backup.archive_id,
+                    &backup.vault_arn,
+                    &backup.creation_date,
+                    &backup.compression.as_str(),
+                    &backup.encryption.map(|e| e.as_str()),
+                    &backup.source_size(),
+                    &backup.encryption_header,
+                    &backup.backup_set,
+                    &backup.parent_archive_id,
+                    &backup.chain_length,
+                ],
+            )
+            .await?;
+
+        let paths: Vec<&str> = backup.sources.iter().map(|s| &*s.path).collect();
+        let sizes: Vec<i64> = backup.sources.iter().map(|s| s.size).collect();
+
+        transaction
+            .query(
+                "INSERT INTO backups_sources (archive_id, path, size) SELECT $1, * FROM UNNEST($2::varchar[], $3::bigint[])",
+                &[&backup.archive_id, &paths, &sizes],
+            )
+            .await?;
+
+        let tar_offsets: Vec<i64> = backup.frames.iter().map(|f| f.tar_offset).collect();
+        let compressed_offsets: Vec<i64> =
+            backup.frames.iter().map(|f| f.compressed_offset).collect();
+
+        transaction
+            .query(
+                "INSERT INTO backups_frames (archive_id, tar_offset, compressed_offset) SELECT $1, * FROM UNNEST($2::bigint[], $3::bigint[])",
+                &[&backup.archive_id, &tar_offsets, &compressed_offsets],
+            )
+            .await?;
+
+        transaction
+            .query(
+                "INSERT INTO backups_deletions (archive_id, path) SELECT $1, * FROM UNNEST($2::varchar[])",
+                &[&backup.archive_id, &backup.deletions],
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Returns the id and chain length of the latest backup of a backup set.
+    pub async fn get_latest_backup(
+        transaction: &Transaction<'_>,
+        backup_set: &str,
+    ) -> Result<Option<(String, i32)>> {
+        debug!("getting latest backup of backup set \"{}\"", backup_set);
+        let rows = transaction
+            .query(
+                "SELECT archive_id, chain_length FROM backups WHERE backup_set=$1 ORDER BY creation_date DESC LIMIT 1",
+                &[&backup_set],
+            )
+            .await?;
+
+        match rows.first() {
+            Some(row) => Ok(Some((
+                row.try_get("archive_id")?,
+                row.try_get("chain_length")?,
+            ))),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the creation date of the latest backup or snapshot of a backup set.
+    pub async fn get_latest_backup_date(
+        transaction: &Transaction<'_>,
+        backup_set: &str,
+    ) -> Result<Option<DateTime<Utc>>> {
+        debug!(
+            "getting latest backup date of backup set \"{}\"",
+            backup_set
+        );
+        let row = transaction
+            .query_one(
+                "SELECT max(creation_date) AS creation_date FROM \
+                 (SELECT creation_date FROM backups WHERE backup_set=$1 \
+                  UNION ALL SELECT creation_date FROM snapshots WHERE backup_set=$1) b",
+                &[&backup_set],
+            )
+            .await?;
+
+        Ok(row.try_get("creation_date")?)
+    }
+
+    /// Returns the compression frames of a backup ordered by offset, which are empty for backups created without a frame index.
+    pub async fn get_backup_frames(
+        transaction: &Transaction<'_>,
+        archive_id: &str,
+    ) -> Result<Vec<BackupFrame>> {
+        debug!("getting frames of backup \"{}\"", archive_id);
+        let rows = transaction
+            .query(
+                "SELECT * FROM backups_frames WHERE archive_id=$1 ORDER BY tar_offset",
+                &[&archive_id],
+            )
+            .await?;
+
+        rows.iter().map(BackupFrame::try_from).collect()
+    }
+
+    pub async fn get_backup_encryption_header(
+        transaction: &Transaction<'_>,
+        archive_id: &str,
+    ) -> Result<Option<Vec<u8>>> {
+        debug!("getting encryption header of backup \"{}\"", archive_id);
+        let rows = transaction
+            .query(
+                "SELECT encryption_header FROM backups WHERE archive_id=$1",
+                &[&archive_id],
+            )
+            .await?;
+
+        match rows.len() {
+            1 => Ok(rows[0].try_get("encryption_header")?),
+            _ => Err(anyhow::Error::msg(format!(
+                "backup \"{}\" not found",
+                archive_id
+            ))),
+        }
+    }
+}
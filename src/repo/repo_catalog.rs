@@ -0,0 +1,106 @@
+use super::Repository;
+use crate::catalog::{CatalogEntry, CatalogMatch};
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset};
+use log::debug;
+use std::convert::TryFrom;
+use tokio_postgres::Transaction;
+
+const CATALOG_BATCH_SIZE: usize = 1000;
+
+impl Repository {
+    /// Stores the catalog of a backup in batches.
+    pub async fn create_catalog_entries(
+        transaction: &Transaction<'_>,
+        archive_id: &str,
+        entries: &[CatalogEntry],
+    ) -> Result<()> {
+        debug!(
+            "creating {} catalog entries for archive \"{}\"",
+            entries.len(),
+            archive_id
+        );
+
+        for batch in entries.chunks(CATALOG_BATCH_SIZE) {
+            let paths: Vec<&str> = batch.iter().map(|e| &*e.path).collect();
+            let entry_types: Vec<&str> = batch.iter().map(|e| e.entry_type.as_str()).collect();
+            let sizes: Vec<i64> = batch.iter().map(|e| e.size).collect();
+            let mtimes: Vec<_> = batch.iter().map(|e| e.mtime).collect();
+            let modes: Vec<i32> = batch.iter().map(|e| e.mode).collect();
+            let content_hashes: Vec<Option<&str>> =
+                batch.iter().map(|e| e.content_hash.as_deref()).collect();
+            let offsets: Vec<i64> = batch.iter().map(|e| e.offset).collect();
+            let inodes: Vec<Option<i64>> = batch.iter().map(|e| e.inode).collect();
+            let data_archive_ids: Vec<Option<&str>> =
+                batch.iter().map(|e| e.data_archive_id.as_deref()).collect();
+
+            transaction
+                .query(
+                    "INSERT INTO catalog_entries (archive_id, path, entry_type, size, mtime, mode, content_hash, \"offset\", inode, data_archive_id) \
+                    SELECT $1, * FROM UNNEST($2::varchar[], $3::varchar[], $4::bigint[], $5::timestamptz[], $6::integer[], $7::varchar[], $8::bigint[], $9::bigint[], $10::varchar[])",
+                    &[&archive_id, &paths, &entry_types, &sizes, &mtimes, &modes, &content_hashes, &offsets, &inodes, &data_archive_ids],
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the catalog of a backup in the order of the tar stream.
+    pub async fn get_catalog_entries(
+        transaction: &Transaction<'_>,
+        archive_id: &str,
+    ) -> Result<Vec<CatalogEntry>> {
+        debug!("getting catalog entries of archive \"{}\"", archive_id);
+        let rows = transaction
+            .query(
+                "SELECT * FROM catalog_entries WHERE archive_id=$1 ORDER BY \"offset\"",
+                &[&archive_id],
+            )
+            .await?;
+
+        rows.iter().map(CatalogEntry::try_from).collect()
+    }
+
+    /// Finds the entries matching the regular expression in backups created in the given period, newest first.
+    pub async fn find_catalog_entries(
+        transaction: &Transaction<'_>,
+        path_regex: &str,
+        after: Option<DateTime<FixedOffset>>,
+        before: Option<DateTime<FixedOffset>>,
+    ) -> Result<Vec<CatalogMatch>> {
+        debug!("finding catalog entries matching \"{}\"", path_regex);
+        let rows = transaction
+            .query(
+                "SELECT c.*, b.creation_date AS backup_date FROM catalog_entries c JOIN backups b ON c.archive_id=b.archive_id \
+                WHERE c.path ~ $1 AND ($2::timestamptz IS NULL OR b.creation_date >= $2) AND ($3::timestamptz IS NULL OR b.creation_date <= $3) \
+                ORDER BY b.creation_date DESC, c.path",
+                &[&path_regex, &after, &before],
+            )
+            .await?;
+
+        rows.iter().map(CatalogMatch::try_from).collect()
+    }
+
+    /// Lists the entries directly within a directory as of the newest backup created before the given date, which contains the directory.
+    pub async fn list_catalog_directory(
+        transaction: &Transaction<'_>,
+        directory: &str,
+        date: Option<DateTime<FixedOffset>>,
+    ) -> Result<Vec<CatalogMatch>> {
+        debug!("listing catalog directory \"{}\"", directory);
+        let prefix = format!("{}/", directory.trim_end_matches('/'));
+        let rows = transaction
+            .query(
+                "WITH archive AS (SELECT b.archive_id FROM backups b JOIN catalog_entries c ON c.archive_id=b.archive_id \
+                WHERE c.path=$1 AND ($2::timestamptz IS NULL OR b.creation_date <= $2) ORDER BY b.creation_date DESC LIMIT 1) \
+                SELECT c.*, b.creation_date AS backup_date FROM catalog_entries c JOIN backups b ON c.archive_id=b.archive_id \
+                WHERE c.archive_id IN (SELECT archive_id FROM archive) AND starts_with(c.path, $3) AND strpos(substr(c.path, length($3) + 1), '/') = 0 \
+                ORDER BY c.path",
+                &[&directory.trim_end_matches('/'), &date, &prefix],
+            )
+            .await?;
+
+        rows.iter().map(CatalogMatch::try_from).collect()
+    }
+}
@@ -0,0 +1,381 @@
+use super::{repo_archive::distinct_archives, Repository, UpsertResult};
+use crate::aws::{aws_archive::AwsArchive, aws_job::AwsJob, aws_vault::AwsVault};
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset};
+use log::debug;
+use std::convert::TryFrom;
+use tokio_postgres::{Row, Transaction};
+
+#[derive(Debug)]
+pub struct Inventory {
+    pub job_id: String,
+    pub vault_arn: String,
+    pub inventory_date: DateTime<FixedOffset>,
+    pub partial: bool,
+    pub marker: Option<String>,
+    pub continuation_job_id: Option<String>,
+}
+
+impl TryFrom<&Row> for Inventory {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &Row) -> Result<Self, Self::Error> {
+        Ok(Inventory {
+            job_id: value.try_get("job_id")?,
+            vault_arn: value.try_get("vault_arn")?,
+            inventory_date: value.try_get("inventory_date")?,
+            partial: value.try_get("partial")?,
+            marker: value.try_get("marker")?,
+            continuation_job_id: value.try_get("continuation_job_id")?,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct InventoryImport {
+    pub inventory: Inventory,
+    pub update_associations: bool,
+}
+
+#[derive(Debug)]
+pub struct ArchiveHistory {
+    pub archive_id: String,
+    pub first_seen: Option<DateTime<FixedOffset>>,
+    pub last_seen: Option<DateTime<FixedOffset>>,
+    pub inventories: Vec<Inventory>,
+}
+
+#[derive(Debug)]
+pub struct ArchiveChange {
+    pub before: AwsArchive,
+    pub after: AwsArchive,
+}
+
+#[derive(Debug)]
+pub struct InventoryDiff {
+    pub added: Vec<AwsArchive>,
+    pub removed: Vec<AwsArchive>,
+    pub changed: Vec<ArchiveChange>,
+}
+
+impl Repository {
+    pub async fn upsert_inventory(
+        transaction: &Transaction<'_>,
+        job: &AwsJob,
+        vault: &AwsVault,
+        inventory_date: &DateTime<FixedOffset>,
+    ) -> Result<UpsertResult> {
+        debug!("upserting inventory for job \"{}\"", job.job_id);
+        let partial = job.is_partial_inventory();
+        let marker = job
+            .inventory_retrieval_parameters
+            .as_ref()
+            .and_then(|p| p.marker.as_deref());
+        let rows = transaction
+            .query(
+                "INSERT INTO inventories (job_id, vault_arn, inventory_date, partial, marker) VALUES ($1, $2, $3, $4, $5) \
+                ON CONFLICT (job_id) DO UPDATE SET vault_arn=EXCLUDED.vault_arn, inventory_date=EXCLUDED.inventory_date, partial=EXCLUDED.partial, marker=EXCLUDED.marker \
+                WHERE (inventories.vault_arn, inventories.inventory_date, inventories.partial, inventories.marker) IS DISTINCT FROM (EXCLUDED.vault_arn, EXCLUDED.inventory_date, EXCLUDED.partial, EXCLUDED.marker) \
+                RETURNING (xmax = 0) AS inserted",
+                &[&job.job_id, &vault.vault_arn, inventory_date, &partial, &marker],
+            )
+            .await?;
+
+        UpsertResult::from_rows(&rows)
+    }
+
+    pub async fn get_inventory(
+        transaction: &Transaction<'_>,
+        job_id: &str,
+    ) -> Result<Option<Inventory>> {
+        debug!("getting inventory for job \"{}\"", job_id);
+        let rows = transaction
+            .query("SELECT * FROM inventories WHERE job_id=$1", &[&job_id])
+            .await?;
+
+        match rows.len() {
+            0 => Ok(None),
+            1 => Ok(Some(Inventory::try_from(&rows[0])?)),
+            _ => Err(anyhow::Error::msg("error getting inventory")),
+        }
+    }
+
+    pub async fn get_inventories_for_vault(
+        transaction: &Transaction<'_>,
+        vault_arn: &str,
+    ) -> Result<Vec<Inventory>> {
+        debug!("getting inventories for vault \"{}\"", vault_arn);
+        let rows = transaction
+            .query(
+                "SELECT * FROM inventories WHERE vault_arn=$1 ORDER BY inventory_date",
+                &[&vault_arn],
+            )
+            .await?;
+        let mut res = Vec::<Inventory>::new();
+
+        for row in rows {
+            res.push(Inventory::try_from(&row)?);
+        }
+
+        Ok(res)
+    }
+
+    /// Records archives as listed by an inventory and extends their first-seen and last-seen timestamps.
+    pub async fn add_inventory_archives(
+        transaction: &Transaction<'_>,
+        inventory: &Inventory,
+        archives: &[AwsArchive],
+    ) -> Result<()> {
+        debug!(
+            "adding {} archives to inventory \"{}\"",
+            archives.len(),
+            inventory.job_id
+        );
+        let archive_ids: Vec<&str> = archives.iter().map(|a| &*a.archive_id).collect();
+        let archive_descriptions: Vec<&str> =
+            archives.iter().map(|a| &*a.archive_description).collect();
+        let sizes: Vec<i64> = archives.iter().map(|a| a.size).collect();
+        let tree_hashes: Vec<&str> = archives.iter().map(|a| &*a.tree_hash).collect();
+
+        transaction
+            .query(
+                "INSERT INTO inventories_archives (job_id, archive_id, archive_description, size, tree_hash) \
+                SELECT $1, * FROM UNNEST($2::varchar[], $3::varchar[], $4::bigint[], $5::varchar[]) \
+                ON CONFLICT (job_id, archive_id) DO UPDATE SET archive_description=EXCLUDED.archive_description, size=EXCLUDED.size, tree_hash=EXCLUDED.tree_hash",
+                &[&inventory.job_id, &archive_ids, &archive_descriptions, &sizes, &tree_hashes],
+            )
+            .await?;
+        transaction
+            .query(
+                "UPDATE archives SET first_seen=LEAST(COALESCE(first_seen, $2), $2), last_seen=GREATEST(COALESCE(last_seen, $2), $2) WHERE archive_id=ANY($1)",
+                &[&archive_ids, &inventory.inventory_date],
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete_inventory_archives(
+        transaction: &Transaction<'_>,
+        job_id: &str,
+    ) -> Result<()> {
+        debug!("deleting archives of inventory \"{}\"", job_id);
+        transaction
+            .query(
+                "DELETE FROM inventories_archives WHERE job_id=$1",
+                &[&job_id],
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Prepares the import of an inventory job result.
+    ///
+    /// Importing an inventory again replaces its snapshot.
+    /// The archive associations of the vault are rebuilt if the inventory is the latest complete one of the vault.
+    /// Partial inventories only add associations.
+    pub async fn start_inventory_import(
+        transaction: &Transaction<'_>,
+        vault: &AwsVault,
+        job: &AwsJob,
+        inventory_date: &DateTime<FixedOffset>,
+    ) -> Result<InventoryImport> {
+        Repository::upsert_inventory(transaction, job, vault, inventory_date).await?;
+        Repository::delete_inventory_archives(transaction, &job.job_id).await?;
+
+        let inventory = Repository::get_inventory(transaction, &job.job_id)
+            .await?
+            .ok_or_else(|| anyhow::Error::msg("error getting inventory"))?;
+        let replace_associations = !inventory.partial
+            && match Repository::get_inventories_for_vault(transaction, &vault.vault_arn)
+                .await?
+                .iter()
+                .rfind(|i| !i.partial)
+            {
+                Some(latest) => latest.job_id == job.job_id,
+                None => true,
+            };
+
+        if replace_associations {
+            Repository::delete_archive_associations(transaction, vault).await?;
+        }
+
+        Ok(InventoryImport {
+            update_associations: replace_associations || inventory.partial,
+            inventory,
+        })
+    }
+
+    pub async fn import_inventory_archives(
+        transaction: &Transaction<'_>,
+        vault: &AwsVault,
+        import: &InventoryImport,
+        archives: &[AwsArchive],
+    ) -> Result<()> {
+        let archives = &distinct_archives(archives);
+
+        Repository::upsert_archives(transaction, archives).await?;
+        Repository::add_inventory_archives(transaction, &import.inventory, archives).await?;
+
+        if import.update_associations {
+            Repository::create_archive_associations(transaction, vault, archives).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the paginated inventories of a vault, for which no job retrieving the next page has been started yet.
+    ///
+    /// An inventory repeating the marker it was started with is not continued.
+    pub async fn get_inventories_to_continue(
+        transaction: &Transaction<'_>,
+        vault_arn: &str,
+    ) -> Result<Vec<Inventory>> {
+        debug!(
+            "getting inventories to continue for vault \"{}\"",
+            vault_arn
+        );
+        let rows = transaction
+            .query(
+                "SELECT * FROM inventories i WHERE i.vault_arn=$1 AND i.marker IS NOT NULL AND i.continuation_job_id IS NULL \
+                AND NOT EXISTS (SELECT 1 FROM inventories p WHERE p.continuation_job_id=i.job_id AND p.marker=i.marker) \
+                ORDER BY i.inventory_date",
+                &[&vault_arn],
+            )
+            .await?;
+        let mut res = Vec::<Inventory>::new();
+
+        for row in rows {
+            res.push(Inventory::try_from(&row)?);
+        }
+
+        Ok(res)
+    }
+
+    pub async fn set_inventory_continuation(
+        transaction: &Transaction<'_>,
+        job_id: &str,
+        continuation_job_id: &str,
+    ) -> Result<()> {
+        debug!(
+            "setting continuation of inventory \"{}\" to \"{}\"",
+            job_id, continuation_job_id
+        );
+        transaction
+            .query(
+                "UPDATE inventories SET continuation_job_id=$2 WHERE job_id=$1",
+                &[&job_id, &continuation_job_id],
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_archive_history(
+        transaction: &Transaction<'_>,
+        archive_id: &str,
+    ) -> Result<ArchiveHistory> {
+        debug!("getting history of archive \"{}\"", archive_id);
+        let rows = transaction
+            .query(
+                "SELECT first_seen, last_seen FROM archives WHERE archive_id=$1",
+                &[&archive_id],
+            )
+            .await?;
+
+        if rows.len() != 1 {
+            return Err(anyhow::Error::msg("error getting archive history"));
+        }
+
+        let inventory_rows = transaction
+            .query(
+                "SELECT i.* FROM inventories i JOIN inventories_archives ia ON ia.job_id=i.job_id WHERE ia.archive_id=$1 ORDER BY i.inventory_date",
+                &[&archive_id],
+            )
+            .await?;
+        let mut inventories = Vec::<Inventory>::new();
+
+        for row in inventory_rows {
+            inventories.push(Inventory::try_from(&row)?);
+        }
+
+        Ok(ArchiveHistory {
+            archive_id: archive_id.into(),
+            first_seen: rows[0].try_get("first_seen")?,
+            last_seen: rows[0].try_get("last_seen")?,
+            inventories,
+        })
+    }
+
+    /// Compares the archives listed by two inventories.
+    ///
+    /// Archives are reported with the description, size and tree hash recorded in the respective inventory.
+    /// The result is only meaningful for complete inventories.
+    pub async fn diff_inventories(
+        transaction: &Transaction<'_>,
+        from_job_id: &str,
+        to_job_id: &str,
+    ) -> Result<InventoryDiff> {
+        debug!(
+            "comparing inventories \"{}\" and \"{}\"",
+            from_job_id, to_job_id
+        );
+        let added = transaction
+            .query(
+                "SELECT ia.archive_id, ia.archive_description, a.creation_date, ia.size, ia.tree_hash FROM inventories_archives ia JOIN archives a ON a.archive_id=ia.archive_id \
+                WHERE ia.job_id=$2 AND NOT EXISTS (SELECT 1 FROM inventories_archives f WHERE f.job_id=$1 AND f.archive_id=ia.archive_id) ORDER BY a.creation_date",
+                &[&from_job_id, &to_job_id],
+            )
+            .await?;
+        let removed = transaction
+            .query(
+                "SELECT ia.archive_id, ia.archive_description, a.creation_date, ia.size, ia.tree_hash FROM inventories_archives ia JOIN archives a ON a.archive_id=ia.archive_id \
+                WHERE ia.job_id=$1 AND NOT EXISTS (SELECT 1 FROM inventories_archives t WHERE t.job_id=$2 AND t.archive_id=ia.archive_id) ORDER BY a.creation_date",
+                &[&from_job_id, &to_job_id],
+            )
+            .await?;
+        let changed = transaction
+            .query(
+                "SELECT f.archive_id, a.creation_date, \
+                f.archive_description AS before_description, f.size AS before_size, f.tree_hash AS before_tree_hash, \
+                t.archive_description AS after_description, t.size AS after_size, t.tree_hash AS after_tree_hash \
+                FROM inventories_archives f JOIN inventories_archives t ON t.archive_id=f.archive_id JOIN archives a ON a.archive_id=f.archive_id \
+                WHERE f.job_id=$1 AND t.job_id=$2 AND (f.archive_description, f.size, f.tree_hash) IS DISTINCT FROM (t.archive_description, t.size, t.tree_hash) \
+                ORDER BY a.creation_date",
+                &[&from_job_id, &to_job_id],
+            )
+            .await?;
+        let mut diff = InventoryDiff {
+            added: Vec::new(),
+            removed: Vec::new(),
+            changed: Vec::new(),
+        };
+
+        for row in added {
+            diff.added.push(AwsArchive::try_from(&row)?);
+        }
+
+        for row in removed {
+            diff.removed.push(AwsArchive::try_from(&row)?);
+        }
+
+        for row in changed {
+            diff.changed.push(ArchiveChange {
+                before: AwsArchive {
+                    archive_id: row.try_get("archive_id")?,
+                    archive_description: row.try_get("before_description")?,
+                    creation_date: row.try_get("creation_date")?,
+                    size: row.try_get("before_size")?,
+                    tree_hash: row.try_get("before_tree_hash")?,
+                },
+                after: AwsArchive {
+                    archive_id: row.try_get("archive_id")?,
+                    archive_description: row.try_get("after_description")?,
+                    creation_date: row.try_get("creation_date")?,
+                    size: row.try_get("after_size")?,
+                    tree_hash: row.try_get("after_tree_hash")?,
+                },
+            });
+        }
+
+        Ok(diff)
+    }
+}
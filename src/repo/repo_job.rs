@@ -1,9 +1,11 @@
-use super::Repository;
-use crate::aws::aws_job::AwsJob;
+use super::{Page, Paged, Repository, UpsertResult};
+use crate::aws::{aws_inventory::AwsInventoryFormat, aws_job::AwsJob};
 use anyhow::Result;
+use chrono::{DateTime, Utc};
 use log::debug;
 use std::convert::TryFrom;
 use tokio_postgres::Transaction;
+use uuid::Uuid;
 
 impl Repository {
     pub async fn create_job(transaction: &Transaction<'_>, job: &AwsJob) -> Result<AwsJob> {
@@ -32,6 +34,29 @@ impl Repository {
         }
     }
 
+    pub async fn upsert_job(transaction: &Transaction<'_>, job: &AwsJob) -> Result<UpsertResult> {
+        debug!("upserting job \"{}\"", job.job_id);
+        let parameters = job.inventory_retrieval_parameters.as_ref();
+        let inventory_format =
+            parameters.map(|p| p.format.unwrap_or(AwsInventoryFormat::Json).as_str());
+        let inventory_start_date = parameters.and_then(|p| p.start_date);
+        let inventory_end_date = parameters.and_then(|p| p.end_date);
+        let inventory_limit = parameters.and_then(|p| p.limit);
+        let inventory_marker = parameters.and_then(|p| p.marker.as_deref());
+        let rows = transaction.query(
+            "INSERT INTO jobs (job_id, action, archive_id, archive_tree_hash, archive_size_in_bytes, completion_date, creation_date, inventory_size_in_bytes, job_description, tree_hash, status_code, status_message, vault_arn, inventory_format, inventory_start_date, inventory_end_date, inventory_limit, inventory_marker, retrieval_byte_range) \
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19) \
+            ON CONFLICT (job_id) DO UPDATE SET action=EXCLUDED.action, archive_id=EXCLUDED.archive_id, archive_tree_hash=EXCLUDED.archive_tree_hash, archive_size_in_bytes=EXCLUDED.archive_size_in_bytes, completion_date=EXCLUDED.completion_date, creation_date=EXCLUDED.creation_date, inventory_size_in_bytes=EXCLUDED.inventory_size_in_bytes, job_description=EXCLUDED.job_description, tree_hash=EXCLUDED.tree_hash, status_code=EXCLUDED.status_code, status_message=EXCLUDED.status_message, vault_arn=EXCLUDED.vault_arn, \
+            inventory_format=EXCLUDED.inventory_format, inventory_start_date=EXCLUDED.inventory_start_date, inventory_end_date=EXCLUDED.inventory_end_date, inventory_limit=EXCLUDED.inventory_limit, inventory_marker=EXCLUDED.inventory_marker, retrieval_byte_range=EXCLUDED.retrieval_byte_range \
+            WHERE (jobs.action, jobs.archive_id, jobs.archive_tree_hash, jobs.archive_size_in_bytes, jobs.completion_date, jobs.creation_date, jobs.inventory_size_in_bytes, jobs.job_description, jobs.tree_hash, jobs.status_code, jobs.status_message, jobs.vault_arn, jobs.inventory_format, jobs.inventory_start_date, jobs.inventory_end_date, jobs.inventory_limit, jobs.inventory_marker, jobs.retrieval_byte_range) \
+            IS DISTINCT FROM (EXCLUDED.action, EXCLUDED.archive_id, EXCLUDED.archive_tree_hash, EXCLUDED.archive_size_in_bytes, EXCLUDED.completion_date, EXCLUDED.creation_date, EXCLUDED.inventory_size_in_bytes, EXCLUDED.job_description, EXCLUDED.tree_hash, EXCLUDED.status_code, EXCLUDED.status_message, EXCLUDED.vault_arn, EXCLUDED.inventory_format, EXCLUDED.inventory_start_date, EXCLUDED.inventory_end_date, EXCLUDED.inventory_limit, EXCLUDED.inventory_marker, EXCLUDED.retrieval_byte_range) \
+            RETURNING (xmax = 0) AS inserted",
+            &[&job.job_id, &job.action, &job.archive_id, &job.archive_tree_hash, &job.archive_size_in_bytes, &job.completion_date, &job.creation_date, &job.inventory_size_in_bytes, &job.job_description, &job.tree_hash, &job.status_code, &job.status_message, &job.vault_arn, &inventory_format, &inventory_start_date, &inventory_end_date, &inventory_limit, &inventory_marker, &job.retrieval_byte_range]
+        ).await?;
+
+        UpsertResult::from_rows(&rows)
+    }
+
     pub async fn get_job_by_id(transaction: &Transaction<'_>, job_id: &str) -> Result<AwsJob> {
         debug!("getting job \"{}\"", job_id);
         let rows = transaction
@@ -44,6 +69,98 @@ impl Repository {
         }
     }
 
+    /// Returns a page of the jobs with their status flag, filtered by vault, action, status code and flag, newest first.
+    pub async fn get_jobs_page(
+        transaction: &Transaction<'_>,
+        vault_arn: Option<&str>,
+        action: Option<&str>,
+        status_code: Option<&str>,
+        active: Option<bool>,
+        page: &Page,
+    ) -> Result<Paged<(AwsJob, Option<bool>)>> {
+        debug!(
+            "getting jobs (vault {:?}, action {:?}, status code {:?}, active {:?})",
+            vault_arn, action, status_code, active
+        );
+        let filter = "FROM jobs j LEFT JOIN jobs_status js ON j.job_id=js.job_id \
+            WHERE ($1::text IS NULL OR j.vault_arn=$1) AND ($2::text IS NULL OR j.action=$2) \
+            AND ($3::text IS NULL OR j.status_code=$3) AND ($4::boolean IS NULL OR js.active=$4)";
+        let rows = transaction
+            .query(
+                format!(
+                    "SELECT j.*, js.active {} ORDER BY j.creation_date DESC, j.job_id LIMIT $5 OFFSET $6",
+                    filter
+                )
+                .as_str(),
+                &[
+                    &vault_arn,
+                    &action,
+                    &status_code,
+                    &active,
+                    &page.limit,
+                    &page.offset,
+                ],
+            )
+            .await?;
+        let total = transaction
+            .query_one(
+                format!("SELECT count(*) AS total {}", filter).as_str(),
+                &[&vault_arn, &action, &status_code, &active],
+            )
+            .await?
+            .try_get("total")?;
+        let mut items = Vec::new();
+
+        for row in rows {
+            items.push((AwsJob::try_from(&row)?, row.try_get("active")?));
+        }
+
+        Ok(Paged { items, total })
+    }
+
+    /// Returns the number of active jobs by status code.
+    pub async fn count_active_jobs_by_status(
+        transaction: &Transaction<'_>,
+    ) -> Result<Vec<(String, i64)>> {
+        debug!("counting active jobs by status");
+        let rows = transaction
+            .query(
+                "SELECT j.status_code, count(*) AS jobs FROM jobs j JOIN jobs_status js ON j.job_id=js.job_id WHERE js.active GROUP BY j.status_code ORDER BY j.status_code",
+                &[],
+            )
+            .await?;
+        let mut counts = Vec::new();
+
+        for row in rows {
+            counts.push((row.try_get("status_code")?, row.try_get("jobs")?));
+        }
+
+        Ok(counts)
+    }
+
+    /// Returns the job with its status flag.
+    pub async fn get_job_with_status(
+        transaction: &Transaction<'_>,
+        job_id: &str,
+    ) -> Result<Option<(AwsJob, Option<bool>)>> {
+        debug!("getting job \"{}\" with status", job_id);
+        let rows = transaction
+            .query(
+                "SELECT j.*, js.active FROM jobs j LEFT JOIN jobs_status js ON j.job_id=js.job_id WHERE j.job_id=$1",
+                &[&job_id],
+            )
+            .await?;
+
+        match rows.len() {
+            0 => Ok(None),
+            1 => Ok(Some((
+                AwsJob::try_from(&rows[0])?,
+                rows[0].try_get("active")?,
+            ))),
+            _ => Err(anyhow::Error::msg("error getting job with status")),
+        }
+    }
+
     pub async fn get_latest_job_by_action_vault(
         transaction: &Transaction<'_>,
         action: &str,
@@ -73,31 +190,93 @@ impl Repository {
         Ok(())
     }
 
-    pub async fn set_job_status_active(transaction: &Transaction<'_>, job: &AwsJob) -> Result<()> {
+    pub async fn upsert_job_status(
+        transaction: &Transaction<'_>,
+        job: &AwsJob,
+        active: bool,
+    ) -> Result<UpsertResult> {
         let rows = transaction
-            .query("SELECT * FROM jobs_status WHERE job_id=$1", &[&job.job_id])
+            .query(
+                "INSERT INTO jobs_status (job_id, active) VALUES ($1, $2) \
+                ON CONFLICT (job_id) DO UPDATE SET active=EXCLUDED.active WHERE jobs_status.active IS DISTINCT FROM EXCLUDED.active \
+                RETURNING (xmax = 0) AS inserted",
+                &[&job.job_id, &active],
+            )
             .await?;
 
-        match rows.len() {
-            0 => {
-                transaction
-                    .query(
-                        "INSERT INTO jobs_status (job_id, active) VALUES ($1, TRUE)",
-                        &[&job.job_id],
-                    )
-                    .await?;
-                Ok(())
-            }
-            1 => {
-                transaction
-                    .query(
-                        "UPDATE jobs_status SET active=TRUE WHERE job_id=$1",
-                        &[&job.job_id],
-                    )
-                    .await?;
-                Ok(())
-            }
-            _ => Err(anyhow::Error::msg("error updating job status active")),
-        }
+        UpsertResult::from_rows(&rows)
+    }
+
+    /// Leases the job to the worker until the lease expires and returns whether the lease was granted.
+    ///
+    /// A job is leased to one worker at a time and never again once it is completed.
+    pub async fn lease_job(
+        transaction: &Transaction<'_>,
+        job: &AwsJob,
+        worker_id: &Uuid,
+        pid: u32,
+        lease_expiry: &DateTime<Utc>,
+    ) -> Result<bool> {
+        debug!("leasing job \"{}\" to worker {}", job.job_id, worker_id);
+        let rows = transaction
+            .query(
+                "INSERT INTO jobs_workers (job_id, vault_arn, completed, pid, worker_id, lease_expiry) VALUES ($1, $2, FALSE, $3, $4, $5) \
+                ON CONFLICT (job_id) DO UPDATE SET pid=EXCLUDED.pid, worker_id=EXCLUDED.worker_id, lease_expiry=EXCLUDED.lease_expiry \
+                WHERE NOT jobs_workers.completed AND (jobs_workers.lease_expiry IS NULL OR jobs_workers.lease_expiry < now()) \
+                RETURNING job_id",
+                &[&job.job_id, &job.vault_arn, &pid, worker_id, lease_expiry],
+            )
+            .await?;
+
+        Ok(rows.len() == 1)
+    }
+
+    /// Extends the lease of the worker on the job and returns whether the worker still holds it.
+    pub async fn renew_job_lease(
+        transaction: &Transaction<'_>,
+        job_id: &str,
+        worker_id: &Uuid,
+        lease_expiry: &DateTime<Utc>,
+    ) -> Result<bool> {
+        debug!("renewing lease of job \"{}\"", job_id);
+        let rows = transaction
+            .execute(
+                "UPDATE jobs_workers SET lease_expiry=$3 WHERE job_id=$1 AND worker_id=$2 AND NOT completed",
+                &[&job_id, worker_id, lease_expiry],
+            )
+            .await?;
+
+        Ok(rows == 1)
+    }
+
+    /// Completes the job and returns whether the worker still held its lease.
+    pub async fn complete_job_lease(
+        transaction: &Transaction<'_>,
+        job_id: &str,
+        worker_id: &Uuid,
+    ) -> Result<bool> {
+        debug!("completing lease of job \"{}\"", job_id);
+        let rows = transaction
+            .execute(
+                "UPDATE jobs_workers SET completed=TRUE, lease_expiry=NULL WHERE job_id=$1 AND worker_id=$2 AND NOT completed",
+                &[&job_id, worker_id],
+            )
+            .await?;
+
+        Ok(rows == 1)
+    }
+
+    /// Releases the leases of the worker on jobs it did not complete and returns their number.
+    pub async fn release_job_leases(
+        transaction: &Transaction<'_>,
+        worker_id: &Uuid,
+    ) -> Result<u64> {
+        debug!("releasing job leases of worker {}", worker_id);
+        Ok(transaction
+            .execute(
+                "DELETE FROM jobs_workers WHERE worker_id=$1 AND NOT completed",
+                &[worker_id],
+            )
+            .await?)
     }
 }
@@ -0,0 +1,138 @@
+use super::{Page, Paged, Repository};
+use crate::job_request::JobRequest;
+use anyhow::Result;
+use log::debug;
+use std::convert::TryFrom;
+use tokio_postgres::Transaction;
+use uuid::Uuid;
+
+impl Repository {
+    pub async fn create_job_request(
+        transaction: &Transaction<'_>,
+        request: &JobRequest,
+    ) -> Result<()> {
+        debug!("creating job request \"{}\"", request.request_id);
+        transaction
+            .execute(
+                "INSERT INTO job_requests (request_id, vault_arn, action, archive_id, tier, inventory_format, requested_by, state, job_id, error, creation_date, update_date) \
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $11)",
+                &[
+                    &request.request_id,
+                    &request.vault_arn,
+                    &request.action.as_str(),
+                    &request.archive_id,
+                    &request.tier,
+                    &request.inventory_format,
+                    &request.requested_by,
+                    &request.state.as_str(),
+                    &request.job_id,
+                    &request.error,
+                    &request.creation_date,
+                ],
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Stores the state, job and error of a job request.
+    pub async fn update_job_request(
+        transaction: &Transaction<'_>,
+        request: &JobRequest,
+    ) -> Result<()> {
+        debug!(
+            "updating job request \"{}\" ({})",
+            request.request_id,
+            request.state.as_str()
+        );
+        let rows = transaction
+            .execute(
+                "UPDATE job_requests SET state=$2, job_id=$3, error=$4, update_date=now() WHERE request_id=$1",
+                &[
+                    &request.request_id,
+                    &request.state.as_str(),
+                    &request.job_id,
+                    &request.error,
+                ],
+            )
+            .await?;
+
+        match rows {
+            1 => Ok(()),
+            _ => Err(anyhow::Error::msg(format!(
+                "job request \"{}\" not found",
+                request.request_id
+            ))),
+        }
+    }
+
+    /// Locks the oldest pending job request, skipping the given ones and those locked by other transactions.
+    pub async fn lock_pending_job_request(
+        transaction: &Transaction<'_>,
+        skip: &[Uuid],
+    ) -> Result<Option<JobRequest>> {
+        debug!("locking pending job request");
+        let rows = transaction
+            .query(
+                "SELECT * FROM job_requests WHERE state='pending' AND request_id <> ALL($1) \
+                ORDER BY creation_date LIMIT 1 FOR UPDATE SKIP LOCKED",
+                &[&skip],
+            )
+            .await?;
+
+        rows.first().map(JobRequest::try_from).transpose()
+    }
+
+    pub async fn get_job_request(
+        transaction: &Transaction<'_>,
+        request_id: &Uuid,
+    ) -> Result<Option<JobRequest>> {
+        debug!("getting job request \"{}\"", request_id);
+        let rows = transaction
+            .query(
+                "SELECT * FROM job_requests WHERE request_id=$1",
+                &[&request_id],
+            )
+            .await?;
+
+        rows.first().map(JobRequest::try_from).transpose()
+    }
+
+    /// Returns a page of the job requests, filtered by vault and state, newest first.
+    pub async fn get_job_requests_page(
+        transaction: &Transaction<'_>,
+        vault_arn: Option<&str>,
+        state: Option<&str>,
+        page: &Page,
+    ) -> Result<Paged<JobRequest>> {
+        debug!(
+            "getting job requests (vault {:?}, state {:?})",
+            vault_arn, state
+        );
+        let filter = "FROM job_requests WHERE ($1::text IS NULL OR vault_arn=$1) AND ($2::text IS NULL OR state=$2)";
+        let rows = transaction
+            .query(
+                format!(
+                    "SELECT * {} ORDER BY creation_date DESC, request_id LIMIT $3 OFFSET $4",
+                    filter
+                )
+                .as_str(),
+                &[&vault_arn, &state, &page.limit, &page.offset],
+            )
+            .await?;
+        let total = transaction
+            .query_one(
+                format!("SELECT count(*) AS total {}", filter).as_str(),
+                &[&vault_arn, &state],
+            )
+            .await?
+            .try_get("total")?;
+
+        Ok(Paged {
+            items: rows
+                .iter()
+                .map(JobRequest::try_from)
+                .collect::<Result<_>>()?,
+            total,
+        })
+    }
+}
@@ -0,0 +1,126 @@
+use super::Repository;
+use crate::crypto::crypto_key::{ArchiveKey, RecipientKey};
+use anyhow::Result;
+use log::debug;
+use std::convert::TryFrom;
+use tokio_postgres::Transaction;
+
+impl Repository {
+    pub async fn create_key(transaction: &Transaction<'_>, key: &RecipientKey) -> Result<()> {
+        debug!("creating key \"{}\"", key.key_id);
+        transaction
+            .query(
+                "INSERT INTO keys (key_id, key_type, salt, iterations, check_value, creation_date, active) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+                &[&key.key_id, &key.key_type, &key.salt, &key.iterations, &key.check_value, &key.creation_date, &key.active],
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_keys(transaction: &Transaction<'_>) -> Result<Vec<RecipientKey>> {
+        debug!("getting keys");
+        let rows = transaction
+            .query("SELECT * FROM keys ORDER BY creation_date", &[])
+            .await?;
+
+        rows.iter().map(RecipientKey::try_from).collect()
+    }
+
+    pub async fn set_key_active(
+        transaction: &Transaction<'_>,
+        key_id: &str,
+        active: bool,
+    ) -> Result<()> {
+        debug!("setting key \"{}\" active: {}", key_id, active);
+        let rows = transaction
+            .query(
+                "UPDATE keys SET active=$2 WHERE key_id=$1 RETURNING key_id",
+                &[&key_id, &active],
+            )
+            .await?;
+
+        match rows.len() {
+            1 => Ok(()),
+            _ => Err(anyhow::Error::msg(format!("key \"{}\" not found", key_id))),
+        }
+    }
+
+    pub async fn count_archive_keys(transaction: &Transaction<'_>, key_id: &str) -> Result<i64> {
+        debug!("counting archives for key \"{}\"", key_id);
+        let rows = transaction
+            .query(
+                "SELECT count(*) AS count FROM archives_keys WHERE key_id=$1",
+                &[&key_id],
+            )
+            .await?;
+
+        match rows.len() {
+            1 => Ok(rows[0].try_get("count")?),
+            _ => Err(anyhow::Error::msg("error counting archive keys")),
+        }
+    }
+
+    pub async fn get_archive_keys(
+        transaction: &Transaction<'_>,
+        archive_id: &str,
+    ) -> Result<Vec<ArchiveKey>> {
+        debug!("getting keys for archive \"{}\"", archive_id);
+        let rows = transaction
+            .query(
+                "SELECT * FROM archives_keys WHERE archive_id=$1",
+                &[&archive_id],
+            )
+            .await?;
+
+        rows.iter().map(ArchiveKey::try_from).collect()
+    }
+
+    /// Returns the wrapped keys of all archives whose data key is not wrapped for the key yet.
+    pub async fn get_archive_keys_missing_key(
+        transaction: &Transaction<'_>,
+        key_id: &str,
+    ) -> Result<Vec<ArchiveKey>> {
+        debug!("getting archive keys missing key \"{}\"", key_id);
+        let rows = transaction
+            .query(
+                "SELECT * FROM archives_keys WHERE archive_id NOT IN (SELECT archive_id FROM archives_keys WHERE key_id=$1) ORDER BY archive_id",
+                &[&key_id],
+            )
+            .await?;
+
+        rows.iter().map(ArchiveKey::try_from).collect()
+    }
+
+    /// Stores wrapped data keys, replacing existing ones for the same archive and key.
+    pub async fn upsert_archive_keys(
+        transaction: &Transaction<'_>,
+        archive_keys: &[ArchiveKey],
+    ) -> Result<()> {
+        debug!("upserting {} archive keys", archive_keys.len());
+        let archive_ids: Vec<&str> = archive_keys.iter().map(|k| &*k.archive_id).collect();
+        let key_ids: Vec<&str> = archive_keys.iter().map(|k| &*k.key_id).collect();
+        let wrapped_keys: Vec<&[u8]> = archive_keys.iter().map(|k| &*k.wrapped_key).collect();
+
+        transaction
+            .query(
+                "INSERT INTO archives_keys (archive_id, key_id, wrapped_key) SELECT * FROM UNNEST($1::varchar[], $2::varchar[], $3::bytea[]) \
+                ON CONFLICT (archive_id, key_id) DO UPDATE SET wrapped_key=EXCLUDED.wrapped_key",
+                &[&archive_ids, &key_ids, &wrapped_keys],
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Deletes the wrapped data keys for a key and returns the number of keys deleted.
+    ///
+    /// Keys are only deleted for archives, whose data key is wrapped for another key as well.
+    pub async fn delete_archive_keys(transaction: &Transaction<'_>, key_id: &str) -> Result<u64> {
+        debug!("deleting archive keys for key \"{}\"", key_id);
+        Ok(transaction
+            .execute(
+                "DELETE FROM archives_keys a WHERE key_id=$1 AND EXISTS (SELECT 1 FROM archives_keys b WHERE b.archive_id=a.archive_id AND b.key_id<>$1)",
+                &[&key_id],
+            )
+            .await?)
+    }
+}
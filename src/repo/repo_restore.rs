@@ -0,0 +1,188 @@
+use super::Repository;
+use crate::restore::Restore;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use log::debug;
+use std::convert::TryFrom;
+use tokio_postgres::Transaction;
+use uuid::Uuid;
+
+impl Repository {
+    pub async fn create_restore(transaction: &Transaction<'_>, restore: &Restore) -> Result<()> {
+        debug!("creating restore \"{}\"", restore.restore_id);
+        transaction
+            .query(
+                "INSERT INTO restores (restore_id, archive_id, vault_arn, tier, destination, download_path, state, job_id, downloaded_size, error, creation_date, update_date, paths, range_start, range_end, snapshot_id, snapshot_restore_id) \
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $11, $12, $13, $14, $15, $16)",
+                &[
+                    &restore.restore_id,
+                    &restore.archive_id,
+                    &restore.vault_arn,
+                    &restore.tier,
+                    &restore.destination,
+                    &restore.download_path,
+                    &restore.state.as_str(),
+                    &restore.job_id,
+                    &restore.downloaded_size,
+                    &restore.error,
+                    &restore.creation_date,
+                    &restore.paths,
+                    &restore.range_start,
+                    &restore.range_end,
+                    &restore.snapshot_id,
+                    &restore.snapshot_restore_id,
+                ],
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Stores the state, job, progress and error of a restore.
+    pub async fn update_restore(transaction: &Transaction<'_>, restore: &Restore) -> Result<()> {
+        debug!(
+            "updating restore \"{}\" ({})",
+            restore.restore_id,
+            restore.state.as_str()
+        );
+        let rows = transaction
+            .query(
+                "UPDATE restores SET state=$2, job_id=$3, downloaded_size=$4, error=$5, update_date=now() WHERE restore_id=$1 RETURNING restore_id",
+                &[
+                    &restore.restore_id,
+                    &restore.state.as_str(),
+                    &restore.job_id,
+                    &restore.downloaded_size,
+                    &restore.error,
+                ],
+            )
+            .await?;
+
+        match rows.len() {
+            1 => Ok(()),
+            _ => Err(anyhow::Error::msg("error updating restore")),
+        }
+    }
+
+    /// Leases the restore to the worker until the lease expires and returns whether the lease was granted.
+    ///
+    /// A restore is leased to one worker at a time, so its retrieval job is started and downloaded only once.
+    pub async fn lease_restore(
+        transaction: &Transaction<'_>,
+        restore_id: &Uuid,
+        worker_id: &Uuid,
+        lease_expiry: &DateTime<Utc>,
+    ) -> Result<bool> {
+        debug!("leasing restore \"{}\" to worker {}", restore_id, worker_id);
+        let rows = transaction
+            .execute(
+                "UPDATE restores SET worker_id=$2, lease_expiry=$3 WHERE restore_id=$1 AND (lease_expiry IS NULL OR lease_expiry < now())",
+                &[restore_id, worker_id, lease_expiry],
+            )
+            .await?;
+
+        Ok(rows == 1)
+    }
+
+    /// Extends the lease of the worker on the restore and returns whether the worker still holds it.
+    pub async fn renew_restore_lease(
+        transaction: &Transaction<'_>,
+        restore_id: &Uuid,
+        worker_id: &Uuid,
+        lease_expiry: &DateTime<Utc>,
+    ) -> Result<bool> {
+        debug!("renewing lease of restore \"{}\"", restore_id);
+        let rows = transaction
+            .execute(
+                "UPDATE restores SET lease_expiry=$3 WHERE restore_id=$1 AND worker_id=$2",
+                &[restore_id, worker_id, lease_expiry],
+            )
+            .await?;
+
+        Ok(rows == 1)
+    }
+
+    /// Releases the lease of the worker on the restore.
+    pub async fn release_restore_lease(
+        transaction: &Transaction<'_>,
+        restore_id: &Uuid,
+        worker_id: &Uuid,
+    ) -> Result<()> {
+        debug!("releasing lease of restore \"{}\"", restore_id);
+        transaction
+            .execute(
+                "UPDATE restores SET worker_id=NULL, lease_expiry=NULL WHERE restore_id=$1 AND worker_id=$2",
+                &[restore_id, worker_id],
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_restore(transaction: &Transaction<'_>, restore_id: &Uuid) -> Result<Restore> {
+        debug!("getting restore \"{}\"", restore_id);
+        let rows = transaction
+            .query(
+                "SELECT r.*, a.size AS archive_size FROM restores r JOIN archives a ON r.archive_id=a.archive_id WHERE r.restore_id=$1",
+                &[&restore_id],
+            )
+            .await?;
+
+        match rows.len() {
+            1 => Ok(Restore::try_from(&rows[0])?),
+            _ => Err(anyhow::Error::msg(format!(
+                "restore \"{}\" not found",
+                restore_id
+            ))),
+        }
+    }
+
+    /// Returns the restores, optionally only those, which are not finished yet, oldest first.
+    pub async fn get_restores(
+        transaction: &Transaction<'_>,
+        unfinished_only: bool,
+    ) -> Result<Vec<Restore>> {
+        debug!("getting restores");
+        let rows = transaction
+            .query(
+                "SELECT r.*, a.size AS archive_size FROM restores r JOIN archives a ON r.archive_id=a.archive_id \
+                WHERE NOT $1 OR r.state NOT IN ('extracted', 'failed') ORDER BY r.creation_date",
+                &[&unfinished_only],
+            )
+            .await?;
+
+        rows.iter().map(Restore::try_from).collect()
+    }
+
+    /// Locks the restores of the packs of a snapshot restore, or returns `None` if another transaction holds any of them.
+    pub async fn lock_snapshot_restores(
+        transaction: &Transaction<'_>,
+        snapshot_restore_id: &Uuid,
+    ) -> Result<Option<Vec<Restore>>> {
+        debug!(
+            "locking restores of snapshot restore \"{}\"",
+            snapshot_restore_id
+        );
+        let rows = transaction
+            .query(
+                "SELECT r.*, a.size AS archive_size FROM restores r JOIN archives a ON r.archive_id=a.archive_id \
+                WHERE r.snapshot_restore_id=$1 ORDER BY r.creation_date FOR UPDATE OF r SKIP LOCKED",
+                &[&snapshot_restore_id],
+            )
+            .await?;
+        let count: i64 = transaction
+            .query_one(
+                "SELECT count(*) FROM restores WHERE snapshot_restore_id=$1",
+                &[&snapshot_restore_id],
+            )
+            .await?
+            .try_get(0)?;
+
+        if (rows.len() as i64) < count {
+            return Ok(None);
+        }
+
+        rows.iter()
+            .map(Restore::try_from)
+            .collect::<Result<_>>()
+            .map(Some)
+    }
+}
@@ -0,0 +1,100 @@
+use super::Repository;
+use crate::aws::aws_vault::AwsVault;
+use crate::retention::{PruneCandidate, RetentionPolicy};
+use anyhow::Result;
+use log::debug;
+use std::convert::TryFrom;
+use tokio_postgres::Transaction;
+
+impl Repository {
+    /// Creates or replaces the policy of a vault or backup set.
+    pub async fn upsert_retention_policy(
+        transaction: &Transaction<'_>,
+        policy: &RetentionPolicy,
+    ) -> Result<()> {
+        debug!(
+            "storing retention policy for vault \"{}\" and backup set {:?}",
+            policy.vault_arn, policy.backup_set
+        );
+        transaction
+            .query(
+                "INSERT INTO retention_policies (vault_arn, backup_set, keep_last, keep_daily, keep_weekly, keep_monthly) VALUES ($1, $2, $3, $4, $5, $6) \
+                ON CONFLICT (vault_arn, (COALESCE(backup_set, ''))) DO UPDATE SET keep_last=EXCLUDED.keep_last, keep_daily=EXCLUDED.keep_daily, \
+                keep_weekly=EXCLUDED.keep_weekly, keep_monthly=EXCLUDED.keep_monthly",
+                &[
+                    &policy.vault_arn,
+                    &policy.backup_set,
+                    &policy.keep_last,
+                    &policy.keep_daily,
+                    &policy.keep_weekly,
+                    &policy.keep_monthly,
+                ],
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_retention_policies(
+        transaction: &Transaction<'_>,
+    ) -> Result<Vec<RetentionPolicy>> {
+        debug!("getting retention policies");
+        let rows = transaction
+            .query(
+                "SELECT * FROM retention_policies ORDER BY vault_arn, backup_set NULLS FIRST",
+                &[],
+            )
+            .await?;
+
+        rows.iter().map(RetentionPolicy::try_from).collect()
+    }
+
+    /// Returns the archives of a vault created by backups, which are not deleted yet.
+    ///
+    /// Packs of deduplicated backups are shared by snapshots and never pruned by age.
+    pub async fn get_prune_candidates(
+        transaction: &Transaction<'_>,
+        vault: &AwsVault,
+    ) -> Result<Vec<PruneCandidate>> {
+        debug!("getting prune candidates of vault \"{}\"", vault.vault_name);
+        let rows = transaction
+            .query(
+                "SELECT a.archive_id, va.vault_arn, COALESCE(b.backup_set, m.backup_set) AS backup_set, a.creation_date, a.size, \
+                ARRAY(SELECT DISTINCT c.data_archive_id FROM catalog_entries c WHERE c.archive_id=a.archive_id AND c.data_archive_id IS NOT NULL) AS depends_on \
+                FROM archives a JOIN vaults_archives va ON a.archive_id=va.archive_id \
+                LEFT JOIN backups b ON a.archive_id=b.archive_id LEFT JOIN archives_metadata m ON a.archive_id=m.archive_id \
+                WHERE va.vault_arn=$1 AND (b.archive_id IS NOT NULL OR m.archive_id IS NOT NULL) \
+                AND NOT EXISTS (SELECT 1 FROM chunks k WHERE k.archive_id=a.archive_id) \
+                AND NOT EXISTS (SELECT 1 FROM archives_deletions d WHERE d.archive_id=a.archive_id) \
+                ORDER BY a.creation_date DESC",
+                &[&vault.vault_arn],
+            )
+            .await?;
+
+        rows.iter().map(PruneCandidate::try_from).collect()
+    }
+
+    /// Records the deletion of an archive from a vault and removes it from the vault.
+    pub async fn create_archive_deletion(
+        transaction: &Transaction<'_>,
+        vault: &AwsVault,
+        archive_id: &str,
+    ) -> Result<()> {
+        debug!(
+            "recording deletion of archive \"{}\" from vault \"{}\"",
+            archive_id, vault.vault_name
+        );
+        transaction
+            .query(
+                "INSERT INTO archives_deletions (archive_id, vault_arn, deletion_date) VALUES ($1, $2, now())",
+                &[&archive_id, &vault.vault_arn],
+            )
+            .await?;
+        transaction
+            .query(
+                "DELETE FROM vaults_archives WHERE vault_arn=$1 AND archive_id=$2",
+                &[&vault.vault_arn, &archive_id],
+            )
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,177 @@
+use super::Repository;
+use crate::dedup::{ChunkLocation, Snapshot};
+use anyhow::Result;
+use log::debug;
+use std::convert::TryFrom;
+use tokio_postgres::Transaction;
+use uuid::Uuid;
+
+const MANIFEST_BATCH_SIZE: usize = 1000;
+
+impl Repository {
+    /// Returns whether the chunk is stored in a pack in the vault, which has not been deleted.
+    ///
+    /// Chunks stored in other vaults are stored again, so a snapshot only depends on packs in its own vault.
+    pub async fn chunk_exists(
+        transaction: &Transaction<'_>,
+        vault_arn: &str,
+        chunk_hash: &str,
+    ) -> Result<bool> {
+        debug!("looking up chunk \"{}\"", chunk_hash);
+        let rows = transaction
+            .query(
+                "SELECT c.chunk_hash FROM chunks c JOIN vaults_archives va ON c.archive_id=va.archive_id \
+                WHERE c.chunk_hash=$1 AND va.vault_arn=$2 \
+                AND NOT EXISTS (SELECT 1 FROM archives_deletions d WHERE d.archive_id=c.archive_id) LIMIT 1",
+                &[&chunk_hash, &vault_arn],
+            )
+            .await?;
+
+        Ok(!rows.is_empty())
+    }
+
+    /// Adds the chunks of an uploaded pack to the chunk index.
+    pub async fn create_chunks(
+        transaction: &Transaction<'_>,
+        chunks: &[ChunkLocation],
+    ) -> Result<()> {
+        debug!("creating {} chunks", chunks.len());
+
+        for batch in chunks.chunks(MANIFEST_BATCH_SIZE) {
+            let chunk_hashes: Vec<&str> = batch.iter().map(|c| &*c.chunk_hash).collect();
+            let archive_ids: Vec<&str> = batch.iter().map(|c| &*c.archive_id).collect();
+            let offsets: Vec<i64> = batch.iter().map(|c| c.offset).collect();
+            let lengths: Vec<i64> = batch.iter().map(|c| c.length).collect();
+            let sizes: Vec<i64> = batch.iter().map(|c| c.size).collect();
+
+            transaction
+                .query(
+                    "INSERT INTO chunks (chunk_hash, archive_id, \"offset\", length, size) \
+                    SELECT * FROM UNNEST($1::varchar[], $2::varchar[], $3::bigint[], $4::bigint[], $5::bigint[]) \
+                    ON CONFLICT (chunk_hash, archive_id) DO NOTHING",
+                    &[&chunk_hashes, &archive_ids, &offsets, &lengths, &sizes],
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Records a snapshot with its sources and manifest. The chunks have to be stored before.
+    pub async fn create_snapshot(transaction: &Transaction<'_>, snapshot: &Snapshot) -> Result<()> {
+        debug!("creating snapshot \"{}\"", snapshot.snapshot_id);
+        transaction
+            .query(
+                "INSERT INTO snapshots (snapshot_id, vault_arn, backup_set, host, creation_date, size, chunk_count, new_chunk_count, new_size) \
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+                &[
+                    &snapshot.snapshot_id,
+                    &snapshot.vault_arn,
+                    &snapshot.backup_set,
+                    &snapshot.host,
+                    &snapshot.creation_date,
+                    &snapshot.size,
+                    &snapshot.chunk_count,
+                    &snapshot.new_chunk_count,
+                    &snapshot.new_size,
+                ],
+            )
+            .await?;
+
+        let paths: Vec<&str> = snapshot.sources.iter().map(|s| &*s.path).collect();
+        let sizes: Vec<i64> = snapshot.sources.iter().map(|s| s.size).collect();
+
+        transaction
+            .query(
+                "INSERT INTO snapshots_sources (snapshot_id, path, size) SELECT $1, * FROM UNNEST($2::varchar[], $3::bigint[])",
+                &[&snapshot.snapshot_id, &paths, &sizes],
+            )
+            .await?;
+
+        for (batch_index, batch) in snapshot.manifest.chunks(MANIFEST_BATCH_SIZE).enumerate() {
+            let first_seq = (batch_index * MANIFEST_BATCH_SIZE) as i64;
+            let seqs: Vec<i64> = (first_seq..first_seq + batch.len() as i64).collect();
+
+            transaction
+                .query(
+                    "INSERT INTO snapshots_chunks (snapshot_id, seq, chunk_hash) SELECT $1, * FROM UNNEST($2::bigint[], $3::varchar[])",
+                    &[&snapshot.snapshot_id, &seqs, &batch],
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the snapshots without their sources and manifests, newest first.
+    pub async fn get_snapshots(transaction: &Transaction<'_>) -> Result<Vec<Snapshot>> {
+        debug!("getting snapshots");
+        let rows = transaction
+            .query("SELECT * FROM snapshots ORDER BY creation_date DESC", &[])
+            .await?;
+
+        rows.iter().map(Snapshot::try_from).collect()
+    }
+
+    /// Returns the backup sets of a vault with snapshots, `None` for snapshots without a backup set.
+    pub async fn get_snapshot_backup_sets(
+        transaction: &Transaction<'_>,
+        vault_arn: &str,
+    ) -> Result<Vec<Option<String>>> {
+        debug!(
+            "getting backup sets with snapshots in vault \"{}\"",
+            vault_arn
+        );
+        let rows = transaction
+            .query(
+                "SELECT DISTINCT backup_set FROM snapshots WHERE vault_arn=$1 ORDER BY backup_set NULLS FIRST",
+                &[&vault_arn],
+            )
+            .await?;
+
+        rows.iter()
+            .map(|r| r.try_get("backup_set").map_err(|e| e.into()))
+            .collect()
+    }
+
+    /// Returns the location of every chunk of a snapshot in the order of the manifest.
+    ///
+    /// Chunks are taken from packs in the vault of the snapshot, which have not been deleted.
+    pub async fn get_snapshot_chunks(
+        transaction: &Transaction<'_>,
+        snapshot_id: &Uuid,
+    ) -> Result<Vec<ChunkLocation>> {
+        debug!("getting chunks of snapshot \"{}\"", snapshot_id);
+        let rows = transaction
+            .query(
+                "SELECT s.chunk_hash AS manifest_hash, c.* FROM snapshots_chunks s JOIN snapshots n ON s.snapshot_id=n.snapshot_id \
+                LEFT JOIN LATERAL (SELECT k.* FROM chunks k JOIN vaults_archives va ON k.archive_id=va.archive_id \
+                WHERE k.chunk_hash=s.chunk_hash AND va.vault_arn=n.vault_arn \
+                AND NOT EXISTS (SELECT 1 FROM archives_deletions d WHERE d.archive_id=k.archive_id) ORDER BY k.archive_id LIMIT 1) c ON TRUE \
+                WHERE s.snapshot_id=$1 ORDER BY s.seq",
+                &[&snapshot_id],
+            )
+            .await?;
+
+        if rows.is_empty() {
+            return Err(anyhow::Error::msg(format!(
+                "snapshot \"{}\" not found",
+                snapshot_id
+            )));
+        }
+
+        rows.iter()
+            .map(|row| {
+                if row.try_get::<_, Option<&str>>("archive_id")?.is_none() {
+                    return Err(anyhow::Error::msg(format!(
+                        "chunk \"{}\" of snapshot \"{}\" is not stored in its vault",
+                        row.try_get::<_, &str>("manifest_hash")?,
+                        snapshot_id
+                    )));
+                }
+
+                ChunkLocation::try_from(row)
+            })
+            .collect()
+    }
+}
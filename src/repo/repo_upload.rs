@@ -0,0 +1,64 @@
+use super::Repository;
+use crate::backup::PendingUpload;
+use anyhow::Result;
+use log::debug;
+use std::convert::TryFrom;
+use tokio_postgres::Transaction;
+
+impl Repository {
+    /// Records a multipart upload interrupted by a shutdown, which is left open to be continued.
+    pub async fn create_pending_upload(
+        transaction: &Transaction<'_>,
+        upload: &PendingUpload,
+    ) -> Result<()> {
+        debug!("creating pending upload \"{}\"", upload.upload_id);
+        let (key_ids, wrapped_keys): (Vec<&str>, Vec<&[u8]>) = upload
+            .wrapped_keys
+            .iter()
+            .map(|(key_id, wrapped_key)| (key_id.as_str(), wrapped_key.as_slice()))
+            .unzip();
+
+        transaction
+            .query(
+                "INSERT INTO multipart_uploads (upload_id, vault_arn, backup_set, paths, archive_description, compression, part_size, parent_archive_id, encryption_header, key_ids, wrapped_keys, size, tree_hashes, creation_date) \
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, now())",
+                &[
+                    &upload.upload_id,
+                    &upload.vault_arn,
+                    &upload.backup_set,
+                    &upload.paths,
+                    &upload.archive_description,
+                    &upload.compression.as_str(),
+                    &(upload.part_size as i64),
+                    &upload.parent_archive_id,
+                    &upload.encryption_header,
+                    &key_ids,
+                    &wrapped_keys,
+                    &(upload.size as i64),
+                    &upload.tree_hashes,
+                ],
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Removes and returns the latest interrupted upload of the paths to the vault.
+    pub async fn take_pending_upload(
+        transaction: &Transaction<'_>,
+        vault_arn: &str,
+        backup_set: Option<&str>,
+        paths: &[String],
+    ) -> Result<Option<PendingUpload>> {
+        debug!("taking pending upload to vault \"{}\"", vault_arn);
+        let rows = transaction
+            .query(
+                "DELETE FROM multipart_uploads WHERE upload_id = \
+                (SELECT upload_id FROM multipart_uploads WHERE vault_arn=$1 AND backup_set IS NOT DISTINCT FROM $2 AND paths=$3 ORDER BY creation_date DESC LIMIT 1) \
+                RETURNING *",
+                &[&vault_arn, &backup_set, &paths],
+            )
+            .await?;
+
+        rows.first().map(PendingUpload::try_from).transpose()
+    }
+}
@@ -1,4 +1,4 @@
-use super::Repository;
+use super::{Page, Paged, Repository, UpsertResult};
 use crate::aws::{aws_archive::AwsArchive, aws_vault::AwsVault};
 use anyhow::Result;
 use log::debug;
@@ -32,6 +32,22 @@ impl Repository {
         }
     }
 
+    pub async fn upsert_vault(
+        transaction: &Transaction<'_>,
+        vault: &AwsVault,
+    ) -> Result<UpsertResult> {
+        debug!("upserting vault \"{}\"", vault.vault_name);
+        let rows = transaction.query(
+            "INSERT INTO vaults (creation_date, last_inventory_date, number_of_archives, size_in_bytes, vault_arn, vault_name) VALUES ($1, $2, $3, $4, $5, $6) \
+            ON CONFLICT (vault_arn) DO UPDATE SET creation_date=EXCLUDED.creation_date, last_inventory_date=EXCLUDED.last_inventory_date, number_of_archives=EXCLUDED.number_of_archives, size_in_bytes=EXCLUDED.size_in_bytes, vault_name=EXCLUDED.vault_name \
+            WHERE (vaults.creation_date, vaults.last_inventory_date, vaults.number_of_archives, vaults.size_in_bytes, vaults.vault_name) IS DISTINCT FROM (EXCLUDED.creation_date, EXCLUDED.last_inventory_date, EXCLUDED.number_of_archives, EXCLUDED.size_in_bytes, EXCLUDED.vault_name) \
+            RETURNING (xmax = 0) AS inserted",
+            &[&vault.creation_date, &vault.last_inventory_date, &vault.number_of_archives, &vault.size_in_bytes, &vault.vault_arn, &vault.vault_name]
+        ).await?;
+
+        UpsertResult::from_rows(&rows)
+    }
+
     pub async fn get_vaults(transaction: &Transaction<'_>) -> Result<Vec<AwsVault>> {
         debug!("getting vaults");
         let rows = transaction.query("SELECT * FROM vaults", &[]).await?;
@@ -44,6 +60,115 @@ impl Repository {
         Ok(res)
     }
 
+    /// Returns the number and the total size in bytes of the active vaults.
+    pub async fn get_vault_totals(transaction: &Transaction<'_>) -> Result<(i64, i64)> {
+        debug!("getting vault totals");
+        let row = transaction
+            .query_one(
+                "SELECT count(*) AS vaults, coalesce(sum(v.size_in_bytes), 0)::bigint AS size_in_bytes FROM vaults v JOIN vaults_status vs ON v.vault_arn=vs.vault_arn WHERE vs.active",
+                &[],
+            )
+            .await?;
+
+        Ok((row.try_get("vaults")?, row.try_get("size_in_bytes")?))
+    }
+
+    /// Returns a page of the vaults with their status flag, filtered by the flag and a part of their name, ordered by name.
+    pub async fn get_vaults_page(
+        transaction: &Transaction<'_>,
+        active: Option<bool>,
+        name: Option<&str>,
+        page: &Page,
+    ) -> Result<Paged<(AwsVault, Option<bool>)>> {
+        debug!("getting vaults (active {:?}, name {:?})", active, name);
+        let filter = "FROM vaults v LEFT JOIN vaults_status vs ON v.vault_arn=vs.vault_arn \
+            WHERE ($1::boolean IS NULL OR vs.active=$1) AND ($2::text IS NULL OR strpos(v.vault_name, $2) > 0)";
+        let rows = transaction
+            .query(
+                format!(
+                    "SELECT v.*, vs.active {} ORDER BY v.vault_name LIMIT $3 OFFSET $4",
+                    filter
+                )
+                .as_str(),
+                &[&active, &name, &page.limit, &page.offset],
+            )
+            .await?;
+        let total = transaction
+            .query_one(
+                format!("SELECT count(*) AS total {}", filter).as_str(),
+                &[&active, &name],
+            )
+            .await?
+            .try_get("total")?;
+        let mut items = Vec::new();
+
+        for row in rows {
+            items.push((AwsVault::try_from(&row)?, row.try_get("active")?));
+        }
+
+        Ok(Paged { items, total })
+    }
+
+    /// Returns the vault with its status flag.
+    pub async fn get_vault_by_name(
+        transaction: &Transaction<'_>,
+        vault_name: &str,
+    ) -> Result<Option<(AwsVault, Option<bool>)>> {
+        debug!("getting vault by name \"{}\"", vault_name);
+        let rows = transaction
+            .query(
+                "SELECT v.*, vs.active FROM vaults v LEFT JOIN vaults_status vs ON v.vault_arn=vs.vault_arn WHERE v.vault_name=$1",
+                &[&vault_name],
+            )
+            .await?;
+
+        match rows.len() {
+            0 => Ok(None),
+            1 => Ok(Some((
+                AwsVault::try_from(&rows[0])?,
+                rows[0].try_get("active")?,
+            ))),
+            _ => Err(anyhow::Error::msg("error getting vault by name")),
+        }
+    }
+
+    pub async fn get_vault_by_arn(
+        transaction: &Transaction<'_>,
+        vault_arn: &str,
+    ) -> Result<AwsVault> {
+        debug!("getting vault \"{}\"", vault_arn);
+        let rows = transaction
+            .query("SELECT * FROM vaults WHERE vault_arn=$1", &[&vault_arn])
+            .await?;
+
+        match rows.len() {
+            1 => Ok(AwsVault::try_from(&rows[0])?),
+            _ => Err(anyhow::Error::msg("error getting vault by arn")),
+        }
+    }
+
+    /// Returns the vault an archive is stored in.
+    pub async fn get_vault_by_archive_id(
+        transaction: &Transaction<'_>,
+        archive_id: &str,
+    ) -> Result<AwsVault> {
+        debug!("getting vault of archive \"{}\"", archive_id);
+        let rows = transaction
+            .query(
+                "SELECT v.* FROM vaults v JOIN vaults_archives va ON v.vault_arn=va.vault_arn WHERE va.archive_id=$1",
+                &[&archive_id],
+            )
+            .await?;
+
+        match rows.len() {
+            1 => Ok(AwsVault::try_from(&rows[0])?),
+            _ => Err(anyhow::Error::msg(format!(
+                "no vault found for archive \"{}\"",
+                archive_id
+            ))),
+        }
+    }
+
     pub async fn reset_vaults_status_active(transaction: &Transaction<'_>) -> Result<()> {
         transaction
             .query("UPDATE vaults_status SET active=FALSE", &[])
@@ -51,38 +176,21 @@ impl Repository {
         Ok(())
     }
 
-    pub async fn set_vault_status_active(
+    pub async fn upsert_vault_status(
         transaction: &Transaction<'_>,
         vault: &AwsVault,
-    ) -> Result<()> {
+        active: bool,
+    ) -> Result<UpsertResult> {
         let rows = transaction
             .query(
-                "SELECT * FROM vaults_status WHERE vault_arn=$1",
-                &[&vault.vault_arn],
+                "INSERT INTO vaults_status (vault_arn, active) VALUES ($1, $2) \
+                ON CONFLICT (vault_arn) DO UPDATE SET active=EXCLUDED.active WHERE vaults_status.active IS DISTINCT FROM EXCLUDED.active \
+                RETURNING (xmax = 0) AS inserted",
+                &[&vault.vault_arn, &active],
             )
             .await?;
 
-        match rows.len() {
-            0 => {
-                transaction
-                    .query(
-                        "INSERT INTO vaults_status (vault_arn, active) VALUES ($1, TRUE)",
-                        &[&vault.vault_arn],
-                    )
-                    .await?;
-                Ok(())
-            }
-            1 => {
-                transaction
-                    .query(
-                        "UPDATE vaults_status SET active=TRUE WHERE vault_arn=$1",
-                        &[&vault.vault_arn],
-                    )
-                    .await?;
-                Ok(())
-            }
-            _ => Err(anyhow::Error::msg("error updating vault status active")),
-        }
+        UpsertResult::from_rows(&rows)
     }
 
     pub async fn delete_archive_associations(
@@ -119,4 +227,25 @@ impl Repository {
             .await?;
         Ok(())
     }
+
+    pub async fn create_archive_associations(
+        transaction: &Transaction<'_>,
+        vault: &AwsVault,
+        archives: &[AwsArchive],
+    ) -> Result<()> {
+        debug!(
+            "creating associations to {} archives for vault \"{}\"",
+            archives.len(),
+            &vault.vault_name
+        );
+        let archive_ids: Vec<&str> = archives.iter().map(|a| &*a.archive_id).collect();
+
+        transaction
+            .query(
+                "INSERT INTO vaults_archives (vault_arn, archive_id) SELECT $1, * FROM UNNEST($2::varchar[]) ON CONFLICT DO NOTHING",
+                &[&vault.vault_arn, &archive_ids],
+            )
+            .await?;
+        Ok(())
+    }
 }
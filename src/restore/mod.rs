@@ -0,0 +1,903 @@
+use crate::aws::{aws_glacier::AwsGlacier, aws_tree_hash::TreeHasher};
+use crate::backup::{
+    backup_reader,
+    backup_segment::{selected_tar_range, tree_hash_aligned_range, ArchiveSegment, PathSelector},
+};
+use crate::crypto::crypto_stream::EncryptionHeader;
+use crate::crypto::{crypto_key::KeyRing, Secret};
+use crate::dedup::{PackFile, SnapshotReader};
+use crate::lease::{LeaseRenewal, WORKER_ID};
+use crate::repo::Repository;
+use crate::shutdown;
+use anyhow::Result;
+use chrono::{DateTime, Duration, FixedOffset, Utc};
+use hyper::body::HttpBody as _;
+use log::{debug, info, warn};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::Path;
+use std::str::FromStr;
+use tokio::io::AsyncWriteExt as _;
+use tokio_postgres::{Row, Transaction};
+use uuid::Uuid;
+
+/// Glacier keeps the output of a job for at least 24 hours after its completion.
+const JOB_OUTPUT_RETENTION_HOURS: i64 = 24;
+const DOWNLOAD_PROGRESS_INTERVAL: i64 = 64 * 1024 * 1024;
+
+/// Time after which the lease of a restore expires, if the process advancing it stopped.
+const RESTORE_LEASE_MINUTES: i64 = 30;
+
+/// Interval in which the lease of a restore being advanced is renewed, well within its expiry.
+const RESTORE_LEASE_RENEWAL_MINUTES: u64 = 5;
+
+/// The states a restore passes through.
+///
+/// Restores end up either extracted or failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestoreState {
+    Requested,
+    JobStarted,
+    JobSucceeded,
+    Downloading,
+    Verified,
+    Extracted,
+    Failed,
+}
+
+impl RestoreState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RestoreState::Requested => "requested",
+            RestoreState::JobStarted => "job_started",
+            RestoreState::JobSucceeded => "job_succeeded",
+            RestoreState::Downloading => "downloading",
+            RestoreState::Verified => "verified",
+            RestoreState::Extracted => "extracted",
+            RestoreState::Failed => "failed",
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self, RestoreState::Extracted | RestoreState::Failed)
+    }
+}
+
+impl FromStr for RestoreState {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "requested" => Ok(RestoreState::Requested),
+            "job_started" => Ok(RestoreState::JobStarted),
+            "job_succeeded" => Ok(RestoreState::JobSucceeded),
+            "downloading" => Ok(RestoreState::Downloading),
+            "verified" => Ok(RestoreState::Verified),
+            "extracted" => Ok(RestoreState::Extracted),
+            "failed" => Ok(RestoreState::Failed),
+            _ => Err(anyhow::Error::msg(format!(
+                "unknown restore state \"{}\"",
+                value
+            ))),
+        }
+    }
+}
+
+/// A request to retrieve, verify and extract an archive.
+#[derive(Debug)]
+pub struct Restore {
+    pub restore_id: Uuid,
+    pub archive_id: String,
+    pub vault_arn: String,
+    /// Retrieval tier of the archive retrieval job ("Expedited", "Standard" or "Bulk").
+    pub tier: String,
+    pub destination: String,
+    /// Paths to extract. If paths are given, only the part of the archive containing them is retrieved.
+    pub paths: Vec<String>,
+    /// First and last byte of the archive to retrieve for a partial restore.
+    pub range_start: Option<i64>,
+    pub range_end: Option<i64>,
+    /// Snapshot the retrieved pack belongs to. Restoring a snapshot retrieves several packs, whose restores share the snapshot restore id.
+    pub snapshot_id: Option<Uuid>,
+    pub snapshot_restore_id: Option<Uuid>,
+    /// File the archive is downloaded to before it is extracted.
+    pub download_path: String,
+    pub state: RestoreState,
+    pub job_id: Option<String>,
+    pub archive_size: i64,
+    pub downloaded_size: i64,
+    /// The last error. Errors only fail a restore if retrying cannot help.
+    pub error: Option<String>,
+    pub creation_date: DateTime<FixedOffset>,
+    pub update_date: DateTime<FixedOffset>,
+}
+
+impl Restore {
+    pub fn new(
+        archive_id: &str,
+        vault_arn: &str,
+        tier: &str,
+        destination: &str,
+        paths: &[String],
+        download_dir: &str,
+    ) -> Self {
+        let restore_id = Uuid::new_v4();
+        let now = Utc::now().into();
+
+        Restore {
+            restore_id,
+            archive_id: archive_id.into(),
+            vault_arn: vault_arn.into(),
+            tier: tier.into(),
+            destination: destination.into(),
+            paths: paths.to_vec(),
+            range_start: None,
+            range_end: None,
+            snapshot_id: None,
+            snapshot_restore_id: None,
+            download_path: Path::new(download_dir)
+                .join(format!("{}.archive", restore_id))
+                .to_string_lossy()
+                .into(),
+            state: RestoreState::Requested,
+            job_id: None,
+            archive_size: 0,
+            downloaded_size: 0,
+            error: None,
+            creation_date: now,
+            update_date: now,
+        }
+    }
+}
+
+impl TryFrom<&Row> for Restore {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &Row) -> Result<Self, Self::Error> {
+        Ok(Restore {
+            restore_id: value.try_get("restore_id")?,
+            archive_id: value.try_get("archive_id")?,
+            vault_arn: value.try_get("vault_arn")?,
+            tier: value.try_get("tier")?,
+            destination: value.try_get("destination")?,
+            paths: value.try_get("paths")?,
+            range_start: value.try_get("range_start")?,
+            range_end: value.try_get("range_end")?,
+            snapshot_id: value.try_get("snapshot_id")?,
+            snapshot_restore_id: value.try_get("snapshot_restore_id")?,
+            download_path: value.try_get("download_path")?,
+            state: value.try_get::<_, &str>("state")?.parse()?,
+            job_id: value.try_get("job_id")?,
+            archive_size: value.try_get("archive_size")?,
+            downloaded_size: value.try_get("downloaded_size")?,
+            error: value.try_get("error")?,
+            creation_date: value.try_get("creation_date")?,
+            update_date: value.try_get("update_date")?,
+        })
+    }
+}
+
+impl Restore {
+    /// Returns the retrieval byte range of a partial restore.
+    pub fn byte_range(&self) -> Option<String> {
+        match (self.range_start, self.range_end) {
+            (Some(start), Some(end)) => Some(format!("{}-{}", start, end)),
+            _ => None,
+        }
+    }
+
+    /// Returns the number of bytes to download.
+    pub fn retrieval_size(&self) -> i64 {
+        match (self.range_start, self.range_end) {
+            (Some(start), Some(end)) => end - start + 1,
+            _ => self.archive_size,
+        }
+    }
+}
+
+/// Limits the retrieval of a restore of selected paths to the part of the archive containing them.
+///
+/// Archives without a frame index are retrieved completely.
+pub async fn set_restore_range(transaction: &Transaction<'_>, restore: &mut Restore) -> Result<()> {
+    if restore.paths.is_empty() {
+        return Ok(());
+    }
+
+    if Repository::get_backup_frames(transaction, &restore.archive_id)
+        .await?
+        .is_empty()
+    {
+        warn!(
+            "archive \"{}\" has no frame index, it is retrieved completely",
+            restore.archive_id
+        );
+        return Ok(());
+    }
+
+    let archive = Repository::get_archive(transaction, &restore.archive_id).await?;
+    let segment = archive_segment(
+        transaction,
+        &restore.archive_id,
+        archive.size,
+        &restore.paths,
+    )
+    .await?;
+
+    info!(
+        "retrieving bytes {} of {} of archive \"{}\"",
+        segment.byte_range(),
+        archive.size,
+        restore.archive_id
+    );
+    restore.range_start = Some(segment.range_start as i64);
+    restore.range_end = Some(segment.range_end as i64);
+
+    Ok(())
+}
+
+/// Creates the restores of a backup as of its creation.
+///
+/// Files an incremental backup took unchanged from earlier backups are restored from the archives holding them, so the restore covers the chain of backups up to the last full backup.
+pub async fn backup_restores(
+    transaction: &Transaction<'_>,
+    archive_id: &str,
+    tier: &str,
+    destination: &str,
+    paths: &[String],
+    download_dir: &str,
+) -> Result<Vec<Restore>> {
+    let catalog = Repository::get_catalog_entries(transaction, archive_id).await?;
+    let selector = PathSelector::new(paths);
+    let mut earlier: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+
+    for entry in &catalog {
+        if let Some(data_archive_id) = &entry.data_archive_id {
+            if paths.is_empty() || selector.selects(&entry.path) {
+                earlier
+                    .entry(data_archive_id)
+                    .or_default()
+                    .push(entry.path.clone());
+            }
+        }
+    }
+
+    let own_selected = paths.is_empty()
+        || catalog.is_empty()
+        || catalog
+            .iter()
+            .any(|e| e.data_archive_id.is_none() && selector.selects(&e.path));
+    let mut restores = Vec::new();
+
+    if own_selected {
+        restores.push((archive_id, paths.to_vec()));
+    }
+
+    restores.extend(earlier);
+
+    if restores.is_empty() {
+        return Err(anyhow::Error::msg(format!(
+            "none of the paths found in the catalog of archive \"{}\"",
+            archive_id
+        )));
+    }
+
+    let mut created = Vec::with_capacity(restores.len());
+
+    for (archive_id, paths) in restores {
+        let archive = Repository::get_archive(transaction, archive_id).await?;
+        let vault = Repository::get_vault_by_archive_id(transaction, archive_id).await?;
+        let mut restore = Restore::new(
+            archive_id,
+            &vault.vault_arn,
+            tier,
+            destination,
+            &paths,
+            download_dir,
+        );
+
+        restore.archive_size = archive.size;
+        set_restore_range(transaction, &mut restore).await?;
+        created.push(restore);
+    }
+
+    Ok(created)
+}
+
+/// Creates the restores retrieving the packs of a snapshot, each limited to the range containing the chunks of the snapshot.
+pub async fn snapshot_restores(
+    transaction: &Transaction<'_>,
+    snapshot_id: &Uuid,
+    tier: &str,
+    destination: &str,
+    download_dir: &str,
+) -> Result<Vec<Restore>> {
+    let mut ranges = BTreeMap::new();
+
+    for chunk in Repository::get_snapshot_chunks(transaction, snapshot_id).await? {
+        let (start, end) = ranges
+            .entry(chunk.archive_id)
+            .or_insert((chunk.offset, chunk.offset + chunk.length));
+
+        *start = i64::min(*start, chunk.offset);
+        *end = i64::max(*end, chunk.offset + chunk.length);
+    }
+
+    let snapshot_restore_id = Uuid::new_v4();
+    let mut restores = Vec::with_capacity(ranges.len());
+
+    for (archive_id, (start, end)) in ranges {
+        let archive = Repository::get_archive(transaction, &archive_id).await?;
+        let vault = Repository::get_vault_by_archive_id(transaction, &archive_id).await?;
+        let mut restore = Restore::new(
+            &archive_id,
+            &vault.vault_arn,
+            tier,
+            destination,
+            &[],
+            download_dir,
+        );
+        let (range_start, range_end) =
+            tree_hash_aligned_range(start as u64, end as u64, archive.size as u64);
+
+        if range_start > 0 || range_end + 1 < archive.size as u64 {
+            restore.range_start = Some(range_start as i64);
+            restore.range_end = Some(range_end as i64);
+        }
+
+        restore.archive_size = archive.size;
+        restore.snapshot_id = Some(*snapshot_id);
+        restore.snapshot_restore_id = Some(snapshot_restore_id);
+        restores.push(restore);
+    }
+
+    Ok(restores)
+}
+
+/// Computes the segment of an archive containing the paths from its catalog and frame index.
+pub async fn archive_segment(
+    transaction: &Transaction<'_>,
+    archive_id: &str,
+    archive_size: i64,
+    paths: &[String],
+) -> Result<ArchiveSegment> {
+    let mut catalog = Repository::get_catalog_entries(transaction, archive_id).await?;
+
+    // entries taken from earlier backups are not part of the tar stream
+    catalog.retain(|e| e.data_archive_id.is_none());
+
+    let (tar_start, tar_end) = selected_tar_range(&catalog, paths).ok_or_else(|| {
+        anyhow::Error::msg(format!(
+            "none of the paths found in the catalog of archive \"{}\"",
+            archive_id
+        ))
+    })?;
+    let frames = Repository::get_backup_frames(transaction, archive_id).await?;
+    let encryption_header =
+        match Repository::get_backup_encryption_header(transaction, archive_id).await? {
+            Some(header_bytes) => Some(EncryptionHeader::from_bytes(&header_bytes)?),
+            None => None,
+        };
+
+    ArchiveSegment::new(
+        tar_start,
+        tar_end,
+        &frames,
+        encryption_header.as_ref(),
+        archive_size as u64,
+    )
+}
+
+/// Returns the secrets to read an archive with.
+///
+/// If the data key of the archive is stored in the repository, it is unwrapped with the given secrets. Otherwise the secrets are used directly.
+pub async fn archive_secrets(
+    transaction: &Transaction<'_>,
+    archive_id: &str,
+    secrets: &[Secret],
+) -> Result<Vec<Secret>> {
+    let archive_keys = Repository::get_archive_keys(transaction, archive_id).await?;
+
+    if archive_keys.is_empty() {
+        return Ok(secrets.to_vec());
+    }
+
+    let key_ring = KeyRing::new(&Repository::get_keys(transaction).await?, secrets)?;
+
+    Ok(vec![Secret::DataKey(
+        key_ring.unwrap_data_key(&archive_keys)?,
+    )])
+}
+
+/// Advances a restore as far as possible and returns it in its latest state.
+///
+/// Every state change is committed, so an interrupted restore continues where it stopped. Errors are recorded with the restore, which is retried the next time it is processed.
+///
+/// The restore is leased while it is advanced, so a restore processed by another worker is returned unchanged.
+pub async fn process_restore(
+    aws_glacier: &AwsGlacier,
+    repo: &mut Repository,
+    db_connection: &str,
+    restore_id: &Uuid,
+    secrets: &[Secret],
+) -> Result<Restore> {
+    let trans = repo.get_transaction().await?;
+    let leased = Repository::lease_restore(&trans, restore_id, &WORKER_ID, &lease_expiry()).await?;
+    let restore = Repository::get_restore(&trans, restore_id).await?;
+
+    trans.commit().await?;
+
+    if !leased {
+        info!("restore \"{}\" is processed by another worker", restore_id);
+
+        return Ok(restore);
+    }
+
+    let renewal = {
+        let db_connection = db_connection.to_string();
+        let restore_id = *restore_id;
+
+        LeaseRenewal::start(
+            format!("restore \"{}\"", restore_id),
+            std::time::Duration::from_secs(RESTORE_LEASE_RENEWAL_MINUTES * 60),
+            move || {
+                let db_connection = db_connection.clone();
+
+                async move { renew_restore_lease(&db_connection, &restore_id).await }
+            },
+        )
+    };
+    let result = advance_restore(aws_glacier, repo, restore_id, secrets).await;
+
+    drop(renewal);
+
+    let trans = repo.get_transaction().await?;
+
+    Repository::release_restore_lease(&trans, restore_id, &WORKER_ID).await?;
+    trans.commit().await?;
+
+    result
+}
+
+async fn advance_restore(
+    aws_glacier: &AwsGlacier,
+    repo: &mut Repository,
+    restore_id: &Uuid,
+    secrets: &[Secret],
+) -> Result<Restore> {
+    loop {
+        let trans = repo.get_transaction().await?;
+        let mut restore = Repository::get_restore(&trans, restore_id).await?;
+
+        trans.commit().await?;
+
+        if restore.state.is_finished() {
+            return Ok(restore);
+        }
+
+        match step(aws_glacier, repo, &mut restore, secrets).await {
+            Ok(true) => continue,
+            Ok(false) => return Ok(restore),
+            Err(e) => {
+                warn!("restore \"{}\" failed: {:?}", restore_id, e);
+                restore.error = Some(e.to_string());
+
+                let trans = repo.get_transaction().await?;
+
+                Repository::update_restore(&trans, &restore).await?;
+                trans.commit().await?;
+
+                return Err(e);
+            }
+        }
+    }
+}
+
+fn lease_expiry() -> DateTime<Utc> {
+    Utc::now() + Duration::minutes(RESTORE_LEASE_MINUTES)
+}
+
+async fn renew_restore_lease(db_connection: &str, restore_id: &Uuid) -> Result<bool> {
+    let mut repo = Repository::new(db_connection).await?;
+    let trans = repo.get_transaction().await?;
+    let renewed =
+        Repository::renew_restore_lease(&trans, restore_id, &WORKER_ID, &lease_expiry()).await?;
+
+    trans.commit().await?;
+
+    Ok(renewed)
+}
+
+/// Performs the step for the current state of a restore and returns whether the state changed.
+async fn step(
+    aws_glacier: &AwsGlacier,
+    repo: &mut Repository,
+    restore: &mut Restore,
+    secrets: &[Secret],
+) -> Result<bool> {
+    debug!(
+        "processing restore \"{}\" ({})",
+        restore.restore_id,
+        restore.state.as_str()
+    );
+    restore.error = None;
+
+    match restore.state {
+        RestoreState::Requested => start_job(aws_glacier, repo, restore).await?,
+        RestoreState::JobStarted => {
+            if !check_job(aws_glacier, repo, restore).await? {
+                return Ok(false);
+            }
+        }
+        RestoreState::JobSucceeded | RestoreState::Downloading => {
+            download(aws_glacier, repo, restore).await?
+        }
+        RestoreState::Verified => {
+            let extracted = match restore.snapshot_restore_id {
+                Some(snapshot_restore_id) => {
+                    extract_snapshot(repo, restore, &snapshot_restore_id, secrets).await?
+                }
+                None => {
+                    extract(repo, restore, secrets).await?;
+                    true
+                }
+            };
+
+            if !extracted {
+                return Ok(false);
+            }
+        }
+        RestoreState::Extracted | RestoreState::Failed => return Ok(false),
+    }
+
+    let trans = repo.get_transaction().await?;
+
+    Repository::update_restore(&trans, restore).await?;
+    trans.commit().await?;
+    info!(
+        "restore \"{}\" is {}",
+        restore.restore_id,
+        restore.state.as_str()
+    );
+
+    Ok(true)
+}
+
+async fn start_job(
+    aws_glacier: &AwsGlacier,
+    repo: &mut Repository,
+    restore: &mut Restore,
+) -> Result<()> {
+    let trans = repo.get_transaction().await?;
+    let vault = Repository::get_vault_by_arn(&trans, &restore.vault_arn).await?;
+    let job_id = aws_glacier
+        .init_archive_retrieval_job(
+            &vault,
+            &restore.archive_id,
+            &restore.tier,
+            restore.byte_range().as_deref(),
+            &format!("restore {}", restore.restore_id),
+        )
+        .await?;
+    let job = aws_glacier.get_job_by_id_vault(&vault, &job_id).await?;
+
+    Repository::upsert_job(&trans, &job).await?;
+    Repository::upsert_job_status(&trans, &job, true).await?;
+    trans.commit().await?;
+
+    restore.job_id = Some(job_id);
+    restore.downloaded_size = 0;
+    restore.state = RestoreState::JobStarted;
+
+    Ok(())
+}
+
+/// Updates the retrieval job and returns whether it is finished.
+async fn check_job(
+    aws_glacier: &AwsGlacier,
+    repo: &mut Repository,
+    restore: &mut Restore,
+) -> Result<bool> {
+    let trans = repo.get_transaction().await?;
+    let vault = Repository::get_vault_by_arn(&trans, &restore.vault_arn).await?;
+    let job = aws_glacier
+        .get_job_by_id_vault(&vault, restore_job_id(restore)?)
+        .await?;
+
+    Repository::upsert_job(&trans, &job).await?;
+    trans.commit().await?;
+
+    match &*job.status_code {
+        "Succeeded" => restore.state = RestoreState::JobSucceeded,
+        "Failed" => {
+            restore.state = RestoreState::Failed;
+            restore.error = Some(format!(
+                "retrieval job failed: {}",
+                job.status_message.as_deref().unwrap_or("")
+            ));
+        }
+        status_code => {
+            debug!(
+                "retrieval job \"{}\" is {}",
+                job.job_id,
+                status_code.to_lowercase()
+            );
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+/// Downloads the job output and verifies its tree hash.
+///
+/// If the job output has expired in the meantime, the restore starts over with a new job.
+async fn download(
+    aws_glacier: &AwsGlacier,
+    repo: &mut Repository,
+    restore: &mut Restore,
+) -> Result<()> {
+    let trans = repo.get_transaction().await?;
+    let vault = Repository::get_vault_by_arn(&trans, &restore.vault_arn).await?;
+    let job = Repository::get_job_by_id(&trans, restore_job_id(restore)?).await?;
+
+    trans.commit().await?;
+
+    if let Some(completion_date) = job.completion_date {
+        if completion_date + Duration::hours(JOB_OUTPUT_RETENTION_HOURS) < Utc::now() {
+            warn!(
+                "output of job \"{}\" expired, requesting the archive again",
+                job.job_id
+            );
+            restore.state = RestoreState::Requested;
+
+            return Ok(());
+        }
+    }
+
+    restore.state = RestoreState::Downloading;
+    restore.downloaded_size = 0;
+    update_progress(repo, restore).await?;
+
+    let mut body = aws_glacier.get_job_output(&vault, &job).await?;
+    let mut file = tokio::fs::File::create(&restore.download_path).await?;
+    let mut tree_hasher = TreeHasher::new();
+    let mut reported_size = 0;
+
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+
+        if shutdown::is_requested() {
+            // the download starts over the next time the restore is processed
+            drop(file);
+            fs::remove_file(&restore.download_path)?;
+            return shutdown::check();
+        }
+
+        tree_hasher.update(&chunk);
+        file.write_all(&chunk).await?;
+        restore.downloaded_size += chunk.len() as i64;
+
+        if restore.downloaded_size - reported_size >= DOWNLOAD_PROGRESS_INTERVAL {
+            reported_size = restore.downloaded_size;
+            update_progress(repo, restore).await?;
+        }
+    }
+
+    file.flush().await?;
+
+    let tree_hash = tree_hasher.finish();
+    // the tree hash of a ranged retrieval only covers the range
+    let expected_tree_hash = match job.retrieval_byte_range {
+        Some(_) => job.tree_hash.as_deref(),
+        None => job.archive_tree_hash.as_deref(),
+    };
+
+    if expected_tree_hash != Some(&tree_hash) {
+        fs::remove_file(&restore.download_path)?;
+        restore.state = RestoreState::Failed;
+        restore.error = Some(format!(
+            "tree hash {} of the download does not match the retrieval job",
+            tree_hash
+        ));
+
+        return Ok(());
+    }
+
+    restore.state = RestoreState::Verified;
+
+    Ok(())
+}
+
+async fn extract(repo: &mut Repository, restore: &mut Restore, secrets: &[Secret]) -> Result<()> {
+    let trans = repo.get_transaction().await?;
+    let secrets = archive_secrets(&trans, &restore.archive_id, secrets).await?;
+    let segment = match restore.range_start {
+        Some(_) => Some((
+            archive_segment(
+                &trans,
+                &restore.archive_id,
+                restore.archive_size,
+                &restore.paths,
+            )
+            .await?,
+            Repository::get_backup_encryption_header(&trans, &restore.archive_id).await?,
+        )),
+        None => None,
+    };
+
+    trans.commit().await?;
+
+    let download_path = restore.download_path.clone();
+    let destination = restore.destination.clone();
+    let paths = restore.paths.clone();
+
+    tokio::task::spawn_blocking(move || -> Result<()> {
+        let reader = BufReader::new(File::open(&download_path)?);
+        let reader = match segment {
+            Some((segment, encryption_header)) => backup_reader::segment_reader(
+                reader,
+                &segment,
+                encryption_header.as_deref(),
+                &secrets,
+            )?,
+            None => backup_reader::archive_reader(reader, &secrets)?,
+        };
+        let mut archive = tar::Archive::new(reader);
+
+        fs::create_dir_all(&destination)?;
+
+        if paths.is_empty() {
+            archive.unpack(&destination)?;
+        } else {
+            let selector = PathSelector::new(&paths);
+
+            // a segment may contain other entries between the selected ones
+            for entry in archive.entries()? {
+                let mut entry = entry?;
+                let path = Path::new("/").join(entry.path()?);
+
+                if selector.selects(&path.to_string_lossy()) {
+                    entry.unpack_in(&destination)?;
+                }
+            }
+        }
+
+        Ok(())
+    })
+    .await??;
+
+    fs::remove_file(&restore.download_path)?;
+    restore.state = RestoreState::Extracted;
+
+    Ok(())
+}
+
+/// Assembles the snapshot from the downloaded packs once all of them are verified and returns whether it was extracted.
+///
+/// The restores of all packs are finished together.
+async fn extract_snapshot(
+    repo: &mut Repository,
+    restore: &mut Restore,
+    snapshot_restore_id: &Uuid,
+    secrets: &[Secret],
+) -> Result<bool> {
+    // the restores of the snapshot stay locked until it is extracted, so only one worker extracts it
+    let trans = repo.get_transaction().await?;
+    let mut siblings: Vec<Restore> =
+        match Repository::lock_snapshot_restores(&trans, snapshot_restore_id).await? {
+            Some(restores) => restores
+                .into_iter()
+                .filter(|r| r.restore_id != restore.restore_id)
+                .collect(),
+            None => {
+                debug!(
+                    "the snapshot of restore \"{}\" is extracted by another worker",
+                    restore.restore_id
+                );
+
+                return Ok(false);
+            }
+        };
+
+    if let Some(failed) = siblings.iter().find(|r| r.state == RestoreState::Failed) {
+        restore.state = RestoreState::Failed;
+        restore.error = Some(format!(
+            "restore \"{}\" of another pack of the snapshot failed",
+            failed.restore_id
+        ));
+
+        return Ok(true);
+    }
+
+    if siblings.iter().any(|r| r.state != RestoreState::Verified) {
+        debug!(
+            "restore \"{}\" waits for the other packs of the snapshot",
+            restore.restore_id
+        );
+
+        return Ok(false);
+    }
+
+    let snapshot_id = restore
+        .snapshot_id
+        .ok_or_else(|| anyhow::Error::msg("restore has no snapshot"))?;
+    let chunks = Repository::get_snapshot_chunks(&trans, &snapshot_id).await?;
+    let mut packs = HashMap::new();
+
+    for pack_restore in siblings.iter().chain(std::iter::once(&*restore)) {
+        packs.insert(
+            pack_restore.archive_id.clone(),
+            PackFile {
+                file: File::open(&pack_restore.download_path)?,
+                range_start: pack_restore.range_start.unwrap_or(0) as u64,
+                secrets: archive_secrets(&trans, &pack_restore.archive_id, secrets).await?,
+            },
+        );
+    }
+
+    let destination = restore.destination.clone();
+
+    tokio::task::spawn_blocking(move || -> Result<()> {
+        fs::create_dir_all(&destination)?;
+        tar::Archive::new(SnapshotReader::new(chunks, packs)).unpack(&destination)?;
+
+        Ok(())
+    })
+    .await??;
+
+    for sibling in &mut siblings {
+        sibling.state = RestoreState::Extracted;
+        Repository::update_restore(&trans, sibling).await?;
+    }
+
+    trans.commit().await?;
+
+    for sibling in siblings.iter().chain(std::iter::once(&*restore)) {
+        fs::remove_file(&sibling.download_path)?;
+    }
+
+    restore.state = RestoreState::Extracted;
+
+    Ok(true)
+}
+
+async fn update_progress(repo: &mut Repository, restore: &Restore) -> Result<()> {
+    let trans = repo.get_transaction().await?;
+
+    Repository::update_restore(&trans, restore).await?;
+    trans.commit().await?;
+
+    Ok(())
+}
+
+fn restore_job_id(restore: &Restore) -> Result<&str> {
+    restore
+        .job_id
+        .as_deref()
+        .ok_or_else(|| anyhow::Error::msg("restore has no retrieval job"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restore_state_1() {
+        for state in &[
+            RestoreState::Requested,
+            RestoreState::JobStarted,
+            RestoreState::JobSucceeded,
+            RestoreState::Downloading,
+            RestoreState::Verified,
+            RestoreState::Extracted,
+            RestoreState::Failed,
+        ] {
+            assert_eq!(state.as_str().parse::<RestoreState>().unwrap(), *state);
+        }
+
+        assert!(RestoreState::Failed.is_finished());
+        assert!(!RestoreState::Verified.is_finished());
+    }
+}
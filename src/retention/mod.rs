@@ -0,0 +1,361 @@
+use anyhow::Result;
+use chrono::{DateTime, Datelike, Duration, FixedOffset, Months, Utc};
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use tokio_postgres::Row;
+
+/// Glacier charges archives for at least 90 days. Deleting an archive earlier costs the storage of the remaining days.
+pub const MINIMUM_STORAGE_DAYS: i64 = 90;
+
+/// Which archives of a vault, or of a backup set within it, to keep.
+///
+/// An archive is kept if any of the rules selects it: the newest `keep_last` archives and the newest archive of every day, week and month within the last `keep_daily` days, `keep_weekly` weeks and `keep_monthly` months.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    pub vault_arn: String,
+    /// The policy without a backup set applies to the archives of the vault not covered by a policy for their backup set.
+    pub backup_set: Option<String>,
+    pub keep_last: i32,
+    pub keep_daily: i32,
+    pub keep_weekly: i32,
+    pub keep_monthly: i32,
+}
+
+impl TryFrom<&Row> for RetentionPolicy {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &Row) -> Result<Self, Self::Error> {
+        Ok(RetentionPolicy {
+            vault_arn: value.try_get("vault_arn")?,
+            backup_set: value.try_get("backup_set")?,
+            keep_last: value.try_get("keep_last")?,
+            keep_daily: value.try_get("keep_daily")?,
+            keep_weekly: value.try_get("keep_weekly")?,
+            keep_monthly: value.try_get("keep_monthly")?,
+        })
+    }
+}
+
+impl RetentionPolicy {
+    /// Returns the policy applying to an archive of the backup set, preferring the policy of the set over the one of the vault.
+    pub fn find<'a>(
+        policies: &'a [RetentionPolicy],
+        vault_arn: &str,
+        backup_set: Option<&str>,
+    ) -> Option<&'a RetentionPolicy> {
+        policies
+            .iter()
+            .filter(|p| p.vault_arn == vault_arn)
+            .find(|p| backup_set.is_some() && p.backup_set.as_deref() == backup_set)
+            .or_else(|| {
+                policies
+                    .iter()
+                    .find(|p| p.vault_arn == vault_arn && p.backup_set.is_none())
+            })
+    }
+}
+
+/// An archive created by a backup, which may be pruned.
+#[derive(Debug, Clone)]
+pub struct PruneCandidate {
+    pub archive_id: String,
+    pub vault_arn: String,
+    pub backup_set: Option<String>,
+    pub creation_date: DateTime<FixedOffset>,
+    pub size: i64,
+    /// Archives holding files of an incremental backup, which must be kept as long as it is.
+    pub depends_on: Vec<String>,
+}
+
+impl TryFrom<&Row> for PruneCandidate {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &Row) -> Result<Self, Self::Error> {
+        Ok(PruneCandidate {
+            archive_id: value.try_get("archive_id")?,
+            vault_arn: value.try_get("vault_arn")?,
+            backup_set: value.try_get("backup_set")?,
+            creation_date: value.try_get("creation_date")?,
+            size: value.try_get("size")?,
+            depends_on: value.try_get("depends_on")?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PruneAction {
+    /// The archive is kept for the given reason.
+    Keep(String),
+    Delete,
+    /// The archive is due for deletion, but deleting it before the end of the minimum storage duration would cost as much as keeping it.
+    Defer(DateTime<FixedOffset>),
+}
+
+#[derive(Debug, Clone)]
+pub struct PruneItem {
+    pub candidate: PruneCandidate,
+    pub action: PruneAction,
+}
+
+/// Decides for every archive covered by a policy whether it is kept, deleted or deferred.
+///
+/// Archives not covered by any policy are left out.
+pub fn prune_plan(
+    policies: &[RetentionPolicy],
+    candidates: &[PruneCandidate],
+    now: DateTime<Utc>,
+) -> Vec<PruneItem> {
+    let mut items: Vec<PruneItem> = Vec::new();
+
+    for policy in policies {
+        let mut covered: Vec<&PruneCandidate> = candidates
+            .iter()
+            .filter(|c| {
+                RetentionPolicy::find(policies, &c.vault_arn, c.backup_set.as_deref())
+                    == Some(policy)
+            })
+            .collect();
+
+        covered.sort_by_key(|c| Reverse(c.creation_date));
+
+        let reasons = keep_reasons(policy, &covered, now);
+
+        items.extend(
+            covered
+                .into_iter()
+                .zip(reasons)
+                .map(|(candidate, reason)| PruneItem {
+                    candidate: candidate.clone(),
+                    action: match reason {
+                        Some(reason) => PruneAction::Keep(reason.into()),
+                        None => PruneAction::Delete,
+                    },
+                }),
+        );
+    }
+
+    keep_dependencies(&mut items);
+
+    for item in &mut items {
+        let end_of_minimum_storage =
+            item.candidate.creation_date + Duration::days(MINIMUM_STORAGE_DAYS);
+
+        if item.action == PruneAction::Delete && end_of_minimum_storage > now {
+            item.action = PruneAction::Defer(end_of_minimum_storage);
+        }
+    }
+
+    items.sort_by_key(|i| Reverse(i.candidate.creation_date));
+    items
+}
+
+/// Maps a date to the day, week or month it belongs to.
+type Period = fn(DateTime<Utc>) -> (i32, u32, u32);
+
+/// Returns the first rule keeping each of the archives, which are sorted newest first.
+fn keep_reasons(
+    policy: &RetentionPolicy,
+    archives: &[&PruneCandidate],
+    now: DateTime<Utc>,
+) -> Vec<Option<&'static str>> {
+    let mut reasons = vec![None; archives.len()];
+
+    for reason in reasons.iter_mut().take(policy.keep_last.max(0) as usize) {
+        *reason = Some("last");
+    }
+
+    let rules: [(&'static str, DateTime<Utc>, Period); 3] = [
+        (
+            "daily",
+            now - Duration::days(policy.keep_daily.into()),
+            |date| (date.year(), date.month(), date.day()),
+        ),
+        (
+            "weekly",
+            now - Duration::weeks(policy.keep_weekly.into()),
+            |date| (date.iso_week().year(), date.iso_week().week(), 0),
+        ),
+        (
+            "monthly",
+            now.checked_sub_months(Months::new(policy.keep_monthly.max(0) as u32))
+                .unwrap_or(now),
+            |date| (date.year(), date.month(), 0),
+        ),
+    ];
+
+    for (name, since, period) in rules.iter() {
+        let mut periods = HashSet::new();
+
+        for (archive, reason) in archives.iter().zip(reasons.iter_mut()) {
+            let date = archive.creation_date.with_timezone(&Utc);
+
+            // the newest archive of a period is the first one seen
+            if date > *since && periods.insert(period(date)) && reason.is_none() {
+                *reason = Some(*name);
+            }
+        }
+    }
+
+    reasons
+}
+
+/// Keeps the archives holding files of incremental backups, which are kept.
+fn keep_dependencies(items: &mut [PruneItem]) {
+    loop {
+        let mut needed: Vec<(String, String)> = Vec::new();
+
+        for item in items.iter() {
+            if let PruneAction::Keep(_) = item.action {
+                for archive_id in &item.candidate.depends_on {
+                    needed.push((archive_id.clone(), item.candidate.archive_id.clone()));
+                }
+            }
+        }
+
+        let mut changed = false;
+
+        for (archive_id, needed_by) in needed {
+            if let Some(item) = items
+                .iter_mut()
+                .find(|i| i.candidate.archive_id == archive_id && i.action == PruneAction::Delete)
+            {
+                item.action = PruneAction::Keep(format!("needed by \"{}\"", needed_by));
+                changed = true;
+            }
+        }
+
+        if !changed {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(archive_id: &str, days_ago: i64, now: DateTime<Utc>) -> PruneCandidate {
+        PruneCandidate {
+            archive_id: archive_id.into(),
+            vault_arn: "vault".into(),
+            backup_set: Some("set".into()),
+            creation_date: (now - Duration::days(days_ago)).into(),
+            size: 1,
+            depends_on: Vec::new(),
+        }
+    }
+
+    fn action<'a>(items: &'a [PruneItem], archive_id: &str) -> &'a PruneAction {
+        &items
+            .iter()
+            .find(|i| i.candidate.archive_id == archive_id)
+            .unwrap()
+            .action
+    }
+
+    #[test]
+    fn prune_plan_1() {
+        let now = DateTime::parse_from_rfc3339("2026-06-15T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let policies = vec![RetentionPolicy {
+            vault_arn: "vault".into(),
+            backup_set: None,
+            keep_last: 2,
+            keep_daily: 0,
+            keep_weekly: 0,
+            keep_monthly: 6,
+        }];
+        let mut candidates = vec![
+            candidate("today", 0, now),
+            candidate("yesterday", 1, now),
+            candidate("last week", 7, now),
+            candidate("april", 60, now),
+            candidate("april older", 62, now),
+            candidate("february", 120, now),
+            candidate("february older", 125, now),
+            candidate("last year", 400, now),
+        ];
+
+        // the incremental backup of last year depends on an older archive
+        candidates.push(candidate("base", 410, now));
+        candidates[7].depends_on = vec!["base".into()];
+        // archives of another vault are not covered
+        candidates.push(PruneCandidate {
+            vault_arn: "other".into(),
+            ..candidate("other", 500, now)
+        });
+        let items = prune_plan(&policies, &candidates, now);
+
+        assert_eq!(items.len(), 9);
+        assert_eq!(action(&items, "today"), &PruneAction::Keep("last".into()));
+        assert_eq!(
+            action(&items, "yesterday"),
+            &PruneAction::Keep("last".into())
+        );
+        // June is already covered by the newest archive
+        assert_eq!(
+            action(&items, "last week"),
+            &PruneAction::Defer((now - Duration::days(7) + Duration::days(90)).into())
+        );
+        assert_eq!(
+            action(&items, "april"),
+            &PruneAction::Keep("monthly".into())
+        );
+        assert_eq!(
+            action(&items, "april older"),
+            &PruneAction::Defer((now - Duration::days(62) + Duration::days(90)).into())
+        );
+        assert_eq!(
+            action(&items, "february"),
+            &PruneAction::Keep("monthly".into())
+        );
+        assert_eq!(action(&items, "february older"), &PruneAction::Delete);
+        // the base is only kept as long as the incremental backup depending on it
+        assert_eq!(action(&items, "last year"), &PruneAction::Delete);
+        assert_eq!(action(&items, "base"), &PruneAction::Delete);
+    }
+
+    #[test]
+    fn prune_plan_2() {
+        let now = Utc::now();
+        let policies = vec![
+            RetentionPolicy {
+                vault_arn: "vault".into(),
+                backup_set: None,
+                keep_last: 0,
+                keep_daily: 0,
+                keep_weekly: 0,
+                keep_monthly: 0,
+            },
+            RetentionPolicy {
+                vault_arn: "vault".into(),
+                backup_set: Some("set".into()),
+                keep_last: 1,
+                keep_daily: 0,
+                keep_weekly: 0,
+                keep_monthly: 0,
+            },
+        ];
+        let mut candidates = vec![
+            candidate("incremental", 100, now),
+            candidate("full", 200, now),
+            candidate("older", 300, now),
+        ];
+
+        candidates[0].depends_on = vec!["full".into()];
+
+        let items = prune_plan(&policies, &candidates, now);
+
+        assert_eq!(
+            action(&items, "incremental"),
+            &PruneAction::Keep("last".into())
+        );
+        assert_eq!(
+            action(&items, "full"),
+            &PruneAction::Keep("needed by \"incremental\"".into())
+        );
+        assert_eq!(action(&items, "older"), &PruneAction::Delete);
+    }
+}
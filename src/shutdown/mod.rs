@@ -0,0 +1,88 @@
+use anyhow::Result;
+use log::{info, warn};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::Notify;
+use tokio::time::{timeout, Duration};
+
+/// Time in-flight work is given to reach a checkpoint after a shutdown was requested.
+pub const DEFAULT_TIMEOUT_SECONDS: u64 = 60;
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+static NOTIFY: Notify = Notify::const_new();
+
+/// Requests a shutdown on SIGTERM or SIGINT.
+pub fn listen() -> Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+
+    tokio::spawn(async move {
+        let name = tokio::select! {
+            _ = terminate.recv() => "SIGTERM",
+            _ = interrupt.recv() => "SIGINT",
+        };
+
+        info!("received {}, shutting down", name);
+        request();
+    });
+
+    Ok(())
+}
+
+/// Stops new work; in-flight work stops at its next checkpoint.
+pub fn request() {
+    REQUESTED.store(true, Ordering::SeqCst);
+    NOTIFY.notify_waiters();
+}
+
+pub fn is_requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Returns once a shutdown is requested.
+pub async fn requested() {
+    let notified = NOTIFY.notified();
+
+    if is_requested() {
+        return;
+    }
+
+    notified.await;
+}
+
+/// A checkpoint of long running work, which fails once a shutdown is requested.
+pub fn check() -> Result<()> {
+    match is_requested() {
+        true => Err(anyhow::Error::msg("shutdown requested")),
+        false => Ok(()),
+    }
+}
+
+/// Waits for the in-flight work to finish, but at most for the given time.
+///
+/// Returns whether the work finished in time.
+pub async fn drain<F: Future>(work: F, deadline: Duration) -> bool {
+    match timeout(deadline, work).await {
+        Ok(_) => true,
+        Err(_) => {
+            warn!(
+                "in-flight work did not finish within {} seconds",
+                deadline.as_secs()
+            );
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::sleep;
+
+    #[tokio::test]
+    async fn drain_1() {
+        assert!(drain(sleep(Duration::from_millis(10)), Duration::from_secs(1)).await);
+        assert!(!drain(sleep(Duration::from_secs(1)), Duration::from_millis(10)).await);
+    }
+}
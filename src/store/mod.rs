@@ -0,0 +1,144 @@
+use crate::aws::aws_inventory::AwsInventoryFormat;
+use anyhow::Result;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use log::debug;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+/// Keeps the raw output of inventory jobs as gzip compressed files, so inventories can be reprocessed after Glacier has discarded the job output.
+pub struct InventoryStore {
+    path: PathBuf,
+}
+
+impl InventoryStore {
+    pub fn new(path: &str) -> Result<Self> {
+        fs::create_dir_all(path)?;
+
+        Ok(InventoryStore { path: path.into() })
+    }
+
+    /// Creates a writer for the output of an inventory job.
+    ///
+    /// The data only becomes visible in the store once the writer is finished.
+    pub fn create(&self, job_id: &str, format: AwsInventoryFormat) -> Result<InventoryStoreWriter> {
+        let path = self.file_path(job_id, format)?;
+        let tmp_path = path.with_extension("tmp");
+        debug!("storing inventory \"{}\" in \"{}\"", job_id, path.display());
+
+        Ok(InventoryStoreWriter {
+            encoder: GzEncoder::new(File::create(&tmp_path)?, Compression::default()),
+            tmp_path,
+            path,
+        })
+    }
+
+    pub fn save(&self, job_id: &str, format: AwsInventoryFormat, data: &[u8]) -> Result<()> {
+        let mut writer = self.create(job_id, format)?;
+
+        writer.write_all(data)?;
+        writer.finish()
+    }
+
+    /// Opens the stored output of an inventory job and returns its format and a reader for the decompressed data.
+    pub fn open(&self, job_id: &str) -> Result<(AwsInventoryFormat, GzDecoder<File>)> {
+        for format in &[AwsInventoryFormat::Json, AwsInventoryFormat::Csv] {
+            let path = self.file_path(job_id, *format)?;
+
+            if path.exists() {
+                debug!(
+                    "loading inventory \"{}\" from \"{}\"",
+                    job_id,
+                    path.display()
+                );
+                return Ok((*format, GzDecoder::new(File::open(&path)?)));
+            }
+        }
+
+        Err(anyhow::Error::msg(format!(
+            "inventory \"{}\" not found in store",
+            job_id
+        )))
+    }
+
+    pub fn contains(&self, job_id: &str) -> Result<bool> {
+        Ok(self.file_path(job_id, AwsInventoryFormat::Json)?.exists()
+            || self.file_path(job_id, AwsInventoryFormat::Csv)?.exists())
+    }
+
+    fn file_path(&self, job_id: &str, format: AwsInventoryFormat) -> Result<PathBuf> {
+        if job_id.is_empty()
+            || !job_id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(anyhow::Error::msg(format!("invalid job id \"{}\"", job_id)));
+        }
+
+        Ok(self
+            .path
+            .join(format!("{}.{}.gz", job_id, format.extension())))
+    }
+}
+
+pub struct InventoryStoreWriter {
+    encoder: GzEncoder<File>,
+    tmp_path: PathBuf,
+    path: PathBuf,
+}
+
+impl InventoryStoreWriter {
+    pub fn finish(self) -> Result<()> {
+        self.encoder.finish()?.sync_all()?;
+        fs::rename(&self.tmp_path, &self.path)?;
+
+        Ok(())
+    }
+}
+
+impl Write for InventoryStoreWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.encoder.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.encoder.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn save_open_1() {
+        let dir = std::env::temp_dir().join(format!("inventory-store-{}", std::process::id()));
+        let store = InventoryStore::new(dir.to_str().unwrap()).unwrap();
+        let mut data = Vec::new();
+
+        store
+            .save("job-1_A", AwsInventoryFormat::Csv, b"ArchiveId\n")
+            .unwrap();
+        assert!(store.contains("job-1_A").unwrap());
+
+        let (format, mut reader) = store.open("job-1_A").unwrap();
+
+        reader.read_to_end(&mut data).unwrap();
+        assert_eq!(format, AwsInventoryFormat::Csv);
+        assert_eq!(data, b"ArchiveId\n");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn file_path_1() {
+        let store = InventoryStore {
+            path: "/tmp".into(),
+        };
+
+        assert!(store
+            .file_path("../etc/passwd", AwsInventoryFormat::Json)
+            .is_err());
+        assert!(store.file_path("", AwsInventoryFormat::Json).is_err());
+    }
+}